@@ -0,0 +1,61 @@
+//! The Immediate Alert Service (IAS), which makes a device alert its user, e.g. to find a phone
+//! by its sound.
+
+use bluer::{
+    gatt::{
+        remote::{Characteristic, CharacteristicWriteRequest},
+        WriteOp,
+    },
+    Device, Uuid,
+};
+
+use crate::error::AncsError;
+
+const IMMEDIATE_ALERT_UUID: Uuid = Uuid::from_u128(0x00001802_0000_1000_8000_00805f9b34fb);
+const ALERT_LEVEL_UUID: Uuid = Uuid::from_u128(0x00002a06_0000_1000_8000_00805f9b34fb);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum AlertLevel {
+    /// Stops alerting.
+    None = 0,
+    Mild = 1,
+    High = 2,
+}
+
+/// The alert level characteristic of a connected device.
+pub struct ImmediateAlert {
+    alert_level: Characteristic,
+}
+
+impl ImmediateAlert {
+    /// Finds IAS on a device whose services are resolved, None if it does not offer it.
+    pub async fn find(device: &Device) -> Result<Option<Self>, AncsError> {
+        for service in device.services().await? {
+            if service.uuid().await? != IMMEDIATE_ALERT_UUID {
+                continue;
+            }
+            for c in service.characteristics().await? {
+                if c.uuid().await? == ALERT_LEVEL_UUID {
+                    return Ok(Some(Self { alert_level: c }));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Alerts until told [`AlertLevel::None`], or as long as the device does on its own.
+    pub async fn alert(&self, level: AlertLevel) -> Result<(), AncsError> {
+        // The alert level only takes writes without response
+        Ok(self
+            .alert_level
+            .write_ext(
+                &[level as u8],
+                &CharacteristicWriteRequest {
+                    op_type: WriteOp::Command,
+                    ..Default::default()
+                },
+            )
+            .await?)
+    }
+}
@@ -0,0 +1,316 @@
+//! The Apple Media Service (AMS), which iPhones offer next to ANCS: commands for the player of
+//! the phone, like play and next track, and updates of what it plays.
+
+use bluer::{
+    gatt::{
+        remote::{Characteristic, CharacteristicWriteRequest},
+        WriteOp,
+    },
+    Device, Uuid,
+};
+use futures::{stream::BoxStream, StreamExt as _};
+
+use crate::error::AncsError;
+
+const AMS_UUID: &str = "89D3502B-0F36-433A-8EF4-C502AD55F8DC";
+const REMOTE_COMMAND_UUID: &str = "9B3C81D8-57B1-4A8A-B8DF-0E56F7CA51C2";
+const ENTITY_UPDATE_UUID: &str = "2F7CABCE-808D-411F-9A0C-BB92BA96C102";
+const ENTITY_ATTRIBUTE_UUID: &str = "C6B2F38C-23AB-46D8-A6AB-A3A870BBD5D7";
+
+pub const REMOTE_COMMAND: &str = "remote command";
+pub const ENTITY_UPDATE: &str = "entity update";
+
+const ENTITY_PLAYER: u8 = 0;
+const ENTITY_TRACK: u8 = 2;
+/// Set on updates whose value did not fit, the rest is read from the entity attribute.
+const FLAG_TRUNCATED: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum RemoteCommand {
+    Play = 0,
+    Pause = 1,
+    TogglePlayPause = 2,
+    NextTrack = 3,
+    PreviousTrack = 4,
+    VolumeUp = 5,
+    VolumeDown = 6,
+}
+
+impl RemoteCommand {
+    const ALL: [Self; 7] = [
+        Self::Play,
+        Self::Pause,
+        Self::TogglePlayPause,
+        Self::NextTrack,
+        Self::PreviousTrack,
+        Self::VolumeUp,
+        Self::VolumeDown,
+    ];
+
+    /// The commands the player of the phone takes at the moment, as the remote command
+    /// characteristic tells them. Others, like skipping forward, are left out.
+    pub fn parse_supported(data: &[u8]) -> Vec<Self> {
+        Self::ALL
+            .into_iter()
+            .filter(|command| data.contains(&(*command as u8)))
+            .collect()
+    }
+}
+
+/// What is subscribed to, of the player and of the track playing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Attribute {
+    /// Of the app playing, e.g. "Music".
+    PlayerName,
+    /// See [`PlaybackInfo`].
+    PlaybackInfo,
+    /// From 0 to 1.
+    Volume,
+    Artist,
+    Album,
+    Title,
+    /// In seconds.
+    Duration,
+}
+
+impl Attribute {
+    const PLAYER: [Self; 3] = [Self::PlayerName, Self::PlaybackInfo, Self::Volume];
+    const TRACK: [Self; 4] = [Self::Artist, Self::Album, Self::Title, Self::Duration];
+
+    /// Entity and attribute id.
+    fn ids(self) -> (u8, u8) {
+        match self {
+            Self::PlayerName => (ENTITY_PLAYER, 0),
+            Self::PlaybackInfo => (ENTITY_PLAYER, 1),
+            Self::Volume => (ENTITY_PLAYER, 2),
+            Self::Artist => (ENTITY_TRACK, 0),
+            Self::Album => (ENTITY_TRACK, 1),
+            Self::Title => (ENTITY_TRACK, 2),
+            Self::Duration => (ENTITY_TRACK, 3),
+        }
+    }
+
+    fn from_ids(entity: u8, attribute: u8) -> Option<Self> {
+        Self::PLAYER
+            .into_iter()
+            .chain(Self::TRACK)
+            .find(|a| a.ids() == (entity, attribute))
+    }
+}
+
+/// A changed attribute, as the phone tells it on the entity update characteristic.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntityUpdate {
+    pub attribute: Attribute,
+    /// Whether `value` is cut short, see [`AmsClient::read_attribute`].
+    pub truncated: bool,
+    pub value: String,
+}
+
+impl EntityUpdate {
+    pub fn parse(data: &[u8]) -> Result<Self, AncsError> {
+        let [entity, attribute, flags, value @ ..] = data else {
+            return Err(AncsError::ParseError(format!(
+                "AMS entity update too short: {:02X?}",
+                data
+            )));
+        };
+        let attribute = Attribute::from_ids(*entity, *attribute).ok_or_else(|| {
+            AncsError::ParseError(format!(
+                "Unknown AMS attribute {} of entity {}",
+                attribute, entity
+            ))
+        })?;
+        Ok(Self {
+            attribute,
+            truncated: flags & FLAG_TRUNCATED != 0,
+            value: String::from_utf8_lossy(value).into_owned(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaybackState {
+    Paused,
+    Playing,
+    Rewinding,
+    FastForwarding,
+}
+
+/// The value of [`Attribute::PlaybackInfo`], "state,rate,elapsed".
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlaybackInfo {
+    pub state: PlaybackState,
+    pub rate: f64,
+    /// Seconds into the track when this was sent.
+    pub elapsed: f64,
+}
+
+impl PlaybackInfo {
+    /// None if the player is gone, which the phone tells with an empty value.
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.split(',');
+        let state = match parts.next()?.trim() {
+            "0" => PlaybackState::Paused,
+            "1" => PlaybackState::Playing,
+            "2" => PlaybackState::Rewinding,
+            "3" => PlaybackState::FastForwarding,
+            _ => return None,
+        };
+        let mut number = || parts.next().and_then(|s| s.trim().parse().ok());
+        Some(Self {
+            state,
+            rate: number().unwrap_or(1.0),
+            elapsed: number().unwrap_or_default(),
+        })
+    }
+}
+
+/// The characteristics of AMS on a connected device.
+pub struct AmsClient {
+    remote_command: Characteristic,
+    entity_update: Characteristic,
+    entity_attribute: Characteristic,
+}
+
+impl AmsClient {
+    /// Finds AMS on a device whose services are resolved, None if it does not offer it.
+    pub async fn find(device: &Device) -> Result<Option<Self>, AncsError> {
+        let ams_uuid: Uuid = AMS_UUID.parse().unwrap();
+        let mut service = None;
+        for s in device.services().await? {
+            if s.uuid().await? == ams_uuid {
+                service = Some(s);
+                break;
+            }
+        }
+        let Some(service) = service else {
+            return Ok(None);
+        };
+
+        let mut remote_command = None;
+        let mut entity_update = None;
+        let mut entity_attribute = None;
+        let remote_command_uuid: Uuid = REMOTE_COMMAND_UUID.parse().unwrap();
+        let entity_update_uuid: Uuid = ENTITY_UPDATE_UUID.parse().unwrap();
+        let entity_attribute_uuid: Uuid = ENTITY_ATTRIBUTE_UUID.parse().unwrap();
+        for c in service.characteristics().await? {
+            let uuid = c.uuid().await?;
+            if uuid == remote_command_uuid {
+                remote_command = Some(c);
+            } else if uuid == entity_update_uuid {
+                entity_update = Some(c);
+            } else if uuid == entity_attribute_uuid {
+                entity_attribute = Some(c);
+            }
+        }
+
+        Ok(match (remote_command, entity_update, entity_attribute) {
+            (Some(remote_command), Some(entity_update), Some(entity_attribute)) => Some(Self {
+                remote_command,
+                entity_update,
+                entity_attribute,
+            }),
+            _ => None,
+        })
+    }
+
+    /// The commands the player takes, whenever that changes.
+    pub async fn supported_commands(
+        &self,
+    ) -> Result<BoxStream<'static, Vec<RemoteCommand>>, AncsError> {
+        match self.remote_command.notify().await {
+            Ok(stream) => Ok(stream
+                .map(|data| RemoteCommand::parse_supported(&data))
+                .boxed()),
+            Err(source) => Err(AncsError::SubscribeFailed {
+                characteristic: REMOTE_COMMAND,
+                source,
+            }),
+        }
+    }
+
+    /// Updates of every [`Attribute`], which the phone starts with their current values.
+    pub async fn updates(
+        &self,
+    ) -> Result<BoxStream<'static, Result<EntityUpdate, AncsError>>, AncsError> {
+        let stream = match self.entity_update.notify().await {
+            Ok(stream) => stream,
+            Err(source) => {
+                return Err(AncsError::SubscribeFailed {
+                    characteristic: ENTITY_UPDATE,
+                    source,
+                })
+            }
+        };
+        // One write per entity, with the ids of its attributes
+        for attributes in [&Attribute::PLAYER[..], &Attribute::TRACK[..]] {
+            let mut data = vec![attributes[0].ids().0];
+            data.extend(attributes.iter().map(|a| a.ids().1));
+            self.entity_update.write_ext(&data, &request()).await?;
+        }
+        Ok(stream.map(|data| EntityUpdate::parse(&data)).boxed())
+    }
+
+    /// Reads the whole value of an attribute, for an update that was truncated.
+    pub async fn read_attribute(&self, attribute: Attribute) -> Result<String, AncsError> {
+        let (entity, attribute) = attribute.ids();
+        self.entity_attribute
+            .write_ext(&[entity, attribute], &request())
+            .await?;
+        let value = self.entity_attribute.read().await?;
+        Ok(String::from_utf8_lossy(&value).into_owned())
+    }
+
+    pub async fn send(&self, command: RemoteCommand) -> Result<(), AncsError> {
+        Ok(self
+            .remote_command
+            .write_ext(&[command as u8], &request())
+            .await?)
+    }
+}
+
+fn request() -> CharacteristicWriteRequest {
+    CharacteristicWriteRequest {
+        op_type: WriteOp::Request,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_updates() {
+        let update = EntityUpdate::parse(b"\x02\x02\x01Bohemian Rhaps").unwrap();
+        assert_eq!(
+            update,
+            EntityUpdate {
+                attribute: Attribute::Title,
+                truncated: true,
+                value: "Bohemian Rhaps".to_string(),
+            }
+        );
+        let update = EntityUpdate::parse(b"\x00\x01\x001,1.0,12.5").unwrap();
+        assert_eq!(
+            PlaybackInfo::parse(&update.value),
+            Some(PlaybackInfo {
+                state: PlaybackState::Playing,
+                rate: 1.0,
+                elapsed: 12.5,
+            })
+        );
+        assert_eq!(PlaybackInfo::parse(""), None);
+        assert!(EntityUpdate::parse(b"\x01\x00\x00").is_err());
+        assert_eq!(
+            RemoteCommand::parse_supported(&[0, 1, 2, 11]),
+            [
+                RemoteCommand::Play,
+                RemoteCommand::Pause,
+                RemoteCommand::TogglePlayPause
+            ]
+        );
+    }
+}
@@ -0,0 +1,200 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+
+use crate::store::{Slot, Stores};
+use serde::{Deserialize, Serialize};
+
+/// Display names of apps by bundle identifier, as looked up from the phone or set with
+/// `ctl app-names set`, kept in the cache so that they are not looked up on every connection.
+pub struct AppNames {
+    slot: Option<Slot>,
+    names: BTreeMap<String, AppName>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AppName {
+    pub name: String,
+    /// Set by the user, which the phone does not override.
+    #[serde(default)]
+    pub custom: bool,
+}
+
+/// Where the names were kept among the data before, which is taken over once.
+const LEGACY_KEY: &str = "app-names.json";
+
+impl AppNames {
+    /// The names in `apps.json` of the cache, taken over from the data if not there yet.
+    pub fn open(stores: &Stores) -> Self {
+        let slot = stores.cache("apps.json");
+        let legacy = match (&slot, stores.data(LEGACY_KEY)) {
+            (Some(slot), Some(legacy)) if matches!(slot.load(), Ok(None)) => Some(legacy),
+            _ => None,
+        };
+        let mut app_names = Self::load(slot);
+        let Some(legacy) = legacy else {
+            return app_names;
+        };
+        let old = Self::load(Some(legacy.clone()));
+        if !old.names.is_empty() {
+            app_names.names = old.names;
+            match app_names.save() {
+                Ok(()) => log::info!(
+                    "Moved the app names from {} in the data to the cache",
+                    legacy
+                ),
+                Err(e) => log::warn!("Failed to move the app names to the cache: {:?}", e),
+            }
+        }
+        app_names
+    }
+
+    pub fn load(slot: Option<Slot>) -> Self {
+        let mut names = BTreeMap::new();
+        if let Some(slot) = &slot {
+            match slot.load() {
+                Ok(Some(content)) => match serde_json::from_str(&content) {
+                    Ok(n) => names = n,
+                    Err(e) => log::warn!("Ignoring {}: {:?}", slot, e),
+                },
+                Ok(None) => {}
+                Err(e) => log::warn!("Failed to read {}: {:?}", slot, e),
+            }
+        }
+
+        Self { slot, names }
+    }
+
+    pub fn get(&self, app_id: &str) -> Option<&str> {
+        self.names.get(app_id).map(|n| n.name.as_str())
+    }
+
+    /// The name set by the user, if any.
+    pub fn custom(&self, app_id: &str) -> Option<&str> {
+        self.names
+            .get(app_id)
+            .filter(|n| n.custom)
+            .map(|n| n.name.as_str())
+    }
+
+    pub fn list(&self) -> impl Iterator<Item = (&str, &AppName)> {
+        self.names.iter().map(|(id, n)| (id.as_str(), n))
+    }
+
+    /// Records the name the phone told, unless the user chose another one.
+    pub fn looked_up(&mut self, app_id: &str, name: &str) -> Result<()> {
+        if self
+            .names
+            .get(app_id)
+            .is_some_and(|n| n.custom || n.name == name)
+        {
+            return Ok(());
+        }
+        self.insert(app_id, name, false)
+    }
+
+    pub fn set(&mut self, app_id: &str, name: &str) -> Result<()> {
+        self.insert(app_id, name, true)
+    }
+
+    /// Takes over names from another machine, keeping the ones set here by hand. Returns how
+    /// many were taken over.
+    pub fn import(&mut self, names: &BTreeMap<String, AppName>) -> Result<usize> {
+        let mut added = 0;
+        for (app_id, name) in names {
+            let keep = self
+                .names
+                .get(app_id)
+                .is_some_and(|n| n.custom || n == name);
+            if !keep {
+                self.names.insert(app_id.clone(), name.clone());
+                added += 1;
+            }
+        }
+        if added > 0 {
+            self.save()?;
+        }
+        Ok(added)
+    }
+
+    fn insert(&mut self, app_id: &str, name: &str, custom: bool) -> Result<()> {
+        self.names.insert(
+            app_id.to_string(),
+            AppName {
+                name: name.to_string(),
+                custom,
+            },
+        );
+        self.save()
+    }
+
+    /// Forgets the name of an app, which is looked up again on its next notification. Returns
+    /// whether it was known.
+    pub fn remove(&mut self, app_id: &str) -> Result<bool> {
+        if self.names.remove(app_id).is_none() {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    fn save(&self) -> Result<()> {
+        match &self.slot {
+            Some(slot) => slot.save(&serde_json::to_string_pretty(&self.names)?),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::store::{FileStore, Store};
+
+    #[test]
+    fn custom_names_win_over_the_phone() {
+        let dir = std::env::temp_dir().join(format!("ancs-linux-app-names-{}", std::process::id()));
+        let slot = Slot::new(Arc::new(FileStore::new(dir.clone())), "app-names.json");
+        let mut names = AppNames::load(Some(slot.clone()));
+        names
+            .looked_up("com.apple.MobileSMS", "Nachrichten")
+            .unwrap();
+        names.set("com.apple.MobileSMS", "Messages").unwrap();
+        names
+            .looked_up("com.apple.MobileSMS", "Nachrichten")
+            .unwrap();
+        names.looked_up("com.apple.mobilemail", "Mail").unwrap();
+
+        let mut loaded = AppNames::load(Some(slot));
+        assert_eq!(loaded.get("com.apple.MobileSMS"), Some("Messages"));
+        assert_eq!(loaded.get("com.apple.mobilemail"), Some("Mail"));
+
+        assert!(loaded.remove("com.apple.MobileSMS").unwrap());
+        assert!(!loaded.remove("com.apple.MobileSMS").unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn takes_over_names_from_the_data_once() {
+        let dir =
+            std::env::temp_dir().join(format!("ancs-linux-app-names-move-{}", std::process::id()));
+        let data: Arc<dyn Store> = Arc::new(FileStore::new(dir.join("data")));
+        let stores = Stores {
+            data: Some(data.clone()),
+            cache: Some(Arc::new(FileStore::new(dir.join("cache")))),
+        };
+        let mut legacy = AppNames::load(Some(Slot::new(data, LEGACY_KEY)));
+        legacy.set("com.apple.MobileSMS", "Messages").unwrap();
+
+        let mut names = AppNames::open(&stores);
+        assert_eq!(names.custom("com.apple.MobileSMS"), Some("Messages"));
+        assert!(dir.join("cache/apps.json").exists());
+
+        // Not again once the cache has them, even if none are left
+        names.remove("com.apple.MobileSMS").unwrap();
+        assert_eq!(AppNames::open(&stores).get("com.apple.MobileSMS"), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
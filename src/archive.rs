@@ -0,0 +1,125 @@
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    appnames::{AppName, AppNames},
+    history::{Entry, History, Query},
+};
+
+const VERSION: u32 = 1;
+
+/// Everything worth taking along to another machine: the history and the app names, so that
+/// the history stays searchable by name without looking every app up from the phone again.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Archive {
+    pub version: u32,
+    pub exported_at: DateTime<Utc>,
+    /// Oldest first.
+    pub history: Vec<Entry>,
+    pub app_names: BTreeMap<String, AppName>,
+}
+
+impl Archive {
+    pub fn collect(history: &History, app_names: &AppNames) -> Result<Self> {
+        let mut entries = history.query(&Query::default())?;
+        entries.reverse();
+
+        Ok(Self {
+            version: VERSION,
+            exported_at: Utc::now(),
+            history: entries,
+            app_names: app_names
+                .list()
+                .map(|(id, name)| (id.to_string(), name.clone()))
+                .collect(),
+        })
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, path)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(())
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let archive: Self = serde_json::from_str(&content)
+            .with_context(|| format!("{} is not an ancs-linux archive", path.display()))?;
+        if archive.version > VERSION {
+            bail!(
+                "{} was written by a newer ancs-linux (archive version {})",
+                path.display(),
+                archive.version
+            );
+        }
+        Ok(archive)
+    }
+
+    /// Adds what is not there yet. Returns how many history entries and app names were added.
+    pub fn restore(&self, history: &History, app_names: &mut AppNames) -> Result<(usize, usize)> {
+        let entries = history.import(&self.history)?;
+        let names = app_names.import(&self.app_names)?;
+        Ok((entries, names))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::HistoryConfig, history::Acknowledgment};
+
+    #[test]
+    fn round_trips_without_duplicates() {
+        let dir = std::env::temp_dir().join(format!("ancs-linux-archive-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let open = |name: &str| {
+            History::open(&HistoryConfig {
+                path: Some(dir.join(name)),
+                ..Default::default()
+            })
+            .unwrap()
+        };
+
+        let old = open("old.db");
+        old.import(&[Entry {
+            uid: 7,
+            app_id: "com.apple.MobileSMS".to_string(),
+            title: Some("Alice".to_string()),
+            subtitle: None,
+            message: Some("See you".to_string()),
+            received_at: DateTime::from_timestamp(1_714_000_000, 0).unwrap(),
+            acknowledgment: Some(Acknowledgment::Missed),
+            seen_at: None,
+            removed_at: None,
+        }])
+        .unwrap();
+        let mut old_names = AppNames::load(None);
+        old_names.set("com.apple.MobileSMS", "Messages").unwrap();
+
+        let path = dir.join("archive.json");
+        Archive::collect(&old, &old_names)
+            .unwrap()
+            .write(&path)
+            .unwrap();
+
+        let new = open("new.db");
+        let mut new_names = AppNames::load(None);
+        let archive = Archive::read(&path).unwrap();
+        assert_eq!(archive.restore(&new, &mut new_names).unwrap(), (1, 1));
+        assert_eq!(archive.restore(&new, &mut new_names).unwrap(), (0, 0));
+        assert_eq!(
+            new.query(&Query::default()).unwrap(),
+            old.query(&Query::default()).unwrap()
+        );
+        assert_eq!(new_names.custom("com.apple.MobileSMS"), Some("Messages"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
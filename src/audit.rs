@@ -0,0 +1,321 @@
+use std::{fmt, time::Duration};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Local, NaiveDate, TimeZone, Utc};
+
+use crate::{
+    blocklist::Blocklist,
+    breakthrough,
+    config::{Attribute, Config, Hold},
+    history::{self, Entry, History},
+    media, paths, privacy,
+    scheduler::Scheduler,
+    sinks,
+};
+
+/// Notifications received within a range, as given to `audit`.
+#[derive(Debug, Default, PartialEq)]
+pub struct Range {
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl Range {
+    /// Takes the last so many days, hours or minutes like `7d`, a day like `2026-10-01`, or
+    /// days from and to like `2026-10-01..2026-10-07`, either of which may be left out.
+    pub fn parse<Tz: TimeZone>(s: &str, now: DateTime<Utc>, tz: &Tz) -> Result<Self> {
+        if let Some((from, to)) = s.split_once("..") {
+            return Ok(Self {
+                since: (!from.is_empty())
+                    .then(|| start_of(from, 0, tz))
+                    .transpose()?,
+                until: (!to.is_empty()).then(|| start_of(to, 1, tz)).transpose()?,
+            });
+        }
+        if let Some(last) = last(s) {
+            return Ok(Self {
+                since: Some(now - last?),
+                until: None,
+            });
+        }
+        Ok(Self {
+            since: Some(start_of(s, 0, tz)?),
+            until: Some(start_of(s, 1, tz)?),
+        })
+    }
+}
+
+/// Like `7d`, `12h` or `30m`, None if it is not of that form.
+fn last(s: &str) -> Option<Result<chrono::Duration>> {
+    let unit = s.chars().last()?;
+    let count = s[..s.len() - unit.len_utf8()].parse::<i64>().ok()?;
+    Some(match unit {
+        'd' => Ok(chrono::Duration::days(count)),
+        'h' => Ok(chrono::Duration::hours(count)),
+        'm' => Ok(chrono::Duration::minutes(count)),
+        _ => Err(anyhow::anyhow!(
+            "Invalid range {:?}, expected days (d), hours (h) or minutes (m)",
+            s
+        )),
+    })
+}
+
+/// Start of the day `days` after the given one.
+fn start_of<Tz: TimeZone>(day: &str, days: u64, tz: &Tz) -> Result<DateTime<Utc>> {
+    let date = NaiveDate::parse_from_str(day, "%Y-%m-%d")
+        .with_context(|| format!("Invalid day {:?}, expected e.g. 2026-10-01", day))?;
+    let Some(date) = date.checked_add_days(chrono::Days::new(days)) else {
+        bail!("Invalid day {:?}", day);
+    };
+    match tz
+        .from_local_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+        .earliest()
+    {
+        Some(t) => Ok(t.with_timezone(&Utc)),
+        None => bail!("Invalid day {:?} in the local timezone", day),
+    }
+}
+
+/// What the config would do with a notification that reached the desktop side.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Verdict {
+    /// Left out by `[filter]`.
+    Filtered,
+    /// Blocked from the desktop with the block action.
+    Blocked,
+    /// Kept off the desktop by quiet hours, and recorded as missed.
+    Quiet,
+    Shown,
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Verdict::Filtered => "filtered",
+            Verdict::Blocked => "blocked",
+            Verdict::Quiet => "quiet hours",
+            Verdict::Shown => "shown",
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Outcome {
+    verdict: Verdict,
+    /// Only these are requested from the phone, if not all.
+    attributes: Option<Vec<Attribute>>,
+    /// The message is neither kept nor forwarded.
+    sensitive: bool,
+    /// Held back this long, merging what follows from the same sender.
+    burst: Option<Duration>,
+    /// Left out while this desktop app has the focus.
+    desktop_app: Option<String>,
+    /// Network sinks it is forwarded to.
+    sinks: Vec<String>,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.verdict)?;
+        if let Some(attributes) = &self.attributes {
+            let names: Vec<String> = attributes
+                .iter()
+                .map(|a| format!("{:?}", a).to_lowercase())
+                .collect();
+            write!(f, ", only requesting {}", names.join(" and "))?;
+        }
+        if self.sensitive {
+            write!(f, ", message not kept")?;
+        }
+        if let Some(burst) = self.burst {
+            write!(f, ", held {} seconds for a burst", burst.as_secs())?;
+        }
+        if let Some(desktop_app) = &self.desktop_app {
+            write!(f, ", unless {} has the focus", desktop_app)?;
+        }
+        if !self.sinks.is_empty() {
+            write!(f, ", forwarded to {}", self.sinks.join(" and "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs a notification through the rules of `config`, as far as the history tells about it.
+/// It keeps neither the category nor whether the phone marked it important, so only rules
+/// about apps and times apply.
+fn audit(
+    config: &Config,
+    blocklist: &Blocklist,
+    scheduler: &Scheduler,
+    sinks: &[String],
+    entry: &Entry,
+) -> Outcome {
+    let app_id = entry.app_id.as_str();
+    let category_id = ancs::attributes::category::CategoryID::Other as u8;
+    let breaks_through =
+        |hold| breakthrough::breaks_through(config, false, category_id, Some(app_id), hold);
+
+    let verdict = if !config.is_allowed(app_id) {
+        Verdict::Filtered
+    } else if blocklist.contains(config, app_id) {
+        Verdict::Blocked
+    } else if scheduler.is_quiet(entry.received_at) && !breaks_through(Hold::QuietHours) {
+        Verdict::Quiet
+    } else {
+        Verdict::Shown
+    };
+    let passed = matches!(verdict, Verdict::Quiet | Verdict::Shown);
+    let attributes = config.allowed_attributes(app_id);
+    let shown = verdict == Verdict::Shown;
+
+    Outcome {
+        attributes: (passed && attributes != Attribute::ALL).then_some(attributes),
+        sensitive: passed && privacy::redact(config, entry, None).is_sensitive(),
+        burst: config
+            .bursts
+            .window()
+            .filter(|_| shown && media::is_chat(config, app_id, None))
+            .filter(|_| !breaks_through(Hold::Bursts)),
+        desktop_app: config
+            .app_sections(app_id)
+            .find_map(|app| app.desktop_app.clone())
+            .filter(|_| shown),
+        sinks: if passed { sinks.to_vec() } else { vec![] },
+        verdict,
+    }
+}
+
+/// Prints what the current config would do with the notifications in the history received
+/// within `range`, oldest first, without showing or forwarding anything.
+pub fn run(config: &Config, range: &str) -> Result<()> {
+    let range = Range::parse(range, Utc::now(), &Local)?;
+    let history = History::open(&config.history)?;
+    let entries = history.query(&history::Query {
+        since: range.since,
+        until: range.until,
+        ..Default::default()
+    })?;
+    let blocklist = Blocklist::load(paths::config_dir().map(|dir| dir.join("blocked-apps")));
+    let scheduler = Scheduler::new(&config.quiet_hours)?;
+    let sinks: Vec<String> = sinks::configured(config)?
+        .iter()
+        .map(|sink| sink.name().to_string())
+        .collect();
+
+    let mut counts = [0; 4];
+    for entry in entries.iter().rev() {
+        let outcome = audit(config, &blocklist, &scheduler, &sinks, entry);
+        counts[outcome.verdict as usize] += 1;
+        println!(
+            "{} [{}] {}",
+            entry
+                .received_at
+                .with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M"),
+            config.canonical_app_id(&entry.app_id),
+            entry.title.as_deref().unwrap_or_default()
+        );
+        println!("    {}", outcome);
+    }
+
+    let [filtered, blocked, quiet, shown] = counts;
+    println!(
+        "\n{} notifications: {} shown, {} during quiet hours, {} filtered, {} blocked",
+        entries.len(),
+        shown,
+        quiet,
+        filtered,
+        blocked
+    );
+    println!(
+        "The history keeps neither categories nor what the phone marked important, so rules \
+         about those are not applied. Notifications left out when they arrived are not in it."
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::FixedOffset;
+
+    use super::*;
+    use crate::config::AppConfig;
+
+    #[test]
+    fn parses_ranges() {
+        let tz = FixedOffset::east_opt(2 * 3600).unwrap();
+        let now = Utc.with_ymd_and_hms(2026, 10, 14, 12, 0, 0).unwrap();
+        let day = |d| Some(Utc.with_ymd_and_hms(2026, 10, d, 22, 0, 0).unwrap());
+
+        let last_week = Range::parse("7d", now, &tz).unwrap();
+        assert_eq!(last_week.since, Some(now - chrono::Duration::days(7)));
+        assert_eq!(last_week.until, None);
+        assert_eq!(
+            Range::parse("2026-10-02", now, &tz).unwrap(),
+            Range {
+                since: day(1),
+                until: day(2),
+            }
+        );
+        assert_eq!(
+            Range::parse("2026-10-02..2026-10-04", now, &tz)
+                .unwrap()
+                .until,
+            day(4)
+        );
+        assert_eq!(Range::parse("..", now, &tz).unwrap(), Range::default());
+        assert!(Range::parse("7w", now, &tz).is_err());
+        assert!(Range::parse("yesterday", now, &tz).is_err());
+    }
+
+    #[test]
+    fn applies_the_rules_about_apps() {
+        let mut config = Config::default();
+        config.filter.ignore = vec!["com.example.game".to_string()];
+        config.apps.insert(
+            "com.example.bank".to_string(),
+            AppConfig {
+                attributes: Some(vec![Attribute::Title]),
+                sensitive: true,
+                desktop_app: None,
+                icon: None,
+            },
+        );
+        let blocklist = Blocklist::load(None);
+        let scheduler = Scheduler::new(&config.quiet_hours).unwrap();
+        let sinks = ["webhook".to_string()];
+        let entry = |app_id: &str| Entry {
+            uid: 1,
+            app_id: app_id.to_string(),
+            title: Some("Title".to_string()),
+            subtitle: None,
+            message: None,
+            received_at: Utc::now(),
+            acknowledgment: None,
+            seen_at: None,
+            removed_at: None,
+        };
+
+        let game = audit(
+            &config,
+            &blocklist,
+            &scheduler,
+            &sinks,
+            &entry("com.example.game"),
+        );
+        assert_eq!(game.verdict, Verdict::Filtered);
+        assert!(game.sinks.is_empty());
+
+        let bank = audit(
+            &config,
+            &blocklist,
+            &scheduler,
+            &sinks,
+            &entry("com.example.bank"),
+        );
+        assert_eq!(
+            bank.to_string(),
+            "shown, only requesting title, message not kept, forwarded to webhook"
+        );
+    }
+}
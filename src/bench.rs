@@ -0,0 +1,273 @@
+#[cfg(feature = "bench")]
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use anyhow::{bail, Result};
+use bluer::Address;
+use tokio::{sync::mpsc, time::Instant};
+
+use crate::{
+    appnames::AppNames,
+    blocklist::Blocklist,
+    config::Config,
+    handles::Handles,
+    lengths::AttributeLengths,
+    safemode,
+    scheduler::Scheduler,
+    service::Control,
+    simulator::Simulator,
+    sinks::{self, AncsNotification, NotificationSink},
+    state::{self, SessionState},
+    AncsProcessor, Phone, Shared,
+};
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static ALLOCATED_BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// The system allocator, counting allocations for [`run`] to report. Only with the `bench`
+/// feature, as counting slows down every allocation.
+#[cfg(feature = "bench")]
+pub struct CountingAllocator;
+
+#[cfg(feature = "bench")]
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(layout.size() as u64, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(new_size as u64, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+pub struct Options {
+    /// Notifications per minute.
+    pub rate: u32,
+    pub duration: Duration,
+    /// Bytes of every message, which is split over many packets at the minimum MTU.
+    pub message_len: usize,
+}
+
+/// What it took to get notifications from the simulated phone through the processor.
+pub struct Report {
+    sent: usize,
+    received: usize,
+    elapsed: Duration,
+    data_packets: u64,
+    /// From adding a notification on the phone to its reaching the network sinks, sorted.
+    latencies: Vec<Duration>,
+    /// Count and bytes, None unless built with the `bench` feature.
+    allocations: Option<(u64, u64)>,
+}
+
+/// Stands in for the network sinks, telling when the processor passes on each notification.
+struct Arrivals(mpsc::UnboundedSender<u32>);
+
+impl NotificationSink for Arrivals {
+    fn name(&self) -> &str {
+        "bench"
+    }
+
+    fn notify(&self, notification: &AncsNotification) {
+        if notification.event == sinks::Kind::Received {
+            let _ = self.0.send(notification.uid);
+        }
+    }
+}
+
+/// Adds notifications to the simulated phone at the given rate, for the processor of `listen`
+/// to request, decode, filter and record like any other. Nothing is shown, as if headless, and
+/// nothing is kept or forwarded.
+pub async fn run(options: &Options) -> Result<Report> {
+    if options.rate == 0 {
+        bail!("The rate must be at least one notification per minute");
+    }
+    let mut config = Config {
+        headless: true,
+        ..Config::default()
+    };
+    config.attribute_length.message = Some(options.message_len.min(u16::MAX as usize) as u16);
+    config.attribute_length.full_message = false;
+    let config = Arc::new(config);
+
+    let address = Address::any();
+    let (arrivals_tx, mut arrivals) = mpsc::unbounded_channel();
+    let (control, _link_commands) = Control::new(config.clone(), &[address]);
+    let shared = Shared {
+        control,
+        history: None,
+        session: None,
+        screen_cast: None,
+        focus: None,
+        power: None,
+        contacts: None,
+        service: None,
+        blocklist: Arc::new(Mutex::new(Blocklist::load(None))),
+        app_names: Arc::new(Mutex::new(AppNames::load(None))),
+        scheduler: Arc::new(Scheduler::new(&config.quiet_hours)?),
+        quiet_tally: Arc::default(),
+        sinks: Arc::new(vec![Arc::new(Arrivals(arrivals_tx))]),
+        failures: Arc::new(Mutex::new(safemode::Failures::start(
+            None,
+            config.safe_mode.after,
+        ))),
+        router: None,
+        trace: None,
+    };
+    let phone = Phone {
+        address,
+        label: None,
+        dedupe: None,
+        handles: Arc::new(Mutex::new(Handles::load(None))),
+        lengths: Arc::new(Mutex::new(AttributeLengths::new(&config.attribute_length))),
+        show_existing: Arc::new(AtomicBool::new(false)),
+    };
+    let simulator = Simulator::idle();
+    let mut processor = tokio::spawn(AncsProcessor::new(shared, phone).simulate(simulator.clone()));
+    // What is added before subscribing would be pre-existing, which is not passed on
+    while state::current(address).state != SessionState::Subscribed {
+        if processor.is_finished() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+
+    let message = "x".repeat(options.message_len);
+    let total = (options.rate as u64 * options.duration.as_secs() / 60).max(1) as usize;
+    let mut interval = tokio::time::interval(Duration::from_secs(60) / options.rate);
+    let mut sent_at: HashMap<u32, Instant> = HashMap::new();
+    let mut sent = 0;
+    let mut latencies = vec![];
+
+    let data_packets = simulator.data_packets();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let allocated_bytes = ALLOCATED_BYTES.load(Ordering::Relaxed);
+    let start = Instant::now();
+    // Gives up on what is still missing a while after the last notification
+    let mut deadline = None;
+    loop {
+        tokio::select! {
+            _ = interval.tick(), if sent < total => {
+                let uid = simulator.add_message("com.apple.MobileSMS", "Bench", &message);
+                sent_at.insert(uid, Instant::now());
+                sent += 1;
+                if sent == total {
+                    deadline = Some(Box::pin(tokio::time::sleep(Duration::from_secs(5))));
+                }
+            }
+            Some(uid) = arrivals.recv() => {
+                if let Some(sent) = sent_at.remove(&uid) {
+                    latencies.push(sent.elapsed());
+                    simulator.remove(uid);
+                }
+                if sent == total && sent_at.is_empty() {
+                    break;
+                }
+            }
+            result = &mut processor => {
+                result??;
+                bail!("The processor stopped");
+            }
+            _ = async { deadline.as_mut().unwrap().await }, if deadline.is_some() => break,
+        }
+    }
+    let elapsed = start.elapsed();
+    processor.abort();
+    latencies.sort();
+
+    Ok(Report {
+        sent,
+        received: latencies.len(),
+        elapsed,
+        data_packets: simulator.data_packets() - data_packets,
+        latencies,
+        allocations: cfg!(feature = "bench").then(|| {
+            (
+                ALLOCATIONS.load(Ordering::Relaxed) - allocations,
+                ALLOCATED_BYTES.load(Ordering::Relaxed) - allocated_bytes,
+            )
+        }),
+    })
+}
+
+/// The latency below which `p` percent of them are, of sorted latencies.
+fn percentile(latencies: &[Duration], p: usize) -> Duration {
+    if latencies.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = (latencies.len() * p).div_ceil(100).max(1);
+    latencies[rank - 1]
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let received = self.received.max(1) as u64;
+        writeln!(
+            f,
+            "notifications: {} sent, {} received in {:.1} s ({:.1}/s)",
+            self.sent,
+            self.received,
+            self.elapsed.as_secs_f64(),
+            self.received as f64 / self.elapsed.as_secs_f64()
+        )?;
+        writeln!(
+            f,
+            "data packets:  {} ({:.1} per notification)",
+            self.data_packets,
+            self.data_packets as f64 / received as f64
+        )?;
+        writeln!(
+            f,
+            "latency:       p50 {:?}, p90 {:?}, p99 {:?}, max {:?}",
+            percentile(&self.latencies, 50),
+            percentile(&self.latencies, 90),
+            percentile(&self.latencies, 99),
+            self.latencies.last().copied().unwrap_or_default()
+        )?;
+        match self.allocations {
+            Some((allocations, bytes)) => write!(
+                f,
+                "allocations:   {} ({} bytes), {} ({} bytes) per notification",
+                allocations,
+                bytes,
+                allocations / received,
+                bytes / received
+            ),
+            None => write!(
+                f,
+                "allocations:   not counted, build with `--features bench`"
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_percentiles_by_rank() {
+        let latencies: Vec<Duration> = (1..=10).map(Duration::from_millis).collect();
+        assert_eq!(percentile(&latencies, 50), Duration::from_millis(5));
+        assert_eq!(percentile(&latencies, 90), Duration::from_millis(9));
+        assert_eq!(percentile(&latencies, 99), Duration::from_millis(10));
+        assert_eq!(percentile(&latencies[..1], 50), Duration::from_millis(1));
+        assert_eq!(percentile(&[], 50), Duration::ZERO);
+    }
+}
@@ -0,0 +1,97 @@
+//! Talks to a running `ancs-linux listen` over the session bus, without Bluetooth or an async
+//! runtime, so that it starts quickly from keybindings and status bars.
+
+use ancs_linux::control::{self, AppNamesCommand, ControlProxyBlocking, PrivacyMode};
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    #[command(flatten)]
+    Control(control::Command),
+    /// Show the most recent notifications
+    History {
+        #[arg(
+            long,
+            help = "Only show notifications that were shown while away from the desktop"
+        )]
+        missed: bool,
+        #[arg(
+            long,
+            short = 'n',
+            default_value_t = 20,
+            help = "Number of notifications to show"
+        )]
+        limit: u32,
+        #[arg(long, help = "Group by app, ordered by name, instead of by time only")]
+        by_app: bool,
+    },
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let conn = zbus::blocking::Connection::session()?;
+    let control = ControlProxyBlocking::new(&conn)?;
+
+    let command = match cli.command {
+        Command::Control(command) => command,
+        Command::History {
+            missed,
+            limit,
+            by_app,
+        } => return control::print_history(&control.recent(missed, limit)?, by_app),
+    };
+    match command {
+        control::Command::Reconnect => control.reconnect()?,
+        control::Command::Disconnect => control.disconnect()?,
+        control::Command::DismissLast => control.dismiss_last()?,
+        control::Command::ActOnLast => control.act_on_last()?,
+        control::Command::SendAction { uid, label, device } => {
+            control.send_action(&control::device_arg(device), uid, &label)?
+        }
+        control::Command::Ring { stop, device } => {
+            let device = control::device_arg(device);
+            if stop {
+                control.stop_ringing(&device)?
+            } else {
+                control.ring(&device)?
+            }
+        }
+        control::Command::Status => control::print_status(
+            &control.safe_mode()?,
+            control.sessions()?,
+            control.sink_status()?,
+        )?,
+        control::Command::AppNames { command } => match command {
+            AppNamesCommand::List => control::print_app_names(control.app_names()?),
+            AppNamesCommand::Set { app_id, name } => control.set_app_name(&app_id, &name)?,
+            AppNamesCommand::Rm { app_id } => control.remove_app_name(&app_id)?,
+        },
+        control::Command::Privacy { mode } => {
+            let enabled = match mode {
+                PrivacyMode::On => true,
+                PrivacyMode::Off => false,
+                PrivacyMode::Toggle => !control.private_mode()?,
+            };
+            control.set_private_mode(enabled)?;
+            println!("Privacy mode is {}", if enabled { "on" } else { "off" });
+        }
+        control::Command::LogLevel { target, level } => control.set_log_level(&target, &level)?,
+        control::Command::Pause => control.pause()?,
+        control::Command::Resume => control.resume()?,
+        control::Command::List => control::print_active(&control.list()?)?,
+        control::Command::Dismiss { uid, device } => {
+            control.dismiss_notification(&control::device_arg(device), uid)?
+        }
+        control::Command::Reload => println!("{}", control.reload()?),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,116 @@
+use std::{collections::BTreeSet, path::PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::config::Config;
+
+/// Prefix of the desktop notification action that blocks the app, followed by its bundle
+/// identifier.
+const ACTION_PREFIX: &str = "never_show:";
+
+/// Apps never to show notifications from, added from the desktop notifications themselves.
+///
+/// Kept apart from the config so that it can be written without touching a file the user
+/// edits, one bundle identifier per line. Removing a line unblocks the app on the next start.
+pub struct Blocklist {
+    path: Option<PathBuf>,
+    apps: BTreeSet<String>,
+}
+
+impl Blocklist {
+    pub fn load(path: Option<PathBuf>) -> Self {
+        let mut apps = BTreeSet::new();
+        if let Some(path) = &path {
+            match std::fs::read_to_string(path) {
+                Ok(content) => {
+                    apps.extend(
+                        content
+                            .lines()
+                            .map(str::trim)
+                            .filter(|l| !l.is_empty())
+                            .map(str::to_string),
+                    );
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => log::warn!("Failed to read {}: {:?}", path.display(), e),
+            }
+        }
+
+        Self { path, apps }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.apps.is_empty()
+    }
+
+    pub fn contains(&self, config: &Config, app_id: &str) -> bool {
+        self.apps.contains(app_id) || self.apps.contains(config.canonical_app_id(app_id))
+    }
+
+    /// Blocks an app, also when saving that fails.
+    pub fn add(&mut self, app_id: &str) -> Result<()> {
+        if !self.apps.insert(app_id.to_string()) {
+            return Ok(());
+        }
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        let mut content = String::new();
+        for app in &self.apps {
+            content.push_str(app);
+            content.push('\n');
+        }
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, content)
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, path)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(())
+    }
+}
+
+/// Identifier of the desktop notification action that blocks `app_id`.
+pub fn action_key(app_id: &str) -> String {
+    format!("{}{}", ACTION_PREFIX, app_id)
+}
+
+/// The app blocked by a desktop notification action, if it is that action.
+pub fn app_from_action_key(key: &str) -> Option<&str> {
+    key.strip_prefix(ACTION_PREFIX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_blocked_apps_on_disk() {
+        let path =
+            std::env::temp_dir().join(format!("ancs-linux-blocklist-{}", std::process::id()));
+        let mut config = Config::default();
+        config.aliases.insert(
+            "net.whatsapp.WhatsApp.beta".to_string(),
+            "whatsapp".to_string(),
+        );
+
+        let mut blocklist = Blocklist::load(Some(path.clone()));
+        assert!(blocklist.is_empty());
+        blocklist.add("whatsapp").unwrap();
+        blocklist.add("whatsapp").unwrap();
+
+        let loaded = Blocklist::load(Some(path.clone()));
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.apps.len(), 1);
+        assert!(loaded.contains(&config, "net.whatsapp.WhatsApp.beta"));
+        assert!(!loaded.contains(&config, "com.apple.MobileSMS"));
+
+        let key = action_key("whatsapp");
+        assert_eq!(app_from_action_key(&key), Some("whatsapp"));
+        assert_eq!(app_from_action_key("positive"), None);
+    }
+}
@@ -0,0 +1,170 @@
+use std::{collections::BTreeSet, fmt, sync::Mutex, time::Duration};
+
+use bluer::{Adapter, Address};
+use notify_rust::{Hint, Timeout, Urgency};
+
+use crate::desktop;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Phones that could not be subscribed to on the last connection although they are paired.
+static REJECTED: Mutex<BTreeSet<Address>> = Mutex::new(BTreeSet::new());
+
+/// Phones that let us subscribe on the last connection, but never sent anything.
+static SILENT: Mutex<BTreeSet<Address>> = Mutex::new(BTreeSet::new());
+
+fn set(devices: &Mutex<BTreeSet<Address>>, device: Address, member: bool) {
+    let mut devices = devices.lock().unwrap();
+    if member {
+        devices.insert(device);
+    } else {
+        devices.remove(&device);
+    }
+}
+
+/// Records whether the phone let us subscribe to ANCS on the last connection.
+pub fn set_rejected(device: Address, rejected: bool) {
+    set(&REJECTED, device, rejected);
+}
+
+/// Records whether the phone stayed silent since subscribing, i.e. sharing notifications with
+/// this computer was declined.
+pub fn set_silent(device: Address, silent: bool) {
+    set(&SILENT, device, silent);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BondProblem {
+    NotPaired,
+    NotTrusted,
+    /// Paired here, but the phone refuses the encrypted link, i.e. it forgot this computer.
+    Rejected,
+    /// Connected and subscribed, but "Share System Notifications" is off on the phone.
+    SharingDeclined,
+}
+
+impl BondProblem {
+    fn find(paired: bool, trusted: bool, rejected: bool, silent: bool) -> Option<Self> {
+        if !paired {
+            Some(BondProblem::NotPaired)
+        } else if rejected {
+            Some(BondProblem::Rejected)
+        } else if silent {
+            Some(BondProblem::SharingDeclined)
+        } else if !trusted {
+            Some(BondProblem::NotTrusted)
+        } else {
+            None
+        }
+    }
+
+    fn remediation(self, addr: Address) -> String {
+        match self {
+            BondProblem::NotPaired => format!(
+                "Pair it again with `bluetoothctl pair {}`, then connect from the Bluetooth \
+                 settings of the phone.",
+                addr
+            ),
+            BondProblem::NotTrusted => format!(
+                "Trust it with `bluetoothctl trust {}` so that it may connect on its own.",
+                addr
+            ),
+            BondProblem::Rejected => format!(
+                "The phone most likely forgot this computer. Remove it here with \
+                 `bluetoothctl remove {}`, then pair again from the Bluetooth settings of the \
+                 phone.",
+                addr
+            ),
+            BondProblem::SharingDeclined => "On the phone, open Settings > Bluetooth, tap the \
+                 (i) next to this computer and turn on \"Share System Notifications\"."
+                .to_string(),
+        }
+    }
+}
+
+impl fmt::Display for BondProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BondProblem::NotPaired => "The phone is not paired",
+            BondProblem::NotTrusted => "The phone is not trusted",
+            BondProblem::Rejected => "The phone rejects the pairing",
+            BondProblem::SharingDeclined => "The phone does not share notifications",
+        })
+    }
+}
+
+/// Checks the bond with the phone every minute, keeping a warning on the desktop with what to
+/// do about it for as long as it is broken.
+pub async fn monitor(adapter: Adapter, addr: Address) {
+    let mut warning: Option<(BondProblem, u32)> = None;
+    loop {
+        // Removing the device in BlueZ also unpairs it
+        let (paired, trusted) = match adapter.device(addr) {
+            Ok(device) => (
+                device.is_paired().await.unwrap_or_default(),
+                device.is_trusted().await.unwrap_or_default(),
+            ),
+            Err(_) => (false, false),
+        };
+        let problem = BondProblem::find(
+            paired,
+            trusted,
+            REJECTED.lock().unwrap().contains(&addr),
+            SILENT.lock().unwrap().contains(&addr),
+        );
+
+        if warning.map(|(p, _)| p) != problem {
+            if let Some((_, desktop_id)) = warning.take() {
+                log::info!("Bond with {} is fine again", addr);
+                if let Err(e) = desktop::close_notification(desktop_id).await {
+                    log::warn!("Failed to close desktop notification: {:?}", e);
+                }
+            }
+            if let Some(problem) = problem {
+                let remediation = problem.remediation(addr);
+                log::warn!("{}: {}", problem, remediation);
+                let result = notify_rust::Notification::new()
+                    .appname("ancs-linux")
+                    .summary(&problem.to_string())
+                    .body(&remediation)
+                    .urgency(Urgency::Critical)
+                    .hint(Hint::Resident(true))
+                    .timeout(Timeout::Never)
+                    .show_async()
+                    .await;
+                match result {
+                    Ok(handle) => warning = Some((problem, handle.id())),
+                    Err(e) => log::warn!("Failed to show the bond warning: {:?}", e),
+                }
+            }
+        }
+
+        tokio::time::sleep(CHECK_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_most_pressing_problem() {
+        assert_eq!(BondProblem::find(true, true, false, false), None);
+        assert_eq!(
+            BondProblem::find(false, false, true, false),
+            Some(BondProblem::NotPaired)
+        );
+        assert_eq!(
+            BondProblem::find(true, false, true, false),
+            Some(BondProblem::Rejected)
+        );
+        assert_eq!(
+            BondProblem::find(true, false, false, true),
+            Some(BondProblem::SharingDeclined)
+        );
+        assert_eq!(
+            BondProblem::find(true, false, false, false),
+            Some(BondProblem::NotTrusted)
+        );
+    }
+}
@@ -0,0 +1,107 @@
+use ancs::attributes::category::CategoryID;
+
+use crate::config::{Config, Hold};
+
+/// Why a notification is time-sensitive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Reason {
+    /// The phone marks it important.
+    Important,
+    IncomingCall,
+    /// It comes from an app in `breakthrough.apps`.
+    App,
+}
+
+fn time_sensitive(
+    config: &Config,
+    important: bool,
+    category_id: u8,
+    app_id: Option<&str>,
+) -> Option<Reason> {
+    let breakthrough = &config.breakthrough;
+    if breakthrough.calls && category_id == CategoryID::IncomingCall as u8 {
+        Some(Reason::IncomingCall)
+    } else if breakthrough.important && important {
+        Some(Reason::Important)
+    } else if app_id.is_some_and(|app_id| {
+        let app_id = config.canonical_app_id(app_id);
+        breakthrough
+            .apps
+            .iter()
+            .any(|a| config.canonical_app_id(a) == app_id)
+    }) {
+        Some(Reason::App)
+    } else {
+        None
+    }
+}
+
+/// Whether a notification is time-sensitive and gets through `hold`, which everything that
+/// holds notifications back asks, so that they agree on what may not wait.
+///
+/// `app_id` is `None` while the app is not known yet, e.g. before asking the phone for the
+/// attributes.
+pub fn breaks_through(
+    config: &Config,
+    important: bool,
+    category_id: u8,
+    app_id: Option<&str>,
+    hold: Hold,
+) -> bool {
+    if !config.breakthrough.bypass.contains(&hold)
+        || hold == Hold::QuietHours && !config.quiet_hours.show_important
+    {
+        return false;
+    }
+    match time_sensitive(config, important, category_id, app_id) {
+        Some(reason) => {
+            log::debug!("Time-sensitive ({:?}), getting through {:?}", reason, hold);
+            true
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lets_through_what_may_not_wait() {
+        let mut config = Config::default();
+        config.breakthrough.apps = vec!["pager".to_string()];
+        config
+            .aliases
+            .insert("com.example.pager".to_string(), "pager".to_string());
+        let social = CategoryID::Social as u8;
+        let call = CategoryID::IncomingCall as u8;
+
+        assert!(breaks_through(&config, false, call, None, Hold::Bursts));
+        assert!(breaks_through(&config, true, social, None, Hold::Debounce));
+        assert!(breaks_through(
+            &config,
+            false,
+            social,
+            Some("com.example.pager"),
+            Hold::QuietHours
+        ));
+        assert!(!breaks_through(
+            &config,
+            false,
+            social,
+            Some("com.apple.MobileSMS"),
+            Hold::QuietHours
+        ));
+
+        config.quiet_hours.show_important = false;
+        assert!(!breaks_through(
+            &config,
+            true,
+            social,
+            None,
+            Hold::QuietHours
+        ));
+        config.breakthrough.bypass = vec![Hold::QuietHours];
+        assert!(!breaks_through(&config, false, call, None, Hold::Bursts));
+    }
+}
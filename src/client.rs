@@ -0,0 +1,350 @@
+use std::{
+    collections::VecDeque,
+    io::Cursor,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use ancs::{
+    attributes::{app::AppAttributeID, command::CommandID, notification::NotificationAttributeID},
+    characteristics::{
+        control_point::{GetAppAttributesRequest, GetNotificationAttributesRequest},
+        data_source::GetNotificationAttributesResponse,
+    },
+};
+use bluer::Device;
+use byteorder_pack::UnpackFrom;
+use futures::{stream::BoxStream, Stream};
+
+use crate::{
+    discovery::{self, GattConfig},
+    error::AncsError,
+    link::Link,
+    protocol::{self, ActionID, AppAttributesResponse},
+    reassembly::Reassembler,
+    simulator::Simulator,
+    trace::ProtocolTrace,
+};
+
+/// How long the phone takes to notice that a subscription went away.
+const RESUBSCRIBE_DELAY: Duration = Duration::from_millis(500);
+
+type Packets = BoxStream<'static, Vec<u8>>;
+
+/// What the notification source tells about a notification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NotificationEvent {
+    /// Added, modified or removed, see [`ancs::attributes::event::EventID`].
+    pub event_id: u8,
+    /// See [`ancs::attributes::event::EventFlag`].
+    pub event_flags: u8,
+    pub category_id: u8,
+    /// Notifications in the category on the phone.
+    pub category_count: u8,
+    pub uid: u32,
+}
+
+/// Something the phone sent.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AncsEvent {
+    Notification(NotificationEvent),
+    /// Attributes asked for with [`AncsClient::fetch_attributes`].
+    Attributes(GetNotificationAttributesResponse),
+    /// Attributes asked for with [`AncsClient::fetch_app_attributes`].
+    AppAttributes(AppAttributesResponse),
+    /// A notification source packet too short to decode, as received.
+    MalformedNotification(Vec<u8>),
+    /// A data source response that could not be decoded, e.g. of a command from a newer
+    /// revision of ANCS, once reassembled.
+    MalformedResponse(Vec<u8>),
+}
+
+/// ANCS on one phone, without anything about showing notifications.
+///
+/// Events come from [`Self::events`], while the methods ask the phone for more. Attributes
+/// arrive as events once the phone sends them.
+pub struct AncsClient {
+    link: Link,
+    sources: Arc<Mutex<Sources>>,
+}
+
+/// What the event streams read from, shared with the client to resubscribe and to expect
+/// responses.
+struct Sources {
+    /// None once ended, or while resubscribing.
+    notifications: Option<Packets>,
+    resubscribing: bool,
+    data: Option<Packets>,
+    reassembler: Reassembler,
+    /// Decoded but not returned yet.
+    ready: VecDeque<AncsEvent>,
+    /// Of the last stream that found nothing to return.
+    waker: Option<Waker>,
+    trace: Option<ProtocolTrace>,
+}
+
+impl AncsClient {
+    /// Subscribes to ANCS on a connected phone, once BlueZ has resolved its services.
+    pub async fn connect(device: &Device, gatt: &GattConfig) -> Result<Self, AncsError> {
+        if !device.is_connected().await? {
+            return Err(AncsError::NotConnected(device.address()));
+        }
+        discovery::wait_for_services(device).await?;
+        let service = discovery::find_service(device, gatt).await?;
+        let ancs = discovery::find_characteristics(&service).await?;
+        Self::subscribe(Link::Gatt(ancs)).await
+    }
+
+    /// Runs against a simulated phone instead, e.g. for tests.
+    pub async fn simulated(simulator: Simulator) -> Result<Self, AncsError> {
+        Self::subscribe(Link::Simulated(simulator)).await
+    }
+
+    async fn subscribe(link: Link) -> Result<Self, AncsError> {
+        // Data first, so that no response to an early request is missed
+        let data = link.subscribe_data().await?;
+        let notifications = link.subscribe_notifications().await?;
+        Ok(Self::from_streams(link, notifications, data))
+    }
+
+    /// For those who subscribed on their own, e.g. to tell which subscription failed.
+    pub fn from_streams(link: Link, notifications: Packets, data: Packets) -> Self {
+        Self {
+            link,
+            sources: Arc::new(Mutex::new(Sources {
+                notifications: Some(notifications),
+                resubscribing: false,
+                data: Some(data),
+                reassembler: Reassembler::default(),
+                ready: VecDeque::new(),
+                waker: None,
+                trace: None,
+            })),
+        }
+    }
+
+    /// Everything the phone sends, in order per characteristic, until both subscriptions end.
+    /// Streams from several calls share the events, each going to whichever is polled first.
+    ///
+    /// Packets are only read while polling, so a slow consumer holds the phone back rather
+    /// than piling up events. Cancel safe: dropping a poll loses nothing, since an event is
+    /// only taken when returned.
+    pub fn events(&self) -> impl Stream<Item = AncsEvent> + Send + Unpin + 'static {
+        let sources = self.sources.clone();
+        futures::stream::poll_fn(move |cx| sources.lock().unwrap().poll_next(cx))
+    }
+
+    /// Writes every packet from now on to a trace, along with what it was decoded into.
+    pub fn trace_to(&self, trace: ProtocolTrace) {
+        self.sources.lock().unwrap().trace = Some(trace);
+    }
+
+    /// Subscribes to the notification source again, to which the phone answers by announcing
+    /// every notification it has once more.
+    pub async fn resubscribe(&self) -> Result<(), AncsError> {
+        let old = {
+            let mut sources = self.sources.lock().unwrap();
+            sources.resubscribing = true;
+            // The phone announces everything anew, which is fetched again
+            sources.reassembler.forget_all();
+            if let Some(trace) = &sources.trace {
+                trace.note("Resubscribing to the notification source");
+            }
+            sources.notifications.take()
+        };
+        // Dropping the subscription ends it, which the phone has to see first
+        drop(old);
+        tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+        let notifications = self.link.subscribe_notifications().await;
+
+        let mut sources = self.sources.lock().unwrap();
+        sources.resubscribing = false;
+        if let Some(waker) = sources.waker.take() {
+            waker.wake();
+        }
+        sources.notifications = Some(notifications?);
+        Ok(())
+    }
+
+    /// Asks for attributes of a notification, with the most bytes to send of those that take a
+    /// length.
+    pub async fn fetch_attributes(
+        &self,
+        uid: u32,
+        attribute_ids: Vec<(NotificationAttributeID, Option<u16>)>,
+    ) -> Result<(), AncsError> {
+        self.sources
+            .lock()
+            .unwrap()
+            .reassembler
+            .expect_notification(uid, attribute_ids.len());
+        let cmd = GetNotificationAttributesRequest {
+            command_id: CommandID::GetNotificationAttributes,
+            notification_uid: uid,
+            attribute_ids,
+        };
+        let result = self.write_control_point(&Vec::from(cmd)).await;
+        if result.is_err() {
+            // Or the next response for it would be framed by this request
+            self.sources
+                .lock()
+                .unwrap()
+                .reassembler
+                .forget_notification(uid);
+        }
+        result
+    }
+
+    /// Asks for the display name of an app.
+    pub async fn fetch_app_attributes(&self, app_identifier: &str) -> Result<(), AncsError> {
+        self.sources
+            .lock()
+            .unwrap()
+            .reassembler
+            .expect_app(app_identifier, 1);
+        let cmd = GetAppAttributesRequest {
+            command_id: CommandID::GetAppAttributes,
+            app_identifier: app_identifier.to_string(),
+            attribute_ids: vec![AppAttributeID::DisplayName],
+        };
+        let result = self.write_control_point(&Vec::from(cmd)).await;
+        if result.is_err() {
+            self.sources
+                .lock()
+                .unwrap()
+                .reassembler
+                .forget_app(app_identifier);
+        }
+        result
+    }
+
+    /// Performs an action of a notification. The phone confirms by removing the notification.
+    pub async fn perform_action(&self, uid: u32, action: ActionID) -> Result<(), AncsError> {
+        self.write_control_point(&protocol::perform_notification_action(uid, action))
+            .await
+    }
+
+    async fn write_control_point(&self, data: &[u8]) -> Result<(), AncsError> {
+        let trace = self.sources.lock().unwrap().trace.clone();
+        if let Some(trace) = &trace {
+            trace.control_point(data);
+        }
+        let result = self.link.write_control_point(data).await;
+        if let (Some(trace), Err(e)) = (&trace, &result) {
+            trace.note(&format!("The write failed: {}", e));
+        }
+        result
+    }
+}
+
+impl Sources {
+    fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<AncsEvent>> {
+        loop {
+            if let Some(event) = self.ready.pop_front() {
+                return Poll::Ready(Some(event));
+            }
+            if let Some(packet) = poll_source(&mut self.notifications, cx) {
+                if let Some(trace) = &self.trace {
+                    trace.notification_source(&packet);
+                }
+                self.ready.push_back(notification(packet));
+                continue;
+            }
+            if let Some(packet) = poll_source(&mut self.data, cx) {
+                let responses = self.reassembler.push(&packet);
+                if let Some(trace) = &self.trace {
+                    trace.data_source(&packet, &responses);
+                }
+                self.ready.extend(responses.into_iter().map(response));
+                continue;
+            }
+            break;
+        }
+
+        if self.notifications.is_none() && !self.resubscribing && self.data.is_none() {
+            return Poll::Ready(None);
+        }
+        self.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+/// The next packet if one is there, forgetting the source once it ends.
+fn poll_source(source: &mut Option<Packets>, cx: &mut Context<'_>) -> Option<Vec<u8>> {
+    match Pin::new(source.as_mut()?).poll_next(cx) {
+        Poll::Ready(Some(packet)) => Some(packet),
+        Poll::Ready(None) => {
+            *source = None;
+            None
+        }
+        Poll::Pending => None,
+    }
+}
+
+fn notification(packet: Vec<u8>) -> AncsEvent {
+    if packet.len() < 8 {
+        return AncsEvent::MalformedNotification(packet);
+    }
+    match <(u8, u8, u8, u8, u32)>::unpack_from_le(&mut Cursor::new(&packet)) {
+        Ok((event_id, event_flags, category_id, category_count, uid)) => {
+            AncsEvent::Notification(NotificationEvent {
+                event_id,
+                event_flags,
+                category_id,
+                category_count,
+                uid,
+            })
+        }
+        Err(_) => AncsEvent::MalformedNotification(packet),
+    }
+}
+
+fn response(data: Vec<u8>) -> AncsEvent {
+    match data.first() {
+        Some(&c) if c == CommandID::GetNotificationAttributes as u8 && data.len() >= 5 => {
+            match GetNotificationAttributesResponse::parse(&data) {
+                Ok((_, response)) => AncsEvent::Attributes(response),
+                Err(_) => AncsEvent::MalformedResponse(data),
+            }
+        }
+        Some(&c) if c == CommandID::GetAppAttributes as u8 => {
+            match protocol::parse_app_attributes(&data) {
+                Ok(response) => AncsEvent::AppAttributes(response),
+                Err(_) => AncsEvent::MalformedResponse(data),
+            }
+        }
+        _ => AncsEvent::MalformedResponse(data),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt as _;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn decodes_what_the_phone_sends() {
+        let simulator = Simulator::idle();
+        let client = AncsClient::simulated(simulator.clone()).await.unwrap();
+        let mut events = client.events();
+        let uid = simulator.add_message("com.apple.MobileSMS", "Bob", "Running late");
+
+        let Some(AncsEvent::Notification(event)) = events.next().await else {
+            panic!("No notification event");
+        };
+        assert_eq!(event.uid, uid);
+
+        client
+            .fetch_attributes(uid, vec![(NotificationAttributeID::Title, Some(100))])
+            .await
+            .unwrap();
+        let Some(AncsEvent::Attributes(response)) = events.next().await else {
+            panic!("No attributes");
+        };
+        assert_eq!(response.notification_uid, uid);
+        assert_eq!(response.attribute_list[0].value.as_deref(), Some("Bob"));
+    }
+}
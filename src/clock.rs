@@ -0,0 +1,110 @@
+//! When things happened, by the wall clock for showing and keeping them, and by the clock
+//! since boot for how long ago, which NTP and setting the time do not move. Unlike
+//! [`std::time::Instant`], the clock since boot keeps counting while suspended.
+
+use std::{fmt, sync::OnceLock, time::Duration};
+
+use chrono::{DateTime, Utc};
+
+/// Time since boot, including suspend, from `CLOCK_BOOTTIME`.
+fn since_boot() -> Duration {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: clock_gettime only writes a timespec to the pointer given
+    let result = unsafe { libc::clock_gettime(libc::CLOCK_BOOTTIME, &mut time) };
+    if result != 0 {
+        return Duration::ZERO;
+    }
+    Duration::new(time.tv_sec as u64, time.tv_nsec as u32)
+}
+
+/// Tells this boot from others, for stamps kept on disk.
+fn boot_id() -> Option<&'static str> {
+    static BOOT_ID: OnceLock<Option<String>> = OnceLock::new();
+    BOOT_ID
+        .get_or_init(|| {
+            std::fs::read_to_string("/proc/sys/kernel/random/boot_id")
+                .ok()
+                .map(|id| id.trim().to_string())
+                .filter(|id| !id.is_empty())
+        })
+        .as_deref()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stamp {
+    pub wall: DateTime<Utc>,
+    /// None if stamped during another boot, when only the wall clock is left to compare.
+    since_boot: Option<Duration>,
+}
+
+impl Stamp {
+    pub fn now() -> Self {
+        Self {
+            wall: Utc::now(),
+            since_boot: Some(since_boot()),
+        }
+    }
+
+    /// How long ago it was stamped, by the clock since boot if stamped during this one.
+    pub fn elapsed(&self) -> Duration {
+        match self.since_boot {
+            Some(then) => since_boot().saturating_sub(then),
+            None => (Utc::now() - self.wall).to_std().unwrap_or_default(),
+        }
+    }
+
+    /// Reads what [`Display`](fmt::Display) wrote, also a bare Unix time from before stamps
+    /// were kept with the clock since boot.
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut parts = s.split_whitespace();
+        let wall = DateTime::from_timestamp(parts.next()?.parse().ok()?, 0)?;
+        let since_boot = parts.next().and_then(|ms| ms.parse().ok());
+        let same_boot = parts.next().is_some_and(|id| Some(id) == boot_id());
+        Some(Self {
+            wall,
+            since_boot: since_boot.filter(|_| same_boot).map(Duration::from_millis),
+        })
+    }
+}
+
+/// Unix time, then milliseconds since boot and the boot they were counted in, if known.
+impl fmt::Display for Stamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.wall.timestamp())?;
+        if let (Some(since_boot), Some(id)) = (self.since_boot, boot_id()) {
+            write!(f, " {} {}", since_boot.as_millis(), id)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measures_by_the_clock_since_boot() {
+        let stamp = Stamp::now();
+        let saved = Stamp::parse(&stamp.to_string()).unwrap();
+        assert_eq!(saved.wall.timestamp(), stamp.wall.timestamp());
+        assert!(saved.elapsed() < Duration::from_secs(60));
+
+        // Moving the wall clock does not change how long ago it was
+        let moved = Stamp {
+            wall: stamp.wall - chrono::Duration::hours(3),
+            ..stamp
+        };
+        assert!(moved.elapsed() < Duration::from_secs(60));
+
+        let other_boot = Stamp::parse(&format!("{} 1000 another-boot", moved.wall.timestamp()));
+        assert!(other_boot.unwrap().elapsed() > Duration::from_secs(3 * 3600 - 60));
+        assert_eq!(
+            Stamp::parse("1760000000").unwrap().wall.timestamp(),
+            1760000000
+        );
+        assert_eq!(Stamp::parse("yesterday"), None);
+    }
+}
@@ -0,0 +1,830 @@
+use std::{collections::HashMap, path::PathBuf, time::Duration};
+
+use ancs::attributes::{category::CategoryID, notification::NotificationAttributeID};
+use ancs_linux::discovery::GattConfig;
+use anyhow::{Context, Result};
+use bluer::Address;
+use serde::{Deserialize, Serialize};
+
+use crate::{output::Output, paths};
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Per-app settings, keyed by bundle identifier or alias.
+    pub apps: HashMap<String, AppConfig>,
+    /// Maps bundle identifiers to a logical app name, so that e.g. beta and release builds of
+    /// the same app are treated as one.
+    pub aliases: HashMap<String, String>,
+    /// Show the notifications already on the phone when first connecting, like `--show-existing`.
+    pub show_existing: bool,
+    /// What to do on startup with desktop notifications the previous run left behind.
+    pub leftover_notifications: Leftovers,
+    /// Phones to mirror when none are given on the command line.
+    pub devices: Vec<DeviceConfig>,
+    /// Advertise ANCS solicitation so that the phone connects on its own, like `--advertise`.
+    pub advertise: bool,
+    /// Leave the desktop alone, e.g. on a relay without one: nothing is shown, and nothing on
+    /// the session bus is watched.
+    pub headless: bool,
+    /// Also `listen --output`.
+    pub output: Output,
+    /// Show an icon in the system tray, needs the `tray` feature. Not in headless mode.
+    pub tray: bool,
+    pub history: HistoryConfig,
+    pub reannounce: ReannounceConfig,
+    pub contacts: ContactsConfig,
+    pub privacy: PrivacyConfig,
+    pub filter: FilterConfig,
+    pub quiet_hours: QuietHoursConfig,
+    pub webhook: WebhookConfig,
+    pub jsonl: JsonlConfig,
+    pub mqtt: MqttConfig,
+    pub ntfy: NtfyConfig,
+    pub exec: ExecConfig,
+    pub sinks: SinksConfig,
+    pub metrics: MetricsConfig,
+    pub media: MediaConfig,
+    pub attribute_length: AttributeLengthConfig,
+    pub titles: TitlesConfig,
+    pub bursts: BurstsConfig,
+    /// Seconds after showing a notification of a category during which the others of that
+    /// category are held back, then shown together as one.
+    pub throttle: HashMap<Category, u64>,
+    pub urgency: UrgencyConfig,
+    pub storage: StorageConfig,
+    pub power: PowerConfig,
+    pub gatt: GattConfig,
+    pub breakthrough: BreakthroughConfig,
+    pub safe_mode: SafeModeConfig,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceConfig {
+    pub address: Address,
+    /// Put before the app of every notification from this phone. The name of the phone in
+    /// BlueZ if not set, while mirroring several.
+    pub label: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Leftovers {
+    /// Leave them up, no longer updated or closed with the notification on the phone.
+    #[default]
+    Keep,
+    /// Close them all.
+    Close,
+    /// Once connected, close those that are gone from the phone and keep the others in sync.
+    Revalidate,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub backend: StorageBackend,
+    /// Database of the `sqlite` backend, `$XDG_DATA_HOME/ancs-linux/state.db` if not set.
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageBackend {
+    /// Files in `$XDG_DATA_HOME/ancs-linux` and `$XDG_CACHE_HOME/ancs-linux`.
+    #[default]
+    Files,
+    /// One SQLite database.
+    Sqlite,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BreakthroughConfig {
+    /// Whether what the phone marks important is time-sensitive.
+    pub important: bool,
+    /// Whether incoming calls are time-sensitive.
+    pub calls: bool,
+    /// Apps, by bundle identifier or alias, all of whose notifications are time-sensitive.
+    pub apps: Vec<String>,
+    /// What time-sensitive notifications are not held back by. Quiet hours only with
+    /// `quiet_hours.show_important`.
+    pub bypass: Vec<Hold>,
+}
+
+impl Default for BreakthroughConfig {
+    fn default() -> Self {
+        Self {
+            important: true,
+            calls: true,
+            apps: vec![],
+            bypass: vec![
+                Hold::QuietHours,
+                Hold::Bursts,
+                Hold::Debounce,
+                Hold::Throttle,
+            ],
+        }
+    }
+}
+
+/// What holds notifications back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Hold {
+    QuietHours,
+    /// Merging chat messages from the same sender, see `bursts.window`.
+    Bursts,
+    /// Waiting for notifications the phone adds and removes rapidly to settle.
+    Debounce,
+    /// Summing up a category with others, see `throttle`.
+    Throttle,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PowerConfig {
+    /// Save power while the computer runs on battery, as UPower tells.
+    pub battery_saver: bool,
+    /// Reannouncing and retrying are done this many times less often on battery.
+    pub slowdown: u32,
+    /// Bytes of title, subtitle and message requested at most on battery. As many as on AC if
+    /// not set.
+    pub attribute_length: Option<u16>,
+    /// Keep delivering to network sinks on battery, instead of holding notifications back until
+    /// on AC.
+    pub network_sinks: bool,
+}
+
+impl Default for PowerConfig {
+    fn default() -> Self {
+        Self {
+            battery_saver: false,
+            slowdown: 4,
+            attribute_length: None,
+            network_sinks: false,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SafeModeConfig {
+    /// Attempts in a row that end before subscribing, crashes included, after which to go into
+    /// safe mode. Never if 0.
+    pub after: u32,
+}
+
+impl Default for SafeModeConfig {
+    fn default() -> Self {
+        Self { after: 5 }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    pub enabled: bool,
+    /// Database location, `$XDG_DATA_HOME/ancs-linux/history.db` if not set.
+    pub path: Option<PathBuf>,
+    /// Day of the week to post a summary of the last 7 days on. Not posted if not set.
+    pub weekly_summary: Option<Day>,
+    /// Apps whose notifications record when they were shown while the desktop session was in
+    /// use, like a read receipt.
+    pub seen_apps: Vec<String>,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            path: None,
+            weekly_summary: None,
+            seen_apps: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhookConfig {
+    /// Every notification is POSTed here as JSON. Disabled if not set.
+    pub url: Option<String>,
+    /// Sent as `Authorization: Bearer <token>`, e.g. a long-lived access token of Home
+    /// Assistant.
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JsonlConfig {
+    /// Every notification is appended here as a line of JSON. Disabled if not set.
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MqttConfig {
+    /// Broker every notification is published to, needs the `mqtt` feature. Disabled if not
+    /// set.
+    pub host: Option<String>,
+    /// 8883 with TLS, 1883 without.
+    pub port: Option<u16>,
+    /// Connect over TLS, verifying the broker with the certificates of the system.
+    pub tls: bool,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Notifications go to `<prefix>/<device>/<app id>`, and whether ancs-linux is running to
+    /// `<prefix>/availability`.
+    pub topic_prefix: String,
+    /// Brokers drop the older connection of the same client id.
+    pub client_id: String,
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        Self {
+            host: None,
+            port: None,
+            tls: false,
+            username: None,
+            password: None,
+            topic_prefix: "ancs".to_string(),
+            client_id: "ancs-linux".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NtfyConfig {
+    /// Every notification is pushed to this topic. Disabled if not set.
+    pub topic: Option<String>,
+    pub server: String,
+    /// Access token for a protected topic.
+    pub token: Option<String>,
+}
+
+impl Default for NtfyConfig {
+    fn default() -> Self {
+        Self {
+            topic: None,
+            server: "https://ntfy.sh".to_string(),
+            token: None,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExecConfig {
+    /// Program and arguments run for every notification received, modified or removed, with
+    /// it as JSON on stdin. Disabled if empty.
+    pub command: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// The counters of `ancs-linux stats` are written here every minute, in the text format of
+    /// Prometheus, e.g. for the textfile collector of node_exporter. Disabled if not set.
+    pub path: Option<PathBuf>,
+    /// Where to serve the same for Prometheus to scrape at `/metrics`, e.g. `127.0.0.1:9109`.
+    /// Disabled if not set.
+    pub addr: Option<std::net::SocketAddr>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AttributeLengthConfig {
+    /// Bytes of title, subtitle and message requested from the phone at first.
+    pub initial: u16,
+    /// Up to how many bytes are requested for apps whose notifications are often truncated.
+    /// Never raised if not above `initial`.
+    pub max: u16,
+    /// Bytes of title and subtitle always requested, instead of learning them.
+    pub title: Option<u16>,
+    /// Bytes of message always requested, instead of learning them.
+    pub message: Option<u16>,
+    /// Request as much of the message as the phone gives, so that it is never cut off.
+    pub full_message: bool,
+}
+
+impl AttributeLengthConfig {
+    /// Length set for an attribute, if it is not learned.
+    pub fn fixed(&self, attribute: Attribute) -> Option<u16> {
+        match attribute {
+            Attribute::Title | Attribute::Subtitle => self.title,
+            Attribute::Message if self.full_message => Some(u16::MAX),
+            Attribute::Message => self.message,
+        }
+    }
+}
+
+impl Default for AttributeLengthConfig {
+    fn default() -> Self {
+        Self {
+            initial: 100,
+            max: 1000,
+            title: None,
+            message: None,
+            full_message: false,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TitlesConfig {
+    /// Titles longer than this many characters without a message are split into a heading and
+    /// a body on the desktop. Never split if 0.
+    pub split_over: usize,
+}
+
+impl Default for TitlesConfig {
+    fn default() -> Self {
+        Self { split_over: 80 }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MediaConfig {
+    /// Shown instead of an empty message when a notification most likely carries a photo or
+    /// other media. Disabled if empty.
+    pub placeholder: String,
+    /// Publish what the phone plays as an MPRIS player on the session bus, over AMS, so media
+    /// keys control it. Not in headless mode.
+    pub mpris: bool,
+}
+
+impl Default for MediaConfig {
+    fn default() -> Self {
+        Self {
+            placeholder: "📷 Photo".to_string(),
+            mpris: false,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BurstsConfig {
+    /// Seconds a notification from a chat app is held back for, merging what the same sender
+    /// sends meanwhile into one desktop notification. Disabled if not set.
+    pub window: Option<u64>,
+}
+
+impl BurstsConfig {
+    pub fn window(&self) -> Option<Duration> {
+        self.window.filter(|&s| s > 0).map(Duration::from_secs)
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SinksConfig {
+    /// Minutes after which a desktop notification tells that delivering to a network sink keeps
+    /// failing. Disabled if not set.
+    pub alert_after: Option<u64>,
+}
+
+impl SinksConfig {
+    pub fn alert_after(&self) -> Option<Duration> {
+        self.alert_after
+            .filter(|&m| m > 0)
+            .map(|m| Duration::from_secs(m * 60))
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ContactsConfig {
+    /// Folder of `.vcf` files whose photos are shown on messages and calls from that contact.
+    pub vcard_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuietHoursConfig {
+    /// IANA name like `Europe/Berlin` the windows are in, the system timezone if not set.
+    pub timezone: Option<String>,
+    /// Whether important notifications and incoming calls are still shown.
+    pub show_important: bool,
+    /// Whether to show what was kept off the desktop once quiet hours end.
+    pub summary: bool,
+    pub windows: Vec<QuietWindow>,
+}
+
+impl Default for QuietHoursConfig {
+    fn default() -> Self {
+        Self {
+            timezone: None,
+            show_important: true,
+            summary: false,
+            windows: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct QuietWindow {
+    /// Days the window starts on, every day if empty.
+    #[serde(default)]
+    pub days: Vec<Day>,
+    /// `HH:MM`, a window ending before it starts lasts past midnight.
+    pub start: String,
+    pub end: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Day {
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+    Sun,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FilterConfig {
+    /// Only notifications from these apps (bundle identifiers or aliases) are shown, and
+    /// nothing but the app is ever requested for the others. All apps if not set.
+    pub allow: Option<Vec<String>>,
+    /// Apps whose notifications are never shown, of which nothing but the app is requested.
+    pub ignore: Vec<String>,
+    /// Categories of notifications that are never shown, of which nothing is requested at all.
+    pub ignore_categories: Vec<Category>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PrivacyConfig {
+    /// Categories whose messages are shown on the desktop but never stored or forwarded.
+    pub sensitive_categories: Vec<Category>,
+    /// What is shown on the desktop while a screen is shared through the portal.
+    pub while_screen_sharing: ScreenSharing,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScreenSharing {
+    /// Everything, as usual.
+    Show,
+    /// Notifications without their message.
+    #[default]
+    TitlesOnly,
+    /// Nothing, notifications are held back until sharing ends.
+    Hide,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UrgencyConfig {
+    /// Urgency of notifications the phone marks as important. Critical ones stay until
+    /// dismissed.
+    pub important: Urgency,
+    /// Urgency of incoming calls, which stay until answered, declined or missed whatever it is.
+    pub calls: Urgency,
+    /// Urgency of notifications the phone delivers silently, which never play a sound.
+    pub silent: Urgency,
+}
+
+impl UrgencyConfig {
+    /// Every notification is shown with normal urgency, as `--ignore-urgency-flags` does.
+    pub fn ignore_flags(&mut self) {
+        self.important = Urgency::Normal;
+        self.calls = Urgency::Normal;
+        self.silent = Urgency::Normal;
+    }
+}
+
+impl Default for UrgencyConfig {
+    fn default() -> Self {
+        Self {
+            important: Urgency::Critical,
+            calls: Urgency::Critical,
+            silent: Urgency::Low,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Urgency {
+    Low,
+    Normal,
+    Critical,
+}
+
+impl From<Urgency> for notify_rust::Urgency {
+    fn from(urgency: Urgency) -> Self {
+        match urgency {
+            Urgency::Low => notify_rust::Urgency::Low,
+            Urgency::Normal => notify_rust::Urgency::Normal,
+            Urgency::Critical => notify_rust::Urgency::Critical,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReannounceConfig {
+    /// Minutes after which an unacknowledged important notification or incoming call is shown
+    /// again. Disabled if not set.
+    pub interval: Option<u64>,
+}
+
+impl ReannounceConfig {
+    pub fn interval(&self) -> Option<Duration> {
+        self.interval
+            .filter(|&m| m > 0)
+            .map(|m| Duration::from_secs(m * 60))
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AppConfig {
+    /// Attributes that may be requested from the phone for this app. All of them if not set.
+    pub attributes: Option<Vec<Attribute>>,
+    /// Show messages from this app on the desktop, but never store or forward them.
+    pub sensitive: bool,
+    /// Window class (X11) or app id (Wayland) of the desktop client of this app, e.g.
+    /// `signal`. Notifications are not shown on the desktop while it has the focus.
+    pub desktop_app: Option<String>,
+    /// Name of the icon its notifications are shown with, or the path of an image, instead of
+    /// the icon of a well-known app or of the category.
+    pub icon: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Attribute {
+    Title,
+    Subtitle,
+    Message,
+}
+
+impl Attribute {
+    pub const ALL: [Attribute; 3] = [Attribute::Title, Attribute::Subtitle, Attribute::Message];
+}
+
+impl From<Attribute> for NotificationAttributeID {
+    fn from(attr: Attribute) -> Self {
+        match attr {
+            Attribute::Title => NotificationAttributeID::Title,
+            Attribute::Subtitle => NotificationAttributeID::Subtitle,
+            Attribute::Message => NotificationAttributeID::Message,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Category {
+    Other,
+    IncomingCall,
+    MissedCall,
+    Voicemail,
+    Social,
+    Schedule,
+    Email,
+    News,
+    HealthAndFitness,
+    BusinessAndFinance,
+    Location,
+    Entertainment,
+}
+
+impl From<Category> for CategoryID {
+    fn from(category: Category) -> Self {
+        match category {
+            Category::Other => CategoryID::Other,
+            Category::IncomingCall => CategoryID::IncomingCall,
+            Category::MissedCall => CategoryID::MissedCall,
+            Category::Voicemail => CategoryID::Voicemail,
+            Category::Social => CategoryID::Social,
+            Category::Schedule => CategoryID::Schedule,
+            Category::Email => CategoryID::Email,
+            Category::News => CategoryID::News,
+            Category::HealthAndFitness => CategoryID::HealthAndFitness,
+            Category::BusinessAndFinance => CategoryID::BusinessAndFinance,
+            Category::Location => CategoryID::Location,
+            Category::Entertainment => CategoryID::Entertainment,
+        }
+    }
+}
+
+impl Category {
+    const ALL: [Category; 12] = [
+        Category::Other,
+        Category::IncomingCall,
+        Category::MissedCall,
+        Category::Voicemail,
+        Category::Social,
+        Category::Schedule,
+        Category::Email,
+        Category::News,
+        Category::HealthAndFitness,
+        Category::BusinessAndFinance,
+        Category::Location,
+        Category::Entertainment,
+    ];
+
+    /// The category with the id the phone sends, None if it is unknown.
+    pub fn of(category_id: u8) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|&c| CategoryID::from(c) as u8 == category_id)
+    }
+
+    /// How notifications of the category are called in summaries, e.g. "3 news notifications".
+    pub fn label(self) -> &'static str {
+        match self {
+            Category::Other => "other",
+            Category::IncomingCall => "incoming call",
+            Category::MissedCall => "missed call",
+            Category::Voicemail => "voicemail",
+            Category::Social => "social",
+            Category::Schedule => "schedule",
+            Category::Email => "email",
+            Category::News => "news",
+            Category::HealthAndFitness => "health and fitness",
+            Category::BusinessAndFinance => "business and finance",
+            Category::Location => "location",
+            Category::Entertainment => "entertainment",
+        }
+    }
+}
+
+impl Config {
+    pub fn default_path() -> Option<PathBuf> {
+        Some(paths::config_dir()?.join("config.toml"))
+    }
+
+    /// Loads the config from `path`, or from the default location if not given.
+    ///
+    /// A missing file at the default location is not an error.
+    pub fn load(path: Option<PathBuf>) -> Result<Self> {
+        let (path, explicit) = match path {
+            Some(p) => (p, true),
+            None => match Self::default_path() {
+                Some(p) => (p, false),
+                None => return Ok(Self::default()),
+            },
+        };
+
+        let content = match std::fs::read_to_string(&path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !explicit => {
+                return Ok(Self::default())
+            }
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", path.display()));
+            }
+        };
+
+        let config = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        log::info!("Loaded config from {}", path.display());
+
+        Ok(config)
+    }
+
+    /// Whether any app is filtered or restricts its attributes, which requires looking up the
+    /// app identifier before the actual attributes can be requested.
+    pub fn needs_app_lookup(&self) -> bool {
+        self.filter.allow.is_some()
+            || !self.filter.ignore.is_empty()
+            || self.apps.values().any(|app| app.attributes.is_some())
+    }
+
+    pub fn is_allowed(&self, app_id: &str) -> bool {
+        let canonical = self.canonical_app_id(app_id);
+        let matches = |a: &String| a == app_id || a == canonical;
+        if self.filter.ignore.iter().any(matches) {
+            return false;
+        }
+        self.filter
+            .allow
+            .as_ref()
+            .is_none_or(|allow| allow.iter().any(matches))
+    }
+
+    /// Whether to record when notifications of an app were seen, see `history.seen_apps`.
+    pub fn records_seen(&self, app_id: &str) -> bool {
+        let canonical = self.canonical_app_id(app_id);
+        self.history
+            .seen_apps
+            .iter()
+            .any(|a| a == app_id || a == canonical)
+    }
+
+    /// The category of `category_id` and how long it is throttled for, if it is.
+    pub fn throttle(&self, category_id: u8) -> Option<(Category, Duration)> {
+        let category = Category::of(category_id)?;
+        let &seconds = self.throttle.get(&category).filter(|&&s| s > 0)?;
+        Some((category, Duration::from_secs(seconds)))
+    }
+
+    /// Whether nothing is shown on the desktop, see `headless`.
+    pub fn is_headless(&self) -> bool {
+        self.headless || self.output == Output::Json
+    }
+
+    pub fn is_ignored_category(&self, category_id: u8) -> bool {
+        self.filter
+            .ignore_categories
+            .iter()
+            .any(|&c| CategoryID::from(c) as u8 == category_id)
+    }
+
+    pub fn canonical_app_id<'a>(&'a self, app_id: &'a str) -> &'a str {
+        self.aliases.get(app_id).map_or(app_id, String::as_str)
+    }
+
+    /// The sections of an app: that of its bundle identifier first, then that of its alias.
+    pub fn app_sections<'a>(&'a self, app_id: &str) -> impl Iterator<Item = &'a AppConfig> {
+        let canonical = self.canonical_app_id(app_id);
+        let aliased = (canonical != app_id)
+            .then(|| self.apps.get(canonical))
+            .flatten();
+        self.apps.get(app_id).into_iter().chain(aliased)
+    }
+
+    pub fn is_sensitive(&self, app_id: &str, category_id: Option<u8>) -> bool {
+        self.app_sections(app_id).any(|app| app.sensitive)
+            || category_id.is_some_and(|id| {
+                self.privacy
+                    .sensitive_categories
+                    .iter()
+                    .any(|&c| CategoryID::from(c) as u8 == id)
+            })
+    }
+
+    /// Those allowed by every section of the app, so that neither loosens what the other
+    /// restricts.
+    pub fn allowed_attributes(&self, app_id: &str) -> Vec<Attribute> {
+        Attribute::ALL
+            .into_iter()
+            .filter(|attr| {
+                self.app_sections(app_id).all(|app| {
+                    app.attributes
+                        .as_ref()
+                        .is_none_or(|attributes| attributes.contains(attr))
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aliased_apps_keep_their_own_restrictions() {
+        let config: Config = toml::from_str(
+            r#"
+            [aliases]
+            "com.apple.mobilemail" = "mail"
+            "com.google.Gmail" = "mail"
+
+            [apps.mail]
+            attributes = ["title", "subtitle"]
+            icon = "internet-mail"
+
+            [apps."com.google.Gmail"]
+            attributes = ["title", "message"]
+            sensitive = true
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.allowed_attributes("com.google.Gmail"),
+            [Attribute::Title]
+        );
+        assert!(config.is_sensitive("com.google.Gmail", None));
+        assert_eq!(
+            config.allowed_attributes("com.apple.mobilemail"),
+            [Attribute::Title, Attribute::Subtitle]
+        );
+        assert!(!config.is_sensitive("com.apple.mobilemail", None));
+        assert_eq!(
+            config
+                .app_sections("com.google.Gmail")
+                .find_map(|app| app.icon.as_deref()),
+            Some("internet-mail")
+        );
+        assert_eq!(
+            config.allowed_attributes("com.example.other"),
+            Attribute::ALL
+        );
+    }
+}
@@ -0,0 +1,180 @@
+use std::{
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+    path::{Path, PathBuf},
+};
+
+use ancs::attributes::category::CategoryID;
+use anyhow::{Context, Result};
+use base64::Engine as _;
+
+use crate::paths;
+
+/// Contact photos from a folder of vCard files, looked up by display name.
+#[derive(Debug)]
+pub struct Contacts {
+    photos: HashMap<String, PathBuf>,
+}
+
+enum Photo {
+    Data { data: Vec<u8>, extension: String },
+    File(PathBuf),
+}
+
+impl Contacts {
+    pub fn load(dir: &Path) -> Result<Self> {
+        let cache_dir = paths::cache_dir()
+            .context("Cannot determine the cache location")?
+            .join("contacts");
+        std::fs::create_dir_all(&cache_dir)
+            .with_context(|| format!("Failed to create {}", cache_dir.display()))?;
+
+        let mut photos = HashMap::new();
+        for file in
+            std::fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))?
+        {
+            let path = file?.path();
+            if path
+                .extension()
+                .is_none_or(|e| !e.eq_ignore_ascii_case("vcf"))
+            {
+                continue;
+            }
+
+            let content = match std::fs::read_to_string(&path) {
+                Ok(c) => c,
+                Err(e) => {
+                    log::warn!("Failed to read {}: {:?}", path.display(), e);
+                    continue;
+                }
+            };
+
+            for (name, photo) in parse_vcards(&content) {
+                let photo_path = match photo {
+                    Photo::File(p) => p,
+                    Photo::Data { data, extension } => {
+                        let mut hasher = DefaultHasher::new();
+                        name.hash(&mut hasher);
+                        let p = cache_dir.join(format!("{:016x}.{}", hasher.finish(), extension));
+                        std::fs::write(&p, data)
+                            .with_context(|| format!("Failed to write {}", p.display()))?;
+                        p
+                    }
+                };
+                photos.insert(normalize(&name), photo_path);
+            }
+        }
+
+        log::info!(
+            "Loaded {} contact photos from {}",
+            photos.len(),
+            dir.display()
+        );
+        Ok(Self { photos })
+    }
+
+    pub fn photo(&self, name: &str) -> Option<&Path> {
+        self.photos.get(&normalize(name)).map(PathBuf::as_path)
+    }
+}
+
+fn normalize(name: &str) -> String {
+    name.trim().to_lowercase()
+}
+
+/// Returns the full name and photo of every contact in `content` that has both.
+fn parse_vcards(content: &str) -> Vec<(String, Photo)> {
+    // Long lines are folded by starting the continuation with whitespace
+    let mut lines: Vec<String> = vec![];
+    for line in content.lines() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(rest), Some(last)) => last.push_str(rest),
+            _ => lines.push(line.to_string()),
+        }
+    }
+
+    let mut contacts = vec![];
+    let mut name = None;
+    let mut photo = None;
+    for line in lines {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let mut params = key.split(';');
+        let property = params.next().unwrap_or_default().to_ascii_uppercase();
+        let params: Vec<String> = params.map(|p| p.to_ascii_uppercase()).collect();
+
+        match property.as_str() {
+            "BEGIN" => {
+                name = None;
+                photo = None;
+            }
+            "FN" => name = Some(value.to_string()),
+            "PHOTO" => photo = parse_photo(&params, value),
+            "END" => {
+                if let (Some(n), Some(p)) = (name.take(), photo.take()) {
+                    contacts.push((n, p));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    contacts
+}
+
+fn parse_photo(params: &[String], value: &str) -> Option<Photo> {
+    let base64 = base64::engine::general_purpose::STANDARD;
+
+    // vCard 4: PHOTO:data:image/jpeg;base64,...
+    if let Some(data) = value.strip_prefix("data:") {
+        let (media_type, data) = data.split_once(";base64,")?;
+        let extension = media_type
+            .strip_prefix("image/")
+            .unwrap_or("jpg")
+            .to_string();
+        return Some(Photo::Data {
+            data: base64.decode(data.trim()).ok()?,
+            extension,
+        });
+    }
+
+    if let Some(path) = value.strip_prefix("file://") {
+        return Some(Photo::File(PathBuf::from(path)));
+    }
+
+    // vCard 3: PHOTO;ENCODING=b;TYPE=JPEG:...
+    let encoded = params
+        .iter()
+        .any(|p| p == "ENCODING=B" || p == "ENCODING=BASE64");
+    if encoded {
+        let extension = params
+            .iter()
+            .find_map(|p| p.strip_prefix("TYPE="))
+            .unwrap_or("jpg")
+            .to_ascii_lowercase();
+        return Some(Photo::Data {
+            data: base64.decode(value.trim()).ok()?,
+            extension,
+        });
+    }
+
+    None
+}
+
+/// Whether a notification is likely titled with the name of a contact.
+pub fn is_from_person(app_id: &str, category_id: Option<u8>) -> bool {
+    const APPS: [&str; 3] = [
+        "com.apple.MobileSMS",
+        "com.apple.mobilephone",
+        "com.apple.facetime",
+    ];
+    const CATEGORIES: [CategoryID; 3] = [
+        CategoryID::IncomingCall,
+        CategoryID::MissedCall,
+        CategoryID::Voicemail,
+    ];
+
+    APPS.contains(&app_id)
+        || category_id.is_some_and(|c| CATEGORIES.iter().any(|&id| id as u8 == c))
+}
@@ -0,0 +1,315 @@
+//! Client side of the daemon's interface on the session bus, shared with the
+//! `ancs-linux-client` binary.
+
+use anyhow::Result;
+use bluer::Address;
+use chrono::{DateTime, Local};
+use clap::{Subcommand, ValueEnum};
+use zbus::proxy;
+
+use crate::text;
+
+/// Columns the app takes at most in listings, so that long identifiers leave room for titles.
+const APP_COLUMN: usize = 24;
+
+/// Name, delivered, failed, queued, failing since and last error of a network sink.
+pub type SinkStatus = (String, u64, u64, u64, String, String);
+
+/// Received at (RFC 3339), app, title, subtitle, message, whether it was missed, when it was
+/// seen and when it was removed (RFC 3339, empty if not recorded), of a notification in the
+/// history.
+pub type HistoryEntry = (String, String, String, String, String, bool, String, String);
+
+/// Phone (empty for the simulated one), uid, received at (RFC 3339), app name, title (empty
+/// in private mode), category (empty if unknown) and whether it is up on the desktop, of a
+/// notification on the phone.
+pub type ActiveEntry = (String, u32, String, String, String, String, bool);
+
+// What `ancs-linux ctl` and `ancs-linux-client` both ask a running daemon, so that neither
+// falls behind. Not a doc comment, which clap would show as the about of both.
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Tear down the connection to the device and establish it again
+    Reconnect,
+    /// Disconnect from the device until `reconnect`
+    Disconnect,
+    /// Dismiss the most recent notification on the phone
+    DismissLast,
+    /// Perform the positive action of the most recent notification, e.g. answer a call
+    ActOnLast,
+    /// Perform the action of a notification by its label as shown on the phone
+    SendAction {
+        #[arg(help = "UID of the notification, as logged by `listen` when showing it")]
+        uid: u32,
+        #[arg(long, help = "Label of the action, e.g. \"Mark as Read\"")]
+        label: String,
+        #[arg(long, help = "Address of the phone, needed while mirroring several")]
+        device: Option<Address>,
+    },
+    /// Make the phone play a sound to find it, until `ring --stop`
+    Ring {
+        #[arg(long, help = "Stop the sound instead")]
+        stop: bool,
+        #[arg(long, help = "Address of the phone, needed while mirroring several")]
+        device: Option<Address>,
+    },
+    /// Show how the connection to the phone and delivering to network sinks go
+    Status,
+    /// Inspect and correct the names apps are shown under
+    AppNames {
+        #[command(subcommand)]
+        command: AppNamesCommand,
+    },
+    /// Show nothing but the app of new notifications, e.g. while sharing the screen
+    Privacy {
+        #[arg(
+            value_enum,
+            help = "Whether to turn it on, off, or the other way it is now"
+        )]
+        mode: PrivacyMode,
+    },
+    /// Change how much is logged for a target until restarting, e.g. `bluer debug`
+    LogLevel {
+        #[arg(help = "Module path to change, including everything below it, e.g. `bluer`")]
+        target: String,
+        #[arg(help = "One of off, error, warn, info, debug, trace, or default for `RUST_LOG`")]
+        level: String,
+    },
+    /// Disconnect from every phone until `resume`
+    Pause,
+    /// Connect to every phone again after `pause`
+    Resume,
+    /// List the notifications on the phone
+    List,
+    /// Dismiss a notification on the phone by its UID
+    Dismiss {
+        #[arg(help = "UID of the notification, as listed by `list`")]
+        uid: u32,
+        #[arg(long, help = "Address of the phone, needed while mirroring several")]
+        device: Option<Address>,
+    },
+    /// Read the config anew and apply it to notifications from now on
+    Reload,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum PrivacyMode {
+    On,
+    Off,
+    Toggle,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum AppNamesCommand {
+    /// List the known app names, marking those set by hand with `*`
+    List,
+    /// Show an app under a name of your choice instead of the one from the phone
+    Set {
+        #[arg(help = "Bundle identifier of the app, e.g. `com.apple.MobileSMS`")]
+        app_id: String,
+        name: String,
+    },
+    /// Forget the name of an app, so that it is looked up from the phone again
+    Rm {
+        #[arg(help = "Bundle identifier of the app")]
+        app_id: String,
+    },
+}
+
+/// The `device` argument of the daemon's methods, empty to mean the only phone.
+pub fn device_arg(device: Option<Address>) -> String {
+    device.map(|d| d.to_string()).unwrap_or_default()
+}
+
+#[proxy(
+    interface = "io.github.kmod_midori.AncsLinux",
+    default_service = "io.github.kmod_midori.AncsLinux",
+    default_path = "/io/github/kmod_midori/AncsLinux"
+)]
+pub trait Control {
+    fn dismiss_last(&self) -> zbus::Result<()>;
+
+    fn act_on_last(&self) -> zbus::Result<()>;
+
+    fn send_action(&self, device: &str, uid: u32, label: &str) -> zbus::Result<()>;
+
+    fn dismiss_notification(&self, device: &str, uid: u32) -> zbus::Result<()>;
+
+    fn ring(&self, device: &str) -> zbus::Result<()>;
+
+    fn stop_ringing(&self, device: &str) -> zbus::Result<()>;
+
+    fn reconnect(&self) -> zbus::Result<()>;
+
+    fn disconnect(&self) -> zbus::Result<()>;
+
+    fn pause(&self) -> zbus::Result<()>;
+
+    fn resume(&self) -> zbus::Result<()>;
+
+    fn status(&self) -> zbus::Result<(String, String, bool, u64, u64)>;
+
+    fn private_mode(&self) -> zbus::Result<bool>;
+
+    fn set_private_mode(&self, enabled: bool) -> zbus::Result<()>;
+
+    fn stats(&self) -> zbus::Result<Vec<(String, u64)>>;
+
+    fn safe_mode(&self) -> zbus::Result<String>;
+
+    fn sessions(&self) -> zbus::Result<Vec<(String, String, String, String)>>;
+
+    fn sink_status(&self) -> zbus::Result<Vec<SinkStatus>>;
+
+    fn recent(&self, missed_only: bool, limit: u32) -> zbus::Result<Vec<HistoryEntry>>;
+
+    fn app_names(&self) -> zbus::Result<Vec<(String, String, bool)>>;
+
+    fn set_app_name(&self, app_id: &str, name: &str) -> zbus::Result<()>;
+
+    fn remove_app_name(&self, app_id: &str) -> zbus::Result<()>;
+
+    fn set_log_level(&self, target: &str, level: &str) -> zbus::Result<()>;
+
+    fn list(&self) -> zbus::Result<Vec<ActiveEntry>>;
+
+    fn reload(&self) -> zbus::Result<String>;
+}
+
+/// Prints what `app_names` returns, ordered by name.
+pub fn print_app_names(mut app_names: Vec<(String, String, bool)>) {
+    app_names.sort_by(|a, b| text::collate(&a.1, &b.1));
+    let id_width = app_names
+        .iter()
+        .map(|(app_id, _, _)| text::width(app_id))
+        .max()
+        .unwrap_or_default();
+    for (app_id, name, custom) in app_names {
+        let mark = if custom { " *" } else { "" };
+        let app_id = text::pad(&format!("{}:", app_id), id_width + 1);
+        println!("{} {}{}", app_id, name, mark);
+    }
+}
+
+/// Prints what `list` returns.
+pub fn print_active(entries: &[ActiveEntry]) -> Result<()> {
+    for (_, uid, received_at, app_name, title, category, shown) in entries {
+        let time = DateTime::parse_from_rfc3339(received_at)?.with_timezone(&Local);
+        let category = if category.is_empty() {
+            String::new()
+        } else {
+            format!(" ({})", category)
+        };
+        let shown = if *shown { "" } else { ", not shown" };
+        println!(
+            "{} {} [{}] {}{}{}",
+            uid,
+            time.format("%H:%M"),
+            app_name,
+            title,
+            category,
+            shown
+        );
+    }
+    Ok(())
+}
+
+/// Prints what `safe_mode`, `sessions` and `sink_status` return.
+pub fn print_status(
+    safe_mode: &str,
+    sessions: Vec<(String, String, String, String)>,
+    sinks: Vec<SinkStatus>,
+) -> Result<()> {
+    if !safe_mode.is_empty() {
+        println!("SAFE MODE: {}", safe_mode);
+        println!("  network sinks get nothing and notifications are printed to stdout, restart once fixed");
+    }
+    for (address, state, since, reason) in sessions {
+        let since = DateTime::parse_from_rfc3339(&since)?.with_timezone(&Local);
+        let phone = if address.is_empty() {
+            "simulated".to_string()
+        } else {
+            address
+        };
+        println!(
+            "phone {}: {} since {}",
+            phone,
+            state,
+            since.format("%Y-%m-%d %H:%M:%S")
+        );
+        if !reason.is_empty() {
+            println!("  reason: {}", reason);
+        }
+    }
+    if sinks.is_empty() {
+        println!("No network sinks configured");
+    }
+    for (name, delivered, failed, queued, failing_since, last_error) in sinks {
+        if failing_since.is_empty() {
+            println!("{}: ok", name);
+        } else {
+            println!("{}: failing since {}", name, failing_since);
+        }
+        println!(
+            "  delivered: {}, failed: {}, queued: {}",
+            delivered, failed, queued
+        );
+        if !last_error.is_empty() {
+            println!("  last error: {}", last_error);
+        }
+    }
+    Ok(())
+}
+
+/// Prints history entries, given most recent first, oldest first with missed ones marked.
+/// `by_app` groups them by app, ordered by name. Lines are cut to fit the terminal.
+pub fn print_history(entries: &[HistoryEntry], by_app: bool) -> Result<()> {
+    let mut entries: Vec<&HistoryEntry> = entries.iter().rev().collect();
+    if by_app {
+        // Stable, so oldest first within each app
+        entries.sort_by(|a, b| text::collate(&a.1, &b.1));
+    }
+    let app_width = entries
+        .iter()
+        .map(|e| text::width(&e.1).min(APP_COLUMN))
+        .max()
+        .unwrap_or_default();
+    let columns = text::terminal_width();
+    let print = |line: String| match columns {
+        Some(columns) => println!("{}", text::truncate(&line, columns)),
+        None => println!("{}", line),
+    };
+
+    for (received_at, app_id, title, subtitle, message, missed, seen_at, removed_at) in entries {
+        let time = DateTime::parse_from_rfc3339(received_at)?.with_timezone(&Local);
+        let marker = if *missed { "*" } else { " " };
+        let app = format!("[{}]", text::truncate(app_id, APP_COLUMN));
+        let seen = if seen_at.is_empty() {
+            String::new()
+        } else {
+            let seen_at = DateTime::parse_from_rfc3339(seen_at)?.with_timezone(&Local);
+            format!(" (seen {})", seen_at.format("%H:%M"))
+        };
+        let removed = if removed_at.is_empty() {
+            String::new()
+        } else {
+            let removed_at = DateTime::parse_from_rfc3339(removed_at)?.with_timezone(&Local);
+            format!(" (removed {})", removed_at.format("%H:%M"))
+        };
+        print(format!(
+            "{}{} {} {}{}{}",
+            marker,
+            time.format("%Y-%m-%d %H:%M"),
+            text::pad(&app, app_width + 2),
+            title,
+            seen,
+            removed
+        ));
+        for line in [subtitle, message] {
+            if !line.is_empty() {
+                print(format!("    {}", line));
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,108 @@
+use std::{
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+    time::Duration,
+};
+
+use anyhow::Result;
+
+use crate::{clock::Stamp, history::Entry, store::Slot};
+
+/// How recently the state must have been saved for a restart to count as quick.
+const CATCH_UP_WINDOW: Duration = Duration::from_secs(15 * 60);
+
+/// Notifications already shown on the desktop or skipped as pre-existing, kept on disk across
+/// restarts.
+///
+/// The phone marks everything it re-announces to a new subscriber as pre-existing, which are
+/// normally skipped. After a quick restart, pre-existing notifications that are not in here
+/// arrived while we were away and are shown after all.
+pub struct Dedupe {
+    slot: Slot,
+    /// Content hash of every known notification by UID, if it was shown.
+    known: HashMap<u32, Option<u64>>,
+    saved_at: Option<Stamp>,
+}
+
+impl Dedupe {
+    pub fn load(slot: Slot) -> Self {
+        let mut dedupe = Self {
+            slot,
+            known: HashMap::new(),
+            saved_at: None,
+        };
+
+        match dedupe.slot.load() {
+            Ok(Some(content)) => dedupe.parse(&content),
+            Ok(None) => {}
+            Err(e) => log::warn!("Failed to read {}: {:?}", dedupe.slot, e),
+        }
+
+        dedupe
+    }
+
+    fn parse(&mut self, content: &str) {
+        let mut lines = content.lines();
+        self.saved_at = lines.next().and_then(Stamp::parse);
+
+        for line in lines {
+            let Some((uid, hash)) = line.split_once(' ') else {
+                continue;
+            };
+            let Ok(uid) = uid.parse() else {
+                continue;
+            };
+            self.known.insert(uid, u64::from_str_radix(hash, 16).ok());
+        }
+    }
+
+    /// Whether the last run ended recently enough to catch up on what it missed, which a
+    /// suspend in between or the wall clock jumping does not fool.
+    pub fn is_recent(&self) -> bool {
+        self.saved_at.is_some_and(|t| t.elapsed() < CATCH_UP_WINDOW)
+    }
+
+    /// Whether the same content was shown before, possibly under another UID if the phone
+    /// restarted in between.
+    pub fn was_shown(&self, hash: u64) -> bool {
+        self.known.values().any(|&h| h == Some(hash))
+    }
+
+    pub fn contains(&self, uid: u32) -> bool {
+        self.known.contains_key(&uid)
+    }
+
+    /// Remembers a notification, with the hash of its content if it was shown.
+    pub fn insert(&mut self, uid: u32, hash: Option<u64>) {
+        self.known.insert(uid, hash);
+    }
+
+    /// Forgets notifications that are no longer on the phone.
+    pub fn retain(&mut self, mut keep: impl FnMut(u32) -> bool) {
+        self.known.retain(|&uid, _| keep(uid));
+    }
+
+    pub fn save(&mut self) -> Result<()> {
+        let now = Stamp::now();
+        let mut content = format!("{}\n", now);
+        for (uid, hash) in &self.known {
+            match hash {
+                Some(hash) => content.push_str(&format!("{} {:016x}\n", uid, hash)),
+                None => content.push_str(&format!("{} -\n", uid)),
+            }
+        }
+        self.slot.save(&content)?;
+
+        self.saved_at = Some(now);
+        Ok(())
+    }
+}
+
+pub fn content_hash(entry: &Entry) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    entry.app_id.hash(&mut hasher);
+    entry.title.hash(&mut hasher);
+    entry.subtitle.hash(&mut hasher);
+    entry.message.hash(&mut hasher);
+    hasher.finish()
+}
@@ -0,0 +1,204 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use ancs::attributes::category::CategoryID;
+use futures::StreamExt as _;
+use notify_rust::CloseReason;
+use tokio::sync::oneshot;
+use zbus::{proxy, Connection};
+
+/// How long a bubble is watched at most, for servers that never tell when they closed one.
+const WATCH_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[proxy(
+    interface = "org.freedesktop.Notifications",
+    default_service = "org.freedesktop.Notifications",
+    default_path = "/org/freedesktop/Notifications"
+)]
+trait Notifications {
+    fn close_notification(&self, id: u32) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    fn action_invoked(&self, id: u32, action_key: String) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    fn notification_closed(&self, id: u32, reason: u32) -> zbus::Result<()>;
+}
+
+/// What became of a bubble.
+#[derive(Debug)]
+pub enum Response {
+    /// The action with this key was chosen, `default` for clicking the bubble.
+    Action(String),
+    Closed(CloseReason),
+}
+
+/// Watches every bubble the notification server shows for us over a single connection, handing
+/// what happens to each to whoever watches its id. Many bubbles with actions may be up at once.
+#[derive(Clone)]
+pub struct Router {
+    watched: Arc<Mutex<HashMap<u32, Watch>>>,
+}
+
+struct Watch {
+    since: Instant,
+    tx: oneshot::Sender<Response>,
+}
+
+impl Router {
+    pub async fn new() -> zbus::Result<Self> {
+        let conn = Connection::session().await?;
+        let proxy = NotificationsProxy::new(&conn).await?;
+        let mut actions = proxy.receive_action_invoked().await?;
+        let mut closed = proxy.receive_notification_closed().await?;
+        let mut owners = zbus::fdo::DBusProxy::new(&conn)
+            .await?
+            .receive_name_owner_changed_with_args(&[(0, "org.freedesktop.Notifications")])
+            .await?;
+
+        let router = Self {
+            watched: Arc::default(),
+        };
+        let watched = router.watched.clone();
+        tokio::spawn(async move {
+            loop {
+                let (id, response) = tokio::select! {
+                    Some(signal) = actions.next() => match signal.args() {
+                        Ok(args) => (args.id, Response::Action(args.action_key)),
+                        Err(e) => {
+                            log::warn!("Ignoring malformed ActionInvoked: {:?}", e);
+                            continue;
+                        }
+                    },
+                    Some(signal) = closed.next() => match signal.args() {
+                        Ok(args) => (args.id, Response::Closed(args.reason.into())),
+                        Err(e) => {
+                            log::warn!("Ignoring malformed NotificationClosed: {:?}", e);
+                            continue;
+                        }
+                    },
+                    Some(_) = owners.next() => {
+                        // Ids of the old server may come back for other bubbles
+                        let stale = std::mem::take(&mut *watched.lock().unwrap());
+                        if !stale.is_empty() {
+                            log::debug!(
+                                "Notification server changed, no longer watching {} bubbles",
+                                stale.len()
+                            );
+                        }
+                        continue;
+                    }
+                    else => break,
+                };
+                answer(&watched, id, response);
+            }
+            log::warn!("The session bus went away, no longer watching bubbles");
+        });
+        Ok(router)
+    }
+
+    /// What becomes of the bubble with `id`, first come first served: an action, which closes
+    /// it on most servers, or closing it. Nothing if it stays up too long or the server goes
+    /// away, or if the same id is watched again.
+    pub async fn watch(&self, id: u32) -> Option<Response> {
+        let (tx, rx) = oneshot::channel();
+        {
+            let mut watched = self.watched.lock().unwrap();
+            let now = Instant::now();
+            watched.retain(|_, w| now - w.since < WATCH_TIMEOUT);
+            watched.insert(id, Watch { since: now, tx });
+        }
+        rx.await.ok()
+    }
+}
+
+fn answer(watched: &Mutex<HashMap<u32, Watch>>, id: u32, response: Response) {
+    // Not watched if not ours, or already answered
+    if let Some(watch) = watched.lock().unwrap().remove(&id) {
+        let _ = watch.tx.send(response);
+    }
+}
+
+/// Closes a desktop notification by id, which notify-rust only allows through the handle that
+/// is already given to the watcher.
+pub async fn close_notification(id: u32) -> zbus::Result<()> {
+    let conn = Connection::session().await?;
+    NotificationsProxy::new(&conn)
+        .await?
+        .close_notification(id)
+        .await
+}
+
+/// Unique bus name of the notification server, which changes when it restarts.
+pub async fn server_owner() -> zbus::Result<String> {
+    let conn = Connection::session().await?;
+    let owner = zbus::fdo::DBusProxy::new(&conn)
+        .await?
+        .get_name_owner("org.freedesktop.Notifications".try_into()?)
+        .await?;
+    Ok(owner.to_string())
+}
+
+/// The freedesktop notification category and a stock icon name for an ANCS category, so that
+/// desktops can group and style them like native ones.
+pub fn category_style(category_id: u8) -> (Option<&'static str>, Option<&'static str>) {
+    let Ok(category) = CategoryID::try_from(category_id) else {
+        return (None, None);
+    };
+    match category {
+        CategoryID::Other => (None, None),
+        CategoryID::IncomingCall => (Some("call.incoming"), Some("call-start")),
+        CategoryID::MissedCall => (Some("call.unanswered"), Some("call-stop")),
+        CategoryID::Voicemail => (Some("call"), Some("audio-x-generic")),
+        CategoryID::Social => (Some("im.received"), Some("mail-message-new")),
+        CategoryID::Schedule => (None, Some("appointment-soon")),
+        CategoryID::Email => (Some("email.arrived"), Some("mail-unread")),
+        CategoryID::News => (None, Some("application-rss+xml")),
+        CategoryID::HealthAndFitness => (None, Some("emblem-favorite")),
+        CategoryID::BusinessAndFinance => (None, Some("x-office-spreadsheet")),
+        CategoryID::Location => (None, Some("mark-location")),
+        CategoryID::Entertainment => (None, Some("applications-multimedia")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn answers_each_bubble_on_its_own() {
+        let router = Router {
+            watched: Arc::default(),
+        };
+        let first = tokio::spawn({
+            let router = router.clone();
+            async move { router.watch(1).await }
+        });
+        let second = tokio::spawn({
+            let router = router.clone();
+            async move { router.watch(2).await }
+        });
+        while router.watched.lock().unwrap().len() < 2 {
+            tokio::task::yield_now().await;
+        }
+
+        answer(&router.watched, 2, Response::Action("reply".to_string()));
+        answer(&router.watched, 3, Response::Closed(CloseReason::Dismissed));
+        answer(
+            &router.watched,
+            2,
+            Response::Closed(CloseReason::CloseAction),
+        );
+        answer(&router.watched, 1, Response::Closed(CloseReason::Expired));
+
+        assert!(matches!(second.await.unwrap(), Some(Response::Action(key)) if key == "reply"));
+        assert!(matches!(
+            first.await.unwrap(),
+            Some(Response::Closed(CloseReason::Expired))
+        ));
+        assert!(router.watched.lock().unwrap().is_empty());
+    }
+}
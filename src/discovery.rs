@@ -0,0 +1,565 @@
+use std::{fmt, time::Duration};
+
+use anyhow::{bail, Result};
+use bluer::{
+    adv::{Advertisement, AdvertisementHandle},
+    gatt::{
+        remote::{Characteristic, CharacteristicWriteRequest, Service},
+        CharacteristicFlags, WriteOp,
+    },
+    Adapter, AdapterEvent, Address, Device, DeviceEvent, DeviceProperty, Uuid,
+};
+use futures::{pin_mut, stream::SelectAll, StreamExt as _};
+use serde::Deserialize;
+
+use crate::error::AncsError;
+
+const ANCS_UUID: &str = "7905F431-B5CE-4E99-A40F-4B1E122D00D0";
+const NOTIFICATION_SOURCE_UUID: &str = "9FBF120D-6301-42D9-8C58-25E699A21DBD";
+const DATA_SOURCE_UUID: &str = "22EAC6E9-24D6-4BB5-BE44-B36ACE7C7BFB";
+const CONTROL_POINT_UUID: &str = "69D1D8F3-45E1-49A8-9821-9BBDFDAAD9D9";
+
+/// How long to scan for a device by name that BlueZ does not know yet.
+const SCAN_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long BlueZ gets to learn the services of a device after it connected.
+const RESOLVE_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long to give BlueZ before looking at the services again, without reconnecting.
+const REFRESH_DELAY: Duration = Duration::from_secs(3);
+/// Generic Access and Generic Attribute, which every device has.
+const GENERIC_SERVICES: [Uuid; 2] = [
+    Uuid::from_u128(0x00001800_0000_1000_8000_00805f9b34fb),
+    Uuid::from_u128(0x00001801_0000_1000_8000_00805f9b34fb),
+];
+
+pub const NOTIFICATION_SOURCE: &str = "notification source";
+pub const DATA_SOURCE: &str = "data source";
+pub const CONTROL_POINT: &str = "control point";
+
+/// How hard to look for ANCS, the `[gatt]` section of the config of `ancs-linux`.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GattConfig {
+    /// How many times to look at the services of the phone again when ANCS is missing, as
+    /// BlueZ often has a stale cache of them.
+    pub refresh_attempts: u32,
+    /// Disconnect and connect again before looking again, which makes BlueZ discover the
+    /// services anew but may lose access to notifications on some phones.
+    pub reconnect: bool,
+}
+
+impl Default for GattConfig {
+    fn default() -> Self {
+        Self {
+            refresh_attempts: 2,
+            reconnect: false,
+        }
+    }
+}
+
+pub struct AncsCharacteristics {
+    pub notification_source: Characteristic,
+    pub data_source: Characteristic,
+    pub control_point: Characteristic,
+}
+
+/// Finds the ANCS characteristics on a connected device.
+pub async fn find_ancs(device: &Device) -> Result<AncsCharacteristics, AncsError> {
+    wait_for_services(device).await?;
+    let service = find_service(device, &GattConfig::default()).await?;
+    find_characteristics(&service).await
+}
+
+/// Waits until BlueZ has learned the services of a connected device, which takes a while right
+/// after connecting. Until then, the services it lists may well lack ANCS. Gives up at once if
+/// the device disconnects meanwhile, as its services never get resolved then.
+pub async fn wait_for_services(device: &Device) -> Result<(), AncsError> {
+    let mut events = device.events().await?;
+    if device.is_services_resolved().await? {
+        return Ok(());
+    }
+    log::debug!(
+        "Waiting for the services of {} to be resolved",
+        device.address()
+    );
+    let resolved = async {
+        while let Some(event) = events.next().await {
+            match event {
+                DeviceEvent::PropertyChanged(DeviceProperty::ServicesResolved(true)) => {
+                    return Ok(());
+                }
+                DeviceEvent::PropertyChanged(DeviceProperty::Connected(false)) => break,
+                _ => {}
+            }
+        }
+        // Disconnected, or removed from BlueZ altogether
+        Err(AncsError::NotConnected(device.address()))
+    };
+    tokio::time::timeout(RESOLVE_TIMEOUT, resolved)
+        .await
+        .unwrap_or(Err(AncsError::ServicesUnresolved(
+            RESOLVE_TIMEOUT.as_secs(),
+        )))
+}
+
+/// Finds ANCS among the services of a device, once they are resolved.
+///
+/// BlueZ often serves a stale cache of the services of an iPhone, so they are looked at again
+/// up to `config.refresh_attempts` times before giving up, reconnecting in between if
+/// `config.reconnect` is set. The error tells a stale cache from a device that does not offer
+/// ANCS.
+pub async fn find_service(device: &Device, config: &GattConfig) -> Result<Service, AncsError> {
+    let ancs_uuid: Uuid = ANCS_UUID.parse().unwrap();
+    let mut attempt = 0;
+    loop {
+        let mut uuids = vec![];
+        for s in device.services().await? {
+            let uuid = s.uuid().await?;
+            if uuid == ancs_uuid {
+                if attempt > 0 {
+                    log::warn!(
+                        "ANCS showed up on {} after refreshing its services, BlueZ had a stale cache",
+                        device.address()
+                    );
+                }
+                return Ok(s);
+            }
+            uuids.push(uuid);
+        }
+
+        if attempt >= config.refresh_attempts {
+            return Err(missing_ancs(device.address(), &uuids, attempt));
+        }
+        attempt += 1;
+        log::info!(
+            "ANCS not found on {}, refreshing its services ({}/{})",
+            device.address(),
+            attempt,
+            config.refresh_attempts
+        );
+        if config.reconnect {
+            device.disconnect().await?;
+            device.connect().await?;
+            wait_for_services(device).await?;
+        } else {
+            tokio::time::sleep(REFRESH_DELAY).await;
+        }
+    }
+}
+
+/// Whether a device with these services most likely has more that BlueZ does not know about.
+fn looks_stale(uuids: &[Uuid]) -> bool {
+    uuids.iter().all(|uuid| GENERIC_SERVICES.contains(uuid))
+}
+
+fn missing_ancs(addr: Address, uuids: &[Uuid], refreshed: u32) -> AncsError {
+    let stale_cache = looks_stale(uuids);
+    let message = if stale_cache {
+        format!(
+            "ANCS service not found, BlueZ knows nothing but the generic services of the device \
+             after refreshing {} times, which looks like a stale service cache. Remove the cache \
+             with `sudo rm /var/lib/bluetooth/*/cache/{}` and restart bluetoothd, or pair again",
+            refreshed, addr
+        )
+    } else {
+        format!(
+            "ANCS service not found among the {} services of the device after refreshing {} \
+             times, so it does not offer ANCS to this computer",
+            uuids.len(),
+            refreshed
+        )
+    };
+    AncsError::ServiceNotFound {
+        stale_cache,
+        message,
+    }
+}
+
+pub async fn find_characteristics(
+    ancs_service: &Service,
+) -> Result<AncsCharacteristics, AncsError> {
+    let mut notification_source = None;
+    let mut data_source = None;
+    let mut control_point = None;
+    let noti_source_uuid: Uuid = NOTIFICATION_SOURCE_UUID.parse().unwrap();
+    let data_source_uuid: Uuid = DATA_SOURCE_UUID.parse().unwrap();
+    let control_point_uuid: Uuid = CONTROL_POINT_UUID.parse().unwrap();
+    for c in ancs_service.characteristics().await? {
+        let uuid = c.uuid().await?;
+
+        if uuid == noti_source_uuid {
+            notification_source = Some(c);
+        } else if uuid == data_source_uuid {
+            data_source = Some(c);
+        } else if uuid == control_point_uuid {
+            control_point = Some(c);
+        }
+    }
+
+    let notification_source =
+        notification_source.ok_or(AncsError::CharacteristicMissing(NOTIFICATION_SOURCE))?;
+    let data_source = data_source.ok_or(AncsError::CharacteristicMissing(DATA_SOURCE))?;
+    let control_point = control_point.ok_or(AncsError::CharacteristicMissing(CONTROL_POINT))?;
+
+    Ok(AncsCharacteristics {
+        notification_source,
+        data_source,
+        control_point,
+    })
+}
+
+/// Sends a command to the control point, the phone answers on the data source.
+pub async fn write_control_point(
+    control_point: &Characteristic,
+    data: &[u8],
+) -> Result<(), AncsError> {
+    control_point
+        .write_ext(
+            data,
+            &CharacteristicWriteRequest {
+                op_type: WriteOp::Request,
+                ..Default::default()
+            },
+        )
+        .await
+        .map_err(|e| AncsError::write_failed(e.to_string()))
+}
+
+/// What the device offers over ANCS, logged on every connection to help diagnose phones that
+/// connect but never send anything.
+pub struct CapabilityReport {
+    paired: bool,
+    trusted: bool,
+    characteristics: Vec<CharacteristicReport>,
+}
+
+struct CharacteristicReport {
+    name: &'static str,
+    flags: Option<CharacteristicFlags>,
+    mtu: Option<usize>,
+    /// Whether subscribing to notifications worked, if tried.
+    subscribed: Option<bool>,
+}
+
+impl CapabilityReport {
+    pub async fn new(device: &Device, ancs: &AncsCharacteristics) -> Self {
+        let mut characteristics = vec![];
+        for (name, c) in [
+            (NOTIFICATION_SOURCE, &ancs.notification_source),
+            (DATA_SOURCE, &ancs.data_source),
+            (CONTROL_POINT, &ancs.control_point),
+        ] {
+            characteristics.push(CharacteristicReport {
+                name,
+                flags: c.flags().await.ok(),
+                mtu: c.mtu().await.ok(),
+                subscribed: None,
+            });
+        }
+
+        Self {
+            paired: device.is_paired().await.unwrap_or_default(),
+            trusted: device.is_trusted().await.unwrap_or_default(),
+            characteristics,
+        }
+    }
+
+    pub fn set_subscribed(&mut self, name: &str, subscribed: bool) {
+        if let Some(c) = self.characteristics.iter_mut().find(|c| c.name == name) {
+            c.subscribed = Some(subscribed);
+        }
+    }
+
+    /// ANCS only allows access over an encrypted link, so a working subscription proves one.
+    fn encrypted(&self) -> Option<bool> {
+        let results: Vec<bool> = self
+            .characteristics
+            .iter()
+            .filter_map(|c| c.subscribed)
+            .collect();
+        if results.is_empty() {
+            None
+        } else {
+            Some(results.contains(&true))
+        }
+    }
+}
+
+impl fmt::Display for CapabilityReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let encrypted = match self.encrypted() {
+            Some(true) => "yes",
+            Some(false) => "no",
+            None => "unknown",
+        };
+        write!(
+            f,
+            "ANCS capabilities: paired {}, trusted {}, encrypted {}",
+            yes_no(self.paired),
+            yes_no(self.trusted),
+            encrypted
+        )?;
+
+        for c in &self.characteristics {
+            let flags = match &c.flags {
+                Some(flags) => flag_names(flags).join(" "),
+                None => "?".to_string(),
+            };
+            let mtu = c.mtu.map_or("?".to_string(), |m| m.to_string());
+            write!(f, "\n  {}: [{}], MTU {}", c.name, flags, mtu)?;
+            if let Some(subscribed) = c.subscribed {
+                let result = if subscribed { "ok" } else { "failed" };
+                write!(f, ", notify {}", result)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn yes_no(value: bool) -> &'static str {
+    if value {
+        "yes"
+    } else {
+        "no"
+    }
+}
+
+/// The flags that matter for ANCS, in the style BlueZ reports them.
+fn flag_names(flags: &CharacteristicFlags) -> Vec<&'static str> {
+    [
+        (flags.read, "read"),
+        (flags.write, "write"),
+        (flags.write_without_response, "write-without-response"),
+        (flags.notify, "notify"),
+        (flags.indicate, "indicate"),
+        (flags.extended_properties, "extended-properties"),
+        (flags.encrypt_read, "encrypt-read"),
+        (flags.encrypt_write, "encrypt-write"),
+        (
+            flags.encrypt_authenticated_read,
+            "encrypt-authenticated-read",
+        ),
+        (
+            flags.encrypt_authenticated_write,
+            "encrypt-authenticated-write",
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(set, name)| set.then_some(name))
+    .collect()
+}
+
+/// Company identifier of Apple in manufacturer data and device ids.
+const APPLE: u16 = 0x004C;
+
+/// A device seen while scanning that may offer notifications.
+pub struct Nearby {
+    pub address: Address,
+    pub name: Option<String>,
+    pub rssi: Option<i16>,
+    pub paired: bool,
+}
+
+/// Scans for a while, returning the devices that solicit ANCS and the paired Apple devices,
+/// strongest signal first.
+pub async fn scan(adapter: &Adapter, duration: Duration) -> Result<Vec<Nearby>> {
+    let ancs_uuid: Uuid = ANCS_UUID.parse()?;
+    let mut seen = vec![];
+    {
+        // Known devices come first, then those newly found
+        let events = adapter.discover_devices().await?;
+        pin_mut!(events);
+        let collect = async {
+            while let Some(event) = events.next().await {
+                if let AdapterEvent::DeviceAdded(address) = event {
+                    if !seen.contains(&address) {
+                        seen.push(address);
+                    }
+                }
+            }
+        };
+        let _ = tokio::time::timeout(duration, collect).await;
+    }
+
+    let mut found = vec![];
+    for address in seen {
+        let Ok(device) = adapter.device(address) else {
+            continue;
+        };
+        let solicits_ancs = device
+            .uuids()
+            .await
+            .ok()
+            .flatten()
+            .is_some_and(|uuids| uuids.contains(&ancs_uuid));
+        let apple = device
+            .manufacturer_data()
+            .await
+            .ok()
+            .flatten()
+            .is_some_and(|data| data.contains_key(&APPLE))
+            || device
+                .modalias()
+                .await
+                .ok()
+                .flatten()
+                .is_some_and(|m| m.vendor == APPLE as u32);
+        let paired = device.is_paired().await.unwrap_or_default();
+        if !(solicits_ancs || paired && apple) {
+            continue;
+        }
+        found.push(Nearby {
+            address,
+            name: device.alias().await.ok(),
+            rssi: device.rssi().await.ok().flatten(),
+            paired,
+        });
+    }
+    found.sort_by_key(|n| std::cmp::Reverse(n.rssi.unwrap_or(i16::MIN)));
+    Ok(found)
+}
+
+/// Finds a device by its name or alias, among those BlueZ knows or else by scanning for it.
+/// Paired devices win over others of the same name.
+pub async fn find_by_name(adapter: &Adapter, name: &str) -> Result<Address> {
+    let mut paired = vec![];
+    let mut others = vec![];
+    for address in adapter.device_addresses().await? {
+        let device = adapter.device(address)?;
+        if !has_name(&device, name).await {
+            continue;
+        }
+        if device.is_paired().await.unwrap_or_default() {
+            paired.push(address);
+        } else {
+            others.push(address);
+        }
+    }
+    let found = if paired.is_empty() { others } else { paired };
+    match found[..] {
+        [address] => return Ok(address),
+        [] => {}
+        _ => {
+            let found: Vec<String> = found.iter().map(|a| a.to_string()).collect();
+            bail!(
+                "Several devices are named {:?} ({}), give the address instead",
+                name,
+                found.join(", ")
+            );
+        }
+    }
+
+    log::info!("No known device is named {:?}, scanning for it", name);
+    let events = adapter.discover_devices().await?;
+    pin_mut!(events);
+    let scan = async {
+        while let Some(event) = events.next().await {
+            let AdapterEvent::DeviceAdded(address) = event else {
+                continue;
+            };
+            if let Ok(device) = adapter.device(address) {
+                if has_name(&device, name).await {
+                    return Some(address);
+                }
+            }
+        }
+        None
+    };
+    match tokio::time::timeout(SCAN_TIMEOUT, scan).await {
+        Ok(Some(address)) => Ok(address),
+        _ => bail!(
+            "No device named {:?} found, check `bluetoothctl devices` for its name",
+            name
+        ),
+    }
+}
+
+async fn has_name(device: &Device, name: &str) -> bool {
+    let alias = device.alias().await.ok();
+    let device_name = device.name().await.ok().flatten();
+    [alias, device_name]
+        .iter()
+        .flatten()
+        .any(|n| same_name(n, name))
+}
+
+/// Names match regardless of case and of the typographic apostrophe iOS puts in them.
+fn same_name(a: &str, b: &str) -> bool {
+    let normalize = |s: &str| s.trim().replace('\u{2019}', "'").to_lowercase();
+    normalize(a) == normalize(b)
+}
+
+/// Advertises that this computer wants ANCS, so that a paired iPhone nearby connects to it on
+/// its own. Advertising stops when the handle is dropped.
+pub async fn advertise(adapter: &Adapter) -> Result<AdvertisementHandle> {
+    let name = adapter.alias().await?;
+    let handle = adapter
+        .advertise(Advertisement {
+            solicit_uuids: [ANCS_UUID.parse()?].into(),
+            discoverable: Some(true),
+            local_name: Some(name.clone()),
+            ..Default::default()
+        })
+        .await?;
+    log::info!("Advertising as {:?} for the phone to connect", name);
+    Ok(handle)
+}
+
+/// Waits for a paired device to connect, e.g. an iPhone that saw the advertisement.
+pub async fn incoming(adapter: &Adapter) -> Result<Address> {
+    let mut adapter_events = adapter.events().await?;
+    let mut device_events = SelectAll::new();
+    for addr in adapter.device_addresses().await? {
+        let device = adapter.device(addr)?;
+        if device.is_paired().await? && device.is_connected().await? {
+            return Ok(addr);
+        }
+        device_events.push(
+            device
+                .events()
+                .await?
+                .map(move |event| (addr, event))
+                .boxed(),
+        );
+    }
+
+    log::info!("Waiting for a paired phone to connect");
+    loop {
+        tokio::select! {
+            Some(event) = adapter_events.next() => {
+                if let AdapterEvent::DeviceAdded(addr) = event {
+                    let events = adapter.device(addr)?.events().await?;
+                    device_events.push(events.map(move |event| (addr, event)).boxed());
+                }
+            }
+            Some((addr, event)) = device_events.next() => {
+                if matches!(event, DeviceEvent::PropertyChanged(DeviceProperty::Connected(true)))
+                    && adapter.device(addr)?.is_paired().await?
+                {
+                    return Ok(addr);
+                }
+            }
+            else => bail!("BlueZ stopped sending events"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_generic_services_look_stale() {
+        let battery = Uuid::from_u128(0x0000180f_0000_1000_8000_00805f9b34fb);
+        assert!(looks_stale(&[]));
+        assert!(looks_stale(&GENERIC_SERVICES));
+        assert!(!looks_stale(&[GENERIC_SERVICES[0], battery]));
+    }
+
+    #[test]
+    fn matches_names_as_typed() {
+        assert!(same_name("Alice\u{2019}s iPhone", "alice's iphone"));
+        assert!(same_name("iPhone", " iPhone"));
+        assert!(!same_name("Alice's iPhone", "Alice's iPad"));
+    }
+}
@@ -0,0 +1,56 @@
+use bluer::Address;
+
+/// What went wrong talking to ANCS, telling failures worth retrying from those that need
+/// something to change first.
+#[derive(Debug, thiserror::Error)]
+pub enum AncsError {
+    #[error("The device {0} is not connected")]
+    NotConnected(Address),
+    #[error("The services of the device are still not resolved after {0} seconds")]
+    ServicesUnresolved(u64),
+    /// With `stale_cache` if BlueZ most likely missed ANCS, rather than the phone not offering
+    /// it to this computer.
+    #[error("{message}")]
+    ServiceNotFound { stale_cache: bool, message: String },
+    /// ANCS without one of its characteristics, by name.
+    #[error("The {0} of ANCS not found")]
+    CharacteristicMissing(&'static str),
+    /// Usually because the link is not encrypted, i.e. the phone is not paired.
+    #[error("Failed to subscribe to the {characteristic}")]
+    SubscribeFailed {
+        characteristic: &'static str,
+        #[source]
+        source: bluer::Error,
+    },
+    /// The phone refused a command, with the ATT error it answered if any.
+    #[error("Failed to write to the control point: {message}")]
+    WriteFailed {
+        att_error: Option<u8>,
+        message: String,
+    },
+    /// The phone sent something that does not decode.
+    #[error("{0}")]
+    ParseError(String),
+    #[error(transparent)]
+    Bluetooth(#[from] bluer::Error),
+}
+
+impl AncsError {
+    pub(crate) fn write_failed(message: String) -> Self {
+        Self::WriteFailed {
+            att_error: crate::protocol::att_error(&message),
+            message,
+        }
+    }
+
+    /// Whether trying again as is may work. Otherwise the phone has to connect anew or be paired
+    /// again, or it is a bug that trying again only repeats.
+    pub fn is_transient(&self) -> bool {
+        !matches!(
+            self,
+            AncsError::ServiceNotFound { .. }
+                | AncsError::CharacteristicMissing(_)
+                | AncsError::ParseError(_)
+        )
+    }
+}
@@ -0,0 +1,145 @@
+use std::{process::Stdio, time::Duration};
+
+use anyhow::{bail, Context, Result};
+use tokio::{io::AsyncWriteExt as _, process::Command, sync::mpsc};
+
+use crate::{
+    privacy,
+    sinks::{AncsNotification, Kind, NotificationSink},
+};
+
+/// Beyond this, the command is killed.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Runs a command for every event with it as JSON on stdin and in `ANCS_*` variables, one at a
+/// time and in order. Like on stdout, the text is left out in private mode.
+pub struct ExecSink {
+    tx: mpsc::UnboundedSender<AncsNotification>,
+}
+
+impl ExecSink {
+    pub fn start(command: &[String]) -> Self {
+        let (tx, mut rx) = mpsc::unbounded_channel::<AncsNotification>();
+        let command = command.to_vec();
+        tokio::spawn(async move {
+            while let Some(notification) = rx.recv().await {
+                if let Err(e) = run(&command, &notification).await {
+                    log::warn!(
+                        "`{}` failed for notification {}: {:#}",
+                        command.join(" "),
+                        notification.uid,
+                        e
+                    );
+                }
+            }
+        });
+        Self { tx }
+    }
+}
+
+impl NotificationSink for ExecSink {
+    fn name(&self) -> &str {
+        "exec"
+    }
+
+    fn notify(&self, notification: &AncsNotification) {
+        let notification = if privacy::is_private_mode() {
+            notification.without_text()
+        } else {
+            notification.clone()
+        };
+        let _ = self.tx.send(notification);
+    }
+}
+
+async fn run(command: &[String], notification: &AncsNotification) -> Result<()> {
+    let Some((program, args)) = command.split_first() else {
+        return Ok(());
+    };
+    let input = serde_json::to_vec(notification)?;
+    let mut child = Command::new(program)
+        .args(args)
+        .envs(variables(notification))
+        .stdin(Stdio::piped())
+        // Kept off stdout, which `--output json` writes to
+        .stdout(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("Failed to run {}", program))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // Commands that do not read it are fine
+        let _ = stdin.write_all(&input).await;
+    }
+    let status = tokio::time::timeout(TIMEOUT, child.wait())
+        .await
+        .context("Timed out")??;
+    if !status.success() {
+        bail!("Exited with {}", status);
+    }
+    Ok(())
+}
+
+/// `ANCS_EVENT`, `ANCS_APP_ID`, `ANCS_TITLE`, `ANCS_MESSAGE` and the like, set to an empty
+/// value for what is not known.
+fn variables(notification: &AncsNotification) -> Vec<(&'static str, String)> {
+    let event = match notification.event {
+        Kind::Received => "received",
+        Kind::Modified => "modified",
+        Kind::Removed => "removed",
+    };
+    let text = |s: &Option<String>| s.as_deref().unwrap_or_default().replace('\0', "");
+    vec![
+        ("ANCS_EVENT", event.to_string()),
+        ("ANCS_DEVICE", notification.device.clone()),
+        ("ANCS_UID", notification.uid.to_string()),
+        ("ANCS_APP_ID", text(&notification.app_id)),
+        ("ANCS_APP_NAME", text(&notification.app_name)),
+        ("ANCS_TITLE", text(&notification.title)),
+        ("ANCS_SUBTITLE", text(&notification.subtitle)),
+        ("ANCS_MESSAGE", text(&notification.message)),
+        // Named as in the JSON, e.g. `incoming_call`
+        (
+            "ANCS_CATEGORY",
+            notification
+                .category
+                .and_then(|category| serde_json::to_value(category).ok())
+                .and_then(|value| value.as_str().map(str::to_string))
+                .unwrap_or_default(),
+        ),
+        (
+            "ANCS_IMPORTANT",
+            if notification.important { "1" } else { "0" }.to_string(),
+        ),
+        ("ANCS_TIME", notification.time.to_rfc3339()),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn passes_the_event_on_stdin() {
+        let notification = AncsNotification {
+            event: Kind::Received,
+            device: String::new(),
+            uid: 7,
+            app_id: Some("com.apple.MobileSMS".to_string()),
+            app_name: None,
+            title: Some("Alice".to_string()),
+            subtitle: None,
+            message: None,
+            category: None,
+            important: false,
+            time: "2024-05-06T18:30:00Z".parse().unwrap(),
+        };
+        let command = |script: &str| ["sh", "-c", script].map(str::to_string);
+        run(&command(r#"grep -q '"uid":7,'"#), &notification)
+            .await
+            .unwrap();
+        let script = r#"[ "$ANCS_EVENT $ANCS_APP_ID $ANCS_TITLE/$ANCS_MESSAGE" = "received com.apple.MobileSMS Alice/" ]"#;
+        run(&command(script), &notification).await.unwrap();
+        let e = run(&command("exit 3"), &notification).await.unwrap_err();
+        assert_eq!(e.to_string(), "Exited with exit status: 3");
+    }
+}
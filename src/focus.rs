@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Context, Result};
+use tokio::sync::watch;
+use wayland_client::{
+    backend::ObjectId,
+    event_created_child,
+    globals::{registry_queue_init, GlobalListContents},
+    protocol::wl_registry,
+    Dispatch, EventQueue, Proxy, QueueHandle,
+};
+use wayland_protocols_wlr::foreign_toplevel::v1::client::{
+    zwlr_foreign_toplevel_handle_v1::{self, ZwlrForeignToplevelHandleV1},
+    zwlr_foreign_toplevel_manager_v1::{self, ZwlrForeignToplevelManagerV1},
+};
+use x11rb::{
+    connection::Connection as _,
+    protocol::{
+        xproto::{AtomEnum, ChangeWindowAttributesAux, ConnectionExt as _, EventMask, Window},
+        Event,
+    },
+    rust_connection::RustConnection,
+};
+
+/// Which desktop app has the focus, to not mirror notifications the user is already looking at
+/// in its desktop client.
+///
+/// Follows the focused window through the wlroots foreign toplevel protocol on Wayland, or the
+/// active window of the window manager on X11. Other Wayland compositors offer neither.
+#[derive(Clone)]
+pub struct FocusMonitor {
+    /// App id (Wayland) or instance and class (X11) of the focused window.
+    focused: watch::Receiver<Vec<String>>,
+}
+
+impl FocusMonitor {
+    pub fn new() -> Result<Self> {
+        let (tx, focused) = watch::channel(vec![]);
+        if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            let (state, queue) = Toplevels::connect(tx)?;
+            std::thread::spawn(move || {
+                if let Err(e) = state.run(queue) {
+                    log::warn!("Stopped following the focused window: {:?}", e);
+                }
+            });
+        } else if std::env::var_os("DISPLAY").is_some() {
+            let window_manager = WindowManager::connect()?;
+            std::thread::spawn(move || {
+                if let Err(e) = window_manager.run(tx) {
+                    log::warn!("Stopped following the focused window: {:?}", e);
+                }
+            });
+        } else {
+            bail!("Neither Wayland nor X11 is running");
+        }
+
+        Ok(Self { focused })
+    }
+
+    /// Whether the focused window belongs to the desktop app of this name.
+    pub fn is_focused(&self, desktop_app: &str) -> bool {
+        self.focused
+            .borrow()
+            .iter()
+            .any(|name| name.eq_ignore_ascii_case(desktop_app))
+    }
+}
+
+struct Toplevels {
+    tx: watch::Sender<Vec<String>>,
+    /// App id and whether it is activated, by toplevel.
+    windows: HashMap<ObjectId, Toplevel>,
+}
+
+#[derive(Default, Clone)]
+struct Toplevel {
+    app_id: String,
+    activated: bool,
+}
+
+impl Toplevels {
+    fn connect(tx: watch::Sender<Vec<String>>) -> Result<(Self, EventQueue<Self>)> {
+        let conn = wayland_client::Connection::connect_to_env()?;
+        let (globals, queue) = registry_queue_init::<Self>(&conn)?;
+        globals
+            .bind::<ZwlrForeignToplevelManagerV1, _, _>(&queue.handle(), 1..=3, ())
+            .context("The compositor does not tell which window is focused")?;
+
+        let state = Self {
+            tx,
+            windows: HashMap::new(),
+        };
+        Ok((state, queue))
+    }
+
+    fn run(mut self, mut queue: EventQueue<Self>) -> Result<()> {
+        loop {
+            queue.blocking_dispatch(&mut self)?;
+        }
+    }
+
+    fn update(&self) {
+        let focused: Vec<String> = self
+            .windows
+            .values()
+            .filter(|w| w.activated)
+            .map(|w| w.app_id.clone())
+            .collect();
+        self.tx.send_if_modified(|f| {
+            let changed = *f != focused;
+            *f = focused;
+            changed
+        });
+    }
+}
+
+impl Dispatch<wl_registry::WlRegistry, GlobalListContents> for Toplevels {
+    fn event(
+        _: &mut Self,
+        _: &wl_registry::WlRegistry,
+        _: wl_registry::Event,
+        _: &GlobalListContents,
+        _: &wayland_client::Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<ZwlrForeignToplevelManagerV1, ()> for Toplevels {
+    fn event(
+        _: &mut Self,
+        _: &ZwlrForeignToplevelManagerV1,
+        event: zwlr_foreign_toplevel_manager_v1::Event,
+        _: &(),
+        _: &wayland_client::Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let zwlr_foreign_toplevel_manager_v1::Event::Finished = event {
+            log::warn!("The compositor stopped telling which window is focused");
+        }
+    }
+
+    event_created_child!(Toplevels, ZwlrForeignToplevelManagerV1, [
+        zwlr_foreign_toplevel_manager_v1::EVT_TOPLEVEL_OPCODE => (ZwlrForeignToplevelHandleV1, ()),
+    ]);
+}
+
+impl Dispatch<ZwlrForeignToplevelHandleV1, ()> for Toplevels {
+    fn event(
+        state: &mut Self,
+        handle: &ZwlrForeignToplevelHandleV1,
+        event: zwlr_foreign_toplevel_handle_v1::Event,
+        _: &(),
+        _: &wayland_client::Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        let window = state.windows.entry(handle.id()).or_default();
+        match event {
+            zwlr_foreign_toplevel_handle_v1::Event::AppId { app_id } => window.app_id = app_id,
+            zwlr_foreign_toplevel_handle_v1::Event::State { state: states } => {
+                let activated = zwlr_foreign_toplevel_handle_v1::State::Activated as u32;
+                window.activated = states
+                    .chunks_exact(4)
+                    .any(|s| u32::from_ne_bytes([s[0], s[1], s[2], s[3]]) == activated);
+            }
+            zwlr_foreign_toplevel_handle_v1::Event::Done => state.update(),
+            zwlr_foreign_toplevel_handle_v1::Event::Closed => {
+                state.windows.remove(&handle.id());
+                handle.destroy();
+                state.update();
+            }
+            _ => {}
+        }
+    }
+}
+
+struct WindowManager {
+    conn: RustConnection,
+    root: Window,
+    active_window: u32,
+}
+
+impl WindowManager {
+    fn connect() -> Result<Self> {
+        let (conn, screen) = x11rb::connect(None)?;
+        let root = conn.setup().roots[screen].root;
+        let active_window = conn
+            .intern_atom(false, b"_NET_ACTIVE_WINDOW")?
+            .reply()?
+            .atom;
+        conn.change_window_attributes(
+            root,
+            &ChangeWindowAttributesAux::new().event_mask(EventMask::PROPERTY_CHANGE),
+        )?
+        .check()?;
+
+        Ok(Self {
+            conn,
+            root,
+            active_window,
+        })
+    }
+
+    fn run(self, tx: watch::Sender<Vec<String>>) -> Result<()> {
+        loop {
+            let focused = self.focused_class()?;
+            tx.send_if_modified(|f| {
+                let changed = *f != focused;
+                *f = focused;
+                changed
+            });
+
+            loop {
+                let event = self.conn.wait_for_event()?;
+                if let Event::PropertyNotify(e) = event {
+                    if e.window == self.root && e.atom == self.active_window {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Instance and class name of the active window.
+    fn focused_class(&self) -> Result<Vec<String>> {
+        let window = self
+            .conn
+            .get_property(false, self.root, self.active_window, AtomEnum::WINDOW, 0, 1)?
+            .reply()?
+            .value32()
+            .and_then(|mut v| v.next())
+            .filter(|&w| w != 0);
+        let Some(window) = window else {
+            return Ok(vec![]);
+        };
+
+        // The window may be gone already
+        let Ok(class) = self
+            .conn
+            .get_property(false, window, AtomEnum::WM_CLASS, AtomEnum::STRING, 0, 1024)?
+            .reply()
+        else {
+            return Ok(vec![]);
+        };
+        Ok(class
+            .value
+            .split(|&b| b == 0)
+            .filter(|s| !s.is_empty())
+            .map(|s| String::from_utf8_lossy(s).into_owned())
+            .collect())
+    }
+}
@@ -0,0 +1,96 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::store::Slot;
+
+/// Desktop id of every notification shown, kept on disk so that the bubbles a previous run
+/// left behind can be closed or checked against the phone.
+pub struct Handles {
+    slot: Option<Slot>,
+    saved: Saved,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Saved {
+    /// Bus name of the notification server the ids belong to.
+    server: Option<String>,
+    ids: BTreeMap<u32, u32>,
+}
+
+impl Handles {
+    pub fn load(slot: Option<Slot>) -> Self {
+        let mut saved = Saved::default();
+        if let Some(slot) = &slot {
+            match slot.load() {
+                Ok(Some(content)) => match serde_json::from_str(&content) {
+                    Ok(s) => saved = s,
+                    Err(e) => log::warn!("Ignoring {}: {:?}", slot, e),
+                },
+                Ok(None) => {}
+                Err(e) => log::warn!("Failed to read {}: {:?}", slot, e),
+            }
+        }
+
+        Self { slot, saved }
+    }
+
+    /// Forgets the ids if they belong to another notification server than `server`, which
+    /// took their bubbles along when it went away.
+    pub fn check_server(&mut self, server: Option<&str>) {
+        if self.saved.server.as_deref() != server {
+            self.saved = Saved::default();
+        }
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        self.saved.ids.iter().map(|(&uid, &id)| (uid, id))
+    }
+
+    pub fn insert(&mut self, uid: u32, desktop_id: u32, server: Option<&str>) {
+        if let Some(server) = server {
+            if self.saved.server.as_deref() != Some(server) {
+                self.saved.ids.clear();
+                self.saved.server = Some(server.to_string());
+            }
+        }
+        self.saved.ids.insert(uid, desktop_id);
+    }
+
+    pub fn remove(&mut self, uid: u32) {
+        self.saved.ids.remove(&uid);
+    }
+
+    pub fn clear(&mut self) {
+        self.saved.ids.clear();
+    }
+
+    pub fn save(&self) -> Result<()> {
+        match &self.slot {
+            Some(slot) => slot.save(&serde_json::to_string(&self.saved)?),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_ids_of_the_same_server_only() {
+        let mut handles = Handles::load(None);
+        handles.insert(1, 10, Some(":1.20"));
+        handles.insert(2, 11, None);
+
+        handles.check_server(Some(":1.20"));
+        assert_eq!(handles.ids().collect::<Vec<_>>(), [(1, 10), (2, 11)]);
+
+        handles.insert(3, 1, Some(":1.42"));
+        assert_eq!(handles.ids().collect::<Vec<_>>(), [(3, 1)]);
+
+        handles.check_server(Some(":1.20"));
+        assert_eq!(handles.ids().count(), 0);
+    }
+}
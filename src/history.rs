@@ -0,0 +1,522 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::Write as _,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+
+use ancs_linux::control::HistoryEntry;
+
+use crate::{
+    config::{Config, HistoryConfig},
+    paths,
+    privacy::Redacted,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Acknowledgment {
+    /// The bubble was clicked or expired while the desktop session was in use.
+    Seen,
+    /// The bubble was shown while the session was locked or idle.
+    Missed,
+}
+
+impl Acknowledgment {
+    fn as_str(self) -> &'static str {
+        match self {
+            Acknowledgment::Seen => "seen",
+            Acknowledgment::Missed => "missed",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "seen" => Some(Acknowledgment::Seen),
+            "missed" => Some(Acknowledgment::Missed),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub uid: u32,
+    pub app_id: String,
+    pub title: Option<String>,
+    pub subtitle: Option<String>,
+    pub message: Option<String>,
+    pub received_at: DateTime<Utc>,
+    pub acknowledgment: Option<Acknowledgment>,
+    /// When it was shown while the desktop session was in use, for apps in
+    /// `history.seen_apps`, like a read receipt.
+    #[serde(default)]
+    pub seen_at: Option<DateTime<Utc>>,
+    /// When it was gone from the phone, dismissed there or from the desktop.
+    #[serde(default)]
+    pub removed_at: Option<DateTime<Utc>>,
+}
+
+impl Entry {
+    /// How it is sent to clients and listed, under its canonical app.
+    pub fn listed(&self, config: &Config) -> HistoryEntry {
+        (
+            self.received_at.to_rfc3339(),
+            config.canonical_app_id(&self.app_id).to_string(),
+            self.title.clone().unwrap_or_default(),
+            self.subtitle.clone().unwrap_or_default(),
+            self.message.clone().unwrap_or_default(),
+            self.acknowledgment == Some(Acknowledgment::Missed),
+            self.seen_at.map(|t| t.to_rfc3339()).unwrap_or_default(),
+            self.removed_at.map(|t| t.to_rfc3339()).unwrap_or_default(),
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Query {
+    pub missed_only: bool,
+    /// Only entries of these apps, if given.
+    pub app_ids: Option<Vec<String>>,
+    pub limit: Option<usize>,
+    /// Only entries received at or after this time.
+    pub since: Option<DateTime<Utc>>,
+    /// Only entries received before this time.
+    pub until: Option<DateTime<Utc>>,
+}
+
+pub struct History {
+    conn: Mutex<Connection>,
+    /// Locked after `conn`.
+    journal: Mutex<Journal>,
+}
+
+/// A change to the history, written to the journal before SQLite.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+enum Change {
+    Insert {
+        entry: Entry,
+    },
+    Acknowledge {
+        id: i64,
+        acknowledgment: Acknowledgment,
+    },
+    Seen {
+        id: i64,
+        seen_at: DateTime<Utc>,
+    },
+    Remove {
+        id: i64,
+        removed_at: DateTime<Utc>,
+    },
+}
+
+/// Changes not known to be in SQLite yet, one JSON object per line next to the database.
+///
+/// A change is synced to the journal before it is made in SQLite, and the journal is emptied
+/// once it is. Whatever is left in it after a crash or power loss is made again on startup.
+struct Journal {
+    path: PathBuf,
+    file: Option<File>,
+}
+
+impl Journal {
+    fn changes(&self) -> Vec<Change> {
+        let content = match std::fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return vec![],
+            Err(e) => {
+                log::warn!("Failed to read {}: {:?}", self.path.display(), e);
+                return vec![];
+            }
+        };
+        content
+            .lines()
+            .filter_map(|line| match serde_json::from_str(line) {
+                Ok(change) => Some(change),
+                // Most likely the last line, cut off while writing it
+                Err(e) => {
+                    log::warn!("Skipping line in {}: {:?}", self.path.display(), e);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    fn append(&mut self, change: &Change) -> Result<()> {
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => self.file.insert(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)
+                    .with_context(|| format!("Failed to open {}", self.path.display()))?,
+            ),
+        };
+        let mut line = serde_json::to_string(change)?;
+        line.push('\n');
+        file.write_all(line.as_bytes())?;
+        file.sync_data()?;
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        match &mut self.file {
+            Some(file) => file.set_len(0)?,
+            None => match std::fs::remove_file(&self.path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            },
+        }
+        Ok(())
+    }
+}
+
+impl History {
+    pub fn open(config: &HistoryConfig) -> Result<Self> {
+        let path = match &config.path {
+            Some(p) => p.clone(),
+            None => match paths::data_dir() {
+                Some(dir) => dir.join("history.db"),
+                None => bail!("Cannot determine the history location, please set history.path"),
+            },
+        };
+
+        Self::open_path(&path)
+    }
+
+    fn open_path(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+
+        let conn = Connection::open(path)
+            .with_context(|| format!("Failed to open history at {}", path.display()))?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS notifications (
+                id INTEGER PRIMARY KEY,
+                uid INTEGER NOT NULL,
+                app_id TEXT NOT NULL,
+                title TEXT,
+                subtitle TEXT,
+                message TEXT,
+                received_at INTEGER NOT NULL,
+                acknowledgment TEXT
+            );",
+        )?;
+        // Added later, so missing from histories that are older
+        for column in ["seen_at", "removed_at"] {
+            let has_column: bool = conn.query_row(
+                "SELECT EXISTS (SELECT 1 FROM pragma_table_info('notifications')
+                    WHERE name = ?1)",
+                [column],
+                |row| row.get(0),
+            )?;
+            if !has_column {
+                conn.execute_batch(&format!(
+                    "ALTER TABLE notifications ADD COLUMN {} INTEGER;",
+                    column
+                ))?;
+            }
+        }
+
+        let mut journal = Journal {
+            path: path.with_extension("journal"),
+            file: None,
+        };
+        replay(&conn, &mut journal)?;
+
+        Ok(Self {
+            conn: Mutex::new(conn),
+            journal: Mutex::new(journal),
+        })
+    }
+
+    pub fn insert(&self, entry: &Redacted) -> Result<i64> {
+        let entry = entry.entry();
+        let conn = self.conn.lock().unwrap();
+        let mut journal = self.journal.lock().unwrap();
+        journal.append(&Change::Insert {
+            entry: entry.clone(),
+        })?;
+        insert(&conn, entry)?;
+        journal.clear()?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    pub fn set_acknowledgment(&self, id: i64, acknowledgment: Acknowledgment) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        let mut journal = self.journal.lock().unwrap();
+        journal.append(&Change::Acknowledge { id, acknowledgment })?;
+        acknowledge(&conn, id, acknowledgment)?;
+        journal.clear()?;
+        Ok(())
+    }
+
+    /// Records when a notification was first seen, see [`Entry::seen_at`].
+    pub fn set_seen(&self, id: i64, seen_at: DateTime<Utc>) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        let mut journal = self.journal.lock().unwrap();
+        journal.append(&Change::Seen { id, seen_at })?;
+        seen(&conn, id, seen_at)?;
+        journal.clear()?;
+        Ok(())
+    }
+
+    /// Records when a notification was gone from the phone, see [`Entry::removed_at`].
+    pub fn set_removed(&self, id: i64, removed_at: DateTime<Utc>) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        let mut journal = self.journal.lock().unwrap();
+        journal.append(&Change::Remove { id, removed_at })?;
+        remove(&conn, id, removed_at)?;
+        journal.clear()?;
+        Ok(())
+    }
+
+    /// Adds entries from another history, e.g. on another machine, skipping those it has
+    /// already. Returns how many were added.
+    pub fn import(&self, entries: &[Entry]) -> Result<usize> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let mut added = 0;
+        for entry in entries {
+            if exists(&tx, entry)? {
+                continue;
+            }
+            insert(&tx, entry)?;
+            added += 1;
+        }
+        tx.commit()?;
+        Ok(added)
+    }
+
+    /// Returns matching entries, most recent first.
+    pub fn query(&self, query: &Query) -> Result<Vec<Entry>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT uid, app_id, title, subtitle, message, received_at, acknowledgment, seen_at,
+                    removed_at
+                FROM notifications
+                WHERE (?1 = 0 OR acknowledgment = 'missed')
+                    AND (?3 IS NULL OR received_at >= ?3)
+                    AND (?4 IS NULL OR received_at < ?4)
+                    AND (?5 IS NULL OR app_id IN (SELECT value FROM json_each(?5)))
+                ORDER BY received_at DESC, id DESC
+                LIMIT ?2",
+        )?;
+        let limit = query.limit.map_or(-1, |l| l as i64);
+        let since = query.since.map(|t| t.timestamp());
+        let until = query.until.map(|t| t.timestamp());
+        let app_ids = query
+            .app_ids
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()?;
+
+        let entries = stmt
+            .query_map(
+                params![query.missed_only, limit, since, until, app_ids],
+                |row| {
+                    let received_at: i64 = row.get(5)?;
+                    let acknowledgment: Option<String> = row.get(6)?;
+                    let seen_at: Option<i64> = row.get(7)?;
+                    let removed_at: Option<i64> = row.get(8)?;
+                    Ok(Entry {
+                        uid: row.get(0)?,
+                        app_id: row.get(1)?,
+                        title: row.get(2)?,
+                        subtitle: row.get(3)?,
+                        message: row.get(4)?,
+                        received_at: DateTime::from_timestamp(received_at, 0).unwrap_or_default(),
+                        acknowledgment: acknowledgment.as_deref().and_then(Acknowledgment::parse),
+                        seen_at: seen_at.and_then(|t| DateTime::from_timestamp(t, 0)),
+                        removed_at: removed_at.and_then(|t| DateTime::from_timestamp(t, 0)),
+                    })
+                },
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(entries)
+    }
+}
+
+/// Makes the changes left in the journal, skipping entries that made it into SQLite before.
+fn replay(conn: &Connection, journal: &mut Journal) -> Result<()> {
+    let changes = journal.changes();
+    if !changes.is_empty() {
+        log::info!("Replaying {} changes to the history", changes.len());
+        let tx = conn.unchecked_transaction()?;
+        for change in &changes {
+            match change {
+                Change::Insert { entry } => {
+                    if !exists(&tx, entry)? {
+                        insert(&tx, entry)?;
+                    }
+                }
+                Change::Acknowledge { id, acknowledgment } => {
+                    acknowledge(&tx, *id, *acknowledgment)?
+                }
+                Change::Seen { id, seen_at } => seen(&tx, *id, *seen_at)?,
+                Change::Remove { id, removed_at } => remove(&tx, *id, *removed_at)?,
+            }
+        }
+        tx.commit()?;
+    }
+    journal.clear()
+}
+
+fn exists(conn: &Connection, entry: &Entry) -> Result<bool> {
+    Ok(conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM notifications
+            WHERE uid = ?1 AND app_id = ?2 AND received_at = ?3 AND title IS ?4)",
+        params![
+            entry.uid,
+            entry.app_id,
+            entry.received_at.timestamp(),
+            entry.title
+        ],
+        |row| row.get(0),
+    )?)
+}
+
+fn insert(conn: &Connection, entry: &Entry) -> Result<()> {
+    conn.execute(
+        "INSERT INTO notifications
+            (uid, app_id, title, subtitle, message, received_at, acknowledgment, seen_at,
+                removed_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        params![
+            entry.uid,
+            entry.app_id,
+            entry.title,
+            entry.subtitle,
+            entry.message,
+            entry.received_at.timestamp(),
+            entry.acknowledgment.map(Acknowledgment::as_str),
+            entry.seen_at.map(|t| t.timestamp()),
+            entry.removed_at.map(|t| t.timestamp()),
+        ],
+    )?;
+    Ok(())
+}
+
+fn acknowledge(conn: &Connection, id: i64, acknowledgment: Acknowledgment) -> Result<()> {
+    conn.execute(
+        "UPDATE notifications SET acknowledgment = ?1 WHERE id = ?2",
+        params![acknowledgment.as_str(), id],
+    )?;
+    Ok(())
+}
+
+fn seen(conn: &Connection, id: i64, seen_at: DateTime<Utc>) -> Result<()> {
+    conn.execute(
+        "UPDATE notifications SET seen_at = ?1 WHERE id = ?2 AND seen_at IS NULL",
+        params![seen_at.timestamp(), id],
+    )?;
+    Ok(())
+}
+
+fn remove(conn: &Connection, id: i64, removed_at: DateTime<Utc>) -> Result<()> {
+    conn.execute(
+        "UPDATE notifications SET removed_at = ?1 WHERE id = ?2 AND removed_at IS NULL",
+        params![removed_at.timestamp(), id],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replays_what_the_journal_has_left() {
+        let dir = std::env::temp_dir().join(format!("ancs-linux-history-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("history.db");
+        let entry = Entry {
+            uid: 3,
+            app_id: "com.apple.MobileSMS".to_string(),
+            title: Some("Bob".to_string()),
+            subtitle: None,
+            message: Some("Running late".to_string()),
+            received_at: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            acknowledgment: None,
+            seen_at: None,
+            removed_at: None,
+        };
+        let insert = serde_json::to_string(&Change::Insert {
+            entry: entry.clone(),
+        })
+        .unwrap();
+        // Once in SQLite already, then left in the journal, followed by a line cut off
+        History::open_path(&path)
+            .unwrap()
+            .import(std::slice::from_ref(&entry))
+            .unwrap();
+        std::fs::write(
+            path.with_extension("journal"),
+            format!("{}\n{}\n{{\"change\":\"ins", insert, insert),
+        )
+        .unwrap();
+
+        let history = History::open_path(&path).unwrap();
+        assert_eq!(history.query(&Query::default()).unwrap(), [entry]);
+        assert_eq!(
+            std::fs::read_to_string(path.with_extension("journal")).ok(),
+            None
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn records_seen_and_removed_in_older_histories() {
+        let dir = std::env::temp_dir().join(format!("ancs-linux-seen-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("history.db");
+        Connection::open(&path)
+            .unwrap()
+            .execute_batch(
+                "CREATE TABLE notifications (
+                    id INTEGER PRIMARY KEY,
+                    uid INTEGER NOT NULL,
+                    app_id TEXT NOT NULL,
+                    title TEXT,
+                    subtitle TEXT,
+                    message TEXT,
+                    received_at INTEGER NOT NULL,
+                    acknowledgment TEXT
+                );
+                INSERT INTO notifications (uid, app_id, title, received_at)
+                    VALUES (3, 'com.apple.MobileSMS', 'Bob', 1700000000);",
+            )
+            .unwrap();
+
+        let history = History::open_path(&path).unwrap();
+        let seen_at = DateTime::from_timestamp(1_700_000_060, 0).unwrap();
+        history.set_seen(1, seen_at).unwrap();
+        history
+            .set_seen(1, DateTime::from_timestamp(1_700_000_600, 0).unwrap())
+            .unwrap();
+        let removed_at = DateTime::from_timestamp(1_700_000_900, 0).unwrap();
+        history.set_removed(1, removed_at).unwrap();
+        let entries = history.query(&Query::default()).unwrap();
+        assert_eq!(entries[0].seen_at, Some(seen_at));
+        assert_eq!(entries[0].removed_at, Some(removed_at));
+
+        let of = |app_id: &str| Query {
+            app_ids: Some(vec![app_id.to_string()]),
+            ..Default::default()
+        };
+        assert_eq!(history.query(&of("com.apple.MobileSMS")).unwrap().len(), 1);
+        assert_eq!(history.query(&of("com.apple.mobilemail")).unwrap(), []);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
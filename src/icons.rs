@@ -0,0 +1,109 @@
+use std::{collections::HashMap, path::Path, sync::Mutex};
+
+use crate::{config::Config, paths};
+
+/// Apps of the phone with a counterpart among the freedesktop standard icons, which every icon
+/// theme has.
+const STANDARD: &[(&str, &str)] = &[
+    ("com.apple.MobileSMS", "internet-chat"),
+    ("com.apple.mobilemail", "internet-mail"),
+    ("com.apple.mobilecal", "x-office-calendar"),
+    ("com.apple.mobilephone", "call-start"),
+    ("com.apple.facetime", "camera-web"),
+    ("com.apple.reminders", "appointment-soon"),
+    ("com.apple.Music", "audio-x-generic"),
+    ("com.apple.Maps", "mark-location"),
+];
+
+/// Apps with a desktop client, by the icon it installs, used only where it is installed.
+const CLIENTS: &[(&str, &str)] = &[
+    ("net.whatsapp.WhatsApp", "whatsapp"),
+    ("ph.telegra.Telegraph", "telegram"),
+    ("org.whispersystems.signal", "signal-desktop"),
+    ("com.tinyspeck.chatlyio", "slack"),
+    ("com.hammerandchisel.discord", "discord"),
+    ("com.spotify.client", "spotify-client"),
+    ("com.burbn.instagram", "instagram"),
+    ("com.atebits.Tweetie2", "twitter"),
+];
+
+/// Whether each icon looked for is installed, as that does not change while running.
+static INSTALLED: Mutex<Option<HashMap<&'static str, bool>>> = Mutex::new(None);
+
+/// Icon of an app: `icon` in its section of the config, or one of those above. None leaves it
+/// to the category.
+pub fn for_app<'a>(config: &'a Config, app_id: &str) -> Option<&'a str> {
+    if let Some(icon) = config
+        .app_sections(app_id)
+        .find_map(|app| app.icon.as_deref())
+    {
+        return Some(icon);
+    }
+    let app_id = config.canonical_app_id(app_id);
+    let find = |icons: &[(&str, &'static str)]| {
+        icons
+            .iter()
+            .find(|(id, _)| *id == app_id)
+            .map(|(_, icon)| *icon)
+    };
+    find(STANDARD).or_else(|| find(CLIENTS).filter(|icon| is_installed(icon)))
+}
+
+fn is_installed(icon: &'static str) -> bool {
+    let mut installed = INSTALLED.lock().unwrap();
+    *installed
+        .get_or_insert_with(HashMap::new)
+        .entry(icon)
+        .or_insert_with(|| {
+            paths::shared_data_dirs()
+                .iter()
+                .any(|dir| has_icon(dir, icon))
+        })
+}
+
+/// Whether an icon is in the fallback theme or the pixmaps of a data directory, where desktop
+/// clients install theirs.
+fn has_icon(dir: &Path, icon: &str) -> bool {
+    let files = ["svg", "png", "xpm"].map(|ext| format!("{}.{}", icon, ext));
+    if files
+        .iter()
+        .any(|file| dir.join("pixmaps").join(file).exists())
+    {
+        return true;
+    }
+    let Ok(sizes) = std::fs::read_dir(dir.join("icons").join("hicolor")) else {
+        return false;
+    };
+    sizes.flatten().any(|size| {
+        let apps = size.path().join("apps");
+        files.iter().any(|file| apps.join(file).exists())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn looks_in_the_fallback_theme() {
+        let dir = std::env::temp_dir().join(format!("ancs-linux-icons-{}", std::process::id()));
+        let apps = dir.join("icons/hicolor/48x48/apps");
+        std::fs::create_dir_all(&apps).unwrap();
+        std::fs::write(apps.join("slack.png"), b"").unwrap();
+        assert!(has_icon(&dir, "slack"));
+        assert!(!has_icon(&dir, "discord"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let config: Config =
+            toml::from_str("[apps.\"com.apple.MobileSMS\"]\nicon = \"org.gnome.Chat\"").unwrap();
+        assert_eq!(
+            for_app(&config, "com.apple.MobileSMS"),
+            Some("org.gnome.Chat")
+        );
+        assert_eq!(
+            for_app(&config, "com.apple.mobilemail"),
+            Some("internet-mail")
+        );
+        assert_eq!(for_app(&config, "com.example.unknown"), None);
+    }
+}
@@ -0,0 +1,193 @@
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{bail, Context, Result};
+use bluer::Address;
+
+use clap::ValueEnum as _;
+
+use crate::{config::Config, paths, preset::Preset};
+
+const UNIT: &str = "ancs-linux.service";
+/// First line of the units written here, so that a unit written by hand is left alone.
+const MARKER: &str = "# Written by `ancs-linux install-service`, changes are lost when run again";
+
+/// Where the user unit goes, `$XDG_CONFIG_HOME/systemd/user/ancs-linux.service`.
+fn unit_path() -> Result<PathBuf> {
+    match paths::config_dir() {
+        Some(dir) => Ok(dir.join("systemd").join("user").join(UNIT)),
+        None => bail!("Cannot determine where user units go, set XDG_CONFIG_HOME or HOME"),
+    }
+}
+
+/// Whether a unit is there that was not written here.
+fn written_by_hand(path: &Path) -> Result<bool> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => Ok(!content.starts_with(MARKER)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+fn systemctl(args: &[&str]) -> Result<()> {
+    let status = Command::new("systemctl")
+        .arg("--user")
+        .args(args)
+        .status()
+        .context("Failed to run systemctl")?;
+    if !status.success() {
+        bail!(
+            "`systemctl --user {}` failed with {}",
+            args.join(" "),
+            status
+        );
+    }
+    Ok(())
+}
+
+/// Writes a user unit running `listen` with `devices`, `adapter` and `preset`, or the devices
+/// of the config if none are given, and enables it, also starting it if `start` is set.
+pub fn install(
+    config: &Config,
+    config_path: Option<&Path>,
+    devices: &[Address],
+    adapter: Option<&str>,
+    preset: Option<Preset>,
+    start: bool,
+) -> Result<()> {
+    if devices.is_empty() && config.devices.is_empty() {
+        bail!("Give the address of the phone, or list it in `devices` in the config");
+    }
+    let path = unit_path()?;
+    if written_by_hand(&path)? {
+        bail!(
+            "{} was not written by `ancs-linux install-service`, remove it first",
+            path.display()
+        );
+    }
+
+    let exe = std::env::current_exe().context("Cannot tell where ancs-linux is")?;
+    let config_path = config_path
+        .map(std::path::absolute)
+        .transpose()
+        .context("Cannot tell where the config is")?;
+    let mut args = Vec::new();
+    if let Some(config_path) = &config_path {
+        args.push("--config".to_string());
+        args.push(config_path.to_string_lossy().into_owned());
+    }
+    args.push("listen".to_string());
+    if let Some(adapter) = adapter {
+        args.push("--adapter".to_string());
+        args.push(adapter.to_string());
+    }
+    if let Some(preset) = preset.and_then(|p| p.to_possible_value()) {
+        args.push("--preset".to_string());
+        args.push(preset.get_name().to_string());
+    }
+    args.extend(devices.iter().map(Address::to_string));
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    std::fs::write(&path, unit(&exe, &args))
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    println!("Wrote {}", path.display());
+
+    systemctl(&["daemon-reload"])?;
+    if start {
+        systemctl(&["enable", "--now", UNIT])?;
+        println!(
+            "Enabled and started, see `systemctl --user status {}`",
+            UNIT
+        );
+    } else {
+        systemctl(&["enable", UNIT])?;
+        println!(
+            "Enabled, it starts with the next login or `systemctl --user start {}`",
+            UNIT
+        );
+    }
+    Ok(())
+}
+
+/// Stops and disables the unit written by [`install`], and removes it.
+pub fn uninstall() -> Result<()> {
+    let path = unit_path()?;
+    if !path.exists() {
+        bail!("{} is not installed", UNIT);
+    }
+    if written_by_hand(&path)? {
+        bail!(
+            "{} was not written by `ancs-linux install-service`, leaving it alone",
+            path.display()
+        );
+    }
+    systemctl(&["disable", "--now", UNIT])?;
+    std::fs::remove_file(&path).with_context(|| format!("Failed to remove {}", path.display()))?;
+    systemctl(&["daemon-reload"])?;
+    println!("Stopped, disabled and removed {}", path.display());
+    Ok(())
+}
+
+fn unit(exe: &Path, args: &[String]) -> String {
+    let command: Vec<String> = std::iter::once(exe.to_string_lossy().into_owned())
+        .chain(args.iter().cloned())
+        .map(|arg| quote(&arg))
+        .collect();
+    format!(
+        "{}
+[Unit]
+Description=Mirror iPhone notifications to the desktop
+
+[Service]
+Type=notify
+ExecStart={}
+# Started once a phone is subscribed, which may take until it is nearby
+TimeoutStartSec=infinity
+WatchdogSec=60
+Restart=on-failure
+
+[Install]
+WantedBy=default.target
+",
+        MARKER,
+        command.join(" ")
+    )
+}
+
+/// An argument of `ExecStart`, quoted if need be and with specifiers and variables escaped.
+fn quote(arg: &str) -> String {
+    let escaped = arg.replace('%', "%%").replace('$', "$$");
+    if !escaped.is_empty() && !escaped.contains(|c: char| c.is_whitespace() || "\"'\\;".contains(c))
+    {
+        return escaped;
+    }
+    format!("\"{}\"", escaped.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bakes_in_the_arguments() {
+        let unit = unit(
+            Path::new("/home/alice/.cargo/bin/ancs-linux"),
+            &[
+                "--config".to_string(),
+                "/home/alice/My Config/100%.toml".to_string(),
+                "listen".to_string(),
+                "AA:BB:CC:DD:EE:FF".to_string(),
+            ],
+        );
+        assert!(unit.starts_with(MARKER));
+        assert!(unit.contains(
+            "\nExecStart=/home/alice/.cargo/bin/ancs-linux --config \
+             \"/home/alice/My Config/100%%.toml\" listen AA:BB:CC:DD:EE:FF\n"
+        ));
+    }
+}
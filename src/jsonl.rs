@@ -0,0 +1,47 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use futures::{future::BoxFuture, FutureExt as _};
+use tokio::io::AsyncWriteExt as _;
+
+use crate::sinks::{Forwarded, NetworkSink};
+
+/// Appends every notification as a line of JSON to a file, e.g. for another program to tail.
+pub struct JsonlSink {
+    path: PathBuf,
+}
+
+impl JsonlSink {
+    pub fn new(path: &Path) -> Result<Self> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl NetworkSink for JsonlSink {
+    fn name(&self) -> &str {
+        "jsonl"
+    }
+
+    fn deliver<'a>(&'a self, notification: &'a Forwarded) -> BoxFuture<'a, Result<()>> {
+        async move {
+            let mut line = serde_json::to_vec(notification)?;
+            line.push(b'\n');
+            let mut file = tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .await
+                .with_context(|| format!("Failed to open {}", self.path.display()))?;
+            // One write, so that readers never see half a line
+            file.write_all(&line).await?;
+            Ok(())
+        }
+        .boxed()
+    }
+}
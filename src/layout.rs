@@ -0,0 +1,76 @@
+/// Where a title may be split, and how much of it is dropped there.
+const BOUNDARIES: &[(&str, usize)] = &[
+    ("\n", 1),
+    (": ", 2),
+    (". ", 2),
+    ("! ", 2),
+    ("? ", 2),
+    (" - ", 3),
+    (" — ", 5),
+];
+
+/// Splits a title longer than `max` characters into a summary and a body, for apps that put
+/// everything in the title.
+///
+/// The summary ends at the first line break, colon, sentence end or dash within `max`, or else
+/// at the last space. Sentence ends are kept in the summary.
+pub fn split_title(title: &str, max: usize) -> Option<(&str, &str)> {
+    if max == 0 || title.chars().count() <= max {
+        return None;
+    }
+    let limit = title
+        .char_indices()
+        .nth(max)
+        .map_or(title.len(), |(i, _)| i);
+    let head = &title[..limit];
+
+    let boundary = BOUNDARIES
+        .iter()
+        .filter_map(|&(sep, skip)| head.find(sep).map(|i| (i, skip)))
+        .filter(|&(i, _)| i > 0)
+        .min_by_key(|&(i, _)| i);
+    let (summary, body) = match boundary {
+        Some((i, skip)) => {
+            let keep = if title[i..].starts_with(['.', '!', '?']) {
+                1
+            } else {
+                0
+            };
+            (&title[..i + keep], &title[i + skip..])
+        }
+        None => {
+            let i = head.rfind(' ').filter(|&i| i > 0)?;
+            (&title[..i], &title[i + 1..])
+        }
+    };
+
+    let body = body.trim();
+    (!body.is_empty()).then_some((summary.trim_end(), body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_at_sensible_boundaries() {
+        assert_eq!(split_title("Alice: are we still on?", 80), None);
+        assert_eq!(
+            split_title("Alice: are we still on for tonight?", 10),
+            Some(("Alice", "are we still on for tonight?"))
+        );
+        assert_eq!(
+            split_title("Your order shipped. It arrives on Tuesday", 25),
+            Some(("Your order shipped.", "It arrives on Tuesday"))
+        );
+        assert_eq!(
+            split_title("Reminder — Dentist at 3 pm", 15),
+            Some(("Reminder", "Dentist at 3 pm"))
+        );
+        assert_eq!(
+            split_title("Someone liked your photo from last weekend", 20),
+            Some(("Someone liked your", "photo from last weekend"))
+        );
+        assert_eq!(split_title("Supercalifragilistic", 5), None);
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::config::{AttributeLengthConfig, Config};
+
+/// Notifications of an app after which its truncation rate is evaluated.
+const WINDOW: u32 = 10;
+/// Truncated notifications in a window that make the app get longer attributes.
+const RAISE_AFTER: u32 = 3;
+
+/// How long the text attributes requested from the phone are, learned per app.
+///
+/// Everything starts at `attribute_length.initial`. Apps whose notifications often come back
+/// cut at that length, i.e. chat apps with long messages, get twice as much up to
+/// `attribute_length.max`.
+pub struct AttributeLengths {
+    initial: u16,
+    max: u16,
+    apps: HashMap<String, AppLengths>,
+    /// Apps seen in every category, for notifications whose app is not known when requesting.
+    categories: HashMap<u8, HashSet<String>>,
+    /// What was requested for notifications whose attributes are still to arrive.
+    requested: HashMap<u32, u16>,
+}
+
+struct AppLengths {
+    length: u16,
+    seen: u32,
+    truncated: u32,
+}
+
+impl AttributeLengths {
+    pub fn new(config: &AttributeLengthConfig) -> Self {
+        Self {
+            initial: config.initial,
+            max: config.max.max(config.initial),
+            apps: HashMap::new(),
+            categories: HashMap::new(),
+            requested: HashMap::new(),
+        }
+    }
+
+    /// Length to request for a notification, by its app if it is already known or else the
+    /// longest of the apps seen in its category.
+    pub fn request(
+        &mut self,
+        config: &Config,
+        uid: u32,
+        app_id: Option<&str>,
+        category_id: Option<u8>,
+    ) -> u16 {
+        let length = match app_id.map(|a| config.canonical_app_id(a)) {
+            Some(app_id) => self.apps.get(app_id).map(|a| a.length),
+            None => category_id
+                .and_then(|id| self.categories.get(&id))
+                .and_then(|apps| {
+                    apps.iter()
+                        .filter_map(|a| self.apps.get(a))
+                        .map(|a| a.length)
+                        .max()
+                }),
+        }
+        .unwrap_or(self.initial);
+        self.requested.insert(uid, length);
+        length
+    }
+
+    /// Learns from the attribute values of a notification requested with [`Self::request`].
+    pub fn record(
+        &mut self,
+        config: &Config,
+        uid: u32,
+        app_id: &str,
+        category_id: Option<u8>,
+        values: &[&str],
+    ) {
+        let Some(requested) = self.requested.remove(&uid) else {
+            return;
+        };
+        let app_id = config.canonical_app_id(app_id);
+        if let Some(id) = category_id {
+            self.categories
+                .entry(id)
+                .or_default()
+                .insert(app_id.to_string());
+        }
+
+        let app = self.apps.entry(app_id.to_string()).or_insert(AppLengths {
+            length: self.initial,
+            seen: 0,
+            truncated: 0,
+        });
+        // Requested under another length, e.g. before the app was known
+        if requested != app.length {
+            return;
+        }
+        app.seen += 1;
+        if values.iter().any(|v| is_truncated(v, requested)) {
+            app.truncated += 1;
+        }
+
+        if app.truncated >= RAISE_AFTER && app.length < self.max {
+            app.length = app.length.saturating_mul(2).min(self.max);
+            log::info!(
+                "Notifications from {} are often truncated, requesting {} bytes from now on",
+                app_id,
+                app.length
+            );
+        }
+        if app.truncated >= RAISE_AFTER || app.seen >= WINDOW {
+            app.seen = 0;
+            app.truncated = 0;
+        }
+    }
+
+    pub fn forget(&mut self, uid: u32) {
+        self.requested.remove(&uid);
+    }
+}
+
+/// Whether the phone cut a value at the requested length. It ends truncated values with an
+/// ellipsis, and may stop short of the limit to not split a character.
+fn is_truncated(value: &str, requested: u16) -> bool {
+    value.len() + 4 > requested as usize && value.ends_with('…')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raises_length_of_frequently_truncated_apps() {
+        let config = Config::default();
+        let mut lengths = AttributeLengths::new(&AttributeLengthConfig {
+            initial: 10,
+            max: 30,
+            ..Default::default()
+        });
+        let social = Some(4);
+        let long = "Hey, are…";
+        let short = "Hi";
+
+        for uid in 0..5 {
+            assert_eq!(lengths.request(&config, uid, None, social), 10);
+            let message = if uid % 2 == 0 { long } else { short };
+            lengths.record(&config, uid, "whatsapp", social, &[message]);
+        }
+        // The third truncated one raised it, which also applies to the category
+        assert_eq!(lengths.request(&config, 5, Some("whatsapp"), social), 20);
+        assert_eq!(lengths.request(&config, 6, None, social), 20);
+        assert_eq!(
+            lengths.request(&config, 7, Some("com.apple.MobileSMS"), social),
+            10
+        );
+
+        for uid in 10..20 {
+            lengths.request(&config, uid, Some("whatsapp"), social);
+            lengths.record(
+                &config,
+                uid,
+                "whatsapp",
+                social,
+                &["A long message, cut be…"],
+            );
+        }
+        assert_eq!(lengths.request(&config, 20, Some("whatsapp"), social), 30);
+    }
+}
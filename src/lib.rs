@@ -0,0 +1,21 @@
+//! Receiving notifications from an iPhone over the Apple Notification Center Service (ANCS)
+//! with BlueZ, without showing them anywhere. [`AncsClient`] is the way in, the `ancs-linux`
+//! binary shows what it gets on the desktop.
+
+pub mod alert;
+pub mod ams;
+pub mod client;
+pub mod control;
+pub mod discovery;
+pub mod error;
+pub mod link;
+pub mod protocol;
+pub mod reassembly;
+#[cfg(test)]
+mod replay;
+pub mod simulator;
+pub mod text;
+pub mod trace;
+
+pub use client::{AncsClient, AncsEvent, NotificationEvent};
+pub use error::AncsError;
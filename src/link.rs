@@ -0,0 +1,50 @@
+use futures::{stream::BoxStream, StreamExt as _};
+
+use crate::{
+    discovery::{self, AncsCharacteristics},
+    error::AncsError,
+    simulator::Simulator,
+};
+
+/// Where the processor gets its ANCS packets from.
+pub enum Link {
+    Gatt(AncsCharacteristics),
+    Simulated(Simulator),
+}
+
+impl Link {
+    pub async fn subscribe_notifications(&self) -> Result<BoxStream<'static, Vec<u8>>, AncsError> {
+        match self {
+            Link::Gatt(ancs) => match ancs.notification_source.notify().await {
+                Ok(stream) => Ok(stream.boxed()),
+                Err(source) => Err(AncsError::SubscribeFailed {
+                    characteristic: discovery::NOTIFICATION_SOURCE,
+                    source,
+                }),
+            },
+            Link::Simulated(simulator) => Ok(simulator.subscribe_notifications()),
+        }
+    }
+
+    pub async fn subscribe_data(&self) -> Result<BoxStream<'static, Vec<u8>>, AncsError> {
+        match self {
+            Link::Gatt(ancs) => match ancs.data_source.notify().await {
+                Ok(stream) => Ok(stream.boxed()),
+                Err(source) => Err(AncsError::SubscribeFailed {
+                    characteristic: discovery::DATA_SOURCE,
+                    source,
+                }),
+            },
+            Link::Simulated(simulator) => Ok(simulator.subscribe_data()),
+        }
+    }
+
+    pub async fn write_control_point(&self, data: &[u8]) -> Result<(), AncsError> {
+        match self {
+            Link::Gatt(ancs) => discovery::write_control_point(&ancs.control_point, data).await,
+            Link::Simulated(simulator) => simulator
+                .write_control_point(data)
+                .map_err(|e| AncsError::write_failed(format!("{:#}", e))),
+        }
+    }
+}
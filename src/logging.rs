@@ -0,0 +1,255 @@
+use std::{
+    os::{fd::AsFd as _, linux::fs::MetadataExt as _, unix::net::UnixDatagram},
+    sync::{OnceLock, RwLock},
+};
+
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use log::{
+    kv::{self, Key, Value, VisitSource},
+    Level, LevelFilter, Log, Metadata, Record,
+};
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Where log lines go, see `--log`.
+#[derive(ValueEnum, Debug, Default, Clone, Copy, PartialEq)]
+pub enum LogOutput {
+    /// The journal when stderr goes there anyway, e.g. in a systemd service, stderr otherwise.
+    #[default]
+    Auto,
+    Stderr,
+    /// The journal of systemd, with the fields of each line such as `APP_ID`.
+    Journald,
+}
+
+/// `env_logger` configured by `RUST_LOG`, with levels for some targets overridden at runtime.
+struct Logger {
+    /// Filters as configured by the environment.
+    env: env_logger::Logger,
+    /// Writes whatever passes the filters, unless it goes to the journal.
+    output: env_logger::Logger,
+    journal: Option<UnixDatagram>,
+    /// Level by target, from `ctl log-level`.
+    overrides: RwLock<Vec<(String, LevelFilter)>>,
+}
+
+pub fn init(output: LogOutput) {
+    let logger = LOGGER.get_or_init(|| Logger {
+        env: env_logger::Builder::from_default_env().build(),
+        output: env_logger::Builder::new()
+            .filter_level(LevelFilter::Trace)
+            .build(),
+        journal: open_journal(output),
+        overrides: RwLock::default(),
+    });
+    log::set_logger(logger).expect("Logger already set");
+    log::set_max_level(logger.env.filter());
+}
+
+fn open_journal(output: LogOutput) -> Option<UnixDatagram> {
+    let wanted = match output {
+        LogOutput::Auto => stderr_is_journal(),
+        LogOutput::Stderr => false,
+        LogOutput::Journald => true,
+    };
+    if !wanted {
+        return None;
+    }
+    let socket = UnixDatagram::unbound().and_then(|socket| {
+        socket.connect(JOURNAL_SOCKET)?;
+        Ok(socket)
+    });
+    match socket {
+        Ok(socket) => Some(socket),
+        Err(e) => {
+            // Not logged yet, as the logger is being set up
+            eprintln!("Cannot log to the journal, logging to stderr: {}", e);
+            None
+        }
+    }
+}
+
+/// Whether systemd connected stderr to the journal, which it tells in `JOURNAL_STREAM`.
+fn stderr_is_journal() -> bool {
+    let Ok(stream) = std::env::var("JOURNAL_STREAM") else {
+        return false;
+    };
+    let Ok(file) = std::io::stderr()
+        .as_fd()
+        .try_clone_to_owned()
+        .map(std::fs::File::from)
+    else {
+        return false;
+    };
+    file.metadata()
+        .is_ok_and(|m| stream == format!("{}:{}", m.st_dev(), m.st_ino()))
+}
+
+/// Sets the level for `target` and everything below it, e.g. `bluer` or
+/// `ancs_linux::discovery`, or goes back to what `RUST_LOG` says with `default`.
+pub fn set_level(target: &str, level: &str) -> Result<()> {
+    let Some(logger) = LOGGER.get() else {
+        bail!("Logging is not initialized");
+    };
+    if target.is_empty() {
+        bail!("Empty log target");
+    }
+
+    let mut overrides = logger.overrides.write().unwrap();
+    overrides.retain(|(t, _)| t != target);
+    if level != "default" {
+        let Ok(level) = level.parse() else {
+            bail!(
+                "Unknown log level {:?}, expected off, error, warn, info, debug, trace or default",
+                level
+            );
+        };
+        overrides.push((target.to_string(), level));
+    }
+
+    let max = overrides
+        .iter()
+        .map(|&(_, level)| level)
+        .fold(logger.env.filter(), Ord::max);
+    log::set_max_level(max);
+    Ok(())
+}
+
+impl Logger {
+    /// The level of the most specific override covering `target`.
+    fn override_for(&self, target: &str) -> Option<LevelFilter> {
+        self.overrides
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(t, _)| covers(t, target))
+            .max_by_key(|(t, _)| t.len())
+            .map(|&(_, level)| level)
+    }
+}
+
+/// Whether `target` is `prefix` or one of its modules.
+fn covers(prefix: &str, target: &str) -> bool {
+    target
+        .strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        match self.override_for(metadata.target()) {
+            Some(level) => metadata.level() <= level,
+            None => self.env.enabled(metadata),
+        }
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let sent = self
+            .journal
+            .as_ref()
+            .is_some_and(|journal| journal.send(&journal_entry(record)).is_ok());
+        // Too large for a datagram, or journald is gone
+        if !sent {
+            self.output.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.output.flush();
+    }
+}
+
+/// A record in the native protocol of journald, with its key-values as fields, e.g.
+/// `app_id = ...` as `APP_ID`.
+fn journal_entry(record: &Record) -> Vec<u8> {
+    let priority = match record.level() {
+        Level::Error => "3",
+        Level::Warn => "4",
+        Level::Info => "6",
+        Level::Debug | Level::Trace => "7",
+    };
+    let mut entry = Vec::new();
+    field(&mut entry, "MESSAGE", &record.args().to_string());
+    field(&mut entry, "PRIORITY", priority);
+    field(&mut entry, "SYSLOG_IDENTIFIER", "ancs-linux");
+    field(&mut entry, "TARGET", record.target());
+    if let Some(file) = record.file() {
+        field(&mut entry, "CODE_FILE", file);
+    }
+    if let Some(line) = record.line() {
+        field(&mut entry, "CODE_LINE", &line.to_string());
+    }
+    let _ = record.key_values().visit(&mut Fields(&mut entry));
+    entry
+}
+
+struct Fields<'a>(&'a mut Vec<u8>);
+
+impl<'kvs> VisitSource<'kvs> for Fields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        let name: String = key
+            .as_str()
+            .chars()
+            .map(|c| match c {
+                'a'..='z' => c.to_ascii_uppercase(),
+                'A'..='Z' | '0'..='9' => c,
+                _ => '_',
+            })
+            .collect();
+        // Those starting with `_` are for journald to set
+        let name = name.trim_start_matches('_');
+        if !name.is_empty() {
+            field(self.0, name, &value.to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Appends `NAME=value`, or the length-prefixed form for values spanning lines.
+fn field(entry: &mut Vec<u8>, name: &str, value: &str) {
+    entry.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        entry.push(b'\n');
+        entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        entry.push(b'=');
+    }
+    entry.extend_from_slice(value.as_bytes());
+    entry.push(b'\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn targets_cover_their_modules() {
+        assert!(covers("bluer", "bluer"));
+        assert!(covers("bluer", "bluer::gatt::remote"));
+        assert!(!covers("bluer", "bluerx"));
+        assert!(!covers("ancs_linux::discovery", "ancs_linux"));
+    }
+
+    #[test]
+    fn sends_key_values_as_fields() {
+        let kvs = [("ancs_uid", 42), ("app-id", 7)];
+        let entry = journal_entry(
+            &Record::builder()
+                .args(format_args!("Two\nlines"))
+                .level(Level::Warn)
+                .target("ancs_linux")
+                .key_values(&kvs)
+                .build(),
+        );
+        let entry = String::from_utf8_lossy(&entry);
+        assert!(entry.starts_with("MESSAGE\n\x09\0\0\0\0\0\0\0Two\nlines\nPRIORITY=4\n"));
+        assert!(entry.contains("\nSYSLOG_IDENTIFIER=ancs-linux\nTARGET=ancs_linux\n"));
+        assert!(entry.ends_with("\nANCS_UID=42\nAPP_ID=7\n"));
+    }
+}
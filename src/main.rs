@@ -1,267 +1,3433 @@
-use std::io::Cursor;
+mod appnames;
+mod archive;
+mod audit;
+mod bench;
+mod blocklist;
+mod bond;
+mod breakthrough;
+mod clock;
+mod config;
+mod contacts;
+mod dedupe;
+mod desktop;
+mod exec;
+mod focus;
+mod handles;
+mod history;
+mod icons;
+mod install;
+mod jsonl;
+mod layout;
+mod lengths;
+mod logging;
+mod media;
+mod metrics;
+mod mpris;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+mod ntfy;
+mod outbox;
+mod output;
+mod pairing;
+mod paths;
+mod power;
+mod preset;
+mod privacy;
+mod safemode;
+mod scheduler;
+mod screencast;
+mod service;
+mod session;
+mod sinks;
+mod socket;
+mod startup;
+mod state;
+mod store;
+mod summary;
+mod supervisor;
+mod systemd;
+mod timers;
+#[cfg(feature = "tray")]
+mod tray;
+mod webhook;
+mod wizard;
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
 
 use ancs::{
     attributes::{
+        category::CategoryID,
         command::CommandID,
         event::{EventFlag, EventID},
         notification::NotificationAttributeID,
     },
-    characteristics::{control_point::GetNotificationAttributesRequest, data_source},
+    characteristics::{
+        control_point::AppAttributeID, data_source::GetNotificationAttributesResponse,
+    },
 };
-use anyhow::{bail, Result};
-use bluer::{
-    gatt::remote::{Characteristic, CharacteristicWriteRequest},
-    Adapter, Address, Uuid,
+use ancs_linux::{
+    alert::ImmediateAlert, control, discovery, link, protocol, protocol::AppAttributesResponse,
+    reassembly, simulator, trace::ProtocolTrace, AncsClient, AncsError, AncsEvent,
+    NotificationEvent,
 };
-use byteorder_pack::UnpackFrom;
-use clap::Parser;
-use futures::{pin_mut, StreamExt as _};
+use anyhow::{bail, Context, Result};
+use appnames::AppNames;
+use archive::Archive;
+use blocklist::Blocklist;
+use bluer::{Adapter, AdapterEvent, Address, DeviceEvent, DeviceProperty};
+use chrono::{Local, Utc};
+use clap::{Args, Parser, Subcommand};
+use clock::Stamp;
+use config::{Attribute, Config, Hold, Leftovers, ScreenSharing};
+use contacts::Contacts;
+use dedupe::Dedupe;
+use discovery::{CapabilityReport, GattConfig};
+use focus::FocusMonitor;
+use futures::{stream::BoxStream, StreamExt as _};
+use handles::Handles;
+use history::{Acknowledgment, History};
+use lengths::AttributeLengths;
+use link::Link;
+use metrics::METRICS;
+use notify_rust::{CloseReason, Hint, Timeout};
+use output::Output;
+use power::PowerMonitor;
+use preset::Preset;
+use protocol::{ActionID, ActionLabels};
+use scheduler::Scheduler;
+use screencast::ScreenCastMonitor;
+use service::{Control, Loader, Service};
+use session::SessionMonitor;
+use simulator::Simulator;
+use sinks::{AncsNotification, NotificationSink};
+use startup::Stage;
+use state::SessionState;
+use store::Stores;
+use summary::{QuietTally, Summary};
+use supervisor::Backoff;
+use timers::TimerQueue;
+use tokio::sync::mpsc;
+
+/// What the notification source told us about a notification.
+#[derive(Debug, Clone, Copy)]
+struct NotificationInfo {
+    event_flags: u8,
+    category_id: u8,
+    /// When the phone announced it, or announced it last if it was modified.
+    announced: Stamp,
+}
+
+impl NotificationInfo {
+    fn is_important(&self) -> bool {
+        self.event_flags & EventFlag::Important as u8 != 0
+            || self.category_id == CategoryID::IncomingCall as u8
+    }
+
+    fn is_silent(&self) -> bool {
+        self.event_flags & EventFlag::Silent as u8 != 0
+    }
+
+    /// Whether the notification gets through `hold`, see [`breakthrough`].
+    fn breaks_through(&self, config: &Config, app_id: Option<&str>, hold: Hold) -> bool {
+        let important = self.event_flags & EventFlag::Important as u8 != 0;
+        breakthrough::breaks_through(config, important, self.category_id, app_id, hold)
+    }
+}
+
+enum DesktopEvent {
+    /// The user clicked or closed the desktop notification.
+    Acknowledged(u32),
+    /// The desktop notification went away on its own.
+    Expired(u32),
+    /// One of the phone's actions was chosen on the desktop notification.
+    Action {
+        uid: u32,
+        desktop_id: u32,
+        action: ActionID,
+    },
+    /// Never show notifications from this app again was chosen.
+    NeverShow { uid: u32, app_id: String },
+}
+
+enum Timer {
+    Reannounce(u32),
+    /// The phone should be done re-announcing its notifications after a (re)subscription.
+    ReconcileDone,
+    /// Gives up waiting for the phone to remove a notification after performing an action.
+    ActionTimeout(u32),
+    /// A notification that kept being added and removed may have settled.
+    Debounced(u32),
+    /// Shows what a sender sent in a burst.
+    Burst(BurstKey),
+    /// Shows what was held back in a throttled category, by category id.
+    Throttle(u8),
+    /// The phone should have answered something since subscribing.
+    SharingCheck,
+    /// The notification server may be reachable again.
+    DesktopRetry,
+}
+
+/// App and sender of a burst of chat messages.
+type BurstKey = (String, String);
+
+/// How long the phone gets to re-announce existing notifications after subscribing.
+const RECONCILE_WINDOW: Duration = Duration::from_secs(5);
+/// Minimum time between two resubscriptions, in case the counts never agree.
+const RESYNC_INTERVAL: Duration = Duration::from_secs(60);
+/// How long the phone gets to remove a notification once its action was performed.
+const ACTION_TIMEOUT: Duration = Duration::from_secs(10);
+/// Events for a notification this soon after the previous one are held back until it settles.
+const FLOOD_WINDOW: Duration = Duration::from_secs(1);
+/// How often to check whether the notification server is back.
+const DESKTOP_RETRY: Duration = Duration::from_secs(5);
+/// Notifications kept while the notification server is unreachable, the oldest are dropped.
+const MAX_OUTAGE_QUEUE: usize = 20;
+/// How long the phone gets to send anything after subscribing, before it is considered to not
+/// share notifications.
+const SHARING_TIMEOUT: Duration = Duration::from_secs(30);
+/// Asked for its name after subscribing, which the phone answers if it shares notifications even
+/// when there are none, and again whenever an idle link needs to prove itself to the watchdog.
+const PROBE_APP: &str = "com.apple.Preferences";
+
+struct Reannouncement {
+    notification: notify_rust::Notification,
+    /// Whether the last bubble is still shown and watched for acknowledgment.
+    visible: bool,
+}
+
+/// The notification most recently shown on the desktop, for acting on it from a shortcut.
+struct LastShown {
+    uid: u32,
+    desktop_id: u32,
+    event_flags: u8,
+}
+
+struct Shown {
+    desktop_id: u32,
+    /// Whether the desktop notification is still up and watched for acknowledgment.
+    watched: bool,
+}
+
+struct QueuedNotification {
+    uid: u32,
+    notification: notify_rust::Notification,
+    entry_id: Option<i64>,
+    /// Whether to record in the history when it was seen, see `history.seen_apps`.
+    records_seen: bool,
+}
+
+/// Everything that outlives a processor, i.e. the connection to the phone.
+#[derive(Clone)]
+struct Shared {
+    /// Also has the config, as last loaded.
+    control: Control,
+    history: Option<Arc<History>>,
+    session: Option<SessionMonitor>,
+    screen_cast: Option<ScreenCastMonitor>,
+    focus: Option<FocusMonitor>,
+    power: Option<PowerMonitor>,
+    contacts: Option<Arc<Contacts>>,
+    service: Option<Service>,
+    blocklist: Arc<Mutex<Blocklist>>,
+    app_names: Arc<Mutex<AppNames>>,
+    scheduler: Arc<Scheduler>,
+    quiet_tally: Arc<QuietTally>,
+    sinks: Arc<Vec<Arc<dyn NotificationSink>>>,
+    failures: Arc<Mutex<safemode::Failures>>,
+    router: Option<desktop::Router>,
+    trace: Option<ProtocolTrace>,
+}
+
+/// A phone being mirrored, with what outlives its processors.
+#[derive(Clone)]
+struct Phone {
+    /// [`Address::any`] for the simulated device.
+    address: Address,
+    /// Put before the app of its notifications, e.g. while mirroring several phones.
+    label: Option<String>,
+    dedupe: Option<Arc<Mutex<Dedupe>>>,
+    handles: Arc<Mutex<Handles>>,
+    lengths: Arc<Mutex<AttributeLengths>>,
+    /// Whether the next connection shows the notifications already on the phone, which only
+    /// the first one does.
+    show_existing: Arc<AtomicBool>,
+}
 
 struct AncsProcessor {
-    control_point: Option<Characteristic>,
+    address: Address,
+    label: Option<String>,
+    config: Arc<Config>,
+    history: Option<Arc<History>>,
+    session: Option<SessionMonitor>,
+    screen_cast: Option<ScreenCastMonitor>,
+    focus: Option<FocusMonitor>,
+    power: Option<PowerMonitor>,
+    contacts: Option<Arc<Contacts>>,
+    control: Control,
+    service: Option<Service>,
+    dedupe: Option<Arc<Mutex<Dedupe>>>,
+    /// What is in `shown`, on disk.
+    handles: Arc<Mutex<Handles>>,
+    blocklist: Arc<Mutex<Blocklist>>,
+    lengths: Arc<Mutex<AttributeLengths>>,
+    scheduler: Arc<Scheduler>,
+    /// What quiet hours kept off the desktop, for their summary.
+    quiet_tally: Arc<QuietTally>,
+    /// Everywhere notifications go besides the desktop.
+    sinks: Arc<Vec<Arc<dyn NotificationSink>>>,
+    failures: Arc<Mutex<safemode::Failures>>,
+    /// What becomes of the bubbles shown, None if that cannot be watched.
+    router: Option<desktop::Router>,
+    /// From `--trace-protocol`.
+    trace: Option<ProtocolTrace>,
+    show_existing: Arc<AtomicBool>,
+    /// Whether all pre-existing notifications are shown, while reconciling the first connection.
+    showing_existing: bool,
+    /// Whether pre-existing notifications are fetched to find those that arrived while we
+    /// were not running.
+    catching_up: bool,
+    client: Option<AncsClient>,
+    /// To make the phone ring, None if it does not offer that or is simulated.
+    alert: Option<ImmediateAlert>,
+    /// Whether anything arrived from the phone since subscribing.
+    heard_from_phone: bool,
+    app_names: Arc<Mutex<AppNames>>,
+    pending_app_names: HashSet<String>,
+    /// Notifications for which only the app identifier has been requested so far.
+    pending_app_lookups: HashSet<u32>,
+    pending: HashMap<u32, NotificationInfo>,
+    /// Time of the last event of every notification within `FLOOD_WINDOW`.
+    recent_events: HashMap<u32, tokio::time::Instant>,
+    /// Notifications held back until they stop flapping, which are fetched once they settle.
+    debounced: HashMap<u32, NotificationInfo>,
+    /// Category of every notification currently on the phone, as far as we know.
+    active: HashMap<u32, u8>,
+    /// What the history keeps of every notification on the phone, for listing them.
+    entries: HashMap<u32, history::Entry>,
+    /// History id of every notification on the phone, to record when it is removed.
+    entry_ids: HashMap<u32, i64>,
+    /// Notifications re-announced since the last subscription, while reconciling.
+    reconciling: Option<HashSet<u32>>,
+    resync_requested: bool,
+    last_resync: Option<tokio::time::Instant>,
+    /// Important notifications that are shown again until acknowledged.
+    reannouncements: HashMap<u32, Reannouncement>,
+    /// Action labels of notifications currently on the phone, as far as they were fetched.
+    action_labels: HashMap<u32, ActionLabels>,
+    /// Actions of sensitive notifications that copy their content, which are never performed.
+    withheld_actions: HashMap<u32, Vec<ActionID>>,
+    /// Desktop notification of every notification whose action was sent to the phone, which
+    /// is closed once the phone confirms by removing the notification.
+    pending_actions: HashMap<u32, u32>,
+    /// Desktop notification of every notification shown, to update it when the phone modifies
+    /// the notification and close it once the phone removes it.
+    shown: HashMap<u32, Shown>,
+    last_shown: Option<LastShown>,
+    timers: TimerQueue<Timer>,
+    /// Notifications held back while another session is in the foreground, or while the
+    /// screen is shared with `while_screen_sharing = "hide"`.
+    queued: Vec<QueuedNotification>,
+    /// Notifications that could not be shown while the notification server was unreachable,
+    /// oldest first.
+    desktop_outage: Option<VecDeque<QueuedNotification>>,
+    /// Bus name of the notification server desktop ids belong to, once known.
+    notification_server: Option<String>,
+    /// Chat notifications held back for `bursts.window`, with the messages of the burst merged.
+    bursts: HashMap<BurstKey, QueuedNotification>,
+    /// Notifications held back in every throttled category that showed one recently, by id.
+    throttled: HashMap<u8, Vec<QueuedNotification>>,
+    desktop_tx: mpsc::UnboundedSender<DesktopEvent>,
+    desktop_rx: mpsc::UnboundedReceiver<DesktopEvent>,
 }
 
 impl AncsProcessor {
-    pub fn new() -> Self {
+    pub fn new(shared: Shared, phone: Phone) -> Self {
+        let Shared {
+            control,
+            history,
+            session,
+            screen_cast,
+            focus,
+            power,
+            contacts,
+            service,
+            blocklist,
+            app_names,
+            scheduler,
+            quiet_tally,
+            sinks,
+            failures,
+            router,
+            trace,
+        } = shared;
+        let Phone {
+            address,
+            label,
+            dedupe,
+            handles,
+            lengths,
+            show_existing,
+        } = phone;
+        let (desktop_tx, desktop_rx) = mpsc::unbounded_channel();
         Self {
-            control_point: None,
+            address,
+            label,
+            config: control.config(),
+            history,
+            session,
+            screen_cast,
+            focus,
+            power,
+            contacts,
+            control,
+            service,
+            dedupe,
+            handles,
+            blocklist,
+            lengths,
+            scheduler,
+            quiet_tally,
+            sinks,
+            failures,
+            router,
+            trace: trace.map(|t| t.for_source(address)),
+            show_existing,
+            showing_existing: false,
+            catching_up: false,
+            client: None,
+            alert: None,
+            heard_from_phone: false,
+            app_names,
+            pending_app_names: HashSet::new(),
+            pending_app_lookups: HashSet::new(),
+            pending: HashMap::new(),
+            recent_events: HashMap::new(),
+            debounced: HashMap::new(),
+            active: HashMap::new(),
+            entries: HashMap::new(),
+            entry_ids: HashMap::new(),
+            reconciling: None,
+            resync_requested: false,
+            last_resync: None,
+            reannouncements: HashMap::new(),
+            action_labels: HashMap::new(),
+            withheld_actions: HashMap::new(),
+            pending_actions: HashMap::new(),
+            shown: HashMap::new(),
+            last_shown: None,
+            timers: TimerQueue::default(),
+            queued: Vec::new(),
+            desktop_outage: None,
+            notification_server: None,
+            bursts: HashMap::new(),
+            throttled: HashMap::new(),
+            desktop_tx,
+            desktop_rx,
         }
     }
 
-    pub async fn main_loop(mut self, device_addr: Address, adapter: &Adapter) -> Result<()> {
+    pub async fn main_loop(mut self, adapter: &Adapter) -> Result<()> {
+        let device_addr = self.address;
+        state::transition(device_addr, SessionState::Connecting, None);
+        startup::enter(device_addr, Stage::Connect);
         let device = adapter.device(device_addr)?;
 
         if !device.is_connected().await? {
             log::info!("Device {} is not connected", device_addr);
+            startup::failed(device_addr, "The device is not connected");
+            state::transition(
+                device_addr,
+                SessionState::Idle,
+                Some("The device is not connected".to_string()),
+            );
             return Ok(());
         }
 
         log::info!("Device {} is connected", device_addr);
 
-        let services = device.services().await?;
-        let mut ancs_service = None;
-        let acns_uuid: Uuid = "7905F431-B5CE-4E99-A40F-4B1E122D00D0".parse()?;
-        for s in services {
-            if s.uuid().await? == acns_uuid {
-                ancs_service = Some(s);
-                break;
+        state::transition(device_addr, SessionState::Discovering, None);
+        let client = match open_link(&device, &self.config.gatt).await {
+            Ok(client) => client,
+            Err(e) => {
+                startup::failed(device_addr, format!("{:#}", e));
+                return Err(e);
             }
-        }
+        };
+        startup::usable(device_addr);
+        self.failures.lock().unwrap().subscribed();
+        state::transition(device_addr, SessionState::Subscribed, None);
 
-        let ancs_service = match ancs_service {
-            Some(s) => s,
-            None => {
-                bail!("ANCS service not found");
+        self.alert = match ImmediateAlert::find(&device).await {
+            Ok(alert) => alert,
+            Err(e) => {
+                log::debug!("Cannot look for the Immediate Alert Service: {:?}", e);
+                None
             }
         };
-
-        let mut notification_source = None;
-        let mut data_source = None;
-        let mut control_point = None;
-        let noti_source_uuid: Uuid = "9FBF120D-6301-42D9-8C58-25E699A21DBD".parse()?;
-        let data_source_uuid: Uuid = "22EAC6E9-24D6-4BB5-BE44-B36ACE7C7BFB".parse()?;
-        let control_point_uuid: Uuid = "69D1D8F3-45E1-49A8-9821-9BBDFDAAD9D9".parse()?;
-        for c in ancs_service.characteristics().await? {
-            let uuid = c.uuid().await?;
-
-            if uuid == noti_source_uuid {
-                notification_source = Some(c);
-            } else if uuid == data_source_uuid {
-                data_source = Some(c);
-            } else if uuid == control_point_uuid {
-                control_point = Some(c);
+        let disconnected = device.events().await?.filter_map(move |event| async move {
+            match event {
+                DeviceEvent::PropertyChanged(DeviceProperty::Connected(false)) => {
+                    log::info!("Device {} disconnected, stopping", device_addr);
+                    Some(())
+                }
+                _ => None,
             }
-        }
+        });
+        let player = (self.config.media.mpris && !self.config.is_headless()).then(|| {
+            let identity = self.label.clone().unwrap_or_else(|| "iPhone".to_string());
+            tokio::spawn(async move {
+                if let Err(e) = mpris::serve(device, identity).await {
+                    log::warn!("Failed to publish the player of {}: {:#}", device_addr, e);
+                }
+            })
+        });
 
-        let notification_source = match notification_source {
-            Some(c) => c,
-            None => {
-                bail!("Notification source not found");
-            }
-        };
+        let removed = adapter
+            .events()
+            .await?
+            .filter_map(move |event| async move {
+                match event {
+                    bluer::AdapterEvent::DeviceRemoved(addr) if addr == device_addr => {
+                        log::info!("Device removed, stopping");
+                        Some(())
+                    }
+                    _ => None,
+                }
+            })
+            .boxed();
 
-        let data_source = match data_source {
-            Some(c) => c,
-            None => {
-                bail!("Data source not found");
-            }
-        };
+        let stop = futures::stream::select(removed, disconnected).boxed();
+        let result = self.run(client, stop).await;
+        if let Some(player) = player {
+            player.abort();
+        }
+        result
+    }
 
-        let control_point = match control_point {
-            Some(c) => c,
-            None => {
-                bail!("Control point not found");
-            }
-        };
+    /// Runs against the fake phone instead of a real one.
+    pub async fn simulate(self, simulator: Simulator) -> Result<()> {
+        log::info!("Running against the simulated device");
+        state::transition(self.address, SessionState::Connecting, None);
+        state::transition(self.address, SessionState::Discovering, None);
+        let client = AncsClient::simulated(simulator).await?;
+        self.failures.lock().unwrap().subscribed();
+        state::transition(self.address, SessionState::Subscribed, None);
 
-        self.control_point = Some(control_point);
+        self.run(client, futures::stream::pending().boxed()).await
+    }
 
-        let data_source_stream = data_source.notify().await?;
-        pin_mut!(data_source_stream);
+    /// Processes events until the link goes away or `stop` yields.
+    async fn run(mut self, client: AncsClient, mut stop: BoxStream<'static, ()>) -> Result<()> {
+        if let Some(trace) = &self.trace {
+            trace.note("Subscribed");
+            client.trace_to(trace.clone());
+        }
+        let mut events = client.events();
+        self.client = Some(client);
+        self.adopt_handles();
+        self.start_reconciling();
+        self.catching_up = self
+            .dedupe
+            .as_ref()
+            .is_some_and(|d| d.lock().unwrap().is_recent());
+        self.showing_existing = self.show_existing.swap(false, Ordering::Relaxed);
+        if self.showing_existing {
+            log::info!("Showing the notifications already on the phone");
+        } else if self.catching_up {
+            log::info!("Restarted recently, catching up on missed notifications");
+        }
 
-        let notification_stream = notification_source.notify().await?;
-        pin_mut!(notification_stream);
+        let (requests_tx, mut requests) = mpsc::unbounded_channel();
+        self.control.attach(self.address, requests_tx);
 
-        let events_stream = adapter.events().await?;
-        pin_mut!(events_stream);
+        let mut foreground_changes = match &self.session {
+            Some(session) => session.foreground_changes().await,
+            None => futures::stream::pending().boxed(),
+        };
+        let mut screen_sharing_changes = match &self.screen_cast {
+            Some(screen_cast) => screen_cast.changes(),
+            None => futures::stream::pending().boxed(),
+        };
 
         log::info!("Starting to listen for notifications");
+        let beating = systemd::beat(self.address);
+        let mut probes = tokio::time::interval(systemd::BEAT_INTERVAL);
+        self.timers.schedule(SHARING_TIMEOUT, Timer::SharingCheck);
+        if let Err(e) = self.request_app_name(PROBE_APP).await {
+            log::debug!("Failed to ask the phone for an app name: {:?}", e);
+        }
 
         loop {
             tokio::select! {
-                Some(noti) = notification_stream.next() => {
-                    self.process_notification(noti).await?;
+                event = events.next() => {
+                    let Some(event) = event else {
+                        log::info!("Both subscriptions ended, stopping");
+                        break;
+                    };
+                    beating.again();
+                    self.process_event(event).await?;
 
+                    if std::mem::take(&mut self.resync_requested) {
+                        // The phone re-announces everything to a new subscriber
+                        self.client().resubscribe().await?;
+                        self.start_reconciling();
+                    }
+                }
+                Some(event) = self.desktop_rx.recv() => {
+                    self.process_desktop_event(event).await?;
+                }
+                Some(request) = requests.recv() => {
+                    self.process_request(request).await;
+                }
+                Some(foreground) = foreground_changes.next() => {
+                    if foreground {
+                        self.show_queued().await?;
+                    }
+                }
+                Some(sharing) = screen_sharing_changes.next() => {
+                    if !sharing {
+                        self.show_queued().await?;
+                    }
                 }
-                Some(data) = data_source_stream.next() => {
-                    self.process_data(data).await?;
+                timer = self.timers.next() => {
+                    self.process_timer(timer).await?;
                 }
-                Some(event) = events_stream.next() => {
-                    if let bluer::AdapterEvent::DeviceRemoved(addr) = event {
-                        if addr == device_addr {
-                            log::info!("Device removed, stopping");
-                            break;
+                _ = probes.tick() => {
+                    // An idle phone proves its link alive by answering; an unanswered probe
+                    // is still pending, so none follows and the watchdog takes over
+                    if systemd::watching() && beating.silence() >= systemd::BEAT_INTERVAL {
+                        if let Err(e) = self.request_app_name(PROBE_APP).await {
+                            log::debug!("Failed to probe the link to the phone: {:?}", e);
                         }
                     }
                 }
-                else => break,
+                Some(()) = stop.next() => break,
             }
         }
 
         Ok(())
     }
 
-    async fn process_notification(&mut self, noti: Vec<u8>) -> Result<()> {
-        let (event_id, event_flags, _category_id, _category_count, notification_uid) =
-            <(u8, u8, u8, u8, u32)>::unpack_from_le(&mut Cursor::new(&noti))?;
+    async fn process_event(&mut self, event: AncsEvent) -> Result<()> {
+        self.heard_from_phone();
+        match event {
+            AncsEvent::Notification(event) => self.process_notification(event).await,
+            AncsEvent::Attributes(notif) => self.process_attributes(notif).await,
+            AncsEvent::AppAttributes(app) => {
+                self.process_app_attributes(app);
+                Ok(())
+            }
+            AncsEvent::MalformedNotification(packet) => {
+                metrics::inc(&METRICS.parse_errors);
+                log::warn!(
+                    "Ignoring short notification source packet: {}",
+                    protocol::hex(&packet)
+                );
+                Ok(())
+            }
+            AncsEvent::MalformedResponse(data) => {
+                self.process_malformed_response(&data);
+                Ok(())
+            }
+        }
+    }
+
+    async fn process_notification(&mut self, event: NotificationEvent) -> Result<()> {
+        let NotificationEvent {
+            event_id,
+            event_flags,
+            category_id,
+            category_count,
+            uid: notification_uid,
+        } = event;
+
+        let now = tokio::time::Instant::now();
+        let flapping = self
+            .recent_events
+            .insert(notification_uid, now)
+            .is_some_and(|t| now - t < FLOOD_WINDOW);
+        self.recent_events.retain(|_, t| now - *t < FLOOD_WINDOW);
+        let event = match event_id {
+            x if x == EventID::NotificationAdded as u8 => "received",
+            x if x == EventID::NotificationModified as u8 => "modified",
+            _ => "removed",
+        };
+        log::info!(
+            ancs_uid = notification_uid, event = event, category = category_id;
+            "Notification {} {}",
+            notification_uid,
+            event
+        );
 
         if event_id == EventID::NotificationRemoved as u8 {
+            metrics::inc(&METRICS.removed);
+            if self.debounced.contains_key(&notification_uid) {
+                metrics::inc(&METRICS.suppressed_flapping);
+                log::debug!(
+                    "Notification {} removed before it settled",
+                    notification_uid
+                );
+            }
+            if let Some(desktop_id) = self.pending_actions.remove(&notification_uid) {
+                log::info!("Action on notification {} succeeded", notification_uid);
+                self.shown.entry(notification_uid).or_insert(Shown {
+                    desktop_id,
+                    watched: false,
+                });
+            }
+            self.remove_notification(notification_uid).await;
+            self.check_category_count(category_id, category_count);
             return Ok(());
         }
 
-        if event_flags & EventFlag::PreExisting as u8 != 0 {
+        if event_id == EventID::NotificationAdded as u8 {
+            metrics::inc(&METRICS.received);
+        }
+        self.active.insert(notification_uid, category_id);
+        if let Some(seen) = &mut self.reconciling {
+            seen.insert(notification_uid);
+        }
+        self.check_category_count(category_id, category_count);
+
+        if self.config.is_ignored_category(category_id) {
+            log::debug!(
+                "Not showing notification {} in category {}",
+                notification_uid,
+                category_id
+            );
+            metrics::inc(&METRICS.suppressed_not_allowed);
             return Ok(());
         }
 
-        let cmd = GetNotificationAttributesRequest {
-            command_id: CommandID::GetNotificationAttributes,
-            notification_uid,
-            attribute_ids: vec![
-                (NotificationAttributeID::AppIdentifier, None),
-                (NotificationAttributeID::Title, Some(100)),
-                (NotificationAttributeID::Subtitle, Some(100)),
-                (NotificationAttributeID::Message, Some(100)),
-            ],
+        if event_flags & EventFlag::PreExisting as u8 != 0 && !self.showing_existing {
+            let Some(dedupe) = &self.dedupe else {
+                metrics::inc(&METRICS.skipped_pre_existing);
+                return Ok(());
+            };
+            let mut dedupe = dedupe.lock().unwrap();
+            let known = dedupe.contains(notification_uid);
+            if !known {
+                // Saved once reconciling is done
+                dedupe.insert(notification_uid, None);
+            }
+            if known || !self.catching_up {
+                metrics::inc(&METRICS.skipped_pre_existing);
+                return Ok(());
+            }
+        }
+
+        let info = NotificationInfo {
+            event_flags,
+            category_id,
+            announced: Stamp::now(),
+        };
+        let flapping = flapping && !info.breaks_through(&self.config, None, Hold::Debounce);
+        if flapping || self.debounced.contains_key(&notification_uid) {
+            log::debug!(
+                "Notification {} is added and removed rapidly, waiting for it to settle",
+                notification_uid
+            );
+            if self.debounced.insert(notification_uid, info).is_none() {
+                self.timers
+                    .schedule(FLOOD_WINDOW, Timer::Debounced(notification_uid));
+            }
+            return Ok(());
+        }
+
+        self.fetch_notification(notification_uid, info).await
+    }
+
+    async fn fetch_notification(
+        &mut self,
+        notification_uid: u32,
+        info: NotificationInfo,
+    ) -> Result<()> {
+        self.pending.insert(notification_uid, info);
+
+        // If some apps are filtered or restrict their attributes, we have to learn the app before
+        // deciding what to ask for, so that restricted attributes never leave the phone.
+        let attributes = if self.needs_app_lookup() {
+            self.pending_app_lookups.insert(notification_uid);
+            vec![]
+        } else {
+            Attribute::ALL.to_vec()
         };
 
-        self.control_point
+        self.request_attributes(notification_uid, None, &attributes)
+            .await
+    }
+
+    /// Whether any app is filtered, blocked or restricts its attributes.
+    fn needs_app_lookup(&self) -> bool {
+        self.config.needs_app_lookup() || !self.blocklist.lock().unwrap().is_empty()
+    }
+
+    /// Forgets a notification that is gone from the phone, closing it on the desktop as well.
+    async fn remove_notification(&mut self, notification_uid: u32) {
+        if let Some(Shown { desktop_id, .. }) = self.shown.remove(&notification_uid) {
+            {
+                let mut handles = self.handles.lock().unwrap();
+                handles.remove(notification_uid);
+                if let Err(e) = handles.save() {
+                    log::warn!("Failed to save desktop ids: {:?}", e);
+                }
+            }
+            log::debug!(
+                "Closing desktop notification {} of removed notification {}",
+                desktop_id,
+                notification_uid
+            );
+            if let Err(e) = desktop::close_notification(desktop_id).await {
+                log::warn!("Failed to close desktop notification: {:?}", e);
+            }
+        }
+        if let Some(service) = &self.service {
+            service.removed(self.address, notification_uid).await;
+        }
+        if let (Some(history), Some(id)) = (&self.history, self.entry_ids.remove(&notification_uid))
+        {
+            if let Err(e) = history.set_removed(id, Utc::now()) {
+                log::warn!("Failed to record removal in the history: {:?}", e);
+            }
+        }
+        self.active.remove(&notification_uid);
+        if let Some(entry) = self.entries.remove(&notification_uid) {
+            self.notify_sinks(AncsNotification {
+                event: sinks::Kind::Removed,
+                device: service::display_address(self.address),
+                uid: notification_uid,
+                app_name: self
+                    .app_names
+                    .lock()
+                    .unwrap()
+                    .get(&entry.app_id)
+                    .map(str::to_string),
+                app_id: Some(entry.app_id),
+                title: None,
+                subtitle: None,
+                message: None,
+                category: None,
+                important: false,
+                time: Utc::now(),
+            });
+        }
+        self.pending.remove(&notification_uid);
+        self.debounced.remove(&notification_uid);
+        self.reannouncements.remove(&notification_uid);
+        self.action_labels.remove(&notification_uid);
+        self.withheld_actions.remove(&notification_uid);
+        self.lengths.lock().unwrap().forget(notification_uid);
+        self.queued.retain(|q| q.uid != notification_uid);
+        self.bursts.retain(|_, q| q.uid != notification_uid);
+        for held in self.throttled.values_mut() {
+            held.retain(|q| q.uid != notification_uid);
+        }
+        if self
+            .last_shown
             .as_ref()
-            .unwrap()
-            .write_ext(
-                &Vec::from(cmd),
-                &CharacteristicWriteRequest {
-                    op_type: bluer::gatt::WriteOp::Request,
-                    ..Default::default()
-                },
-            )
-            .await?;
+            .is_some_and(|l| l.uid == notification_uid)
+        {
+            self.last_shown = None;
+        }
+    }
+
+    /// Takes over the desktop notifications of earlier processors and runs, which are closed
+    /// once reconciling finds them gone from the phone, unless they are to be left alone.
+    fn adopt_handles(&mut self) {
+        let mut handles = self.handles.lock().unwrap();
+        if self.config.leftover_notifications != Leftovers::Revalidate {
+            handles.clear();
+            return;
+        }
+        for (uid, desktop_id) in handles.ids() {
+            self.shown.entry(uid).or_insert(Shown {
+                desktop_id,
+                watched: false,
+            });
+        }
+    }
+
+    /// Records the desktop notification of a notification, also on disk for the next run.
+    fn set_shown(&mut self, uid: u32, shown: Shown) {
+        {
+            let mut handles = self.handles.lock().unwrap();
+            handles.insert(uid, shown.desktop_id, self.notification_server.as_deref());
+            if let Err(e) = handles.save() {
+                log::warn!("Failed to save desktop ids: {:?}", e);
+            }
+        }
+        self.shown.insert(uid, shown);
+    }
+
+    fn start_reconciling(&mut self) {
+        self.reconciling = Some(HashSet::new());
+        self.last_resync = Some(tokio::time::Instant::now());
+        self.timers.schedule(RECONCILE_WINDOW, Timer::ReconcileDone);
+    }
+
+    /// Compares the phone's count of active notifications in a category with ours, which drift
+    /// apart if we miss events, e.g. removals while a write was in flight.
+    fn check_category_count(&mut self, category_id: u8, category_count: u8) {
+        if self.reconciling.is_some() {
+            return;
+        }
+
+        let local_count = self.active.values().filter(|&&c| c == category_id).count();
+        if local_count == category_count as usize {
+            return;
+        }
 
+        let recently_resynced = self
+            .last_resync
+            .is_some_and(|t| t.elapsed() < RESYNC_INTERVAL);
+        if recently_resynced {
+            log::debug!(
+                "Category {} count still differs (phone {}, local {})",
+                category_id,
+                category_count,
+                local_count
+            );
+            return;
+        }
+
+        log::info!(
+            "Category {} count differs (phone {}, local {}), resubscribing to reconcile",
+            category_id,
+            category_count,
+            local_count
+        );
+        self.resync_requested = true;
+    }
+
+    async fn request_attributes(
+        &mut self,
+        notification_uid: u32,
+        app_id: Option<&str>,
+        attributes: &[Attribute],
+    ) -> Result<()> {
+        let info = self.pending.get(&notification_uid);
+        let mut attribute_ids = vec![(NotificationAttributeID::AppIdentifier, None)];
+        if !attributes.is_empty() {
+            let length = self.lengths.lock().unwrap().request(
+                &self.config,
+                notification_uid,
+                app_id,
+                info.map(|i| i.category_id),
+            );
+            let config = &self.config.attribute_length;
+            let most = match self.config.power.attribute_length {
+                Some(most) if self.on_battery() => most,
+                _ => u16::MAX,
+            };
+            attribute_ids.extend(attributes.iter().map(|&a| {
+                let length = config.fixed(a).unwrap_or(length).min(most);
+                (a.into(), Some(length))
+            }));
+        }
+        if let Some(info) = info {
+            if info.event_flags & EventFlag::PositiveAction as u8 != 0 {
+                attribute_ids.push((NotificationAttributeID::PositiveActionLabel, None));
+            }
+            if info.event_flags & EventFlag::NegativeAction as u8 != 0 {
+                attribute_ids.push((NotificationAttributeID::NegativeActionLabel, None));
+            }
+        }
+
+        metrics::control_point_write(
+            self.client()
+                .fetch_attributes(notification_uid, attribute_ids)
+                .await,
+        )?;
         Ok(())
     }
 
-    async fn process_data(&mut self, data: Vec<u8>) -> Result<()> {
-        match data[0] {
-            0 => {
-                let notif = match data_source::GetNotificationAttributesResponse::parse(&data) {
-                    Ok((_, app)) => app,
-                    Err(e) => {
-                        bail!("Error parsing notification attributes: {:?}", e);
+    fn on_battery(&self) -> bool {
+        self.power.as_ref().is_some_and(|p| p.on_battery())
+    }
+
+    /// How long to wait for periodic work, which is done less often on battery.
+    fn stretched(&self, interval: Duration) -> Duration {
+        if self.on_battery() {
+            interval.saturating_mul(self.config.power.slowdown.max(1))
+        } else {
+            interval
+        }
+    }
+
+    async fn request_app_name(&mut self, app_identifier: &str) -> Result<()> {
+        if !self.pending_app_names.insert(app_identifier.to_string()) {
+            return Ok(());
+        }
+
+        metrics::control_point_write(self.client().fetch_app_attributes(app_identifier).await)?;
+        Ok(())
+    }
+
+    /// Only called while running, once the client is there.
+    fn client(&self) -> &AncsClient {
+        self.client.as_ref().unwrap()
+    }
+
+    fn heard_from_phone(&mut self) {
+        if !self.heard_from_phone {
+            self.heard_from_phone = true;
+            bond::set_silent(self.address, false);
+            if state::current(self.address).state == SessionState::Degraded {
+                state::transition(self.address, SessionState::Subscribed, None);
+            }
+        }
+    }
+
+    /// Bundle identifiers are shown as the app name until the display name is known, unless
+    /// the user gave the app an alias or a name.
+    fn app_name(&self, app_identifier: &str) -> String {
+        let app_names = self.app_names.lock().unwrap();
+        if let Some(name) = app_names.custom(app_identifier) {
+            return name.to_string();
+        }
+        if self.config.aliases.contains_key(app_identifier) {
+            return self.config.canonical_app_id(app_identifier).to_string();
+        }
+
+        app_names
+            .get(app_identifier)
+            .unwrap_or(app_identifier)
+            .to_string()
+    }
+
+    /// Puts the label of the phone before an app name, if it has one.
+    fn labelled(&self, app_name: String) -> String {
+        match &self.label {
+            Some(label) => format!("{} · {}", label, app_name),
+            None => app_name,
+        }
+    }
+
+    async fn process_attributes(&mut self, notif: GetNotificationAttributesResponse) -> Result<()> {
+        let app_id = notif
+            .attribute_list
+            .iter()
+            .find(|attr| attr.id == NotificationAttributeID::AppIdentifier)
+            .and_then(|attr| attr.value.as_deref())
+            .unwrap_or_default();
+        log::info!(
+            ancs_uid = notif.notification_uid, app_id = app_id;
+            "Got the attributes of notification {} from {}",
+            notif.notification_uid,
+            app_id
+        );
+
+        if self.pending_app_lookups.remove(&notif.notification_uid) {
+            let blocked = self
+                .blocklist
+                .lock()
+                .unwrap()
+                .contains(&self.config, app_id);
+            if blocked || !self.config.is_allowed(app_id) {
+                log::debug!(
+                    "Not showing notification {} from {}",
+                    notif.notification_uid,
+                    app_id
+                );
+                metrics::inc(&METRICS.suppressed_not_allowed);
+                self.pending.remove(&notif.notification_uid);
+                return Ok(());
+            }
+            let attributes = self.config.allowed_attributes(app_id);
+            let app_id = app_id.to_string();
+            return self
+                .request_attributes(notif.notification_uid, Some(&app_id), &attributes)
+                .await;
+        }
+
+        let mut entry = history::Entry {
+            uid: notif.notification_uid,
+            app_id: String::new(),
+            title: None,
+            subtitle: None,
+            message: None,
+            received_at: self
+                .pending
+                .get(&notif.notification_uid)
+                .map_or_else(Utc::now, |i| i.announced.wall),
+            acknowledgment: None,
+            seen_at: None,
+            removed_at: None,
+        };
+        let private = privacy::is_private_mode();
+        let titles_only = private || self.screen_sharing() == ScreenSharing::TitlesOnly;
+        let mut desktop_notification = notify_rust::Notification::new();
+        if private {
+            desktop_notification.summary("New notification");
+        }
+        let mut labels = ActionLabels::default();
+        for attr in notif.attribute_list {
+            match attr.id {
+                NotificationAttributeID::AppIdentifier => {
+                    if let Some(v) = attr.value {
+                        desktop_notification.appname(&self.labelled(self.app_name(&v)));
+                        entry.app_id = v;
                     }
-                };
-                log::info!("Notif: {:?}", notif);
-
-                let mut desktop_notification = notify_rust::Notification::new();
-                for attr in notif.attribute_list {
-                    match attr.id {
-                        NotificationAttributeID::AppIdentifier => {
-                            if let Some(v) = attr.value {
-                                desktop_notification.appname(&v);
-                            }
-                        }
-                        NotificationAttributeID::Title => {
-                            if let Some(v) = attr.value {
-                                desktop_notification.summary(&v);
-                            }
+                }
+                NotificationAttributeID::Title => {
+                    if let Some(v) = attr.value {
+                        if !private {
+                            desktop_notification.summary(&v);
                         }
-                        NotificationAttributeID::Message => {
-                            if let Some(v) = attr.value {
-                                desktop_notification.body(&v);
-                            }
+                        entry.title = Some(v);
+                    }
+                }
+                NotificationAttributeID::Subtitle => {
+                    entry.subtitle = attr.value;
+                }
+                NotificationAttributeID::Message => {
+                    if let Some(v) = attr.value {
+                        if !titles_only {
+                            desktop_notification.body(&v);
                         }
-                        _ => {}
+                        entry.message = Some(v);
+                    }
+                }
+                NotificationAttributeID::PositiveActionLabel => {
+                    if let Some(v) = attr.value.filter(|v| !v.is_empty()) {
+                        labels.positive = Some(v);
+                    }
+                }
+                NotificationAttributeID::NegativeActionLabel => {
+                    if let Some(v) = attr.value.filter(|v| !v.is_empty()) {
+                        labels.negative = Some(v);
                     }
                 }
-                let handle = desktop_notification.show_async().await?;
+                _ => {}
+            }
+        }
+        let category_id = self
+            .pending
+            .get(&notif.notification_uid)
+            .map(|i| i.category_id);
+        let call = category_id == Some(CategoryID::IncomingCall as u8);
+        if call {
+            // Answered or declined on the phone all the same when it leaves out the labels
+            for (label, given) in [
+                ("Answer", &mut labels.positive),
+                ("Decline", &mut labels.negative),
+            ] {
+                if given.is_none() {
+                    *given = Some(label.to_string());
+                }
+            }
+        }
+        if let Some(id) = category_id {
+            let (category, icon) = desktop::category_style(id);
+            if let Some(category) = category {
+                desktop_notification.hint(Hint::Category(category.to_string()));
+            }
+            if let Some(icon) = icon {
+                desktop_notification.icon(icon);
+            }
+        }
+        if let Some(icon) = icons::for_app(&self.config, &entry.app_id) {
+            desktop_notification.icon(icon);
+        }
+        let values: Vec<&str> = [&entry.title, &entry.subtitle, &entry.message]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect();
+        self.lengths.lock().unwrap().record(
+            &self.config,
+            notif.notification_uid,
+            &entry.app_id,
+            category_id,
+            &values,
+        );
+        if !titles_only {
+            let message = entry.message.as_deref();
+            if let Some(placeholder) =
+                media::placeholder(&self.config, &entry.app_id, category_id, message)
+            {
+                desktop_notification.body(placeholder);
+            } else if message.is_none_or(str::is_empty) {
+                let split = entry
+                    .title
+                    .as_deref()
+                    .and_then(|title| layout::split_title(title, self.config.titles.split_over));
+                if let Some((summary, body)) = split {
+                    desktop_notification.summary(summary).body(body);
+                }
+            }
+        }
+        if let (Some(contacts), Some(title)) = (&self.contacts, &entry.title) {
+            if !private && contacts::is_from_person(&entry.app_id, category_id) {
+                if let Some(photo) = contacts.photo(title) {
+                    desktop_notification.image_path(&photo.to_string_lossy());
+                }
+            }
+        }
+
+        let known = self.app_names.lock().unwrap().get(&entry.app_id).is_some();
+        if !known {
+            self.request_app_name(&entry.app_id).await?;
+        }
+
+        let info = self.pending.get(&notif.notification_uid);
+        let important = info.is_some_and(|i| i.is_important());
+        if call {
+            // Rings until the phone removes it, once answered, declined or missed, or replaces it
+            desktop_notification
+                .urgency(self.config.urgency.calls.into())
+                .timeout(Timeout::Never)
+                .hint(Hint::Resident(true));
+        } else if important {
+            let urgency = self.config.urgency.important;
+            desktop_notification.urgency(urgency.into());
+            if urgency == config::Urgency::Critical {
+                desktop_notification.timeout(Timeout::Never);
+            }
+        } else if info.is_some_and(|i| i.is_silent()) {
+            desktop_notification
+                .urgency(self.config.urgency.silent.into())
+                .hint(Hint::SuppressSound(true));
+        }
+        let quiet = self.scheduler.is_quiet(Utc::now())
+            && !info.is_some_and(|i| {
+                i.breaks_through(&self.config, Some(&entry.app_id), Hold::QuietHours)
+            });
+        if quiet {
+            entry.acknowledgment = Some(Acknowledgment::Missed);
+        }
+
+        let redacted = privacy::redact(&self.config, &entry, category_id);
+        let (labels, withheld) = redacted.offered_actions(labels);
+        if redacted.is_sensitive() {
+            log::debug!(
+                "Notification {} is sensitive, its message is not kept",
+                notif.notification_uid
+            );
+        }
+        for (action, label) in [
+            (ActionID::Positive, &labels.positive),
+            (ActionID::Negative, &labels.negative),
+        ] {
+            if let Some(label) = label {
+                desktop_notification.action(action.key(), label);
+            }
+        }
+        if !entry.app_id.is_empty() && !call {
+            desktop_notification.action(
+                &blocklist::action_key(&entry.app_id),
+                "Never show from this app",
+            );
+        }
+        if !labels.list().is_empty() {
+            self.action_labels.insert(notif.notification_uid, labels);
+        }
+        if !withheld.is_empty() {
+            log::info!(
+                ancs_uid = notif.notification_uid, app_id = entry.app_id.as_str();
+                "Not offering to copy from sensitive notification {}",
+                notif.notification_uid
+            );
+            self.withheld_actions
+                .insert(notif.notification_uid, withheld);
+        }
+
+        let pre_existing = self
+            .pending
+            .get(&notif.notification_uid)
+            .is_some_and(|i| i.event_flags & EventFlag::PreExisting as u8 != 0);
+        if let Some(dedupe) = &self.dedupe {
+            let hash = dedupe::content_hash(redacted.entry());
+            let mut dedupe = dedupe.lock().unwrap();
+            let duplicate = pre_existing && dedupe.was_shown(hash);
+            dedupe.insert(notif.notification_uid, Some(hash));
+            if let Err(e) = dedupe.save() {
+                log::warn!("Failed to save shown notifications: {:?}", e);
+            }
+            if duplicate {
+                metrics::inc(&METRICS.suppressed_duplicates);
+                log::debug!(
+                    "Notification {} was shown before the restart",
+                    notif.notification_uid
+                );
+                self.pending.remove(&notif.notification_uid);
+                return Ok(());
+            }
+        }
+
+        let entry_id = self
+            .history
+            .as_ref()
+            .and_then(|history| match history.insert(&redacted) {
+                Ok(id) => Some(id),
+                Err(e) => {
+                    log::warn!(
+                        "Failed to keep notification {} in the history: {:?}",
+                        notif.notification_uid,
+                        e
+                    );
+                    None
+                }
+            });
+        if let Some(id) = entry_id {
+            self.entry_ids.insert(notif.notification_uid, id);
+        }
+        let modified = self
+            .entries
+            .insert(notif.notification_uid, redacted.entry().clone())
+            .is_some();
+        let kept = redacted.entry();
+        self.notify_sinks(AncsNotification {
+            event: if modified {
+                sinks::Kind::Modified
+            } else {
+                sinks::Kind::Received
+            },
+            device: service::display_address(self.address),
+            uid: kept.uid,
+            app_id: Some(kept.app_id.clone()),
+            app_name: self
+                .app_names
+                .lock()
+                .unwrap()
+                .get(&kept.app_id)
+                .map(str::to_string),
+            title: kept.title.clone(),
+            subtitle: kept.subtitle.clone(),
+            message: kept.message.clone(),
+            category: category_id.and_then(config::Category::of),
+            important,
+            time: kept.received_at,
+        });
+        if let Some(service) = &self.service {
+            let app_name = self.app_name(&entry.app_id);
+            service
+                .received(
+                    self.address,
+                    redacted.entry(),
+                    &app_name,
+                    category_id,
+                    important,
+                )
+                .await;
+        }
+        let desktop_app = self
+            .config
+            .app_sections(&entry.app_id)
+            .find_map(|app| app.desktop_app.as_deref());
+        if let (Some(focus), Some(desktop_app)) = (&self.focus, desktop_app) {
+            if focus.is_focused(desktop_app) {
+                metrics::inc(&METRICS.suppressed_focused);
                 log::info!(
-                    "Shown notification {} with desktop handle {}",
-                    notif.notification_uid,
-                    handle.id()
+                    ancs_uid = notif.notification_uid, app_id = entry.app_id.as_str();
+                    "{} has the focus, not showing notification {}",
+                    desktop_app,
+                    notif.notification_uid
                 );
+                self.pending.remove(&notif.notification_uid);
+                return Ok(());
             }
-            1 => {
-                let app = match data_source::GetAppAttributesResponse::parse(&data) {
-                    Ok((_, app)) => app,
-                    Err(e) => {
-                        bail!("Error parsing app attributes: {:?}", e);
-                    }
-                };
-                log::info!("App: {:?}", app);
+        }
+        if quiet {
+            metrics::inc(&METRICS.suppressed_quiet_hours);
+            self.quiet_tally.suppressed(&self.config, &entry.app_id);
+            log::info!(
+                ancs_uid = notif.notification_uid, app_id = entry.app_id.as_str();
+                "Quiet hours, not showing notification {}",
+                notif.notification_uid
+            );
+            self.pending.remove(&notif.notification_uid);
+            return Ok(());
+        }
+        let queued = QueuedNotification {
+            uid: notif.notification_uid,
+            notification: desktop_notification,
+            entry_id,
+            records_seen: self.config.records_seen(&entry.app_id),
+        };
+
+        let burst_window = self.config.bursts.window().filter(|_| {
+            media::is_chat(&self.config, &entry.app_id, category_id)
+                && !info.is_some_and(|i| {
+                    i.breaks_through(&self.config, Some(&entry.app_id), Hold::Bursts)
+                })
+        });
+        if let Some(window) = burst_window {
+            let key = (
+                entry.app_id.clone(),
+                entry.title.clone().unwrap_or_default(),
+            );
+            self.hold_in_burst(key, queued, window);
+            return Ok(());
+        }
+        let throttle = category_id
+            .and_then(|id| self.config.throttle(id))
+            .filter(|_| {
+                !info.is_some_and(|i| {
+                    i.breaks_through(&self.config, Some(&entry.app_id), Hold::Throttle)
+                })
+            });
+        if let (Some(id), Some((category, window))) = (category_id, throttle) {
+            match self.throttled.get_mut(&id) {
+                Some(held) => {
+                    log::debug!(
+                        "Holding notification {} back, {} notifications are throttled",
+                        queued.uid,
+                        category.label()
+                    );
+                    held.push(queued);
+                    return Ok(());
+                }
+                None => {
+                    self.throttled.insert(id, vec![]);
+                    self.timers.schedule(window, Timer::Throttle(id));
+                }
             }
-            _ => {}
         }
+        self.present(queued).await?;
 
         Ok(())
     }
-}
 
-#[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
-struct Args {
-    #[arg(
-        help = "Public Bluetooth MAC address of the device to connect to (as shown in system or `bluetoothctl`)"
-    )]
-    device_addr: Address,
+    fn notify_sinks(&self, notification: AncsNotification) {
+        for sink in self.sinks.iter() {
+            log::trace!(
+                "Passing notification {} to {}",
+                notification.uid,
+                sink.name()
+            );
+            sink.notify(&notification);
+        }
+    }
 
-    #[arg(long, help = "Bluetooth adapter name to use, if not the default one")]
-    adapter: Option<String>,
-}
+    fn process_app_attributes(&mut self, app: AppAttributesResponse) {
+        self.pending_app_names.remove(&app.app_identifier);
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    if std::env::var("RUST_LOG").is_err() {
-        std::env::set_var("RUST_LOG", "info");
+        let display_name = app
+            .attributes
+            .into_iter()
+            .find(|attr| attr.id == AppAttributeID::DisplayName as u8)
+            .filter(|attr| !attr.value.is_empty());
+        if let Some(attr) = display_name {
+            log::info!("App {} is called {}", app.app_identifier, attr.value);
+            let result = self
+                .app_names
+                .lock()
+                .unwrap()
+                .looked_up(&app.app_identifier, &attr.value);
+            if let Err(e) = result {
+                log::warn!("Failed to save app names: {:?}", e);
+            }
+        }
     }
-    env_logger::init();
 
-    let args = Args::parse();
+    fn process_malformed_response(&self, data: &[u8]) {
+        let Some(&command_id) = data.first() else {
+            log::warn!("Ignoring empty data source packet");
+            return;
+        };
+        if command_id <= CommandID::GetAppAttributes as u8 {
+            metrics::inc(&METRICS.parse_errors);
+            log::warn!(
+                "Ignoring malformed data source response: {}",
+                protocol::hex(data)
+            );
+            return;
+        }
 
-    let session = bluer::Session::new().await?;
-    let adapter = if let Some(name) = args.adapter {
-        session.adapter(&name)?
-    } else {
-        session.default_adapter().await?
-    };
-    adapter.set_powered(true).await?;
+        // Possibly from a newer revision of ANCS
+        let count = metrics::inc(&METRICS.unknown_data_commands);
+        log::warn!(
+            "Ignoring data source response with unknown command {} ({} so far): {}",
+            command_id,
+            count,
+            protocol::hex(data)
+        );
+    }
+
+    async fn show_desktop_notification(&mut self, queued: QueuedNotification) -> Result<()> {
+        let QueuedNotification {
+            uid,
+            mut notification,
+            entry_id,
+            records_seen,
+        } = queued;
+
+        // Before printing, which would mix with `--output json`
+        if self.config.is_headless() {
+            log::debug!("Headless, not showing notification {}", uid);
+            return Ok(());
+        }
+        if safemode::is_active() {
+            println!(
+                "[{}] {}: {}",
+                notification.appname, notification.summary, notification.body
+            );
+            return Ok(());
+        }
+        if self.desktop_outage.is_some() {
+            self.hold_during_outage(QueuedNotification {
+                uid,
+                notification,
+                entry_id,
+                records_seen,
+            });
+            return Ok(());
+        }
+
+        // Modified on the phone, e.g. a new message in the same thread
+        let previous = self.shown.get(&uid);
+        if let Some(previous) = previous {
+            notification.id(previous.desktop_id);
+        }
+        let handle = match notification.show_async().await {
+            Ok(handle) => {
+                metrics::inc(&METRICS.shown);
+                handle
+            }
+            Err(e) => {
+                log::warn!(
+                    "Cannot reach the notification server, holding notifications back until it is back: {:?}",
+                    e
+                );
+                self.desktop_outage = Some(VecDeque::new());
+                self.timers
+                    .schedule(self.stretched(DESKTOP_RETRY), Timer::DesktopRetry);
+                self.hold_during_outage(QueuedNotification {
+                    uid,
+                    notification,
+                    entry_id,
+                    records_seen,
+                });
+                return Ok(());
+            }
+        };
+        if self.notification_server.is_none() {
+            self.notification_server = desktop::server_owner().await.ok();
+        }
+        // A bubble that is still up keeps its watcher, replacing it does not close it
+        if previous.is_some_and(|p| p.watched) {
+            log::info!(ancs_uid = uid; "Updated notification {} in place", uid);
+        } else {
+            log::info!(
+                ancs_uid = uid, desktop_id = handle.id();
+                "Shown notification {} with desktop handle {}",
+                uid,
+                handle.id()
+            );
+            self.watch_desktop_notification(uid, handle.id(), entry_id);
+        }
+        if let (true, Some(history), Some(session), Some(entry_id)) =
+            (records_seen, &self.history, &self.session, entry_id)
+        {
+            if session.is_active().await {
+                if let Err(e) = history.set_seen(entry_id, Utc::now()) {
+                    log::warn!(
+                        "Failed to record when notification {} was seen: {:?}",
+                        uid,
+                        e
+                    );
+                }
+            }
+        }
+        self.set_shown(
+            uid,
+            Shown {
+                desktop_id: handle.id(),
+                watched: true,
+            },
+        );
+
+        let info = self.pending.remove(&uid);
+        if let Some(info) = info {
+            log::debug!(
+                "Showing notification {} took {:?} since the phone announced it",
+                uid,
+                info.announced.elapsed()
+            );
+            self.last_shown = Some(LastShown {
+                uid,
+                desktop_id: handle.id(),
+                event_flags: info.event_flags,
+            });
+            self.control.shown_by(self.address);
+        }
+        if let Some(interval) = self.config.reannounce.interval() {
+            if info.is_some_and(|i| i.is_important()) {
+                notification.id(handle.id());
+                self.reannouncements.insert(
+                    uid,
+                    Reannouncement {
+                        notification,
+                        visible: true,
+                    },
+                );
+                self.timers
+                    .schedule(self.stretched(interval), Timer::Reannounce(uid));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn hold_during_outage(&mut self, queued: QueuedNotification) {
+        let Some(outage) = &mut self.desktop_outage else {
+            return;
+        };
+        // A modification replaces what was held back of the same notification
+        outage.retain(|q| q.uid != queued.uid);
+        if outage.len() >= MAX_OUTAGE_QUEUE {
+            let dropped = outage.pop_front().unwrap();
+            log::warn!(
+                "Too many notifications held back, dropping notification {}",
+                dropped.uid
+            );
+        }
+        outage.push_back(queued);
+    }
+
+    /// Shows what was held back once the notification server is reachable again.
+    ///
+    /// A restarted server hands out desktop ids anew, so the ids of what the old one showed
+    /// are forgotten rather than replacing unrelated notifications.
+    async fn retry_desktop(&mut self) -> Result<()> {
+        let server = match desktop::server_owner().await {
+            Ok(server) => server,
+            Err(e) => {
+                log::debug!("Notification server still unreachable: {:?}", e);
+                self.timers
+                    .schedule(self.stretched(DESKTOP_RETRY), Timer::DesktopRetry);
+                return Ok(());
+            }
+        };
+        if self.notification_server.as_ref() != Some(&server) {
+            log::info!("The notification server restarted, forgetting its desktop ids");
+            self.shown.clear();
+            self.handles.lock().unwrap().clear();
+            self.last_shown = None;
+            for r in self.reannouncements.values_mut() {
+                r.notification.id(0);
+                r.visible = false;
+            }
+            self.notification_server = Some(server);
+        }
+
+        let held = self.desktop_outage.take().unwrap_or_default();
+        log::info!(
+            "The notification server is back, showing {} held back notifications",
+            held.len()
+        );
+        for queued in held {
+            self.show_desktop_notification(queued).await?;
+        }
+        Ok(())
+    }
+
+    /// Shows a notification, unless it has to be held back for now.
+    async fn present(&mut self, queued: QueuedNotification) -> Result<()> {
+        let foreground = match &self.session {
+            Some(session) => session.is_foreground().await,
+            None => true,
+        };
+        if !foreground {
+            log::info!(
+                "Session is in the background, queueing notification {}",
+                queued.uid
+            );
+            self.queued.push(queued);
+        } else if self.screen_sharing() == ScreenSharing::Hide {
+            log::info!("Screen is shared, queueing notification {}", queued.uid);
+            self.queued.push(queued);
+        } else {
+            self.show_desktop_notification(queued).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Holds a chat notification back until the burst it starts is over, or merges it into the
+    /// burst already held back from the same sender.
+    fn hold_in_burst(&mut self, key: BurstKey, mut queued: QueuedNotification, window: Duration) {
+        match self.bursts.remove(&key) {
+            Some(held) => {
+                log::debug!(
+                    "Merging notification {} into the burst of {}",
+                    queued.uid,
+                    held.uid
+                );
+                // The latest one stands for the whole burst
+                self.pending.remove(&held.uid);
+                let body = &mut queued.notification.body;
+                let held_body = held.notification.body;
+                // Unless the phone modified the held one
+                if held.uid != queued.uid && !held_body.is_empty() {
+                    *body = if body.is_empty() {
+                        held_body
+                    } else {
+                        format!("{}\n{}", held_body, body)
+                    };
+                }
+                self.bursts.insert(key, queued);
+            }
+            None => {
+                self.bursts.insert(key.clone(), queued);
+                self.timers.schedule(window, Timer::Burst(key));
+            }
+        }
+    }
+
+    /// One notification standing for all those held back in a throttled category, the most
+    /// recent one with every app and summary listed if there are several.
+    fn sum_up(
+        &mut self,
+        mut held: Vec<QueuedNotification>,
+        category: config::Category,
+    ) -> Option<QueuedNotification> {
+        let mut latest = held.pop()?;
+        if held.is_empty() {
+            return Some(latest);
+        }
+        let count = held.len() + 1;
+        log::debug!(
+            "Summing up {} {} notifications in {}",
+            count,
+            category.label(),
+            latest.uid
+        );
+        let lines: Vec<String> = held
+            .iter()
+            .chain(std::iter::once(&latest))
+            .map(|q| format!("{}: {}", q.notification.appname, q.notification.summary))
+            .collect();
+        for earlier in held {
+            metrics::inc(&METRICS.suppressed_throttled);
+            self.pending.remove(&earlier.uid);
+        }
+        latest
+            .notification
+            .summary(&format!("{} {} notifications", count, category.label()))
+            .body(&lines.join("\n"));
+        Some(latest)
+    }
+
+    fn screen_sharing(&self) -> ScreenSharing {
+        match &self.screen_cast {
+            Some(s) if s.is_sharing() => self.config.privacy.while_screen_sharing,
+            _ => ScreenSharing::Show,
+        }
+    }
+
+    /// Shows held back notifications, unless they are still held back.
+    async fn show_queued(&mut self) -> Result<()> {
+        if self.queued.is_empty() {
+            return Ok(());
+        }
+        let foreground = match &self.session {
+            Some(session) => session.is_foreground().await,
+            None => true,
+        };
+        if !foreground || self.screen_sharing() == ScreenSharing::Hide {
+            return Ok(());
+        }
+
+        log::info!("Showing {} queued notifications", self.queued.len());
+        for queued in std::mem::take(&mut self.queued) {
+            self.show_desktop_notification(queued).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn process_desktop_event(&mut self, event: DesktopEvent) -> Result<()> {
+        let uid = match &event {
+            DesktopEvent::Acknowledged(uid)
+            | DesktopEvent::Expired(uid)
+            | DesktopEvent::Action { uid, .. }
+            | DesktopEvent::NeverShow { uid, .. } => *uid,
+        };
+        // Its watcher is done
+        if let Some(shown) = self.shown.get_mut(&uid) {
+            shown.watched = false;
+        }
+
+        match event {
+            DesktopEvent::Acknowledged(uid) => {
+                if self.reannouncements.remove(&uid).is_some() {
+                    log::info!("Notification {} acknowledged, no longer reannouncing", uid);
+                }
+            }
+            DesktopEvent::Expired(uid) => {
+                if let Some(r) = self.reannouncements.get_mut(&uid) {
+                    r.visible = false;
+                }
+            }
+            DesktopEvent::Action {
+                uid,
+                desktop_id,
+                action,
+            } => {
+                self.reannouncements.remove(&uid);
+                if let Err(e) = self.perform_action(uid, desktop_id, action).await {
+                    log::warn!("Not performing the action: {:?}", e);
+                }
+            }
+            DesktopEvent::NeverShow { uid, app_id } => {
+                self.reannouncements.remove(&uid);
+                log::info!("Never showing notifications from {} again", app_id);
+                // Blocked until the next restart all the same
+                if let Err(e) = self.blocklist.lock().unwrap().add(&app_id) {
+                    log::warn!("Failed to save the blocklist: {:?}", e);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn process_request(&mut self, request: service::Request) {
+        match request {
+            service::Request::ActOnLast { action, reply } => {
+                let result = match &self.last_shown {
+                    None => Err("No notification to act on".to_string()),
+                    Some(last) => {
+                        let flag = match action {
+                            ActionID::Positive => EventFlag::PositiveAction,
+                            ActionID::Negative => EventFlag::NegativeAction,
+                        };
+                        if last.event_flags & flag as u8 == 0 {
+                            Err(format!("The notification has no {} action", action.key()))
+                        } else {
+                            let (uid, desktop_id) = (last.uid, last.desktop_id);
+                            self.perform_action(uid, desktop_id, action)
+                                .await
+                                .map_err(|e| e.to_string())
+                        }
+                    }
+                };
+                let _ = reply.send(result);
+            }
+            service::Request::Perform { uid, action, reply } => {
+                let result = if self.active.contains_key(&uid) {
+                    let desktop_id = self.shown.get(&uid).map_or(0, |s| s.desktop_id);
+                    self.perform_action(uid, desktop_id, action)
+                        .await
+                        .map_err(|e| e.to_string())
+                } else {
+                    Err(format!("Notification {} is not on the phone", uid))
+                };
+                let _ = reply.send(result);
+            }
+            service::Request::Ring { level, reply } => {
+                let result = match &self.alert {
+                    Some(alert) => alert.alert(level).await.map_err(|e| e.to_string()),
+                    None => Err("The phone offers no Immediate Alert Service".to_string()),
+                };
+                let _ = reply.send(result);
+            }
+            service::Request::List { reply } => {
+                let _ = reply.send(self.list());
+            }
+            service::Request::Reload { config } => {
+                self.config = config;
+            }
+            service::Request::SendAction { uid, label, reply } => {
+                let result = match self.action_labeled(uid, &label) {
+                    Ok(action) => {
+                        let desktop_id = self.shown.get(&uid).map_or(0, |s| s.desktop_id);
+                        self.perform_action(uid, desktop_id, action)
+                            .await
+                            .map_err(|e| e.to_string())
+                    }
+                    Err(e) => Err(e),
+                };
+                let _ = reply.send(result);
+            }
+        }
+    }
+
+    /// The notifications on the phone whose attributes arrived, as they arrived.
+    fn list(&self) -> Vec<service::Active> {
+        let private = privacy::is_private_mode();
+        let mut active: Vec<service::Active> = self
+            .entries
+            .values()
+            .map(|entry| service::Active {
+                device: service::display_address(self.address),
+                uid: entry.uid,
+                app_id: entry.app_id.clone(),
+                app_name: self.app_name(&entry.app_id),
+                title: match &entry.title {
+                    Some(title) if !private => title.clone(),
+                    _ => String::new(),
+                },
+                category: self
+                    .active
+                    .get(&entry.uid)
+                    .and_then(|&id| config::Category::of(id)),
+                received_at: entry.received_at,
+                shown: self.shown.contains_key(&entry.uid),
+            })
+            .collect();
+        active.sort_by_key(|a| (a.received_at, a.uid));
+        active
+    }
+
+    /// The action of a notification with the given label, as the phone told it.
+    fn action_labeled(&self, uid: u32, label: &str) -> Result<ActionID, String> {
+        if !self.active.contains_key(&uid) {
+            return Err(format!("Notification {} is not on the phone", uid));
+        }
+        let Some(labels) = self.action_labels.get(&uid) else {
+            return Err(format!("Notification {} has no actions", uid));
+        };
+        labels.find(label).ok_or_else(|| {
+            let known: Vec<String> = labels.list().iter().map(|l| format!("{:?}", l)).collect();
+            format!(
+                "Notification {} has no action labeled {:?}, only {}",
+                uid,
+                label,
+                known.join(" and ")
+            )
+        })
+    }
+
+    /// Asks the phone to perform an action, it confirms by removing the notification.
+    async fn perform_action(&mut self, uid: u32, desktop_id: u32, action: ActionID) -> Result<()> {
+        if self
+            .withheld_actions
+            .get(&uid)
+            .is_some_and(|withheld| withheld.contains(&action))
+        {
+            bail!(
+                "The {} action of notification {} would copy sensitive content",
+                action.key(),
+                uid
+            );
+        }
+        log::info!(
+            ancs_uid = uid, action = action.key();
+            "Performing {} action on notification {}",
+            action.key(),
+            uid
+        );
+        match metrics::control_point_write(self.client().perform_action(uid, action).await) {
+            Ok(()) => {
+                self.pending_actions.insert(uid, desktop_id);
+                self.timers
+                    .schedule(ACTION_TIMEOUT, Timer::ActionTimeout(uid));
+            }
+            Err(e) => {
+                if let AncsError::WriteFailed {
+                    att_error: Some(protocol::ERROR_ACTION_FAILED),
+                    ..
+                } = e
+                {
+                    log::warn!(
+                        "The phone could not perform the action on notification {}",
+                        uid
+                    );
+                } else {
+                    log::warn!("Failed to perform action on notification {}: {:?}", uid, e);
+                }
+
+                let result = notify_rust::Notification::new()
+                    .id(desktop_id)
+                    .summary("Action failed")
+                    .body("The phone could not perform the action.")
+                    .show_async()
+                    .await;
+                if let Err(e) = result {
+                    log::warn!("Failed to tell that the action failed: {:?}", e);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn process_timer(&mut self, timer: Timer) -> Result<()> {
+        match timer {
+            Timer::Reannounce(uid) => {
+                let (Some(r), Some(interval)) = (
+                    self.reannouncements.get_mut(&uid),
+                    self.config.reannounce.interval(),
+                ) else {
+                    return Ok(());
+                };
+
+                let handle = match r.notification.show_async().await {
+                    Ok(handle) => handle,
+                    Err(e) => {
+                        log::warn!("Failed to reannounce notification {}: {:?}", uid, e);
+                        self.timers
+                            .schedule(self.stretched(interval), Timer::Reannounce(uid));
+                        return Ok(());
+                    }
+                };
+                log::info!("Reannounced notification {}", uid);
+                r.notification.id(handle.id());
+                // A bubble that is still up keeps its watcher, replacing it does not close it
+                let watch = !r.visible;
+                r.visible = true;
+                self.set_shown(
+                    uid,
+                    Shown {
+                        desktop_id: handle.id(),
+                        watched: true,
+                    },
+                );
+
+                if watch {
+                    self.watch_desktop_notification(uid, handle.id(), None);
+                }
+                self.timers
+                    .schedule(self.stretched(interval), Timer::Reannounce(uid));
+            }
+            Timer::ReconcileDone => {
+                let Some(seen) = self.reconciling.take() else {
+                    return Ok(());
+                };
+
+                // Shown but not on the phone are those left over from before
+                let stale: HashSet<u32> = self
+                    .active
+                    .keys()
+                    .chain(self.shown.keys())
+                    .filter(|uid| !seen.contains(uid))
+                    .copied()
+                    .collect();
+                if !stale.is_empty() {
+                    log::info!("Dropping {} notifications gone from the phone", stale.len());
+                }
+                for uid in stale {
+                    self.remove_notification(uid).await;
+                }
+
+                self.catching_up = false;
+                self.showing_existing = false;
+                if let Some(dedupe) = &self.dedupe {
+                    let mut dedupe = dedupe.lock().unwrap();
+                    dedupe.retain(|uid| self.active.contains_key(&uid));
+                    if let Err(e) = dedupe.save() {
+                        log::warn!("Failed to save shown notifications: {:?}", e);
+                    }
+                }
+            }
+            Timer::Debounced(uid) => {
+                let since_last = self.recent_events.get(&uid).map(|t| t.elapsed());
+                if let Some(since_last) = since_last.filter(|&d| d < FLOOD_WINDOW) {
+                    self.timers
+                        .schedule(FLOOD_WINDOW - since_last, Timer::Debounced(uid));
+                    return Ok(());
+                }
+                if let Some(info) = self.debounced.remove(&uid) {
+                    log::debug!("Notification {} settled", uid);
+                    self.fetch_notification(uid, info).await?;
+                }
+            }
+            Timer::Burst(key) => {
+                if let Some(queued) = self.bursts.remove(&key) {
+                    self.present(queued).await?;
+                }
+            }
+            Timer::Throttle(id) => {
+                let held = self.throttled.remove(&id).unwrap_or_default();
+                if let Some((category, window)) = self.config.throttle(id) {
+                    if let Some(queued) = self.sum_up(held, category) {
+                        // Showing it starts the next window
+                        self.throttled.insert(id, vec![]);
+                        self.timers.schedule(window, Timer::Throttle(id));
+                        self.present(queued).await?;
+                    }
+                }
+            }
+            Timer::DesktopRetry => self.retry_desktop().await?,
+            Timer::SharingCheck => {
+                if !self.heard_from_phone {
+                    log::warn!(
+                        "Nothing arrived from the phone in {} seconds since subscribing, it probably does not share notifications",
+                        SHARING_TIMEOUT.as_secs()
+                    );
+                    bond::set_silent(self.address, true);
+                    state::transition(
+                        self.address,
+                        SessionState::Degraded,
+                        Some("The phone has not sent anything since subscribing".to_string()),
+                    );
+                }
+            }
+            Timer::ActionTimeout(uid) => {
+                if self.pending_actions.remove(&uid).is_some() {
+                    log::debug!("Notification {} was kept after its action", uid);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Waits for the desktop notification to go away, then reports it back to the processor and
+    /// records in the history whether it was seen or missed.
+    fn watch_desktop_notification(&self, uid: u32, desktop_id: u32, entry_id: Option<i64>) {
+        let history = self.history.clone();
+        let session = self.session.clone();
+        let desktop_tx = self.desktop_tx.clone();
+        let Some(router) = self.router.clone() else {
+            return;
+        };
+
+        tokio::spawn(async move {
+            let shown_while_active = match &session {
+                Some(s) => Some(s.is_active().await),
+                None => None,
+            };
+
+            let event = match router.watch(desktop_id).await {
+                Some(desktop::Response::Action(key)) => Some(match ActionID::from_key(&key) {
+                    Some(action) => DesktopEvent::Action {
+                        uid,
+                        desktop_id,
+                        action,
+                    },
+                    None => match blocklist::app_from_action_key(&key) {
+                        Some(app_id) => DesktopEvent::NeverShow {
+                            uid,
+                            app_id: app_id.to_string(),
+                        },
+                        None => DesktopEvent::Acknowledged(uid),
+                    },
+                }),
+                Some(desktop::Response::Closed(CloseReason::Dismissed)) => {
+                    Some(DesktopEvent::Acknowledged(uid))
+                }
+                Some(desktop::Response::Closed(CloseReason::Expired)) => {
+                    Some(DesktopEvent::Expired(uid))
+                }
+                Some(desktop::Response::Closed(_)) | None => None,
+            };
+            if let Some(event) = event {
+                let _ = desktop_tx.send(event);
+            }
+
+            let (Some(history), Some(session), Some(entry_id)) = (history, session, entry_id)
+            else {
+                return;
+            };
+            let acknowledgment = if shown_while_active == Some(true) && session.is_active().await {
+                Acknowledgment::Seen
+            } else {
+                Acknowledgment::Missed
+            };
+            if let Err(e) = history.set_acknowledgment(entry_id, acknowledgment) {
+                log::warn!("Failed to record acknowledgment: {:?}", e);
+            }
+        });
+    }
+}
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Cli {
+    #[arg(
+        long,
+        global = true,
+        help = "Path to the config file [default: $XDG_CONFIG_HOME/ancs-linux/config.toml]"
+    )]
+    config: Option<PathBuf>,
+
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        default_value_t,
+        help = "Where log lines go, the journal with fields such as APP_ID or stderr"
+    )]
+    log: logging::LogOutput,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Forward notifications from the device to the desktop
+    Listen(ListenArgs),
+    /// Show previously received notifications
+    History(HistoryArgs),
+    /// Show what the current config would do with notifications from the history, without
+    /// showing or forwarding them
+    Audit(AuditArgs),
+    /// Pair with the device and trust it, which it needs to share notifications
+    Pair(DeviceArgs),
+    /// Check the connection to the device and show what it supports
+    Doctor(DeviceArgs),
+    /// Scan for nearby iPhones and paired Apple devices, to find the address to listen to
+    Discover(DiscoverArgs),
+    /// Control a running `listen`
+    Ctl(CtlArgs),
+    /// Show how many notifications a running `listen` suppressed, and why
+    Stats(StatsArgs),
+    /// Watch which apps send notifications, then choose which of them to allow
+    Setup(SetupArgs),
+    /// Measure how fast notifications from a simulated device get through the pipeline
+    Bench(BenchArgs),
+    /// Run `listen` as a systemd user service, enabled with every login
+    InstallService(InstallServiceArgs),
+    /// Stop, disable and remove the service of `install-service`
+    UninstallService,
+}
+
+#[derive(Args, Debug)]
+struct InstallServiceArgs {
+    #[arg(
+        help = "Public Bluetooth MAC addresses of the devices to connect to, those in `devices` in the config when it runs if none"
+    )]
+    device_addr: Vec<Address>,
+
+    #[arg(long, help = "Bluetooth adapter name to use, if not the default one")]
+    adapter: Option<String>,
+
+    #[arg(long, value_enum, help = "Passed on to `listen`")]
+    preset: Option<Preset>,
+
+    #[arg(long, help = "Also start it right away")]
+    now: bool,
+}
+
+#[derive(Args, Debug)]
+struct BenchArgs {
+    #[arg(long, default_value_t = 600, help = "Notifications per minute")]
+    rate: u32,
+
+    #[arg(
+        long,
+        default_value_t = 10,
+        help = "Seconds to keep sending notifications for"
+    )]
+    seconds: u64,
+
+    #[arg(
+        long,
+        default_value_t = 500,
+        help = "Bytes of every message, split over many packets like on a real link"
+    )]
+    message_len: usize,
+}
+
+#[derive(Args, Debug)]
+struct DiscoverArgs {
+    #[arg(long, help = "Bluetooth adapter name to use, if not the default one")]
+    adapter: Option<String>,
+
+    #[arg(long, default_value_t = 10, help = "Seconds to scan for")]
+    seconds: u64,
+}
+
+#[derive(Args, Debug)]
+struct StatsArgs {
+    #[arg(
+        long,
+        help = "Summarize the last 7 days of the history instead, no running `listen` needed"
+    )]
+    weekly: bool,
+}
+
+#[derive(Args, Debug)]
+struct SetupArgs {
+    #[command(flatten)]
+    device: DeviceArgs,
+
+    #[arg(
+        long,
+        default_value_t = 10,
+        help = "Minutes to watch for notifications before asking"
+    )]
+    minutes: u64,
+}
+
+#[derive(Args, Debug)]
+struct CtlArgs {
+    #[command(subcommand)]
+    command: control::Command,
+}
+
+#[derive(Args, Debug, Clone)]
+struct ListenArgs {
+    #[arg(
+        help = "Public Bluetooth MAC addresses of the devices to connect to (as shown in system or `bluetoothctl`), those in `devices` in the config if none"
+    )]
+    device_addr: Vec<Address>,
+
+    #[arg(
+        long,
+        value_name = "NAME",
+        help = "Name or alias of a device to connect to instead of its address, e.g. \"Alice's iPhone\", can be given several times"
+    )]
+    device_name: Vec<String>,
+
+    #[arg(long, help = "Bluetooth adapter name to use, if not the default one")]
+    adapter: Option<String>,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["device_addr", "device_name", "adapter"],
+        help = "Run against a fake device playing scripted notifications, for development"
+    )]
+    simulate: bool,
+
+    #[arg(
+        long,
+        conflicts_with = "simulate",
+        help = "Advertise that this computer wants notifications, so that the phone connects on its own; without devices, mirror the first paired phone that connects"
+    )]
+    advertise: bool,
+
+    #[arg(
+        long,
+        help = "Show the notifications already on the phone when first connecting, to catch up on them"
+    )]
+    show_existing: bool,
+
+    #[arg(
+        long,
+        help = "Show every notification with normal urgency, whether the phone marks it important or silent"
+    )]
+    ignore_urgency_flags: bool,
+
+    #[arg(
+        long,
+        help = "Bytes of title and subtitle to request from the phone, instead of learning it per app"
+    )]
+    title_len: Option<u16>,
+
+    #[arg(
+        long,
+        help = "Bytes of message to request from the phone, instead of learning it per app"
+    )]
+    message_len: Option<u16>,
+
+    #[arg(
+        long,
+        conflicts_with = "message_len",
+        help = "Request whole messages from the phone, however long"
+    )]
+    full_message: bool,
+
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        conflicts_with = "simulate",
+        help = "Explain what keeps notifications from working if they still do not after this long"
+    )]
+    startup_timeout: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "APP_ID",
+        help = "Never show notifications from this app, can be given several times"
+    )]
+    ignore_app: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "APP_ID",
+        help = "Only show notifications from this app instead of those in `filter.allow`, can be given several times"
+    )]
+    only_app: Vec<String>,
+
+    #[arg(
+        long,
+        help = "Show nothing but the app of new notifications, until `ctl privacy off`"
+    )]
+    privacy: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        help = "Settings for a kind of setup, over those in the config"
+    )]
+    preset: Option<Preset>,
+
+    #[arg(
+        long,
+        value_enum,
+        help = "Where notifications go, instead of `output` in the config"
+    )]
+    output: Option<Output>,
+
+    #[arg(
+        long,
+        value_name = "COMMAND",
+        help = "Shell command to run for every notification, with it in ANCS_* variables, instead of `exec.command`"
+    )]
+    exec: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "ADDR",
+        help = "Serve metrics for Prometheus over HTTP on this address, e.g. 127.0.0.1:9109, instead of `metrics.addr`"
+    )]
+    metrics_addr: Option<std::net::SocketAddr>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Write every packet exchanged with the phone to this file, as hex and decoded"
+    )]
+    trace_protocol: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct DeviceArgs {
+    #[arg(
+        help = "Public Bluetooth MAC address of the device to connect to (as shown in system or `bluetoothctl`)"
+    )]
+    device_addr: Address,
+
+    #[arg(long, help = "Bluetooth adapter name to use, if not the default one")]
+    adapter: Option<String>,
+}
+
+#[derive(Args, Debug)]
+#[command(args_conflicts_with_subcommands = true)]
+struct HistoryArgs {
+    #[command(subcommand)]
+    command: Option<HistoryCommand>,
+
+    #[arg(
+        long,
+        help = "Only show notifications that were shown while away from the desktop"
+    )]
+    missed: bool,
+
+    #[arg(
+        long,
+        short = 'n',
+        default_value_t = 20,
+        help = "Number of notifications to show"
+    )]
+    limit: usize,
+
+    #[arg(long, help = "Group by app, ordered by name, instead of by time only")]
+    by_app: bool,
+
+    #[arg(
+        long,
+        help = "Only show notifications from this app, by bundle identifier or name"
+    )]
+    app: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "RANGE",
+        help = "Only show notifications received since e.g. `7d`, `12h` or `2026-10-01`, or \
+                within `2026-10-01..2026-10-07`"
+    )]
+    since: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct AuditArgs {
+    #[arg(
+        help = "Notifications received within e.g. `7d`, `12h`, `2026-10-01` or \
+                  `2026-10-01..2026-10-07`"
+    )]
+    range: String,
+}
+
+#[derive(Subcommand, Debug)]
+enum HistoryCommand {
+    /// Write the history and app names to a file, to take them along to another machine
+    Export {
+        #[arg(long, help = "File to write the archive to")]
+        archive: PathBuf,
+    },
+    /// Add the history and app names from an exported archive, skipping what is already here
+    Import {
+        #[arg(help = "Archive written by `history export`")]
+        archive: PathBuf,
+    },
+}
 
-    log::info!("Using adapter: {}", adapter.name());
+/// Bundle identifiers `app` may stand for: itself, the apps of that name, and the builds aliased
+/// to either.
+fn app_ids(config: &Config, app_names: &AppNames, app: &str) -> Vec<String> {
+    let mut ids: HashSet<String> = app_names
+        .list()
+        .filter(|(_, name)| name.name.to_lowercase() == app.to_lowercase())
+        .map(|(id, _)| id.to_string())
+        .collect();
+    ids.insert(app.to_string());
+    // Along with the builds aliased to them
+    ids.extend(
+        config
+            .aliases
+            .iter()
+            .filter(|(_, canonical)| ids.contains(*canonical))
+            .map(|(id, _)| id.clone())
+            .collect::<Vec<_>>(),
+    );
+    ids.into_iter().collect()
+}
 
-    loop {
-        let proc = AncsProcessor::new();
-        if let Err(e) = proc.main_loop(args.device_addr, &adapter).await {
+fn history(config: &Config, args: HistoryArgs) -> Result<()> {
+    let history = History::open(&config.history)?;
+    match args.command {
+        Some(HistoryCommand::Export { archive: path }) => {
+            let app_names = AppNames::open(&Stores::open(&config.storage)?);
+            let archive = Archive::collect(&history, &app_names)?;
+            archive.write(&path)?;
+            println!(
+                "Exported {} notifications and {} app names to {}",
+                archive.history.len(),
+                archive.app_names.len(),
+                path.display()
+            );
+            return Ok(());
+        }
+        Some(HistoryCommand::Import { archive: path }) => {
+            let mut app_names = AppNames::open(&Stores::open(&config.storage)?);
+            let (entries, names) = Archive::read(&path)?.restore(&history, &mut app_names)?;
+            println!("Imported {} notifications and {} app names", entries, names);
+            return Ok(());
+        }
+        None => {}
+    }
+
+    let app_ids = match &args.app {
+        Some(app) => Some(app_ids(
+            config,
+            &AppNames::open(&Stores::open(&config.storage)?),
+            app,
+        )),
+        None => None,
+    };
+    let mut range = match &args.since {
+        Some(since) => audit::Range::parse(since, Utc::now(), &Local)?,
+        None => audit::Range::default(),
+    };
+    // A day on its own is where to start from, not the only one
+    if args
+        .since
+        .as_deref()
+        .is_some_and(|since| !since.contains(".."))
+    {
+        range.until = None;
+    }
+    let entries = history.query(&history::Query {
+        missed_only: args.missed,
+        app_ids,
+        limit: Some(args.limit),
+        since: range.since,
+        until: range.until,
+    })?;
+
+    let entries: Vec<_> = entries.iter().map(|e| e.listed(config)).collect();
+    control::print_history(&entries, args.by_app)
+}
+
+#[cfg(feature = "bench")]
+#[global_allocator]
+static ALLOCATOR: bench::CountingAllocator = bench::CountingAllocator;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    if std::env::var("RUST_LOG").is_err() {
+        // The processor logs every notification, which would bury the report of `bench`
+        let level = match cli.command {
+            Command::Bench(_) => "warn",
+            _ => "info",
+        };
+        std::env::set_var("RUST_LOG", level);
+    }
+    logging::init(cli.log);
+    supervisor::install_panic_hook();
+
+    if let Command::Setup(args) = cli.command {
+        return setup(cli.config, args).await;
+    }
+    let config_path = cli.config.clone();
+    let mut config = Config::load(cli.config)?;
+
+    match cli.command {
+        Command::Listen(args) => {
+            args.apply(&mut config);
+            listen(Arc::new(config), config_path, args).await
+        }
+        Command::History(args) => history(&config, args),
+        Command::Audit(args) => audit::run(&config, &args.range),
+        Command::Pair(args) => {
+            let session = bluer::Session::new().await?;
+            let adapter = session_adapter(&session, args.adapter.as_deref()).await?;
+            pairing::run(&session, &adapter, args.device_addr).await
+        }
+        Command::Doctor(args) => doctor(args).await,
+        Command::Discover(args) => discover(args).await,
+        Command::Ctl(args) => ctl(args).await,
+        Command::Stats(args) => stats(&config, args).await,
+        Command::InstallService(args) => install::install(
+            &config,
+            config_path.as_deref(),
+            &args.device_addr,
+            args.adapter.as_deref(),
+            args.preset,
+            args.now,
+        ),
+        Command::UninstallService => install::uninstall(),
+        Command::Bench(args) => {
+            let report = bench::run(&bench::Options {
+                rate: args.rate,
+                duration: Duration::from_secs(args.seconds),
+                message_len: args.message_len,
+            })
+            .await?;
+            println!("{}", report);
+            Ok(())
+        }
+        Command::Setup(_) => unreachable!(),
+    }
+}
+
+impl ListenArgs {
+    /// Overrides what the command line gives in the config, also when reloaded.
+    fn apply(&self, config: &mut Config) {
+        if let Some(preset) = self.preset {
+            preset.apply(config);
+        }
+        if let Some(output) = self.output {
+            config.output = output;
+        }
+        if let Some(addr) = self.metrics_addr {
+            config.metrics.addr = Some(addr);
+        }
+        if let Some(command) = &self.exec {
+            config.exec.command = ["sh", "-c", command].map(str::to_string).to_vec();
+        }
+        if self.ignore_urgency_flags {
+            config.urgency.ignore_flags();
+        }
+        let lengths = &mut config.attribute_length;
+        lengths.title = self.title_len.or(lengths.title);
+        if self.message_len.is_some() {
+            lengths.message = self.message_len;
+            lengths.full_message = false;
+        }
+        lengths.full_message |= self.full_message;
+        config.filter.ignore.extend(self.ignore_app.iter().cloned());
+        if !self.only_app.is_empty() {
+            config.filter.allow = Some(self.only_app.clone());
+        }
+    }
+}
+
+async fn listen(config: Arc<Config>, config_path: Option<PathBuf>, args: ListenArgs) -> Result<()> {
+    tokio::spawn(systemd::watchdog());
+    if let Some(path) = &config.metrics.path {
+        tokio::spawn(metrics::export(path.clone()));
+    }
+    if let Some(addr) = config.metrics.addr {
+        tokio::spawn(metrics::serve(addr));
+    }
+    let trace = match &args.trace_protocol {
+        Some(path) => {
+            let trace = ProtocolTrace::create(path).with_context(|| {
+                format!("Cannot write the protocol trace to {}", path.display())
+            })?;
+            log::info!("Tracing the ANCS protocol to {}", path.display());
+            Some(trace)
+        }
+        None => None,
+    };
+    let scheduler = Arc::new(Scheduler::new(&config.quiet_hours)?);
+    let history = if config.history.enabled {
+        Some(Arc::new(History::open(&config.history)?))
+    } else {
+        None
+    };
+    if let (Some(history), Some(day)) = (&history, config.history.weekly_summary) {
+        if !config.is_headless() {
+            tokio::spawn(summary::post_weekly(config.clone(), history.clone(), day));
+        }
+    }
+    let quiet_tally = Arc::new(QuietTally::default());
+    if config.quiet_hours.summary && !config.is_headless() {
+        tokio::spawn(summary::post_after_quiet_hours(
+            scheduler.clone(),
+            quiet_tally.clone(),
+        ));
+    }
+
+    let session_monitor = if config.is_headless() {
+        None
+    } else {
+        match SessionMonitor::new().await {
+            Ok(s) => Some(s),
+            Err(e) => {
+                log::warn!(
+                    "Cannot watch the desktop session, acknowledgments will not be tracked: {:?}",
+                    e
+                );
+                None
+            }
+        }
+    };
+
+    let contacts = match &config.contacts.vcard_dir {
+        Some(dir) => match Contacts::load(dir) {
+            Ok(c) => Some(Arc::new(c)),
+            Err(e) => {
+                log::warn!("Failed to load contacts: {:?}", e);
+                None
+            }
+        },
+        None => None,
+    };
+
+    let stores = Stores::open(&config.storage)?;
+    let failures = Arc::new(Mutex::new(safemode::Failures::start(
+        stores.cache("failed-attempts"),
+        config.safe_mode.after,
+    )));
+    let app_names = Arc::new(Mutex::new(AppNames::open(&stores)));
+
+    let adapter = if args.simulate {
+        None
+    } else {
+        Some(open_adapter(args.adapter.as_deref()).await?)
+    };
+    let mut devices = args.device_addr.clone();
+    for name in &args.device_name {
+        // Present unless simulating, which conflicts with names
+        let address = discovery::find_by_name(adapter.as_ref().unwrap(), name).await?;
+        log::info!("Device {:?} is {}", name, address);
+        devices.push(address);
+    }
+    if args.simulate {
+        devices = vec![Address::any()];
+    } else if devices.is_empty() {
+        devices = config.devices.iter().map(|d| d.address).collect();
+    }
+    let advertise = args.advertise || config.advertise && !args.simulate;
+    // Kept until the end, advertising stops when dropped
+    let _advertisement = match &adapter {
+        Some(adapter) if advertise => Some(discovery::advertise(adapter).await?),
+        _ => None,
+    };
+    if devices.is_empty() && advertise {
+        let address = discovery::incoming(adapter.as_ref().unwrap()).await?;
+        log::info!("Device {} connected", address);
+        devices.push(address);
+    }
+    if devices.is_empty() {
+        bail!("No device to listen to, give its address or name, or list it in `devices` in the config");
+    }
+
+    let (control, mut link_commands) = Control::new(config.clone(), &devices);
+    let reload: Loader = {
+        let args = args.clone();
+        Arc::new(move || {
+            let mut config = Config::load(config_path.clone())?;
+            args.apply(&mut config);
+            Ok(config)
+        })
+    };
+    let service = match Service::new(
+        control.clone(),
+        config.clone(),
+        app_names.clone(),
+        history.clone(),
+        reload.clone(),
+    )
+    .await
+    {
+        Ok(s) => Some(s),
+        Err(e) => {
+            log::warn!("Cannot register on the session bus: {:?}", e);
+            None
+        }
+    };
+    tokio::spawn(socket::serve(control.clone(), reload));
+    if config.tray && !config.is_headless() {
+        #[cfg(feature = "tray")]
+        tokio::spawn(tray::run(
+            control.clone(),
+            config.clone(),
+            app_names.clone(),
+            history.clone(),
+        ));
+        #[cfg(not(feature = "tray"))]
+        log::warn!("Not showing a tray icon, ancs-linux was built without the `tray` feature");
+    }
+
+    let blocklist = Arc::new(Mutex::new(Blocklist::load(
+        paths::config_dir().map(|dir| dir.join("blocked-apps")),
+    )));
+
+    let power = if config.power.battery_saver {
+        match PowerMonitor::new().await {
+            Ok(p) => Some(p),
+            Err(e) => {
+                log::warn!(
+                    "Cannot tell whether running on battery, assuming AC: {:?}",
+                    e
+                );
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let sinks = sinks::start(&config, power.as_ref())?;
+
+    let screen_cast =
+        if config.is_headless() || config.privacy.while_screen_sharing == ScreenSharing::Show {
+            None
+        } else {
+            match ScreenCastMonitor::new().await {
+                Ok(s) => Some(s),
+                Err(e) => {
+                    log::warn!(
+                    "Cannot watch screen sharing, notifications are shown in full meanwhile: {:?}",
+                    e
+                );
+                    None
+                }
+            }
+        };
+
+    let show_existing = args.show_existing || config.show_existing;
+    privacy::set_private_mode(args.privacy);
+    let focus = if !config.is_headless()
+        && config.apps.values().any(|app| app.desktop_app.is_some())
+    {
+        match FocusMonitor::new() {
+            Ok(f) => Some(f),
+            Err(e) => {
+                log::warn!(
+                    "Cannot tell which window has the focus, showing notifications from desktop apps anyway: {:?}",
+                    e
+                );
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let router = if config.is_headless() {
+        None
+    } else {
+        match desktop::Router::new().await {
+            Ok(r) => Some(r),
+            Err(e) => {
+                log::warn!(
+                    "Cannot watch the notifications shown, their actions do nothing: {:?}",
+                    e
+                );
+                None
+            }
+        }
+    };
+
+    let server = if config.is_headless() {
+        None
+    } else {
+        desktop::server_owner().await.ok()
+    };
+    let mut phones = Vec::new();
+    for &address in &devices {
+        // Notification uids are only unique per phone
+        let suffix = if devices.len() == 1 {
+            String::new()
+        } else {
+            format!("-{}", address.to_string().replace(':', "_"))
+        };
+        let mut handles = Handles::load(stores.cache(&format!("desktop-ids{}", suffix)));
+        handles.check_server(server.as_deref());
+        close_leftovers(&config, &mut handles).await;
+        phones.push(Phone {
+            address,
+            label: device_label(&config, adapter.as_ref(), address, devices.len() > 1).await,
+            dedupe: stores
+                .cache(&format!("shown{}", suffix))
+                .map(|slot| Arc::new(Mutex::new(Dedupe::load(slot)))),
+            handles: Arc::new(Mutex::new(handles)),
+            lengths: Arc::new(Mutex::new(AttributeLengths::new(&config.attribute_length))),
+            show_existing: Arc::new(AtomicBool::new(show_existing)),
+        });
+    }
+
+    let shared = Shared {
+        control,
+        history,
+        session: session_monitor,
+        screen_cast,
+        focus,
+        power,
+        contacts,
+        service,
+        blocklist,
+        app_names,
+        scheduler,
+        quiet_tally,
+        sinks: Arc::new(sinks),
+        failures,
+        router,
+        trace,
+    };
+
+    let Some(adapter) = adapter else {
+        log::info!("Simulating a device, link commands only restart the session");
+        let phone = &phones[0];
+        let simulator = Simulator::start();
+        let mut backoff = Backoff::default();
+        loop {
+            shared.failures.lock().unwrap().attempt();
+            let proc = AncsProcessor::new(shared.clone(), phone.clone());
+            tokio::select! {
+                result = supervisor::supervise(proc.simulate(simulator.clone())) => {
+                    let delay = match session_ended(&shared, phone, &mut backoff, result) {
+                        Retry::After(delay) => delay,
+                        // Nothing connects anew with the simulator
+                        Retry::OnReconnect => backoff.failed(),
+                    };
+                    tokio::time::sleep(delay).await;
+                }
+                Some(_) = link_commands[0].recv() => {}
+            }
+        }
+    };
+
+    if let Some(timeout) = args.startup_timeout {
+        for &address in &devices {
+            tokio::spawn(startup::deadline(Duration::from_secs(timeout), address));
+        }
+    }
+    let mirrors = phones
+        .into_iter()
+        .zip(link_commands)
+        .map(|(phone, link_commands)| mirror(&shared, phone, &adapter, link_commands));
+    futures::future::join_all(mirrors).await;
+    Ok(())
+}
+
+/// Closes the desktop notifications of the previous run if so configured. Those to revalidate
+/// are left to the first processor.
+async fn close_leftovers(config: &Config, handles: &mut Handles) {
+    if config.leftover_notifications != Leftovers::Close {
+        return;
+    }
+    let ids: Vec<(u32, u32)> = handles.ids().collect();
+    if !ids.is_empty() {
+        log::info!("Closing {} notifications left over from before", ids.len());
+    }
+    for (_, desktop_id) in ids {
+        if let Err(e) = desktop::close_notification(desktop_id).await {
+            log::warn!("Failed to close desktop notification: {:?}", e);
+        }
+    }
+    handles.clear();
+    if let Err(e) = handles.save() {
+        log::warn!("Failed to save desktop ids: {:?}", e);
+    }
+}
+
+/// The label from the config, or the name of the phone in BlueZ when there are several to
+/// tell apart.
+async fn device_label(
+    config: &Config,
+    adapter: Option<&Adapter>,
+    address: Address,
+    several: bool,
+) -> Option<String> {
+    let configured = config.devices.iter().find(|d| d.address == address);
+    if let Some(label) = configured.and_then(|d| d.label.clone()) {
+        return Some(label);
+    }
+    if !several {
+        return None;
+    }
+    let alias = match adapter.map(|a| a.device(address)) {
+        Some(Ok(device)) => device.alias().await.ok(),
+        _ => None,
+    };
+    Some(alias.unwrap_or_else(|| address.to_string()))
+}
+
+/// When to try again after a session ended.
+enum Retry {
+    After(Duration),
+    /// Once the phone connected anew, since trying again as is would fail the same way.
+    OnReconnect,
+}
+
+fn session_ended(
+    shared: &Shared,
+    phone: &Phone,
+    backoff: &mut Backoff,
+    result: Result<()>,
+) -> Retry {
+    let reason = match &result {
+        Ok(()) => None,
+        Err(e) => Some(format!("{:#}", e)),
+    };
+    // Unless it already went idle for a reason of its own
+    if reason.is_some() || state::current(phone.address).state != SessionState::Idle {
+        state::transition(phone.address, SessionState::Idle, reason);
+    }
+    let delay = match result {
+        Err(e) if e.is::<supervisor::Panic>() => {
+            let delay = backoff.panicked();
+            log::error!("{}, restarting in {} seconds", e, delay.as_secs());
+            // Whatever it was doing with the state is lost, but the state itself is fine
+            if let Some(dedupe) = &phone.dedupe {
+                dedupe.clear_poison();
+            }
+            shared.blocklist.clear_poison();
+            phone.handles.clear_poison();
+            phone.lengths.clear_poison();
+            shared.app_names.clear_poison();
+            return Retry::After(delay);
+        }
+        Err(e)
+            if e.downcast_ref::<AncsError>()
+                .is_some_and(|e| !e.is_transient()) =>
+        {
+            log::error!("Error: {:?}", e);
+            log::info!("Retrying once {} connects again", phone.address);
+            return Retry::OnReconnect;
+        }
+        Err(e) => {
+            let delay = backoff.failed();
             log::error!("Error: {:?}", e);
+            log::info!("Retrying in {} seconds", delay.as_secs());
+            return Retry::After(delay);
+        }
+        Ok(()) => Duration::ZERO,
+    };
+    backoff.reset();
+    Retry::After(delay)
+}
+
+/// Mirrors a phone for the whole run, connecting to it again whenever the connection ends.
+async fn mirror(
+    shared: &Shared,
+    phone: Phone,
+    adapter: &Adapter,
+    mut link_commands: mpsc::UnboundedReceiver<service::LinkCommand>,
+) {
+    let device_addr = phone.address;
+    tokio::spawn(bond::monitor(adapter.clone(), device_addr));
+
+    let mut backoff = Backoff::default();
+    let mut disconnected = false;
+    loop {
+        if disconnected {
+            log::info!(
+                "Staying disconnected from {} until asked to reconnect",
+                device_addr
+            );
+            state::transition(
+                device_addr,
+                SessionState::Idle,
+                Some("Disconnected until asked to reconnect".to_string()),
+            );
+            while let Some(command) = link_commands.recv().await {
+                disconnected = handle_link_command(adapter, device_addr, command).await;
+                if !disconnected {
+                    break;
+                }
+            }
+        }
+
+        let connected = tokio::select! {
+            result = wait_for_connection(adapter, device_addr) => Ok(result),
+            Some(command) = link_commands.recv() => Err(command),
+        };
+        match connected {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                log::warn!("Cannot watch {} for connections: {:?}", device_addr, e);
+                tokio::time::sleep(backoff.failed()).await;
+                continue;
+            }
+            Err(command) => {
+                disconnected = handle_link_command(adapter, device_addr, command).await;
+                continue;
+            }
+        }
+
+        shared.failures.lock().unwrap().attempt();
+        let proc = AncsProcessor::new(shared.clone(), phone.clone());
+        // A link command drops the processor, which ends its subscriptions
+        let result = tokio::select! {
+            result = supervisor::supervise(proc.main_loop(adapter)) => Ok(result),
+            Some(command) = link_commands.recv() => Err(command),
+        };
+        let retry = match result {
+            Ok(result) => Some(session_ended(shared, &phone, &mut backoff, result)),
+            Err(command) => {
+                disconnected = handle_link_command(adapter, device_addr, command).await;
+                None
+            }
+        };
+        // Missed notifications are caught up on after reconnecting soon enough
+        if let Some(dedupe) = &phone.dedupe {
+            if let Err(e) = dedupe.lock().unwrap().save() {
+                log::warn!("Failed to save shown notifications: {:?}", e);
+            }
+        }
+
+        let Some(retry) = retry else {
+            continue;
+        };
+        let wait = async {
+            match retry {
+                Retry::After(delay) => tokio::time::sleep(delay).await,
+                Retry::OnReconnect => {
+                    if let Err(e) = wait_for_disconnection(adapter, device_addr).await {
+                        log::warn!("Cannot watch {} for disconnections: {:?}", device_addr, e);
+                        tokio::time::sleep(backoff.failed()).await;
+                    }
+                }
+            }
+        };
+        tokio::select! {
+            _ = wait => {}
+            Some(command) = link_commands.recv() => {
+                disconnected = handle_link_command(adapter, device_addr, command).await;
+            }
+        }
+    }
+}
+
+/// Returns once the device is connected, which may be right away. The phone connects on its own
+/// when it comes into range, so there is no point in trying before.
+async fn wait_for_connection(adapter: &Adapter, addr: Address) -> Result<()> {
+    let mut adapter_events = adapter.events().await?;
+    loop {
+        if !adapter.device_addresses().await?.contains(&addr) {
+            log::info!("Waiting for BlueZ to know {}", addr);
+            state::transition(
+                addr,
+                SessionState::Idle,
+                Some("The device is not known to BlueZ".to_string()),
+            );
+            while let Some(event) = adapter_events.next().await {
+                if matches!(event, AdapterEvent::DeviceAdded(a) if a == addr) {
+                    break;
+                }
+            }
+            continue;
+        }
+
+        let device = adapter.device(addr)?;
+        let mut device_events = device.events().await?;
+        if device.is_connected().await? {
+            return Ok(());
+        }
+        log::info!("Waiting for {} to connect", addr);
+        startup::failed(addr, "The device is not connected");
+        state::transition(
+            addr,
+            SessionState::Idle,
+            Some("The device is not connected".to_string()),
+        );
+        loop {
+            tokio::select! {
+                Some(event) = device_events.next() => {
+                    if matches!(event, DeviceEvent::PropertyChanged(DeviceProperty::Connected(true))) {
+                        log::info!("Device {} connected", addr);
+                        return Ok(());
+                    }
+                }
+                Some(event) = adapter_events.next() => {
+                    if matches!(event, AdapterEvent::DeviceRemoved(a) if a == addr) {
+                        break;
+                    }
+                }
+                else => bail!("BlueZ stopped sending events"),
+            }
+        }
+    }
+}
+
+/// Returns once the device is disconnected or gone, which may be right away.
+async fn wait_for_disconnection(adapter: &Adapter, addr: Address) -> Result<()> {
+    if !adapter.device_addresses().await?.contains(&addr) {
+        return Ok(());
+    }
+    let device = adapter.device(addr)?;
+    let mut events = device.events().await?;
+    if !device.is_connected().await? {
+        return Ok(());
+    }
+    while let Some(event) = events.next().await {
+        if matches!(
+            event,
+            DeviceEvent::PropertyChanged(DeviceProperty::Connected(false))
+        ) {
+            log::info!("Device {} disconnected", addr);
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Subscribes to ANCS on a connected device, recording how far it got in [`startup`].
+async fn open_link(device: &bluer::Device, gatt: &GattConfig) -> Result<AncsClient> {
+    let addr = device.address();
+    startup::enter(addr, Stage::Resolve);
+    discovery::wait_for_services(device).await?;
+
+    startup::enter(addr, Stage::Service);
+    let service = discovery::find_service(device, gatt).await?;
+    startup::enter(addr, Stage::Characteristic);
+    let ancs = discovery::find_characteristics(&service).await?;
+    let mut report = CapabilityReport::new(device, &ancs).await;
+
+    startup::enter(addr, Stage::Subscribe);
+    let link = Link::Gatt(ancs);
+    let data_stream = link.subscribe_data().await;
+    report.set_subscribed(discovery::DATA_SOURCE, data_stream.is_ok());
+    let notification_stream = link.subscribe_notifications().await;
+    report.set_subscribed(discovery::NOTIFICATION_SOURCE, notification_stream.is_ok());
+    log::info!("{}", report);
+    let paired = device.is_paired().await.unwrap_or_default();
+    bond::set_rejected(addr, paired && notification_stream.is_err());
+
+    Ok(AncsClient::from_streams(
+        link,
+        notification_stream?,
+        data_stream?,
+    ))
+}
+
+/// Returns whether to stay disconnected.
+async fn handle_link_command(
+    adapter: &Adapter,
+    device_addr: Address,
+    command: service::LinkCommand,
+) -> bool {
+    let device = match adapter.device(device_addr) {
+        Ok(d) => d,
+        Err(e) => {
+            log::warn!("Cannot find device {}: {:?}", device_addr, e);
+            return matches!(command, service::LinkCommand::Disconnect);
+        }
+    };
+
+    log::info!("Disconnecting from {}", device_addr);
+    if let Err(e) = device.disconnect().await {
+        log::warn!("Failed to disconnect: {:?}", e);
+    }
+
+    match command {
+        service::LinkCommand::Reconnect => {
+            log::info!("Reconnecting to {}", device_addr);
+            // The phone usually reconnects on its own if this does not work
+            if let Err(e) = device.connect().await {
+                log::warn!("Failed to reconnect: {:?}", e);
+            }
+            false
+        }
+        service::LinkCommand::Disconnect => true,
+    }
+}
+
+async fn open_adapter(name: Option<&str>) -> Result<Adapter> {
+    let session = bluer::Session::new().await?;
+    session_adapter(&session, name).await
+}
+
+async fn session_adapter(session: &bluer::Session, name: Option<&str>) -> Result<Adapter> {
+    let adapter = if let Some(name) = name {
+        session.adapter(name)?
+    } else {
+        session.default_adapter().await?
+    };
+    adapter.set_powered(true).await?;
+
+    log::info!("Using adapter: {}", adapter.name());
+    Ok(adapter)
+}
+
+async fn ctl(args: CtlArgs) -> Result<()> {
+    use control::{AppNamesCommand, Command as CtlCommand, PrivacyMode};
+
+    // Over the control socket, which also works without the session bus, e.g. over SSH
+    let command = match &args.command {
+        CtlCommand::Pause => Some(socket::Command::Pause),
+        CtlCommand::Resume => Some(socket::Command::Resume),
+        CtlCommand::List => Some(socket::Command::List),
+        CtlCommand::Dismiss { uid, device } => Some(socket::Command::Dismiss {
+            uid: *uid,
+            device: control::device_arg(*device),
+        }),
+        CtlCommand::Reload => Some(socket::Command::Reload),
+        _ => None,
+    };
+    if let Some(command) = command {
+        let reply = socket::request(&command).await?;
+        if let Some(message) = reply.message {
+            println!("{}", message);
+        }
+        let active: Vec<_> = reply
+            .notifications
+            .unwrap_or_default()
+            .iter()
+            .map(service::Active::listed)
+            .collect();
+        return control::print_active(&active);
+    }
+
+    let conn = zbus::Connection::session().await?;
+    let control = control::ControlProxy::new(&conn).await?;
+
+    match args.command {
+        CtlCommand::Reconnect => control.reconnect().await?,
+        CtlCommand::Disconnect => control.disconnect().await?,
+        CtlCommand::DismissLast => control.dismiss_last().await?,
+        CtlCommand::ActOnLast => control.act_on_last().await?,
+        CtlCommand::SendAction { uid, label, device } => {
+            control
+                .send_action(&control::device_arg(device), uid, &label)
+                .await?
+        }
+        CtlCommand::Ring { stop, device } => {
+            let device = control::device_arg(device);
+            if stop {
+                control.stop_ringing(&device).await?
+            } else {
+                control.ring(&device).await?
+            }
+        }
+        CtlCommand::Status => control::print_status(
+            &control.safe_mode().await?,
+            control.sessions().await?,
+            control.sink_status().await?,
+        )?,
+        CtlCommand::AppNames { command } => match command {
+            AppNamesCommand::List => control::print_app_names(control.app_names().await?),
+            AppNamesCommand::Set { app_id, name } => control.set_app_name(&app_id, &name).await?,
+            AppNamesCommand::Rm { app_id } => control.remove_app_name(&app_id).await?,
+        },
+        CtlCommand::Privacy { mode } => {
+            let enabled = match mode {
+                PrivacyMode::On => true,
+                PrivacyMode::Off => false,
+                PrivacyMode::Toggle => !control.private_mode().await?,
+            };
+            control.set_private_mode(enabled).await?;
+            println!("Privacy mode is {}", if enabled { "on" } else { "off" });
         }
+        CtlCommand::LogLevel { target, level } => control.set_log_level(&target, &level).await?,
+        CtlCommand::Pause
+        | CtlCommand::Resume
+        | CtlCommand::List
+        | CtlCommand::Dismiss { .. }
+        | CtlCommand::Reload => unreachable!(),
+    }
+
+    Ok(())
+}
+
+async fn setup(config_path: Option<PathBuf>, args: SetupArgs) -> Result<()> {
+    let Some(config_path) = config_path.or_else(Config::default_path) else {
+        bail!("Cannot determine the config location, please pass --config");
+    };
+
+    let adapter = open_adapter(args.device.adapter.as_deref()).await?;
+    wizard::run(
+        &adapter,
+        args.device.device_addr,
+        Duration::from_secs(args.minutes * 60),
+        &config_path,
+    )
+    .await
+}
+
+async fn stats(config: &Config, args: StatsArgs) -> Result<()> {
+    if args.weekly {
+        let history = History::open(&config.history)?;
+        println!("{}", Summary::last_week(config, &history)?);
+        return Ok(());
+    }
+
+    let conn = zbus::Connection::session().await?;
+    let control = control::ControlProxy::new(&conn).await?;
 
-        log::info!("Restarting in 10 seconds");
-        tokio::time::sleep(std::time::Duration::from_secs(10)).await;
+    for (name, value) in control.stats().await? {
+        println!("{}: {}", name, value);
     }
+
+    Ok(())
+}
+
+async fn discover(args: DiscoverArgs) -> Result<()> {
+    let adapter = open_adapter(args.adapter.as_deref()).await?;
+    adapter.set_powered(true).await?;
+    println!("Scanning for {} seconds...", args.seconds);
+    let found = discovery::scan(&adapter, Duration::from_secs(args.seconds)).await?;
+    if found.is_empty() {
+        println!(
+            "No iPhone found. Keep it unlocked with the Bluetooth settings open, or pair it first."
+        );
+        return Ok(());
+    }
+
+    println!("{:<17}  {:>8}  NAME", "ADDRESS", "RSSI");
+    for device in found {
+        let rssi = match device.rssi {
+            Some(rssi) => format!("{} dBm", rssi),
+            None => "-".to_string(),
+        };
+        println!(
+            "{}  {:>8}  {}{}",
+            device.address,
+            rssi,
+            device.name.as_deref().unwrap_or("(unnamed)"),
+            if device.paired { " (paired)" } else { "" }
+        );
+    }
+    println!("\nPass the address to `ancs-linux listen`, e.g. `ancs-linux listen <ADDRESS>`.");
+    Ok(())
+}
+
+async fn doctor(args: DeviceArgs) -> Result<()> {
+    let adapter = open_adapter(args.adapter.as_deref()).await?;
+    println!("Adapter: {}", adapter.name());
+
+    let device = adapter.device(args.device_addr)?;
+    if !device.is_connected().await? {
+        println!("Device {} is not connected", args.device_addr);
+        return Ok(());
+    }
+    println!("Device {} is connected", args.device_addr);
+
+    let ancs = discovery::find_ancs(&device).await?;
+    let mut report = CapabilityReport::new(&device, &ancs).await;
+    // Only checks that subscribing works, the streams are dropped right away
+    for (name, c) in [
+        (discovery::NOTIFICATION_SOURCE, &ancs.notification_source),
+        (discovery::DATA_SOURCE, &ancs.data_source),
+    ] {
+        let result = c.notify().await;
+        if let Err(e) = &result {
+            println!("Subscribing to the {} failed: {}", name, e);
+        }
+        report.set_subscribed(name, result.is_ok());
+    }
+    println!("{}", report);
+
+    Ok(())
 }
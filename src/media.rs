@@ -0,0 +1,67 @@
+use ancs::attributes::category::CategoryID;
+
+use crate::config::Config;
+
+/// Chat apps, which send an empty message along with a photo, video or voice message.
+const CHAT_APPS: &[&str] = &[
+    "com.apple.MobileSMS",
+    "net.whatsapp.WhatsApp",
+    "ph.telegra.Telegraph",
+    "org.whispersystems.signal",
+    "com.facebook.Messenger",
+    "jp.naver.line",
+];
+
+/// What to show instead of the message, if it is empty because the notification carries media.
+///
+/// The phone does not tell whether there is an attachment, so this goes by the app and
+/// category. `message` is `None` if it was not requested at all.
+pub fn placeholder<'a>(
+    config: &'a Config,
+    app_id: &str,
+    category_id: Option<u8>,
+    message: Option<&str>,
+) -> Option<&'a str> {
+    if message != Some("") || config.media.placeholder.is_empty() {
+        return None;
+    }
+    is_chat(config, app_id, category_id).then_some(config.media.placeholder.as_str())
+}
+
+/// Whether notifications come from a chat app, by its bundle identifier or category.
+pub fn is_chat(config: &Config, app_id: &str, category_id: Option<u8>) -> bool {
+    CHAT_APPS.contains(&app_id)
+        || CHAT_APPS.contains(&config.canonical_app_id(app_id))
+        || category_id == Some(CategoryID::Social as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_for_empty_messages_from_chat_apps() {
+        let config = Config::default();
+        let whatsapp = "net.whatsapp.WhatsApp";
+        assert_eq!(
+            placeholder(&config, whatsapp, None, Some("")),
+            Some("📷 Photo")
+        );
+        assert_eq!(placeholder(&config, whatsapp, None, Some("Hi")), None);
+        // Not requested, e.g. restricted by `apps.<app>.attributes`
+        assert_eq!(placeholder(&config, whatsapp, None, None), None);
+        assert_eq!(
+            placeholder(&config, "com.example.game", None, Some("")),
+            None
+        );
+        assert_eq!(
+            placeholder(
+                &config,
+                "com.example.social",
+                Some(CategoryID::Social as u8),
+                Some("")
+            ),
+            Some("📷 Photo")
+        );
+    }
+}
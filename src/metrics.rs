@@ -0,0 +1,317 @@
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use bluer::Address;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _},
+    net::TcpListener,
+};
+
+use crate::{
+    clock::Stamp,
+    state::{self, SessionState},
+};
+
+/// Counters for the lifetime of the daemon.
+pub struct Metrics {
+    /// Notifications added on the phone.
+    pub received: AtomicU64,
+    /// Notifications removed from the phone.
+    pub removed: AtomicU64,
+    /// Desktop notifications shown or updated, not counting reannouncements.
+    pub shown: AtomicU64,
+    /// Requests to the phone over the control point, e.g. for attributes or actions.
+    pub control_point_writes: AtomicU64,
+    /// Requests the phone refused or that did not reach it.
+    pub control_point_failures: AtomicU64,
+    /// Packets from the phone that did not decode.
+    pub parse_errors: AtomicU64,
+    /// Times a phone was subscribed to again after the first time since startup.
+    pub reconnects: AtomicU64,
+    pub unknown_data_commands: AtomicU64,
+    /// Notifications already on the phone when subscribing, which are not shown.
+    pub skipped_pre_existing: AtomicU64,
+    /// Pre-existing notifications caught up on after a restart that had been shown before it.
+    pub suppressed_duplicates: AtomicU64,
+    /// Notifications from apps or categories that are filtered, or were blocked from the desktop.
+    pub suppressed_not_allowed: AtomicU64,
+    /// Notifications kept off the desktop during quiet hours, they are still in the history.
+    pub suppressed_quiet_hours: AtomicU64,
+    /// Notifications removed again while held back for being added and removed rapidly.
+    pub suppressed_flapping: AtomicU64,
+    /// Notifications kept off the desktop while the desktop client of their app has the focus.
+    pub suppressed_focused: AtomicU64,
+    /// Notifications summed up with others of their category instead of shown, see `throttle`.
+    pub suppressed_throttled: AtomicU64,
+    /// Times the phone stopped sending anything while subscribed.
+    pub session_degraded: AtomicU64,
+    /// Delivery to every network sink, by name.
+    pub sinks: Mutex<BTreeMap<String, SinkStats>>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SinkStats {
+    pub delivered: u64,
+    /// Failed attempts, including retries.
+    pub failed: u64,
+    /// Notifications waiting to be delivered.
+    pub queued: usize,
+    pub last_error: Option<String>,
+    /// Since when every attempt has failed.
+    pub failing_since: Option<Stamp>,
+}
+
+pub static METRICS: Metrics = Metrics {
+    received: AtomicU64::new(0),
+    removed: AtomicU64::new(0),
+    shown: AtomicU64::new(0),
+    control_point_writes: AtomicU64::new(0),
+    control_point_failures: AtomicU64::new(0),
+    parse_errors: AtomicU64::new(0),
+    reconnects: AtomicU64::new(0),
+    unknown_data_commands: AtomicU64::new(0),
+    skipped_pre_existing: AtomicU64::new(0),
+    suppressed_duplicates: AtomicU64::new(0),
+    suppressed_not_allowed: AtomicU64::new(0),
+    suppressed_quiet_hours: AtomicU64::new(0),
+    suppressed_flapping: AtomicU64::new(0),
+    suppressed_focused: AtomicU64::new(0),
+    suppressed_throttled: AtomicU64::new(0),
+    session_degraded: AtomicU64::new(0),
+    sinks: Mutex::new(BTreeMap::new()),
+};
+
+/// Notifications kept off the desktop so far, for any reason.
+pub fn suppressed() -> u64 {
+    [
+        &METRICS.skipped_pre_existing,
+        &METRICS.suppressed_duplicates,
+        &METRICS.suppressed_not_allowed,
+        &METRICS.suppressed_quiet_hours,
+        &METRICS.suppressed_flapping,
+        &METRICS.suppressed_focused,
+        &METRICS.suppressed_throttled,
+    ]
+    .iter()
+    .map(|counter| counter.load(Ordering::Relaxed))
+    .sum()
+}
+
+/// How often the counters are written to `metrics.path`.
+const EXPORT_INTERVAL: Duration = Duration::from_secs(60);
+/// How long a scrape gets to send its request line, and how long it may be.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_REQUEST_LINE: usize = 8192;
+
+/// Writes the counters to `path` every so often for the whole run, replacing the file at once
+/// so that collectors never read half of it.
+pub async fn export(path: PathBuf) {
+    let temporary = path.with_extension("tmp");
+    let mut ticks = tokio::time::interval(EXPORT_INTERVAL);
+    loop {
+        ticks.tick().await;
+        let result = std::fs::write(&temporary, METRICS.prometheus())
+            .and_then(|()| std::fs::rename(&temporary, &path));
+        if let Err(e) = result {
+            log::warn!("Failed to write metrics to {}: {:?}", path.display(), e);
+        }
+    }
+}
+
+/// Counts a request written to the control point, and whether it failed.
+pub fn control_point_write<T, E>(result: Result<T, E>) -> Result<T, E> {
+    inc(&METRICS.control_point_writes);
+    if result.is_err() {
+        inc(&METRICS.control_point_failures);
+    }
+    result
+}
+
+/// Serves the same as `metrics.path` has over HTTP for the whole run, for Prometheus to
+/// scrape.
+pub async fn serve(addr: SocketAddr) {
+    let listener = match TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            log::warn!("Cannot serve metrics on {}: {:?}", addr, e);
+            return;
+        }
+    };
+    log::info!("Serving metrics on http://{}/metrics", addr);
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(async move {
+                    if let Err(e) = respond(stream).await {
+                        log::debug!("Failed to answer a metrics request: {:?}", e);
+                    }
+                });
+            }
+            Err(e) => {
+                log::warn!("Failed to accept a metrics request: {:?}", e);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+/// Answers one request, the metrics for `GET /metrics` and 404 for anything else.
+async fn respond(mut stream: impl AsyncRead + AsyncWrite + Unpin) -> std::io::Result<()> {
+    // Only the request line matters, the rest is left unread
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    while !request.contains(&b'\n') && request.len() < MAX_REQUEST_LINE {
+        let n = tokio::time::timeout(REQUEST_TIMEOUT, stream.read(&mut buf)).await??;
+        if n == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+    let line = String::from_utf8_lossy(&request);
+    let mut parts = line.split_whitespace();
+    let found = parts.next() == Some("GET")
+        && parts
+            .next()
+            .is_some_and(|path| path == "/metrics" || path.starts_with("/metrics?"));
+    let (status, body) = if found {
+        ("200 OK", METRICS.prometheus())
+    } else {
+        ("404 Not Found", "Not found, try /metrics\n".to_string())
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Increments `counter`, returning the new value.
+pub fn inc(counter: &AtomicU64) -> u64 {
+    counter.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+impl Metrics {
+    /// Current value of every counter, by name.
+    pub fn snapshot(&self) -> Vec<(String, u64)> {
+        let mut snapshot: Vec<(String, u64)> = [
+            ("control_point_failures", &self.control_point_failures),
+            ("control_point_writes", &self.control_point_writes),
+            ("parse_errors", &self.parse_errors),
+            ("received", &self.received),
+            ("reconnects", &self.reconnects),
+            ("removed", &self.removed),
+            ("session_degraded", &self.session_degraded),
+            ("shown", &self.shown),
+            ("skipped_pre_existing", &self.skipped_pre_existing),
+            ("suppressed_duplicates", &self.suppressed_duplicates),
+            ("suppressed_flapping", &self.suppressed_flapping),
+            ("suppressed_focused", &self.suppressed_focused),
+            ("suppressed_not_allowed", &self.suppressed_not_allowed),
+            ("suppressed_quiet_hours", &self.suppressed_quiet_hours),
+            ("suppressed_throttled", &self.suppressed_throttled),
+            ("unknown_data_commands", &self.unknown_data_commands),
+        ]
+        .into_iter()
+        .map(|(name, counter)| (name.to_string(), counter.load(Ordering::Relaxed)))
+        .collect();
+
+        for (name, stats) in self.sinks() {
+            snapshot.push((format!("{}_delivered", name), stats.delivered));
+            snapshot.push((format!("{}_failed", name), stats.failed));
+            snapshot.push((format!("{}_queued", name), stats.queued as u64));
+        }
+        snapshot.sort();
+        snapshot
+    }
+
+    /// The counters in the text format of Prometheus, prefixed with `ancs_linux_`, then the
+    /// state of the session with every phone as a gauge that is 1 for the state it is in.
+    fn prometheus(&self) -> String {
+        let mut text = String::new();
+        for (name, value) in self.snapshot() {
+            let kind = if name.ends_with("_queued") {
+                "gauge"
+            } else {
+                "counter"
+            };
+            text += &format!("# TYPE ancs_linux_{} {}\n", name, kind);
+            text += &format!("ancs_linux_{} {}\n", name, value);
+        }
+
+        let sessions = state::all();
+        if !sessions.is_empty() {
+            text += "# TYPE ancs_linux_session_state gauge\n";
+        }
+        for (device, session) in sessions {
+            let device = if device == Address::any() {
+                String::new()
+            } else {
+                device.to_string()
+            };
+            for state in SessionState::ALL {
+                text += &format!(
+                    "ancs_linux_session_state{{device=\"{}\",state=\"{}\"}} {}\n",
+                    device,
+                    state,
+                    u8::from(session.state == state)
+                );
+            }
+        }
+        text
+    }
+
+    pub fn sinks(&self) -> Vec<(String, SinkStats)> {
+        let sinks = self.sinks.lock().unwrap();
+        sinks
+            .iter()
+            .map(|(name, stats)| (name.clone(), stats.clone()))
+            .collect()
+    }
+
+    pub fn update_sink(&self, name: &str, update: impl FnOnce(&mut SinkStats)) {
+        let mut sinks = self.sinks.lock().unwrap();
+        update(sinks.entry(name.to_string()).or_default());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn serves_the_counters_over_http() {
+        let scrape = |request: &'static str| async move {
+            let (mut client, server) = tokio::io::duplex(64 * 1024);
+            let answer = tokio::spawn(respond(server));
+            client.write_all(request.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            client.read_to_string(&mut response).await.unwrap();
+            answer.await.unwrap().unwrap();
+            response
+        };
+
+        let device: Address = "AA:BB:CC:DD:EE:FF".parse().unwrap();
+        state::transition(device, SessionState::Connecting, None);
+        let response = scrape("GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("# TYPE ancs_linux_received counter\nancs_linux_received "));
+        assert!(response.contains(
+            "ancs_linux_session_state{device=\"AA:BB:CC:DD:EE:FF\",state=\"connecting\"} 1\n\
+             ancs_linux_session_state{device=\"AA:BB:CC:DD:EE:FF\",state=\"discovering\"} 0\n"
+        ));
+
+        let response = scrape("GET / HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+}
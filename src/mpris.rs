@@ -0,0 +1,422 @@
+//! An MPRIS player on the session bus for what the phone plays, over AMS, so media keys and
+//! tools like playerctl control it.
+
+use std::{collections::HashMap, sync::Arc, time::Instant};
+
+use ancs_linux::ams::{
+    AmsClient, Attribute, EntityUpdate, PlaybackInfo, PlaybackState, RemoteCommand,
+};
+use anyhow::Result;
+use bluer::Device;
+use futures::StreamExt as _;
+use zbus::{
+    connection, fdo, interface,
+    object_server::InterfaceRef,
+    zvariant::{ObjectPath, OwnedValue, Value},
+    SignalContext,
+};
+
+const OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+
+struct Root {
+    /// Of the app playing on the phone once known, the phone before.
+    identity: String,
+}
+
+#[interface(name = "org.mpris.MediaPlayer2")]
+impl Root {
+    /// Nothing to show on the desktop.
+    async fn raise(&self) {}
+
+    async fn quit(&self) {}
+
+    #[zbus(property)]
+    async fn can_quit(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    async fn can_raise(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    async fn has_track_list(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    async fn identity(&self) -> String {
+        self.identity.clone()
+    }
+
+    #[zbus(property)]
+    async fn supported_uri_schemes(&self) -> Vec<String> {
+        vec![]
+    }
+
+    #[zbus(property)]
+    async fn supported_mime_types(&self) -> Vec<String> {
+        vec![]
+    }
+}
+
+#[derive(Default)]
+struct Track {
+    /// Counts changes of the title, for a track id that changes along with it.
+    number: u32,
+    title: String,
+    artist: String,
+    album: String,
+    /// In seconds.
+    duration: Option<f64>,
+}
+
+struct Player {
+    ams: Arc<AmsClient>,
+    /// None while nothing plays on the phone.
+    playback: Option<PlaybackInfo>,
+    /// When `playback` came, which its elapsed time is counted from.
+    since: Instant,
+    volume: f64,
+    track: Track,
+    /// What the player of the phone takes, None until it told.
+    supported: Option<Vec<RemoteCommand>>,
+}
+
+impl Player {
+    async fn send(&self, command: RemoteCommand) -> fdo::Result<()> {
+        self.ams
+            .send(command)
+            .await
+            .map_err(|e| fdo::Error::Failed(e.to_string()))
+    }
+
+    fn supports(&self, command: RemoteCommand) -> bool {
+        self.supported
+            .as_ref()
+            .is_none_or(|supported| supported.contains(&command))
+    }
+
+    /// In microseconds, as far as it played since the phone last told.
+    fn position_now(&self) -> i64 {
+        let Some(playback) = &self.playback else {
+            return 0;
+        };
+        let mut elapsed = playback.elapsed;
+        if playback.state != PlaybackState::Paused {
+            elapsed += self.since.elapsed().as_secs_f64() * playback.rate;
+        }
+        (elapsed.max(0.0) * 1e6) as i64
+    }
+}
+
+#[interface(name = "org.mpris.MediaPlayer2.Player")]
+impl Player {
+    async fn next(&self) -> fdo::Result<()> {
+        self.send(RemoteCommand::NextTrack).await
+    }
+
+    async fn previous(&self) -> fdo::Result<()> {
+        self.send(RemoteCommand::PreviousTrack).await
+    }
+
+    async fn pause(&self) -> fdo::Result<()> {
+        self.send(RemoteCommand::Pause).await
+    }
+
+    async fn play_pause(&self) -> fdo::Result<()> {
+        self.send(RemoteCommand::TogglePlayPause).await
+    }
+
+    /// AMS cannot stop, so this pauses.
+    async fn stop(&self) -> fdo::Result<()> {
+        self.send(RemoteCommand::Pause).await
+    }
+
+    async fn play(&self) -> fdo::Result<()> {
+        self.send(RemoteCommand::Play).await
+    }
+
+    async fn seek(&self, _offset: i64) -> fdo::Result<()> {
+        Err(fdo::Error::NotSupported(
+            "The phone cannot seek".to_string(),
+        ))
+    }
+
+    async fn set_position(&self, _track_id: ObjectPath<'_>, _position: i64) -> fdo::Result<()> {
+        Err(fdo::Error::NotSupported(
+            "The phone cannot seek".to_string(),
+        ))
+    }
+
+    async fn open_uri(&self, _uri: &str) -> fdo::Result<()> {
+        Err(fdo::Error::NotSupported(
+            "The phone cannot open URIs".to_string(),
+        ))
+    }
+
+    #[zbus(signal)]
+    async fn seeked(ctxt: &SignalContext<'_>, position: i64) -> zbus::Result<()>;
+
+    #[zbus(property)]
+    async fn playback_status(&self) -> String {
+        match self.playback.map(|p| p.state) {
+            None => "Stopped",
+            Some(PlaybackState::Paused) => "Paused",
+            Some(_) => "Playing",
+        }
+        .to_string()
+    }
+
+    #[zbus(property)]
+    async fn rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    async fn set_rate(&mut self, _rate: f64) {}
+
+    #[zbus(property)]
+    async fn minimum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    async fn maximum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    async fn metadata(&self) -> HashMap<String, OwnedValue> {
+        let track = &self.track;
+        let mut metadata = HashMap::new();
+        let path = format!("/io/github/kmod_midori/AncsLinux/track/{}", track.number);
+        if let Ok(path) = ObjectPath::try_from(path) {
+            insert(&mut metadata, "mpris:trackid", Value::from(path));
+        }
+        if !track.title.is_empty() {
+            insert(&mut metadata, "xesam:title", Value::from(&track.title));
+        }
+        if !track.artist.is_empty() {
+            insert(
+                &mut metadata,
+                "xesam:artist",
+                Value::from(vec![&track.artist]),
+            );
+        }
+        if !track.album.is_empty() {
+            insert(&mut metadata, "xesam:album", Value::from(&track.album));
+        }
+        if let Some(duration) = track.duration {
+            insert(
+                &mut metadata,
+                "mpris:length",
+                Value::from((duration * 1e6) as i64),
+            );
+        }
+        metadata
+    }
+
+    #[zbus(property)]
+    async fn volume(&self) -> f64 {
+        self.volume
+    }
+
+    /// The phone only steps its volume, so this takes one step towards it.
+    #[zbus(property)]
+    async fn set_volume(&mut self, volume: f64) {
+        let command = if volume > self.volume {
+            RemoteCommand::VolumeUp
+        } else if volume < self.volume {
+            RemoteCommand::VolumeDown
+        } else {
+            return;
+        };
+        if let Err(e) = self.send(command).await {
+            log::warn!("Failed to change the volume of the phone: {}", e);
+        }
+    }
+
+    #[zbus(property(emits_changed_signal = "false"))]
+    async fn position(&self) -> i64 {
+        self.position_now()
+    }
+
+    #[zbus(property)]
+    async fn can_go_next(&self) -> bool {
+        self.supports(RemoteCommand::NextTrack)
+    }
+
+    #[zbus(property)]
+    async fn can_go_previous(&self) -> bool {
+        self.supports(RemoteCommand::PreviousTrack)
+    }
+
+    #[zbus(property)]
+    async fn can_play(&self) -> bool {
+        self.supports(RemoteCommand::Play)
+    }
+
+    #[zbus(property)]
+    async fn can_pause(&self) -> bool {
+        self.supports(RemoteCommand::Pause)
+    }
+
+    #[zbus(property)]
+    async fn can_seek(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    async fn can_control(&self) -> bool {
+        true
+    }
+}
+
+fn insert(metadata: &mut HashMap<String, OwnedValue>, key: &str, value: Value<'_>) {
+    if let Ok(value) = OwnedValue::try_from(value) {
+        metadata.insert(key.to_string(), value);
+    }
+}
+
+/// Bus name of the player of a phone, which takes one of its own per phone as the spec allows.
+fn bus_name(device: &Device) -> String {
+    format!(
+        "org.mpris.MediaPlayer2.ancs_linux.device_{}",
+        device.address().to_string().replace(':', "_")
+    )
+}
+
+/// Publishes the player of a connected phone until it goes away. Phones without AMS get none.
+pub async fn serve(device: Device, identity: String) -> Result<()> {
+    let Some(ams) = AmsClient::find(&device).await? else {
+        log::info!(
+            "{} does not offer AMS, not publishing a player",
+            device.address()
+        );
+        return Ok(());
+    };
+    let ams = Arc::new(ams);
+    let mut supported = ams.supported_commands().await?;
+    let mut updates = ams.updates().await?;
+
+    let conn = connection::Builder::session()?
+        .name(bus_name(&device))?
+        .serve_at(OBJECT_PATH, Root { identity })?
+        .serve_at(
+            OBJECT_PATH,
+            Player {
+                ams: ams.clone(),
+                playback: None,
+                since: Instant::now(),
+                volume: 1.0,
+                track: Track::default(),
+                supported: None,
+            },
+        )?
+        .build()
+        .await?;
+    let root = conn
+        .object_server()
+        .interface::<_, Root>(OBJECT_PATH)
+        .await?;
+    let player = conn
+        .object_server()
+        .interface::<_, Player>(OBJECT_PATH)
+        .await?;
+    log::info!("Publishing the player of {} over MPRIS", device.address());
+
+    loop {
+        let result = tokio::select! {
+            commands = supported.next() => match commands {
+                Some(commands) => set_supported(&player, commands).await,
+                None => break,
+            },
+            update = updates.next() => match update {
+                Some(Ok(update)) => apply(&ams, &root, &player, update).await,
+                Some(Err(e)) => {
+                    log::debug!("Ignoring an AMS update: {}", e);
+                    Ok(())
+                }
+                None => break,
+            },
+        };
+        if let Err(e) = result {
+            log::warn!("Failed to tell of a change of the player: {:?}", e);
+        }
+    }
+    Ok(())
+}
+
+async fn set_supported(
+    player: &InterfaceRef<Player>,
+    commands: Vec<RemoteCommand>,
+) -> zbus::Result<()> {
+    let ctxt = player.signal_context();
+    let mut player = player.get_mut().await;
+    player.supported = Some(commands);
+    player.can_go_next_changed(ctxt).await?;
+    player.can_go_previous_changed(ctxt).await?;
+    player.can_play_changed(ctxt).await?;
+    player.can_pause_changed(ctxt).await
+}
+
+async fn apply(
+    ams: &AmsClient,
+    root: &InterfaceRef<Root>,
+    player: &InterfaceRef<Player>,
+    update: EntityUpdate,
+) -> zbus::Result<()> {
+    let mut value = update.value;
+    if update.truncated {
+        match ams.read_attribute(update.attribute).await {
+            Ok(whole) => value = whole,
+            Err(e) => log::debug!("Keeping a truncated {:?}: {}", update.attribute, e),
+        }
+    }
+
+    if update.attribute == Attribute::PlayerName {
+        let mut root_mut = root.get_mut().await;
+        if !value.is_empty() {
+            root_mut.identity = value;
+            root_mut.identity_changed(root.signal_context()).await?;
+        }
+        return Ok(());
+    }
+
+    let ctxt = player.signal_context();
+    let mut player = player.get_mut().await;
+    match update.attribute {
+        // Handled above
+        Attribute::PlayerName => {}
+        Attribute::PlaybackInfo => {
+            player.playback = PlaybackInfo::parse(&value);
+            player.since = Instant::now();
+            player.playback_status_changed(ctxt).await?;
+            Player::seeked(ctxt, player.position_now()).await?;
+        }
+        Attribute::Volume => {
+            player.volume = value.trim().parse().unwrap_or(player.volume);
+            player.volume_changed(ctxt).await?;
+        }
+        Attribute::Title => {
+            player.track.number += 1;
+            player.track.title = value;
+            player.metadata_changed(ctxt).await?;
+        }
+        Attribute::Artist => {
+            player.track.artist = value;
+            player.metadata_changed(ctxt).await?;
+        }
+        Attribute::Album => {
+            player.track.album = value;
+            player.metadata_changed(ctxt).await?;
+        }
+        Attribute::Duration => {
+            player.track.duration = value.trim().parse().ok();
+            player.metadata_changed(ctxt).await?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,492 @@
+//! Publishing to an MQTT broker, speaking just enough of MQTT 3.1.1 over TCP or TLS for that:
+//! notifications are published with QoS 1 and delivered once the broker acknowledges them, and
+//! whether ancs-linux is running is kept retained in `<prefix>/availability`, set to `offline`
+//! by the broker as the last will once the connection is gone.
+
+use std::{
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use futures::{future::BoxFuture, FutureExt as _};
+use rustls_platform_verifier::ConfigVerifierExt as _;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _},
+    net::TcpStream,
+    sync::{mpsc, oneshot},
+};
+use tokio_rustls::{
+    rustls::{pki_types::ServerName, ClientConfig},
+    TlsConnector,
+};
+
+use crate::{
+    config::MqttConfig,
+    sinks::{Forwarded, NetworkSink},
+};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// The broker drops the connection after one and a half of these without hearing anything.
+const KEEP_ALIVE: Duration = Duration::from_secs(60);
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const PUBACK: u8 = 0x40;
+const PINGREQ: u8 = 0xc0;
+const PINGRESP: u8 = 0xd0;
+
+/// TCP, or TLS over it.
+trait Stream: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Stream for T {}
+
+struct Publish {
+    topic: String,
+    payload: Vec<u8>,
+    reply: oneshot::Sender<Result<()>>,
+}
+
+/// Publishes every notification as JSON to `<prefix>/<device>/<app id>`, through a connection
+/// kept open from [`NetworkSink::start`] on.
+pub struct MqttSink {
+    config: MqttConfig,
+    host: String,
+    tx: mpsc::Sender<Publish>,
+    rx: Mutex<Option<mpsc::Receiver<Publish>>>,
+}
+
+impl MqttSink {
+    pub fn new(host: &str, config: &MqttConfig) -> Self {
+        if config.password.is_some() && !config.tls && !is_local(host) {
+            log::warn!(
+                "The password for the MQTT broker at {} goes over the network in the clear, set `mqtt.tls` to encrypt it",
+                host
+            );
+        }
+        let (tx, rx) = mpsc::channel(1);
+        Self {
+            config: config.clone(),
+            host: host.to_string(),
+            tx,
+            rx: Mutex::new(Some(rx)),
+        }
+    }
+}
+
+impl NetworkSink for MqttSink {
+    fn name(&self) -> &str {
+        "mqtt"
+    }
+
+    fn deliver<'a>(&'a self, notification: &'a Forwarded) -> BoxFuture<'a, Result<()>> {
+        async move {
+            let device = match notification.device.as_str() {
+                "" => "simulated",
+                device => device,
+            };
+            let topic = format!(
+                "{}/{}/{}",
+                self.config.topic_prefix,
+                segment(device),
+                segment(&notification.app_id)
+            );
+            let (reply, replied) = oneshot::channel();
+            let publish = Publish {
+                topic,
+                payload: serde_json::to_vec(notification)?,
+                reply,
+            };
+            self.tx
+                .send(publish)
+                .await
+                .map_err(|_| anyhow!("Not connected to the broker"))?;
+            replied
+                .await
+                .map_err(|_| anyhow!("Not connected to the broker"))?
+        }
+        .boxed()
+    }
+
+    fn start(&self) {
+        if let Some(rx) = self.rx.lock().unwrap().take() {
+            tokio::spawn(run(self.host.clone(), self.config.clone(), rx));
+        }
+    }
+}
+
+fn is_local(host: &str) -> bool {
+    host == "localhost" || host.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+/// A level of a topic, without the wildcards and separators it must not contain.
+fn segment(s: &str) -> String {
+    s.replace(['/', '+', '#'], "_")
+}
+
+/// Keeps connecting, answering what is published meanwhile with why it cannot be, so that the
+/// outbox keeps it.
+async fn run(host: String, config: MqttConfig, mut rx: mpsc::Receiver<Publish>) {
+    let mut failures = 0;
+    loop {
+        let error = match Connection::open(&host, &config).await {
+            Ok(mut connection) => {
+                log::info!("Connected to the MQTT broker at {}", host);
+                failures = 0;
+                match connection.serve(&mut rx).await {
+                    Ok(()) => return,
+                    Err(e) => e,
+                }
+            }
+            Err(e) => e,
+        };
+        failures += 1;
+        let delay = Duration::from_secs((5 << (failures - 1).min(6)).min(300));
+        log::warn!(
+            "No connection to the MQTT broker at {}, retrying in {} seconds: {:#}",
+            host,
+            delay.as_secs(),
+            error
+        );
+
+        let error = format!("Not connected to the broker: {:#}", error);
+        let until = tokio::time::sleep(delay);
+        tokio::pin!(until);
+        loop {
+            tokio::select! {
+                _ = &mut until => break,
+                publish = rx.recv() => match publish {
+                    Some(publish) => {
+                        let _ = publish.reply.send(Err(anyhow!("{}", error)));
+                    }
+                    None => return,
+                },
+            }
+        }
+    }
+}
+
+struct Connection {
+    stream: Box<dyn Stream>,
+    /// What was read but not taken as a packet yet.
+    received: Vec<u8>,
+    last_id: u16,
+}
+
+impl Connection {
+    /// Connects and marks ancs-linux as available.
+    async fn open(host: &str, config: &MqttConfig) -> Result<Self> {
+        let stream = connect(host, config).await?;
+        let mut connection = Self {
+            stream,
+            received: Vec::new(),
+            last_id: 0,
+        };
+        connection.stream.write_all(&connect_packet(config)).await?;
+
+        let (header, body) = tokio::time::timeout(TIMEOUT, connection.read_packet())
+            .await
+            .context("Timed out waiting for the broker to accept the connection")??;
+        if header != CONNACK || body.len() < 2 {
+            bail!("Unexpected packet {:#04x} instead of CONNACK", header);
+        }
+        match body[1] {
+            0 => {}
+            1 => bail!("The broker does not speak MQTT 3.1.1"),
+            2 => bail!("The broker rejected client id {:?}", config.client_id),
+            3 => bail!("The broker is unavailable"),
+            4 => bail!("The broker rejected the username or password"),
+            5 => bail!("Not authorized by the broker"),
+            code => bail!("The broker refused the connection with code {}", code),
+        }
+
+        let online = publish_packet(&availability(config), b"online", None, true);
+        connection.stream.write_all(&online).await?;
+        Ok(connection)
+    }
+
+    async fn read_packet(&mut self) -> Result<(u8, Vec<u8>)> {
+        loop {
+            if let Some(packet) = take_packet(&mut self.received)? {
+                return Ok(packet);
+            }
+            self.read().await?;
+        }
+    }
+
+    async fn read(&mut self) -> Result<()> {
+        let mut chunk = [0; 1024];
+        let n = self.stream.read(&mut chunk).await?;
+        if n == 0 {
+            bail!("The broker closed the connection");
+        }
+        self.received.extend_from_slice(&chunk[..n]);
+        Ok(())
+    }
+
+    /// Publishes one notification at a time until the sink is gone, failing once the
+    /// connection is lost.
+    async fn serve(&mut self, rx: &mut mpsc::Receiver<Publish>) -> Result<()> {
+        let mut waiting: Option<(u16, oneshot::Sender<Result<()>>)> = None;
+        let deadline = tokio::time::sleep(TIMEOUT);
+        tokio::pin!(deadline);
+        let mut ping = tokio::time::interval(KEEP_ALIVE / 2);
+        ping.tick().await;
+        let mut last_heard = Instant::now();
+
+        loop {
+            tokio::select! {
+                read = self.read() => {
+                    read?;
+                    last_heard = Instant::now();
+                    while let Some((header, body)) = take_packet(&mut self.received)? {
+                        match header {
+                            PUBACK if body.len() >= 2 => {
+                                let id = u16::from_be_bytes([body[0], body[1]]);
+                                if waiting.as_ref().is_some_and(|(waiting, _)| *waiting == id) {
+                                    let (_, reply) = waiting.take().unwrap();
+                                    let _ = reply.send(Ok(()));
+                                }
+                            }
+                            PINGRESP => {}
+                            header => log::debug!("Ignoring MQTT packet {:#04x}", header),
+                        }
+                    }
+                }
+                publish = rx.recv(), if waiting.is_none() => {
+                    let Some(publish) = publish else {
+                        return Ok(());
+                    };
+                    self.last_id = self.last_id.checked_add(1).unwrap_or(1);
+                    let packet =
+                        publish_packet(&publish.topic, &publish.payload, Some(self.last_id), false);
+                    if let Err(e) = self.stream.write_all(&packet).await {
+                        let _ = publish.reply.send(Err(anyhow!("Failed to publish: {}", e)));
+                        return Err(e.into());
+                    }
+                    waiting = Some((self.last_id, publish.reply));
+                    deadline.as_mut().reset((Instant::now() + TIMEOUT).into());
+                }
+                _ = &mut deadline, if waiting.is_some() => {
+                    let (_, reply) = waiting.take().unwrap();
+                    let _ = reply.send(Err(anyhow!("The broker did not acknowledge in time")));
+                    bail!("The broker stopped acknowledging");
+                }
+                _ = ping.tick() => {
+                    if last_heard.elapsed() > KEEP_ALIVE + TIMEOUT {
+                        bail!("The broker stopped answering");
+                    }
+                    self.stream.write_all(&[PINGREQ, 0]).await?;
+                }
+            }
+        }
+    }
+}
+
+async fn connect(host: &str, config: &MqttConfig) -> Result<Box<dyn Stream>> {
+    let port = config.port.unwrap_or(if config.tls { 8883 } else { 1883 });
+    let tcp = tokio::time::timeout(TIMEOUT, TcpStream::connect((host, port)))
+        .await
+        .context("Timed out connecting")??;
+    if !config.tls {
+        return Ok(Box::new(tcp));
+    }
+    let tls = ClientConfig::with_platform_verifier().context("Cannot set up TLS")?;
+    let name = ServerName::try_from(host.to_string()).context("Invalid host name for TLS")?;
+    let stream = tokio::time::timeout(
+        TIMEOUT,
+        TlsConnector::from(Arc::new(tls)).connect(name, tcp),
+    )
+    .await
+    .context("Timed out setting up TLS")?
+    .context("Failed to set up TLS")?;
+    Ok(Box::new(stream))
+}
+
+fn availability(config: &MqttConfig) -> String {
+    format!("{}/availability", config.topic_prefix)
+}
+
+/// A packet with its fixed header, the remaining length encoded 7 bits at a time.
+fn packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![header];
+    let mut length = body.len();
+    loop {
+        let byte = (length % 128) as u8;
+        length /= 128;
+        if length == 0 {
+            packet.push(byte);
+            break;
+        }
+        packet.push(byte | 0x80);
+    }
+    packet.extend_from_slice(body);
+    packet
+}
+
+fn push_string(body: &mut Vec<u8>, s: &[u8]) {
+    body.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    body.extend_from_slice(s);
+}
+
+/// CONNECT with a clean session and `offline` as the retained last will.
+fn connect_packet(config: &MqttConfig) -> Vec<u8> {
+    let username = config.username.as_deref();
+    // Brokers only take a password along with a username
+    let password = config.password.as_deref().filter(|_| username.is_some());
+    let mut flags = 0x02 | 0x04 | 0x20;
+    if username.is_some() {
+        flags |= 0x80;
+    }
+    if password.is_some() {
+        flags |= 0x40;
+    }
+
+    let mut body = Vec::new();
+    push_string(&mut body, b"MQTT");
+    body.push(4);
+    body.push(flags);
+    body.extend_from_slice(&(KEEP_ALIVE.as_secs() as u16).to_be_bytes());
+    push_string(&mut body, config.client_id.as_bytes());
+    push_string(&mut body, availability(config).as_bytes());
+    push_string(&mut body, b"offline");
+    for s in [username, password].into_iter().flatten() {
+        push_string(&mut body, s.as_bytes());
+    }
+    packet(CONNECT, &body)
+}
+
+/// PUBLISH with QoS 1 if given a packet id, otherwise QoS 0.
+fn publish_packet(topic: &str, payload: &[u8], id: Option<u16>, retain: bool) -> Vec<u8> {
+    let mut header = PUBLISH;
+    if id.is_some() {
+        header |= 0x02;
+    }
+    if retain {
+        header |= 0x01;
+    }
+    let mut body = Vec::new();
+    push_string(&mut body, topic.as_bytes());
+    if let Some(id) = id {
+        body.extend_from_slice(&id.to_be_bytes());
+    }
+    body.extend_from_slice(payload);
+    packet(header, &body)
+}
+
+/// Takes the first packet off `received` if it arrived in full, as the type from its fixed
+/// header and its body.
+fn take_packet(received: &mut Vec<u8>) -> Result<Option<(u8, Vec<u8>)>> {
+    let mut length = 0;
+    let mut offset = 1;
+    loop {
+        let Some(&byte) = received.get(offset) else {
+            return Ok(None);
+        };
+        if offset > 4 {
+            bail!("Malformed packet from the broker");
+        }
+        length |= ((byte & 0x7f) as usize) << (7 * (offset - 1));
+        offset += 1;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    if received.len() < offset + length {
+        return Ok(None);
+    }
+    let header = received[0] & 0xf0;
+    let body = received[offset..offset + length].to_vec();
+    received.drain(..offset + length);
+    Ok(Some((header, body)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_packets() {
+        let config = MqttConfig {
+            username: Some("relay".to_string()),
+            password: Some("hunter2".to_string()),
+            ..Default::default()
+        };
+        let mut connect = connect_packet(&config);
+        assert_eq!(&connect[..12], b"\x10\x42\x00\x04MQTT\x04\xe6\x00\x3c");
+        assert_eq!(take_packet(&mut connect).unwrap().unwrap().0, CONNECT);
+        assert!(connect.is_empty());
+
+        // Long enough to take two bytes of length, then arriving in pieces
+        let payload = [b'x'; 200];
+        let publish = publish_packet("ancs/AA:BB/com.apple.MobileSMS", &payload, Some(7), false);
+        assert_eq!(&publish[..3], b"\x32\xea\x01");
+        let mut received = publish[..100].to_vec();
+        assert_eq!(take_packet(&mut received).unwrap(), None);
+        received.extend_from_slice(&publish[100..]);
+        received.extend_from_slice(&[PUBACK, 2, 0, 7]);
+        let (header, body) = take_packet(&mut received).unwrap().unwrap();
+        assert_eq!(header, PUBLISH);
+        assert_eq!(&body[body.len() - 200..], &payload);
+        assert_eq!(
+            take_packet(&mut received).unwrap(),
+            Some((PUBACK, vec![0, 7]))
+        );
+        assert_eq!(take_packet(&mut received).unwrap(), None);
+    }
+
+    async fn next_packet(broker: &mut TcpStream, received: &mut Vec<u8>) -> (u8, Vec<u8>) {
+        loop {
+            if let Some(packet) = take_packet(received).unwrap() {
+                return packet;
+            }
+            let mut chunk = [0; 1024];
+            let n = broker.read(&mut chunk).await.unwrap();
+            assert_ne!(n, 0, "The sink closed the connection");
+            received.extend_from_slice(&chunk[..n]);
+        }
+    }
+
+    #[tokio::test]
+    async fn delivers_once_acknowledged() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = MqttConfig {
+            host: Some("127.0.0.1".to_string()),
+            port: Some(listener.local_addr().unwrap().port()),
+            ..Default::default()
+        };
+        let sink = MqttSink::new("127.0.0.1", &config);
+        sink.start();
+        let (mut broker, _) = listener.accept().await.unwrap();
+        let mut received = Vec::new();
+
+        assert_eq!(next_packet(&mut broker, &mut received).await.0, CONNECT);
+        broker.write_all(&[CONNACK, 2, 0, 0]).await.unwrap();
+        let (header, online) = next_packet(&mut broker, &mut received).await;
+        assert_eq!(header, PUBLISH);
+        assert_eq!(online, b"\x00\x11ancs/availabilityonline");
+
+        let notification = Forwarded {
+            device: "AA:BB:CC:DD:EE:FF".to_string(),
+            uid: 1,
+            app_id: "com.apple.MobileSMS".to_string(),
+            app_name: Some("Messages".to_string()),
+            title: Some("Alice".to_string()),
+            subtitle: None,
+            message: Some("Are we still on for tonight?".to_string()),
+            important: false,
+            received_at: "2024-05-06T18:30:00Z".parse().unwrap(),
+        };
+        let topic = b"ancs/AA:BB:CC:DD:EE:FF/com.apple.MobileSMS";
+        let (delivered, ()) = tokio::join!(sink.deliver(&notification), async {
+            let (header, body) = next_packet(&mut broker, &mut received).await;
+            assert_eq!(header, PUBLISH);
+            assert_eq!(&body[2..2 + topic.len()], topic);
+            let id = &body[2 + topic.len()..4 + topic.len()];
+            broker.write_all(&[PUBACK, 2, id[0], id[1]]).await.unwrap();
+        });
+        delivered.unwrap();
+    }
+}
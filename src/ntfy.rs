@@ -0,0 +1,135 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use futures::{future::BoxFuture, FutureExt as _};
+use serde::Serialize;
+
+use crate::sinks::{Forwarded, NetworkSink};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What ntfy takes when publishing as JSON, which keeps titles out of headers that would have to
+/// be ASCII.
+#[derive(Debug, PartialEq, Serialize)]
+struct Message<'a> {
+    topic: &'a str,
+    title: &'a str,
+    message: String,
+    /// 4 ("high") for important notifications, 3 ("default") otherwise.
+    priority: u8,
+    tags: Vec<&'a str>,
+}
+
+impl<'a> Message<'a> {
+    fn new(topic: &'a str, notification: &'a Forwarded) -> Self {
+        let app = notification
+            .app_name
+            .as_deref()
+            .unwrap_or(&notification.app_id);
+        let title = notification
+            .title
+            .as_deref()
+            .filter(|title| !title.is_empty())
+            .unwrap_or(app);
+        let message = [&notification.subtitle, &notification.message]
+            .into_iter()
+            .flatten()
+            .map(|s| s.as_str())
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n");
+        Self {
+            topic,
+            title,
+            // ntfy fills in "triggered" for an empty message
+            message: if message.is_empty() {
+                app.to_string()
+            } else {
+                message
+            },
+            priority: if notification.important { 4 } else { 3 },
+            tags: vec![app],
+        }
+    }
+}
+
+/// Pushes every notification to a topic on ntfy.sh or a server of its own, with an access token
+/// if given.
+pub struct NtfySink {
+    client: reqwest::Client,
+    server: reqwest::Url,
+    topic: String,
+    token: Option<String>,
+}
+
+impl NtfySink {
+    pub fn new(server: &str, topic: &str, token: Option<&str>) -> Result<Self> {
+        let server = server
+            .parse()
+            .with_context(|| format!("Invalid ntfy server {:?}", server))?;
+        let client = reqwest::Client::builder().timeout(TIMEOUT).build()?;
+        Ok(Self {
+            client,
+            server,
+            topic: topic.to_string(),
+            token: token.map(str::to_string),
+        })
+    }
+}
+
+impl NetworkSink for NtfySink {
+    fn name(&self) -> &str {
+        "ntfy"
+    }
+
+    fn deliver<'a>(&'a self, notification: &'a Forwarded) -> BoxFuture<'a, Result<()>> {
+        async move {
+            let message = Message::new(&self.topic, notification);
+            let mut request = self.client.post(self.server.clone()).json(&message);
+            if let Some(token) = &self.token {
+                request = request.bearer_auth(token);
+            }
+            request.send().await?.error_for_status()?;
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn falls_back_to_the_app() {
+        let mut notification = Forwarded {
+            device: String::new(),
+            uid: 1,
+            app_id: "com.apple.MobileSMS".to_string(),
+            app_name: Some("Messages".to_string()),
+            title: Some("Alice".to_string()),
+            subtitle: Some(String::new()),
+            message: Some("Are we still on for tonight?".to_string()),
+            important: true,
+            received_at: "2024-05-06T18:30:00Z".parse().unwrap(),
+        };
+        assert_eq!(
+            Message::new("iphone", &notification),
+            Message {
+                topic: "iphone",
+                title: "Alice",
+                message: "Are we still on for tonight?".to_string(),
+                priority: 4,
+                tags: vec!["Messages"],
+            }
+        );
+
+        // Sensitive notifications are forwarded without their message
+        notification.title = None;
+        notification.message = None;
+        notification.app_name = None;
+        let message = Message::new("iphone", &notification);
+        assert_eq!(message.title, "com.apple.MobileSMS");
+        assert_eq!(message.message, "com.apple.MobileSMS");
+    }
+}
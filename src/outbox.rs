@@ -0,0 +1,330 @@
+use std::{collections::VecDeque, future::Future, path::PathBuf, sync::Arc, time::Duration};
+
+use anyhow::{Context, Result};
+use tokio::sync::{mpsc, watch};
+
+use crate::{
+    clock::Stamp,
+    metrics::METRICS,
+    sinks::{AncsNotification, Forwarded, Kind, NetworkSink, NotificationSink},
+};
+
+const FIRST_RETRY: Duration = Duration::from_secs(5);
+const MAX_RETRY: Duration = Duration::from_secs(5 * 60);
+/// Beyond this, the oldest undelivered notifications are dropped.
+const MAX_QUEUED: usize = 1000;
+
+/// Delivers notifications to a sink in order, retrying with backoff while it fails.
+///
+/// Undelivered notifications are kept on disk while the sink fails, so that they survive a
+/// restart, and the file is removed once everything got through. How delivery goes is tracked
+/// in [`METRICS`].
+pub struct Outbox {
+    name: String,
+    tx: mpsc::UnboundedSender<Forwarded>,
+}
+
+impl Outbox {
+    /// Raises a desktop notification once the sink has been failing for `alert_after`. Nothing
+    /// is delivered while `paused` is true, e.g. on battery.
+    pub fn start(
+        sink: Arc<dyn NetworkSink>,
+        path: Option<PathBuf>,
+        alert_after: Option<Duration>,
+        paused: Option<watch::Receiver<bool>>,
+    ) -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let queue = Queue::load(path);
+        if !queue.items.is_empty() {
+            log::info!(
+                "{} notifications left to deliver to {}",
+                queue.items.len(),
+                sink.name()
+            );
+        }
+        METRICS.update_sink(sink.name(), |stats| stats.queued = queue.items.len());
+        let name = sink.name().to_string();
+        tokio::spawn(run(sink, queue, rx, alert_after, paused));
+
+        Self { name, tx }
+    }
+
+    pub fn push(&self, notification: Forwarded) {
+        let _ = self.tx.send(notification);
+    }
+}
+
+impl NotificationSink for Outbox {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn notify(&self, notification: &AncsNotification) {
+        if notification.event != Kind::Removed {
+            self.push(notification.into());
+        }
+    }
+}
+
+async fn run(
+    sink: Arc<dyn NetworkSink>,
+    mut queue: Queue,
+    mut rx: mpsc::UnboundedReceiver<Forwarded>,
+    alert_after: Option<Duration>,
+    mut paused: Option<watch::Receiver<bool>>,
+) {
+    let mut failures = 0;
+    let mut alerted = false;
+    loop {
+        if queue.items.is_empty() {
+            match rx.recv().await {
+                Some(notification) => queue.push(sink.name(), notification),
+                None => return,
+            }
+        }
+        while let Ok(notification) = rx.try_recv() {
+            queue.push(sink.name(), notification);
+        }
+        if let Some(paused) = paused.as_mut().filter(|p| *p.borrow()) {
+            log::info!(
+                "Holding {} notifications for {} while paused",
+                queue.items.len(),
+                sink.name()
+            );
+            hold(sink.name(), &mut queue, &mut rx, paused.wait_for(|p| !*p)).await;
+        }
+
+        match sink.deliver(&queue.items[0]).await {
+            Ok(()) => {
+                if failures > 0 {
+                    log::info!("Delivering to {} works again", sink.name());
+                }
+                failures = 0;
+                alerted = false;
+                queue.items.pop_front();
+                queue.save_if_needed(sink.name());
+                METRICS.update_sink(sink.name(), |stats| {
+                    stats.delivered += 1;
+                    stats.queued = queue.items.len();
+                    stats.failing_since = None;
+                });
+            }
+            Err(e) => {
+                failures += 1;
+                let delay = retry_delay(failures);
+                let mut failing_since = Stamp::now();
+                METRICS.update_sink(sink.name(), |stats| {
+                    stats.failed += 1;
+                    stats.queued = queue.items.len();
+                    stats.last_error = Some(format!("{:#}", e));
+                    failing_since = *stats.failing_since.get_or_insert(failing_since);
+                });
+                log::warn!(
+                    "Failed to deliver to {}, retrying in {} seconds ({} queued): {:#}",
+                    sink.name(),
+                    delay.as_secs(),
+                    queue.items.len(),
+                    e
+                );
+                queue.persistent = true;
+                queue.save_if_needed(sink.name());
+
+                let failing_for = failing_since.elapsed();
+                if !alerted && alert_after.is_some_and(|after| failing_for >= after) {
+                    alerted = true;
+                    alert(sink.name(), failing_for, &e).await;
+                }
+
+                hold(sink.name(), &mut queue, &mut rx, tokio::time::sleep(delay)).await;
+            }
+        }
+    }
+}
+
+/// Queues what arrives meanwhile until `until` completes.
+async fn hold(
+    sink: &str,
+    queue: &mut Queue,
+    rx: &mut mpsc::UnboundedReceiver<Forwarded>,
+    until: impl Future,
+) {
+    tokio::pin!(until);
+    loop {
+        tokio::select! {
+            _ = &mut until => break,
+            Some(notification) = rx.recv() => {
+                queue.push(sink, notification);
+                queue.save_if_needed(sink);
+                METRICS.update_sink(sink, |stats| stats.queued = queue.items.len());
+            }
+        }
+    }
+}
+
+async fn alert(sink: &str, failing_for: Duration, error: &anyhow::Error) {
+    let result = notify_rust::Notification::new()
+        .appname("ancs-linux")
+        .summary(&format!("Cannot deliver to {}", sink))
+        .body(&format!(
+            "Failing for {} minutes: {:#}",
+            failing_for.as_secs() / 60,
+            error
+        ))
+        .show_async()
+        .await;
+    if let Err(e) = result {
+        log::warn!("Failed to show that {} is failing: {:?}", sink, e);
+    }
+}
+
+fn retry_delay(failures: u32) -> Duration {
+    FIRST_RETRY
+        .saturating_mul(2u32.saturating_pow(failures.saturating_sub(1)))
+        .min(MAX_RETRY)
+}
+
+struct Queue {
+    items: VecDeque<Forwarded>,
+    path: Option<PathBuf>,
+    /// Whether the queue is kept on disk, from the first failure until it is empty again.
+    persistent: bool,
+}
+
+impl Queue {
+    fn load(path: Option<PathBuf>) -> Self {
+        let mut items = VecDeque::new();
+        if let Some(path) = &path {
+            match std::fs::read_to_string(path) {
+                Ok(content) => {
+                    for line in content.lines() {
+                        match serde_json::from_str(line) {
+                            Ok(n) => items.push_back(n),
+                            Err(e) => log::warn!("Skipping line in {}: {:?}", path.display(), e),
+                        }
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => log::warn!("Failed to read {}: {:?}", path.display(), e),
+            }
+        }
+
+        Self {
+            persistent: !items.is_empty(),
+            items,
+            path,
+        }
+    }
+
+    fn push(&mut self, sink: &str, notification: Forwarded) {
+        if self.items.len() >= MAX_QUEUED {
+            let dropped = self.items.pop_front().unwrap();
+            log::warn!(
+                "Too many notifications waiting for {}, dropping notification {}",
+                sink,
+                dropped.uid
+            );
+        }
+        self.items.push_back(notification);
+    }
+
+    fn save_if_needed(&mut self, sink: &str) {
+        if !self.persistent {
+            return;
+        }
+        if let Err(e) = self.save() {
+            log::warn!("Failed to keep notifications for {}: {:?}", sink, e);
+        }
+        if self.items.is_empty() {
+            self.persistent = false;
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if self.items.is_empty() {
+            return match std::fs::remove_file(path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    Err(e).with_context(|| format!("Failed to remove {}", path.display()))
+                }
+                _ => Ok(()),
+            };
+        }
+
+        let mut content = String::new();
+        for item in &self.items {
+            content.push_str(&serde_json::to_string(item)?);
+            content.push('\n');
+        }
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, content)
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, path)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notification(uid: u32) -> Forwarded {
+        Forwarded {
+            device: "AA:BB:CC:DD:EE:FF".to_string(),
+            uid,
+            app_id: "com.apple.MobileSMS".to_string(),
+            app_name: Some("Messages".to_string()),
+            title: Some("Alice".to_string()),
+            subtitle: None,
+            message: Some("Are we still on\nfor tonight?".to_string()),
+            important: false,
+            received_at: "2024-05-06T18:30:00Z".parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn keeps_queue_on_disk_until_empty() {
+        let path = std::env::temp_dir().join(format!("ancs-linux-outbox-{}", std::process::id()));
+        let mut queue = Queue::load(Some(path.clone()));
+        queue.push("test", notification(1));
+        queue.push("test", notification(2));
+
+        // Nothing is written until a delivery fails
+        queue.save_if_needed("test");
+        assert!(!path.exists());
+
+        queue.persistent = true;
+        queue.save_if_needed("test");
+        let loaded = Queue::load(Some(path.clone()));
+        assert_eq!(loaded.items, queue.items);
+        assert!(loaded.persistent);
+
+        queue.items.clear();
+        queue.save_if_needed("test");
+        assert!(!path.exists());
+        assert!(!queue.persistent);
+    }
+
+    #[test]
+    fn drops_the_oldest_when_full() {
+        let mut queue = Queue::load(None);
+        for uid in 0..MAX_QUEUED as u32 + 1 {
+            queue.push("test", notification(uid));
+        }
+        assert_eq!(queue.items.len(), MAX_QUEUED);
+        assert_eq!(queue.items[0].uid, 1);
+    }
+
+    #[test]
+    fn backs_off_up_to_a_limit() {
+        assert_eq!(retry_delay(1), FIRST_RETRY);
+        assert_eq!(retry_delay(2), FIRST_RETRY * 2);
+        assert_eq!(retry_delay(100), MAX_RETRY);
+    }
+}
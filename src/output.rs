@@ -0,0 +1,86 @@
+use std::io::Write as _;
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::{
+    privacy,
+    sinks::{AncsNotification, NotificationSink},
+};
+
+/// Where notifications go besides the history and network sinks.
+#[derive(ValueEnum, Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Output {
+    /// Shown on the desktop
+    #[default]
+    Desktop,
+    /// Written to stdout as one JSON object per event instead, like `headless`
+    Json,
+    /// Both shown and written to stdout
+    Both,
+}
+
+impl Output {
+    pub fn prints_json(self) -> bool {
+        matches!(self, Output::Json | Output::Both)
+    }
+}
+
+/// Writes every event as a line to stdout for `--output json`, flushed right away for whatever
+/// reads it. The text is left out in private mode.
+pub struct StdoutSink;
+
+impl NotificationSink for StdoutSink {
+    fn name(&self) -> &str {
+        "stdout"
+    }
+
+    fn notify(&self, notification: &AncsNotification) {
+        let line = if privacy::is_private_mode() {
+            serde_json::to_string(&notification.without_text())
+        } else {
+            serde_json::to_string(notification)
+        };
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                log::warn!("Failed to write notification {}: {:?}", notification.uid, e);
+                return;
+            }
+        };
+        let mut stdout = std::io::stdout().lock();
+        if let Err(e) = writeln!(stdout, "{}", line).and_then(|()| stdout.flush()) {
+            log::warn!("Failed to write notification {}: {:?}", notification.uid, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::*;
+    use crate::sinks::Kind;
+
+    #[test]
+    fn leaves_out_what_is_unknown() {
+        let event = AncsNotification {
+            event: Kind::Removed,
+            device: "AA:BB:CC:DD:EE:FF".to_string(),
+            uid: 42,
+            app_id: Some("com.apple.MobileSMS".to_string()),
+            app_name: None,
+            title: None,
+            subtitle: None,
+            message: None,
+            category: None,
+            important: false,
+            time: Utc.with_ymd_and_hms(2026, 10, 14, 12, 0, 0).unwrap(),
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"event":"removed","device":"AA:BB:CC:DD:EE:FF","uid":42,"app_id":"com.apple.MobileSMS","important":false,"time":"2026-10-14T12:00:00Z"}"#
+        );
+    }
+}
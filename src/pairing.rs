@@ -0,0 +1,143 @@
+use std::{
+    io::{BufRead, Write},
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use bluer::{
+    agent::{Agent, ReqError},
+    Adapter, AdapterEvent, Address, Device, Session,
+};
+use futures::{pin_mut, StreamExt as _};
+
+/// How long to scan for the phone if BlueZ does not know it yet.
+const SCAN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Pairs with the phone and trusts it, answering BlueZ on the terminal meanwhile.
+pub async fn run(session: &Session, adapter: &Adapter, addr: Address) -> Result<()> {
+    let _agent = session.register_agent(agent(addr)).await?;
+    adapter.set_pairable(true).await?;
+
+    let device = find(adapter, addr).await?;
+    if device.is_paired().await? {
+        println!("{} is already paired", addr);
+    } else {
+        println!("Pairing with {}, keep the phone unlocked...", addr);
+        device
+            .pair()
+            .await
+            .with_context(|| format!("Failed to pair with {}", addr))?;
+        println!("Paired");
+    }
+    if !device.is_trusted().await? {
+        device.set_trusted(true).await?;
+        println!("Trusted, so that it may connect on its own");
+    }
+
+    println!(
+        "\nNow connect from the Bluetooth settings of the phone by tapping this computer, and \
+         allow it to share system notifications. Then run `ancs-linux listen {}`.",
+        addr
+    );
+    Ok(())
+}
+
+/// The device, scanning for it if need be.
+async fn find(adapter: &Adapter, addr: Address) -> Result<Device> {
+    if adapter.device_addresses().await?.contains(&addr) {
+        return Ok(adapter.device(addr)?);
+    }
+
+    println!(
+        "Looking for {}, open the Bluetooth settings of the phone...",
+        addr
+    );
+    let events = adapter.discover_devices().await?;
+    pin_mut!(events);
+    let found = async {
+        while let Some(event) = events.next().await {
+            if matches!(event, AdapterEvent::DeviceAdded(a) if a == addr) {
+                return true;
+            }
+        }
+        false
+    };
+    if !tokio::time::timeout(SCAN_TIMEOUT, found)
+        .await
+        .unwrap_or_default()
+    {
+        bail!(
+            "{} not found, check the address with `ancs-linux discover`",
+            addr
+        );
+    }
+    Ok(adapter.device(addr)?)
+}
+
+/// Shows and confirms codes for the phone only, rejecting any other device.
+fn agent(addr: Address) -> Agent {
+    Agent {
+        request_default: true,
+        request_confirmation: Some(Box::new(move |req| {
+            Box::pin(async move {
+                if req.device != addr {
+                    return Err(ReqError::Rejected);
+                }
+                let question = format!("Does the phone show the code {:06}? [y/N] ", req.passkey);
+                match tokio::task::spawn_blocking(move || confirm(&question)).await {
+                    Ok(true) => Ok(()),
+                    _ => Err(ReqError::Rejected),
+                }
+            })
+        })),
+        display_passkey: Some(Box::new(move |req| {
+            Box::pin(async move {
+                if req.device != addr {
+                    return Err(ReqError::Rejected);
+                }
+                println!("Enter the code {:06} on the phone", req.passkey);
+                Ok(())
+            })
+        })),
+        display_pin_code: Some(Box::new(move |req| {
+            Box::pin(async move {
+                if req.device != addr {
+                    return Err(ReqError::Rejected);
+                }
+                println!("Enter the code {} on the phone", req.pincode);
+                Ok(())
+            })
+        })),
+        request_authorization: Some(Box::new(move |req| {
+            Box::pin(async move {
+                if req.device == addr {
+                    Ok(())
+                } else {
+                    Err(ReqError::Rejected)
+                }
+            })
+        })),
+        authorize_service: Some(Box::new(move |req| {
+            Box::pin(async move {
+                if req.device == addr {
+                    Ok(())
+                } else {
+                    Err(ReqError::Rejected)
+                }
+            })
+        })),
+        ..Default::default()
+    }
+}
+
+fn confirm(question: &str) -> bool {
+    print!("{}", question);
+    if std::io::stdout().flush().is_err() {
+        return false;
+    }
+    let mut line = String::new();
+    if std::io::stdin().lock().read_line(&mut line).is_err() {
+        return false;
+    }
+    matches!(line.trim(), "y" | "Y" | "yes")
+}
@@ -0,0 +1,61 @@
+use futures::StreamExt as _;
+use tokio::sync::watch;
+use zbus::{proxy, Connection};
+
+#[proxy(
+    interface = "org.freedesktop.UPower",
+    default_service = "org.freedesktop.UPower",
+    default_path = "/org/freedesktop/UPower"
+)]
+trait UPower {
+    #[zbus(property)]
+    fn on_battery(&self) -> zbus::Result<bool>;
+}
+
+/// Watches whether the computer runs on battery through UPower.
+#[derive(Clone)]
+pub struct PowerMonitor {
+    on_battery: watch::Receiver<bool>,
+}
+
+impl PowerMonitor {
+    pub async fn new() -> zbus::Result<Self> {
+        let conn = Connection::system().await?;
+        let upower = UPowerProxy::new(&conn).await?;
+        let (tx, on_battery) = watch::channel(upower.on_battery().await?);
+        if *on_battery.borrow() {
+            log::info!("Running on battery");
+        }
+
+        let mut changes = upower.receive_on_battery_changed().await;
+        tokio::spawn(async move {
+            let _conn = conn;
+            while let Some(change) = changes.next().await {
+                let Ok(now) = change.get().await else {
+                    continue;
+                };
+                if now != *tx.borrow() {
+                    if now {
+                        log::info!("Running on battery");
+                    } else {
+                        log::info!("Running on AC power");
+                    }
+                    tx.send_replace(now);
+                }
+            }
+            log::warn!("Stopped watching the power source");
+            tx.send_replace(false);
+        });
+
+        Ok(Self { on_battery })
+    }
+
+    pub fn on_battery(&self) -> bool {
+        *self.on_battery.borrow()
+    }
+
+    /// Tells whether it runs on battery, every time that changes.
+    pub fn watch(&self) -> watch::Receiver<bool> {
+        self.on_battery.clone()
+    }
+}
@@ -0,0 +1,66 @@
+use clap::ValueEnum;
+
+use crate::{config::Config, paths};
+
+/// Settings for a kind of setup at once, applied over the config by `listen --preset`. What
+/// the config sets itself is kept where a preset only needs something to be set.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Preset {
+    /// A headless device near the phone, e.g. a Raspberry Pi, forwarding notifications
+    /// everywhere else
+    Relay,
+}
+
+impl Preset {
+    pub fn apply(self, config: &mut Config) {
+        match self {
+            Preset::Relay => {
+                // There is no desktop to show anything on, or session bus to watch
+                config.headless = true;
+                // Something to tail or ship on, next to whatever `webhook` forwards to
+                if config.jsonl.path.is_none() {
+                    config.jsonl.path =
+                        paths::data_dir().map(|dir| dir.join("notifications.jsonl"));
+                }
+                if config.metrics.path.is_none() {
+                    config.metrics.path = paths::data_dir().map(|dir| dir.join("metrics.prom"));
+                }
+                // A battery is there to keep forwarding through power cuts
+                config.power.network_sinks = true;
+                // Alerts about failing sinks would be shown on the desktop
+                config.sinks.alert_after = None;
+                if let (None, Some(path)) = (&config.webhook.url, &config.jsonl.path) {
+                    log::warn!(
+                        "Nothing is forwarded over the network without `webhook.url`, only \
+                         written to {}",
+                        path.display()
+                    );
+                }
+                // The systemd watchdog is pinged as always, see `install-service --preset`
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn relay_keeps_what_is_configured() {
+        let mut config = Config::default();
+        config.jsonl.path = Some(PathBuf::from("/srv/notifications.jsonl"));
+        config.sinks.alert_after = Some(10);
+        Preset::Relay.apply(&mut config);
+
+        assert!(config.headless);
+        assert!(config.power.network_sinks);
+        assert_eq!(config.sinks.alert_after, None);
+        assert_eq!(
+            config.jsonl.path,
+            Some(PathBuf::from("/srv/notifications.jsonl"))
+        );
+    }
+}
@@ -0,0 +1,115 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use ancs_linux::protocol::{ActionID, ActionLabels};
+
+use crate::{config::Config, history::Entry};
+
+/// Whether desktop notifications show nothing but their app, e.g. while sharing the screen.
+static PRIVATE_MODE: AtomicBool = AtomicBool::new(false);
+
+pub fn set_private_mode(enabled: bool) {
+    PRIVATE_MODE.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_private_mode() -> bool {
+    PRIVATE_MODE.load(Ordering::Relaxed)
+}
+
+/// A notification as it may be kept or passed on beyond the desktop bubble.
+///
+/// Only [`redact`] creates one, so anything that stores or forwards notifications has to take
+/// this instead of a plain [`Entry`] and cannot skip the privacy rules.
+#[derive(Debug, Clone)]
+pub struct Redacted {
+    entry: Entry,
+    sensitive: bool,
+}
+
+impl Redacted {
+    pub fn entry(&self) -> &Entry {
+        &self.entry
+    }
+
+    /// Whether the message was removed. Sensitive notifications must not offer copying their
+    /// content either, e.g. one-time codes.
+    pub fn is_sensitive(&self) -> bool {
+        self.sensitive
+    }
+
+    /// Splits the actions of the phone into those to offer and those withheld, which copy the
+    /// content of a sensitive notification, e.g. "Copy Code".
+    pub fn offered_actions(&self, labels: ActionLabels) -> (ActionLabels, Vec<ActionID>) {
+        let mut withheld = Vec::new();
+        let mut offer = |label: Option<String>, action| match label {
+            Some(label) if self.sensitive && copies_content(&label) => {
+                withheld.push(action);
+                None
+            }
+            label => label,
+        };
+        let offered = ActionLabels {
+            positive: offer(labels.positive, ActionID::Positive),
+            negative: offer(labels.negative, ActionID::Negative),
+        };
+        (offered, withheld)
+    }
+}
+
+/// Whether an action labeled so puts the content of the notification on the clipboard.
+fn copies_content(label: &str) -> bool {
+    label.to_lowercase().contains("copy")
+}
+
+/// Applies the privacy rules, dropping the message of sensitive apps and categories.
+pub fn redact(config: &Config, entry: &Entry, category_id: Option<u8>) -> Redacted {
+    let sensitive = config.is_sensitive(&entry.app_id, category_id);
+    let mut entry = entry.clone();
+    if sensitive {
+        entry.message = None;
+    }
+
+    Redacted { entry, sensitive }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+
+    #[test]
+    fn withholds_copying_from_sensitive_notifications() {
+        let config: Config = toml::from_str(
+            r#"
+            [apps."com.apple.MobileSMS"]
+            sensitive = true
+            "#,
+        )
+        .unwrap();
+        let labels = || ActionLabels {
+            positive: Some("Copy Code".to_string()),
+            negative: Some("Clear".to_string()),
+        };
+        let entry = |app_id: &str| Entry {
+            uid: 1,
+            app_id: app_id.to_string(),
+            title: None,
+            subtitle: None,
+            message: Some("Your code is 123456".to_string()),
+            received_at: Utc::now(),
+            acknowledgment: None,
+            seen_at: None,
+            removed_at: None,
+        };
+
+        let (offered, withheld) =
+            redact(&config, &entry("com.apple.MobileSMS"), None).offered_actions(labels());
+        assert_eq!(offered.list(), ["Clear"]);
+        assert_eq!(withheld, [ActionID::Positive]);
+
+        let (offered, withheld) =
+            redact(&config, &entry("com.apple.mobilemail"), None).offered_actions(labels());
+        assert_eq!(offered.list(), ["Copy Code", "Clear"]);
+        assert!(withheld.is_empty());
+    }
+}
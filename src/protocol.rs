@@ -0,0 +1,277 @@
+use ancs::attributes::command::CommandID;
+
+use crate::error::AncsError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attribute {
+    pub id: u8,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AppAttributesResponse {
+    pub app_identifier: String,
+    pub attributes: Vec<Attribute>,
+}
+
+/// Splits a NUL terminated string off the start of `buf`, without the terminator.
+pub fn split_nul_terminated(buf: &[u8]) -> Option<(&[u8], &[u8])> {
+    let nul = buf.iter().position(|&b| b == 0)?;
+    Some((&buf[..nul], &buf[nul + 1..]))
+}
+
+/// An attribute ID and its undecoded value.
+pub type RawAttribute<'a> = (u8, &'a [u8]);
+
+/// Splits an attribute (ID, 16-bit length and value) off the start of `buf`, if it is complete.
+pub fn split_attribute(buf: &[u8]) -> Option<(RawAttribute<'_>, &[u8])> {
+    let header = buf.get(..3)?;
+    let len = u16::from_le_bytes([header[1], header[2]]) as usize;
+    let value = buf.get(3..3 + len)?;
+    Some(((header[0], value), &buf[3 + len..]))
+}
+
+/// Formats bytes as space separated hex, for logging packets.
+pub fn hex(data: &[u8]) -> String {
+    data.iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ActionID {
+    Positive = 0,
+    Negative = 1,
+}
+
+impl ActionID {
+    /// Identifier of the matching desktop notification action.
+    pub fn key(self) -> &'static str {
+        match self {
+            ActionID::Positive => "positive",
+            ActionID::Negative => "negative",
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<Self> {
+        match key {
+            "positive" => Some(ActionID::Positive),
+            "negative" => Some(ActionID::Negative),
+            _ => None,
+        }
+    }
+}
+
+/// The action labels the phone gave for a notification.
+#[derive(Debug, Default, Clone)]
+pub struct ActionLabels {
+    pub positive: Option<String>,
+    pub negative: Option<String>,
+}
+
+impl ActionLabels {
+    /// The action labeled `label`, regardless of case.
+    pub fn find(&self, label: &str) -> Option<ActionID> {
+        let matches = |l: &Option<String>| {
+            l.as_deref()
+                .is_some_and(|l| l.trim().to_lowercase() == label.trim().to_lowercase())
+        };
+        if matches(&self.positive) {
+            Some(ActionID::Positive)
+        } else if matches(&self.negative) {
+            Some(ActionID::Negative)
+        } else {
+            None
+        }
+    }
+
+    pub fn list(&self) -> Vec<&str> {
+        [&self.positive, &self.negative]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect()
+    }
+}
+
+pub fn perform_notification_action(notification_uid: u32, action: ActionID) -> Vec<u8> {
+    let mut data = vec![CommandID::PerformNotificationAction as u8];
+    data.extend_from_slice(&notification_uid.to_le_bytes());
+    data.push(action as u8);
+    data
+}
+
+/// The error the phone answers a control point write with when it cannot perform an action.
+pub const ERROR_ACTION_FAILED: u8 = 0xA3;
+
+/// Extracts the ATT error code from a failed GATT write, which BlueZ only has in the message.
+pub fn att_error(message: &str) -> Option<u8> {
+    let (_, code) = message.rsplit_once("ATT error: 0x")?;
+    u8::from_str_radix(code.get(..2)?, 16).ok()
+}
+
+/// Parses a complete response to GetAppAttributes.
+pub fn parse_app_attributes(data: &[u8]) -> Result<AppAttributesResponse, AncsError> {
+    let Some((&command_id, rest)) = data.split_first() else {
+        return Err(AncsError::ParseError(
+            "Empty app attributes response".to_string(),
+        ));
+    };
+    if command_id != CommandID::GetAppAttributes as u8 {
+        return Err(AncsError::ParseError(format!(
+            "Not an app attributes response: command {}",
+            command_id
+        )));
+    }
+
+    let Some((app_identifier, mut rest)) = split_nul_terminated(rest) else {
+        return Err(AncsError::ParseError(
+            "Unterminated app identifier in app attributes".to_string(),
+        ));
+    };
+    let app_identifier = String::from_utf8_lossy(app_identifier).into_owned();
+
+    let mut attributes = vec![];
+    while !rest.is_empty() {
+        let Some(((id, value), next)) = split_attribute(rest) else {
+            return Err(AncsError::ParseError(format!(
+                "Truncated attribute in app attributes for {}",
+                app_identifier
+            )));
+        };
+        attributes.push(Attribute {
+            id,
+            value: String::from_utf8_lossy(value).into_owned(),
+        });
+        rest = next;
+    }
+
+    Ok(AppAttributesResponse {
+        app_identifier,
+        attributes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(app_identifier: &str, attributes: &[(u8, &str)]) -> Vec<u8> {
+        let mut data = vec![CommandID::GetAppAttributes as u8];
+        data.extend_from_slice(app_identifier.as_bytes());
+        data.push(0);
+        for (id, value) in attributes {
+            data.push(*id);
+            data.extend_from_slice(&(value.len() as u16).to_le_bytes());
+            data.extend_from_slice(value.as_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn parses_display_name() {
+        let data = response("com.apple.MobileSMS", &[(0, "Messages")]);
+        let app = parse_app_attributes(&data).unwrap();
+
+        assert_eq!(app.app_identifier, "com.apple.MobileSMS");
+        assert_eq!(
+            app.attributes,
+            vec![Attribute {
+                id: 0,
+                value: "Messages".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn parses_multiple_attributes() {
+        let data = response("com.example.app", &[(0, "Example"), (7, ""), (3, "信息")]);
+        let app = parse_app_attributes(&data).unwrap();
+
+        let ids: Vec<u8> = app.attributes.iter().map(|a| a.id).collect();
+        let values: Vec<&str> = app.attributes.iter().map(|a| a.value.as_str()).collect();
+        assert_eq!(ids, vec![0, 7, 3]);
+        assert_eq!(values, vec!["Example", "", "信息"]);
+    }
+
+    #[test]
+    fn parses_response_without_attributes() {
+        let app = parse_app_attributes(&response("com.example.app", &[])).unwrap();
+
+        assert_eq!(app.app_identifier, "com.example.app");
+        assert!(app.attributes.is_empty());
+    }
+
+    #[test]
+    fn rejects_unterminated_app_identifier() {
+        assert!(parse_app_attributes(b"\x01com.example.app").is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_attribute() {
+        let mut data = response("com.example.app", &[(0, "Example")]);
+        data.pop();
+        assert!(parse_app_attributes(&data).is_err());
+
+        // Not even a complete attribute header
+        let mut data = response("com.example.app", &[]);
+        data.extend_from_slice(&[0, 5]);
+        assert!(parse_app_attributes(&data).is_err());
+    }
+
+    #[test]
+    fn rejects_other_commands() {
+        assert!(parse_app_attributes(&[]).is_err());
+
+        let mut data = response("com.example.app", &[(0, "Example")]);
+        data[0] = CommandID::GetNotificationAttributes as u8;
+        assert!(parse_app_attributes(&data).is_err());
+    }
+
+    #[test]
+    fn formats_hex() {
+        assert_eq!(hex(&[0x00, 0x1f, 0xab]), "00 1f ab");
+        assert_eq!(hex(&[]), "");
+    }
+
+    #[test]
+    fn builds_perform_notification_action() {
+        assert_eq!(
+            perform_notification_action(0x01020304, ActionID::Negative),
+            [2, 4, 3, 2, 1, 1]
+        );
+    }
+
+    #[test]
+    fn finds_actions_by_label() {
+        let labels = ActionLabels {
+            positive: Some("Mark as Read".to_string()),
+            negative: Some("Clear".to_string()),
+        };
+        assert_eq!(labels.find("mark as read"), Some(ActionID::Positive));
+        assert_eq!(labels.find("Clear"), Some(ActionID::Negative));
+        assert_eq!(labels.find("Reply"), None);
+        assert_eq!(ActionLabels::default().find(""), None);
+    }
+
+    #[test]
+    fn parses_att_errors() {
+        assert_eq!(
+            att_error("Operation failed with ATT error: 0xa3"),
+            Some(ERROR_ACTION_FAILED)
+        );
+        assert_eq!(att_error("Not connected"), None);
+    }
+
+    #[test]
+    fn splits_attributes() {
+        let data = [1, 2, 0, b'h', b'i', 9];
+        let ((id, value), rest) = split_attribute(&data).unwrap();
+
+        assert_eq!(id, 1);
+        assert_eq!(value, b"hi");
+        assert_eq!(rest, [9]);
+        assert!(split_attribute(&data[..4]).is_none());
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::{HashMap, VecDeque};
+
+use ancs::attributes::command::CommandID;
+
+use crate::protocol;
+
+/// Joins data source packets into complete responses.
+///
+/// Responses larger than the MTU are split over several packets without any framing, so the
+/// only way to tell where one ends is to know how many attributes were requested.
+#[derive(Default)]
+pub struct Reassembler {
+    buffer: Vec<u8>,
+    notification_requests: HashMap<u32, VecDeque<usize>>,
+    app_requests: HashMap<String, VecDeque<usize>>,
+}
+
+enum Frame {
+    Complete(usize),
+    Incomplete,
+    /// Not a response to one of our requests, we can only pass it on as is.
+    Unknown,
+}
+
+impl Reassembler {
+    pub fn expect_notification(&mut self, notification_uid: u32, attribute_count: usize) {
+        self.notification_requests
+            .entry(notification_uid)
+            .or_default()
+            .push_back(attribute_count);
+    }
+
+    pub fn expect_app(&mut self, app_identifier: &str, attribute_count: usize) {
+        self.app_requests
+            .entry(app_identifier.to_string())
+            .or_default()
+            .push_back(attribute_count);
+    }
+
+    /// Forgets the request last expected for a notification, as writing it failed.
+    pub fn forget_notification(&mut self, notification_uid: u32) {
+        if let Some(requests) = self.notification_requests.get_mut(&notification_uid) {
+            requests.pop_back();
+            if requests.is_empty() {
+                self.notification_requests.remove(&notification_uid);
+            }
+        }
+    }
+
+    /// Forgets the request last expected for an app, as writing it failed.
+    pub fn forget_app(&mut self, app_identifier: &str) {
+        if let Some(requests) = self.app_requests.get_mut(app_identifier) {
+            requests.pop_back();
+            if requests.is_empty() {
+                self.app_requests.remove(app_identifier);
+            }
+        }
+    }
+
+    /// Forgets every request and what was received of their responses, e.g. when starting
+    /// over with the phone.
+    pub fn forget_all(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Adds a packet, returning the responses it completes.
+    pub fn push(&mut self, packet: &[u8]) -> Vec<Vec<u8>> {
+        self.buffer.extend_from_slice(packet);
+
+        let mut responses = vec![];
+        loop {
+            match self.frame() {
+                Frame::Complete(len) => {
+                    let rest = self.buffer.split_off(len);
+                    responses.push(std::mem::replace(&mut self.buffer, rest));
+                }
+                Frame::Incomplete => break,
+                Frame::Unknown => {
+                    responses.push(std::mem::take(&mut self.buffer));
+                    break;
+                }
+            }
+        }
+
+        responses
+    }
+
+    /// Finds the end of the response at the start of the buffer, and forgets its request once
+    /// it is complete.
+    fn frame(&mut self) -> Frame {
+        let buf = &self.buffer;
+        match buf.first().copied() {
+            None => Frame::Incomplete,
+            Some(id) if id == CommandID::GetNotificationAttributes as u8 => {
+                let Some(uid) = buf.get(1..5) else {
+                    return Frame::Incomplete;
+                };
+                let uid = u32::from_le_bytes(uid.try_into().unwrap());
+                let Some(requests) = self.notification_requests.get_mut(&uid) else {
+                    return Frame::Unknown;
+                };
+                let Some(&count) = requests.front() else {
+                    return Frame::Unknown;
+                };
+
+                match attributes_len(&buf[5..], count) {
+                    Some(len) => {
+                        requests.pop_front();
+                        if requests.is_empty() {
+                            self.notification_requests.remove(&uid);
+                        }
+                        Frame::Complete(5 + len)
+                    }
+                    None => Frame::Incomplete,
+                }
+            }
+            Some(id) if id == CommandID::GetAppAttributes as u8 => {
+                let Some((app_identifier, _)) = protocol::split_nul_terminated(&buf[1..]) else {
+                    return Frame::Incomplete;
+                };
+                let header_len = 1 + app_identifier.len() + 1;
+                let app_identifier = String::from_utf8_lossy(app_identifier);
+                let Some(requests) = self.app_requests.get_mut(app_identifier.as_ref()) else {
+                    return Frame::Unknown;
+                };
+                let Some(&count) = requests.front() else {
+                    return Frame::Unknown;
+                };
+
+                match attributes_len(&buf[header_len..], count) {
+                    Some(len) => {
+                        requests.pop_front();
+                        if requests.is_empty() {
+                            let app_identifier = app_identifier.into_owned();
+                            self.app_requests.remove(&app_identifier);
+                        }
+                        Frame::Complete(header_len + len)
+                    }
+                    None => Frame::Incomplete,
+                }
+            }
+            Some(_) => Frame::Unknown,
+        }
+    }
+}
+
+/// Length of `count` attributes (ID, 16-bit length and value) at the start of `buf`, if all of
+/// them are there.
+fn attributes_len(buf: &[u8], count: usize) -> Option<usize> {
+    let mut rest = buf;
+    for _ in 0..count {
+        (_, rest) = protocol::split_attribute(rest)?;
+    }
+
+    Some(buf.len() - rest.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forgets_requests_that_were_not_sent() {
+        let mut reassembler = Reassembler::default();
+        // Only the app identifier, then the write fails
+        reassembler.expect_notification(7, 1);
+        reassembler.forget_notification(7);
+        reassembler.expect_notification(7, 2);
+        reassembler.expect_app("com.apple.MobileSMS", 1);
+        reassembler.forget_app("com.apple.MobileSMS");
+
+        let response = b"\x00\x07\x00\x00\x00\x00\x03\x00abc\x01\x03\x00Bob";
+        assert_eq!(reassembler.push(&response[..10]), Vec::<Vec<u8>>::new());
+        assert_eq!(reassembler.push(&response[10..]), [response.to_vec()]);
+        assert!(reassembler.notification_requests.is_empty());
+        assert!(reassembler.app_requests.is_empty());
+    }
+}
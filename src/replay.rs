@@ -0,0 +1,133 @@
+//! Golden tests replaying ANCS exchanges through the reassembler and the parsers.
+//!
+//! Every file in `tests/replay` is a transcript, one item per line:
+//!
+//! - `cp <hex>`: a request written to the control point
+//! - `ns <hex>`, `ds <hex>`: a packet received on the notification or data source
+//! - `= <event>`: what the request or packet above was decoded into
+//!
+//! Comments (`#`) and blank lines are kept as is. The test replays the requests and packets,
+//! writes down the events and compares the result with the whole file, so any change in how
+//! packets are decoded shows up as a diff. Run with `UPDATE_GOLDEN=1` to rewrite the files
+//! after an intended change, and review the diff.
+//!
+//! Personal content in captures, e.g. from `listen --trace-protocol`, must be replaced before
+//! adding them.
+
+use std::path::Path;
+
+use ancs::{
+    attributes::command::CommandID,
+    characteristics::control_point::{GetAppAttributesRequest, GetNotificationAttributesRequest},
+};
+
+use crate::{
+    reassembly::Reassembler,
+    trace::{notification_event, request_event, response_event},
+};
+
+/// Replays a transcript, returning it with freshly decoded events.
+pub(crate) fn replay(transcript: &str) -> String {
+    let mut reassembler = Reassembler::default();
+    let mut out = String::new();
+
+    for line in transcript.lines() {
+        if line.starts_with('=') {
+            continue;
+        }
+        out.push_str(line);
+        out.push('\n');
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (kind, data) = line.split_once(' ').unwrap_or((line, ""));
+        let data = parse_hex(data).unwrap_or_else(|| panic!("Invalid hex in {:?}", line));
+        let events = match kind {
+            "cp" => {
+                expect_response(&mut reassembler, &data);
+                vec![request_event(&data)]
+            }
+            "ns" => vec![notification_event(&data)],
+            "ds" => reassembler
+                .push(&data)
+                .iter()
+                .map(|r| response_event(r))
+                .collect(),
+            _ => panic!("Unknown line {:?}", line),
+        };
+        for event in events {
+            out.push_str(&format!("= {}\n", event));
+        }
+    }
+
+    out
+}
+
+/// Tells the reassembler about a request, like the processor does when sending it.
+fn expect_response(reassembler: &mut Reassembler, request: &[u8]) {
+    match request.first().copied() {
+        Some(id) if id == CommandID::GetNotificationAttributes as u8 => {
+            let (_, request) = GetNotificationAttributesRequest::parse(request).unwrap();
+            reassembler.expect_notification(request.notification_uid, request.attribute_ids.len());
+        }
+        Some(id) if id == CommandID::GetAppAttributes as u8 => {
+            let (_, request) = GetAppAttributesRequest::parse(request).unwrap();
+            reassembler.expect_app(&request.app_identifier, request.attribute_ids.len());
+        }
+        // Actions get no response on the data source
+        _ => {}
+    }
+}
+
+fn parse_hex(s: &str) -> Option<Vec<u8>> {
+    let digits: Vec<u8> = s.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    if !digits.len().is_multiple_of(2) {
+        return None;
+    }
+    digits
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+#[test]
+fn golden_transcripts() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/replay");
+    let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+
+    let mut paths: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "txt"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "No transcripts in {}", dir.display());
+
+    let mut failed = vec![];
+    for path in paths {
+        let expected = std::fs::read_to_string(&path).unwrap();
+        let actual = replay(&expected);
+        if actual == expected {
+            continue;
+        }
+
+        if update {
+            std::fs::write(&path, actual).unwrap();
+        } else {
+            let line = expected
+                .lines()
+                .zip(actual.lines())
+                .position(|(e, a)| e != a)
+                .unwrap_or(expected.lines().count().min(actual.lines().count()))
+                + 1;
+            failed.push(format!("{}:{}", path.display(), line));
+        }
+    }
+
+    assert!(
+        failed.is_empty(),
+        "Transcripts differ, rerun with UPDATE_GOLDEN=1 if intended:\n{}",
+        failed.join("\n")
+    );
+}
@@ -0,0 +1,131 @@
+use std::sync::Mutex;
+
+use crate::{logging, store::Slot};
+
+/// Why the daemon is in safe mode, if it is: network sinks get nothing, notifications are
+/// printed to stdout instead of shown on the desktop, and everything is logged in detail.
+static REASON: Mutex<Option<String>> = Mutex::new(None);
+
+pub fn reason() -> Option<String> {
+    REASON.lock().unwrap().clone()
+}
+
+pub fn is_active() -> bool {
+    REASON.lock().unwrap().is_some()
+}
+
+fn enter(reason: String) {
+    let mut current = REASON.lock().unwrap();
+    if current.is_some() {
+        return;
+    }
+    log::error!(
+        "{}, entering safe mode: not forwarding to network sinks, printing notifications \
+         instead of showing them, and logging everything. Restart once fixed.",
+        reason
+    );
+    for target in ["ancs_linux", "bluer"] {
+        if let Err(e) = logging::set_level(target, "debug") {
+            log::warn!("Failed to log {} in detail: {:?}", target, e);
+        }
+    }
+    *current = Some(reason);
+}
+
+/// Attempts in a row to subscribe to a phone that did not get there, kept across runs so that
+/// crashes count too. An attempt counts as failed from when it starts until it subscribes,
+/// since a crash leaves no chance to count it afterwards.
+pub struct Failures {
+    slot: Option<Slot>,
+    count: u32,
+    /// Enters safe mode after this many, never if 0.
+    limit: u32,
+    /// Whether the first attempt of the run was counted on startup already.
+    first: bool,
+}
+
+impl Failures {
+    /// Counts starting up as the beginning of the first attempt, so that failing to start at
+    /// all counts as well.
+    pub fn start(slot: Option<Slot>, limit: u32) -> Self {
+        let mut count = 0;
+        if let Some(slot) = &slot {
+            match slot.load() {
+                Ok(Some(content)) => count = content.trim().parse().unwrap_or_default(),
+                Ok(None) => {}
+                Err(e) => log::warn!("Failed to read {}: {:?}", slot, e),
+            }
+        }
+        let mut failures = Self {
+            slot,
+            count,
+            limit,
+            first: true,
+        };
+        failures.begin();
+        failures
+    }
+
+    /// Counts a session that is about to start.
+    pub fn attempt(&mut self) {
+        if !std::mem::take(&mut self.first) {
+            self.begin();
+        }
+    }
+
+    pub fn subscribed(&mut self) {
+        self.first = false;
+        if self.count != 0 {
+            self.count = 0;
+            self.save();
+        }
+    }
+
+    fn begin(&mut self) {
+        let failed = self.count;
+        self.count = self.count.saturating_add(1);
+        self.save();
+        if self.limit > 0 && failed >= self.limit {
+            enter(format!(
+                "{} attempts in a row did not get to receiving notifications",
+                failed
+            ));
+        }
+    }
+
+    fn save(&self) {
+        if let Some(slot) = &self.slot {
+            if let Err(e) = slot.save(&self.count.to_string()) {
+                log::warn!("Failed to save {}: {:?}", slot, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::store::FileStore;
+
+    #[test]
+    fn counts_attempts_across_runs() {
+        let dir = std::env::temp_dir().join(format!("ancs-linux-safemode-{}", std::process::id()));
+        let slot = Slot::new(Arc::new(FileStore::new(dir.clone())), "failed-attempts");
+
+        // Crashed twice while connecting, then fails once more
+        Failures::start(Some(slot.clone()), 3);
+        Failures::start(Some(slot.clone()), 3);
+        let mut failures = Failures::start(Some(slot.clone()), 3);
+        failures.attempt();
+        assert_eq!(failures.count, 3);
+        assert!(!is_active());
+        failures.attempt();
+        assert!(is_active());
+
+        failures.subscribed();
+        assert_eq!(Failures::start(Some(slot), 3).count, 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,163 @@
+use anyhow::{bail, Context, Result};
+use chrono::{
+    DateTime, Datelike, Duration, Local, NaiveDate, NaiveDateTime, NaiveTime, Utc, Weekday,
+};
+use chrono_tz::Tz;
+
+use crate::config::{Day, QuietHoursConfig};
+
+/// Decides when quiet hours are in effect.
+///
+/// Windows are evaluated in their configured timezone, so that quiet hours keep following the
+/// user's home time while the laptop travels, or the local time if no timezone is given.
+#[derive(Debug)]
+pub struct Scheduler {
+    timezone: Option<Tz>,
+    windows: Vec<Window>,
+}
+
+#[derive(Debug)]
+struct Window {
+    /// Days the window starts on, every day if empty.
+    days: Vec<Weekday>,
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl Scheduler {
+    pub fn new(config: &QuietHoursConfig) -> Result<Self> {
+        let timezone = match &config.timezone {
+            Some(name) => match name.parse::<Tz>() {
+                Ok(tz) => Some(tz),
+                Err(_) => bail!("Unknown timezone {:?} in quiet_hours", name),
+            },
+            None => None,
+        };
+
+        let mut windows = vec![];
+        for w in &config.windows {
+            windows.push(Window {
+                days: w.days.iter().map(|&d| d.into()).collect(),
+                start: parse_time(&w.start)?,
+                end: parse_time(&w.end)?,
+            });
+        }
+
+        Ok(Self { timezone, windows })
+    }
+
+    pub fn is_quiet(&self, now: DateTime<Utc>) -> bool {
+        let now = match self.timezone {
+            Some(tz) => now.with_timezone(&tz).naive_local(),
+            None => now.with_timezone(&Local).naive_local(),
+        };
+
+        self.windows.iter().any(|w| w.contains(now))
+    }
+}
+
+impl Window {
+    fn contains(&self, now: NaiveDateTime) -> bool {
+        let starts_on =
+            |date: NaiveDate| self.days.is_empty() || self.days.contains(&date.weekday());
+        let today = now.date();
+        let time = now.time();
+
+        if self.start <= self.end {
+            starts_on(today) && self.start <= time && time < self.end
+        } else {
+            // Past midnight, the window belongs to the day it started on
+            let yesterday = today - Duration::days(1);
+            (starts_on(today) && time >= self.start) || (starts_on(yesterday) && time < self.end)
+        }
+    }
+}
+
+fn parse_time(s: &str) -> Result<NaiveTime> {
+    NaiveTime::parse_from_str(s, "%H:%M")
+        .with_context(|| format!("Invalid time {:?} in quiet_hours, expected HH:MM", s))
+}
+
+impl From<Day> for Weekday {
+    fn from(day: Day) -> Self {
+        match day {
+            Day::Mon => Weekday::Mon,
+            Day::Tue => Weekday::Tue,
+            Day::Wed => Weekday::Wed,
+            Day::Thu => Weekday::Thu,
+            Day::Fri => Weekday::Fri,
+            Day::Sat => Weekday::Sat,
+            Day::Sun => Weekday::Sun,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::QuietWindow;
+
+    fn scheduler(timezone: &str, days: &[Day], start: &str, end: &str) -> Scheduler {
+        Scheduler::new(&QuietHoursConfig {
+            timezone: Some(timezone.to_string()),
+            windows: vec![QuietWindow {
+                days: days.to_vec(),
+                start: start.to_string(),
+                end: end.to_string(),
+            }],
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn window_within_a_day() {
+        let s = scheduler("UTC", &[], "12:00", "13:00");
+
+        assert!(!s.is_quiet(utc("2024-05-06T11:59:00Z")));
+        assert!(s.is_quiet(utc("2024-05-06T12:00:00Z")));
+        assert!(!s.is_quiet(utc("2024-05-06T13:00:00Z")));
+    }
+
+    #[test]
+    fn window_past_midnight_belongs_to_its_start_day() {
+        // 2024-05-10 is a Friday
+        let s = scheduler("UTC", &[Day::Fri], "22:00", "07:00");
+
+        assert!(s.is_quiet(utc("2024-05-10T23:00:00Z")));
+        assert!(s.is_quiet(utc("2024-05-11T06:59:00Z")));
+        assert!(!s.is_quiet(utc("2024-05-11T07:00:00Z")));
+        // Early Friday morning belongs to Thursday night
+        assert!(!s.is_quiet(utc("2024-05-10T03:00:00Z")));
+    }
+
+    #[test]
+    fn uses_configured_timezone() {
+        // 22:00 in Tokyo is 13:00 UTC
+        let s = scheduler("Asia/Tokyo", &[Day::Mon], "22:00", "23:00");
+
+        assert!(s.is_quiet(utc("2024-05-06T13:30:00Z")));
+        assert!(!s.is_quiet(utc("2024-05-06T22:30:00Z")));
+    }
+
+    #[test]
+    fn rejects_invalid_config() {
+        let mut config = QuietHoursConfig {
+            timezone: Some("Mars/Olympus_Mons".to_string()),
+            ..Default::default()
+        };
+        assert!(Scheduler::new(&config).is_err());
+
+        config.timezone = None;
+        config.windows = vec![QuietWindow {
+            days: vec![],
+            start: "25:00".to_string(),
+            end: "07:00".to_string(),
+        }];
+        assert!(Scheduler::new(&config).is_err());
+    }
+}
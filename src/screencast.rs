@@ -0,0 +1,249 @@
+use std::collections::{HashMap, VecDeque};
+
+use futures::{stream::BoxStream, StreamExt as _};
+use tokio::sync::watch;
+use zbus::{
+    fdo::MonitoringProxy,
+    message::Type,
+    zvariant::{OwnedObjectPath, OwnedValue},
+    Connection, MatchRule, Message, MessageStream,
+};
+
+/// Portal interfaces whose `Start` may share the screen.
+const START_INTERFACES: [&str; 2] = [
+    "org.freedesktop.portal.ScreenCast",
+    "org.freedesktop.portal.RemoteDesktop",
+];
+
+/// Watches screen sharing through xdg-desktop-portal.
+///
+/// The portal does not tell others about its sessions, so this eavesdrops on the session bus
+/// as a monitor, like `dbus-monitor`. Screens shared without the portal, e.g. by X11 apps or
+/// the desktop's own recorder, go unnoticed.
+#[derive(Clone)]
+pub struct ScreenCastMonitor {
+    sharing: watch::Receiver<bool>,
+}
+
+impl ScreenCastMonitor {
+    pub async fn new() -> zbus::Result<Self> {
+        let conn = Connection::session().await?;
+        let mut rules = vec![];
+        for interface in START_INTERFACES {
+            rules.push(
+                MatchRule::builder()
+                    .msg_type(Type::MethodCall)
+                    .interface(interface)?
+                    .member("Start")?
+                    .build(),
+            );
+        }
+        rules.push(
+            MatchRule::builder()
+                .msg_type(Type::Signal)
+                .interface("org.freedesktop.portal.Request")?
+                .member("Response")?
+                .build(),
+        );
+        rules.push(
+            MatchRule::builder()
+                .msg_type(Type::MethodCall)
+                .interface("org.freedesktop.portal.Session")?
+                .member("Close")?
+                .build(),
+        );
+        rules.push(
+            MatchRule::builder()
+                .msg_type(Type::Signal)
+                .interface("org.freedesktop.portal.Session")?
+                .member("Closed")?
+                .build(),
+        );
+        rules.push(
+            MatchRule::builder()
+                .msg_type(Type::Signal)
+                .sender("org.freedesktop.DBus")?
+                .interface("org.freedesktop.DBus")?
+                .member("NameOwnerChanged")?
+                .build(),
+        );
+
+        let mut messages = MessageStream::from(&conn);
+        MonitoringProxy::new(&conn)
+            .await?
+            .become_monitor(&rules, 0)
+            .await?;
+
+        let (tx, sharing) = watch::channel(false);
+        tokio::spawn(async move {
+            let _conn = conn;
+            let mut tracker = Tracker::default();
+            while let Some(msg) = messages.next().await {
+                let Ok(msg) = msg else {
+                    continue;
+                };
+                tracker.observe(&msg);
+
+                let now = tracker.is_sharing();
+                if now != *tx.borrow() {
+                    if now {
+                        log::info!("Screen sharing started");
+                    } else {
+                        log::info!("Screen sharing ended");
+                    }
+                    tx.send_replace(now);
+                }
+            }
+            log::warn!("Stopped watching screen sharing");
+            tx.send_replace(false);
+        });
+
+        Ok(Self { sharing })
+    }
+
+    pub fn is_sharing(&self) -> bool {
+        *self.sharing.borrow()
+    }
+
+    pub fn changes(&self) -> BoxStream<'static, bool> {
+        futures::stream::unfold(self.sharing.clone(), |mut rx| async move {
+            rx.changed().await.ok()?;
+            let sharing = *rx.borrow_and_update();
+            Some((sharing, rx))
+        })
+        .boxed()
+    }
+}
+
+/// Portal sessions that share a screen, from what was seen on the bus.
+#[derive(Default)]
+struct Tracker {
+    /// Sessions whose start was requested but not granted yet, by client.
+    starting: HashMap<String, VecDeque<String>>,
+    /// Sessions sharing a screen, with their client.
+    sharing: HashMap<String, String>,
+}
+
+impl Tracker {
+    fn observe(&mut self, msg: &Message) {
+        let header = msg.header();
+        let member = header.member().map(|m| m.as_str()).unwrap_or_default();
+        let interface = header.interface().map(|i| i.as_str()).unwrap_or_default();
+
+        match (msg.message_type(), member) {
+            (Type::MethodCall, "Start") if START_INTERFACES.contains(&interface) => {
+                let body = msg
+                    .body()
+                    .deserialize::<(OwnedObjectPath, String, HashMap<String, OwnedValue>)>();
+                if let (Some(client), Ok((session, _, _))) = (header.sender(), body) {
+                    self.start_requested(client, session.as_str());
+                }
+            }
+            (Type::Signal, "Response") => {
+                let body = msg
+                    .body()
+                    .deserialize::<(u32, HashMap<String, OwnedValue>)>();
+                // Only a granted start comes with streams
+                if let (Some(client), Ok((0, results))) = (header.destination(), body) {
+                    if results.contains_key("streams") {
+                        self.started(client);
+                    }
+                }
+            }
+            (Type::MethodCall, "Close") | (Type::Signal, "Closed") => {
+                if let Some(session) = header.path() {
+                    self.closed(session.as_str());
+                }
+            }
+            (Type::Signal, "NameOwnerChanged") => {
+                if let Ok((name, _, new_owner)) =
+                    msg.body().deserialize::<(String, String, String)>()
+                {
+                    if new_owner.is_empty() {
+                        self.client_gone(&name);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn start_requested(&mut self, client: &str, session: &str) {
+        self.starting
+            .entry(client.to_string())
+            .or_default()
+            .push_back(session.to_string());
+    }
+
+    fn started(&mut self, client: &str) {
+        let Some(starting) = self.starting.get_mut(client) else {
+            return;
+        };
+        if let Some(session) = starting.pop_front() {
+            self.sharing.insert(session, client.to_string());
+        }
+        if starting.is_empty() {
+            self.starting.remove(client);
+        }
+    }
+
+    fn closed(&mut self, session: &str) {
+        self.sharing.remove(session);
+        for starting in self.starting.values_mut() {
+            starting.retain(|s| s != session);
+        }
+        self.starting.retain(|_, starting| !starting.is_empty());
+    }
+
+    /// Sessions end with the client that created them.
+    fn client_gone(&mut self, client: &str) {
+        self.starting.remove(client);
+        self.sharing.retain(|_, c| c != client);
+    }
+
+    fn is_sharing(&self) -> bool {
+        !self.sharing.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SESSION: &str = "/org/freedesktop/portal/desktop/session/1_42/obs1";
+
+    #[test]
+    fn shares_from_grant_until_close() {
+        let mut tracker = Tracker::default();
+        tracker.start_requested(":1.42", SESSION);
+        assert!(!tracker.is_sharing());
+
+        tracker.started(":1.42");
+        assert!(tracker.is_sharing());
+
+        tracker.closed(SESSION);
+        assert!(!tracker.is_sharing());
+    }
+
+    #[test]
+    fn ends_when_the_client_leaves() {
+        let mut tracker = Tracker::default();
+        tracker.start_requested(":1.42", SESSION);
+        tracker.started(":1.42");
+        tracker.client_gone(":1.42");
+
+        assert!(!tracker.is_sharing());
+        assert!(tracker.starting.is_empty());
+    }
+
+    #[test]
+    fn ignores_grants_without_start() {
+        let mut tracker = Tracker::default();
+        tracker.started(":1.42");
+        tracker.start_requested(":1.7", SESSION);
+        tracker.closed(SESSION);
+        tracker.started(":1.7");
+
+        assert!(!tracker.is_sharing());
+    }
+}
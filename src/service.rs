@@ -0,0 +1,873 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use ancs_linux::{
+    alert::AlertLevel,
+    control::{ActiveEntry, HistoryEntry, SinkStatus},
+};
+use bluer::Address;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, oneshot};
+use zbus::{connection, fdo, interface, Connection, SignalContext};
+
+use crate::{
+    appnames::AppNames,
+    config::{Category, Config},
+    history::{self, History},
+    logging,
+    metrics::{self, METRICS},
+    privacy,
+    protocol::ActionID,
+    safemode,
+    state::{self, SessionState},
+};
+
+/// What a reload leaves as it was, being set up once at startup.
+const RELOAD_NOTE: &str = "Reloaded, changes to devices, storage, the history, quiet hours, \
+                           sinks and contacts take effect after restarting";
+
+const BUS_NAME: &str = "io.github.kmod_midori.AncsLinux";
+const OBJECT_PATH: &str = "/io/github/kmod_midori/AncsLinux";
+/// Of the generic interface, for status bars and scripts that are not written for ancs-linux
+/// alone.
+const SERVICE1_NAME: &str = "org.ancslinux.Service1";
+const SERVICE1_PATH: &str = "/org/ancslinux/Service1";
+
+/// A method call the processor has to answer.
+pub enum Request {
+    /// Performs an action on the most recently shown notification.
+    ActOnLast {
+        action: ActionID,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    /// Performs an action on a notification that is on the phone.
+    Perform {
+        uid: u32,
+        action: ActionID,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    /// Performs the action of a notification that has the given label.
+    SendAction {
+        uid: u32,
+        label: String,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    /// Makes the phone alert, or stop alerting with [`AlertLevel::None`].
+    Ring {
+        level: AlertLevel,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    /// Lists the notifications on the phone.
+    List { reply: oneshot::Sender<Vec<Active>> },
+    /// Applies a config read anew to every notification from now on.
+    Reload { config: Arc<Config> },
+}
+
+/// A notification on the phone, as listed on the control socket.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Active {
+    /// Empty for the simulated device.
+    pub device: String,
+    pub uid: u32,
+    pub app_id: String,
+    pub app_name: String,
+    /// Empty in private mode.
+    pub title: String,
+    pub category: Option<Category>,
+    pub received_at: DateTime<Utc>,
+    /// Whether it is up on the desktop.
+    pub shown: bool,
+}
+
+impl Active {
+    /// As the `List` method returns it.
+    pub fn listed(&self) -> ActiveEntry {
+        (
+            self.device.clone(),
+            self.uid,
+            self.received_at.to_rfc3339(),
+            self.app_name.clone(),
+            self.title.clone(),
+            self.category
+                .map(|c| c.label().to_string())
+                .unwrap_or_default(),
+            self.shown,
+        )
+    }
+}
+
+/// Reads the config anew for a reload, with what the command line of `listen` overrides.
+pub type Loader = Arc<dyn Fn() -> anyhow::Result<Config> + Send + Sync>;
+
+/// How the daemon is doing at a glance, see the `Status` method.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Status {
+    /// Of the phone that is furthest along.
+    pub state: String,
+    pub safe_mode: Option<String>,
+    pub private_mode: bool,
+    pub received: u64,
+    pub suppressed: u64,
+}
+
+/// Why a request from the bus or the control socket was not carried out.
+#[derive(Debug, thiserror::Error)]
+pub enum ControlError {
+    #[error("{0}")]
+    InvalidArgs(String),
+    #[error("{0}")]
+    Failed(String),
+}
+
+impl From<ControlError> for fdo::Error {
+    fn from(e: ControlError) -> Self {
+        match e {
+            ControlError::InvalidArgs(message) => fdo::Error::InvalidArgs(message),
+            ControlError::Failed(message) => fdo::Error::Failed(message),
+        }
+    }
+}
+
+fn not_connected() -> ControlError {
+    ControlError::Failed("Not connected to the phone".to_string())
+}
+
+/// A method call about the connection to the phone, which outlives any processor.
+#[derive(Clone, Copy)]
+pub enum LinkCommand {
+    /// Tears down the connection and establishes it again, also ending a disconnect.
+    Reconnect,
+    /// Disconnects and stays so until asked to reconnect.
+    Disconnect,
+}
+
+/// The processor of every phone, and which phone showed the most recent notification.
+#[derive(Default)]
+struct Processors {
+    senders: BTreeMap<Address, mpsc::UnboundedSender<Request>>,
+    last: Option<Address>,
+}
+
+/// Reaches the processor and the connection of every phone, for the session bus and the
+/// control socket alike.
+///
+/// Lives for the whole run, while processors come and go with the connection to the phone.
+#[derive(Clone)]
+pub struct Control {
+    processors: Arc<Mutex<Processors>>,
+    /// One for every phone.
+    links: Arc<Vec<(Address, mpsc::UnboundedSender<LinkCommand>)>>,
+    /// As last loaded, which new processors start with.
+    config: Arc<Mutex<Arc<Config>>>,
+}
+
+/// The daemon's interface on the session bus, e.g. for binding keyboard shortcuts.
+#[derive(Clone)]
+pub struct Service {
+    conn: Connection,
+}
+
+struct Daemon {
+    control: Control,
+    app_names: Arc<Mutex<AppNames>>,
+    config: Arc<Config>,
+    history: Option<Arc<History>>,
+    load: Loader,
+}
+
+/// The generic interface, with the signals of [`Daemon`] and the few methods every integration
+/// needs.
+struct Service1 {
+    control: Control,
+}
+
+/// A phone, below the daemon's object, so that UIs can show and control each on its own.
+struct DeviceObject {
+    /// [`Address::any`] for the simulated device.
+    address: Address,
+    link: mpsc::UnboundedSender<LinkCommand>,
+}
+
+impl Control {
+    /// `devices` are the phones to listen to, [`Address::any`] when simulating one. Returns
+    /// the link commands for each, in the same order.
+    pub fn new(
+        config: Arc<Config>,
+        devices: &[Address],
+    ) -> (Self, Vec<mpsc::UnboundedReceiver<LinkCommand>>) {
+        let (links, link_commands): (Vec<_>, Vec<_>) = devices
+            .iter()
+            .map(|&device| {
+                let (tx, rx) = mpsc::unbounded_channel();
+                ((device, tx), rx)
+            })
+            .unzip();
+        let control = Self {
+            processors: Default::default(),
+            links: Arc::new(links),
+            config: Arc::new(Mutex::new(config)),
+        };
+        (control, link_commands)
+    }
+
+    /// Sends requests about a phone to a new processor from now on.
+    pub fn attach(&self, device: Address, tx: mpsc::UnboundedSender<Request>) {
+        self.processors.lock().unwrap().senders.insert(device, tx);
+    }
+
+    /// Records that a phone showed the most recent notification, which requests act on.
+    pub fn shown_by(&self, device: Address) {
+        self.processors.lock().unwrap().last = Some(device);
+    }
+
+    /// The config as last loaded.
+    pub fn config(&self) -> Arc<Config> {
+        self.config.lock().unwrap().clone()
+    }
+
+    /// Performs an action on the most recently shown notification.
+    pub async fn perform_on_last(&self, action: ActionID) -> Result<(), ControlError> {
+        let (reply, response) = oneshot::channel();
+        let sent = {
+            let processors = self.processors.lock().unwrap();
+            match processors.last.and_then(|d| processors.senders.get(&d)) {
+                Some(tx) => tx.send(Request::ActOnLast { action, reply }).is_ok(),
+                None => false,
+            }
+        };
+        if !sent {
+            return Err(not_connected());
+        }
+        Self::wait(response).await
+    }
+
+    async fn wait(response: oneshot::Receiver<Result<(), String>>) -> Result<(), ControlError> {
+        match response.await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(e)) => Err(ControlError::Failed(e)),
+            Err(_) => Err(not_connected()),
+        }
+    }
+
+    /// Sends a request to the processor of a phone by address, which may be empty while
+    /// mirroring one.
+    fn send(&self, device: &str, request: Request) -> Result<(), ControlError> {
+        let processors = self.processors.lock().unwrap();
+        let tx = if device.is_empty() {
+            if processors.senders.len() > 1 {
+                return Err(ControlError::InvalidArgs(
+                    "Several phones are mirrored, give the address of one".to_string(),
+                ));
+            }
+            processors.senders.values().next()
+        } else {
+            let device: Address = device
+                .parse()
+                .map_err(|_| ControlError::InvalidArgs(format!("Not an address: {}", device)))?;
+            processors.senders.get(&device)
+        };
+        match tx {
+            Some(tx) if tx.send(request).is_ok() => Ok(()),
+            _ => Err(not_connected()),
+        }
+    }
+
+    /// Performs an action on a notification of a phone, see [`Control::send`].
+    pub async fn perform(
+        &self,
+        device: &str,
+        uid: u32,
+        action: ActionID,
+    ) -> Result<(), ControlError> {
+        let (reply, response) = oneshot::channel();
+        self.send(device, Request::Perform { uid, action, reply })?;
+        Self::wait(response).await
+    }
+
+    /// Performs the action with the given label on a notification, see [`Control::send`].
+    pub async fn send_action(
+        &self,
+        device: &str,
+        uid: u32,
+        label: &str,
+    ) -> Result<(), ControlError> {
+        let (reply, response) = oneshot::channel();
+        let request = Request::SendAction {
+            uid,
+            label: label.to_string(),
+            reply,
+        };
+        self.send(device, request)?;
+        Self::wait(response).await
+    }
+
+    /// Makes a phone alert to be found, see [`Control::send`].
+    pub async fn ring(&self, device: &str, level: AlertLevel) -> Result<(), ControlError> {
+        let (reply, response) = oneshot::channel();
+        self.send(device, Request::Ring { level, reply })?;
+        Self::wait(response).await
+    }
+
+    /// Sends a command about the link to every phone.
+    pub fn send_link_command(&self, command: LinkCommand) -> Result<(), ControlError> {
+        for (_, link) in self.links.iter() {
+            link.send(command)
+                .map_err(|_| ControlError::Failed("Shutting down".to_string()))?;
+        }
+        Ok(())
+    }
+
+    /// The notifications on every phone connected, by phone and as they arrived.
+    pub async fn list(&self) -> Vec<Active> {
+        let responses: Vec<_> = {
+            let processors = self.processors.lock().unwrap();
+            processors
+                .senders
+                .values()
+                .filter_map(|tx| {
+                    let (reply, response) = oneshot::channel();
+                    tx.send(Request::List { reply }).ok().map(|_| response)
+                })
+                .collect()
+        };
+        let mut active = Vec::new();
+        for response in responses {
+            // Gone if the processor ended meanwhile
+            if let Ok(list) = response.await {
+                active.extend(list);
+            }
+        }
+        active
+    }
+
+    /// Uses `config` for every notification from now on, also in new processors.
+    /// Reads the config with `load` and applies it, telling what still needs a restart.
+    pub fn reload_with(
+        &self,
+        load: &(dyn Fn() -> anyhow::Result<Config> + Send + Sync),
+    ) -> anyhow::Result<&'static str> {
+        self.reload(load()?);
+        log::info!("Reloaded the config");
+        Ok(RELOAD_NOTE)
+    }
+
+    pub fn reload(&self, config: Config) {
+        let config = Arc::new(config);
+        *self.config.lock().unwrap() = config.clone();
+        let processors = self.processors.lock().unwrap();
+        for tx in processors.senders.values() {
+            let _ = tx.send(Request::Reload {
+                config: config.clone(),
+            });
+        }
+    }
+
+    /// The phones being mirrored, in the order given.
+    fn devices(&self) -> impl Iterator<Item = Address> + '_ {
+        self.links.iter().map(|&(address, _)| address)
+    }
+
+    pub fn status(&self) -> Status {
+        let state = self
+            .devices()
+            .map(|address| state::current(address).state)
+            .max_by_key(|&state| progress(state))
+            .unwrap_or(SessionState::Idle);
+        Status {
+            state: state.to_string(),
+            safe_mode: safemode::reason(),
+            private_mode: privacy::is_private_mode(),
+            received: METRICS.received.load(std::sync::atomic::Ordering::Relaxed),
+            suppressed: metrics::suppressed(),
+        }
+    }
+}
+
+impl Service {
+    pub async fn new(
+        control: Control,
+        config: Arc<Config>,
+        app_names: Arc<Mutex<AppNames>>,
+        history: Option<Arc<History>>,
+        load: Loader,
+    ) -> zbus::Result<Self> {
+        let mut builder = connection::Builder::session()?
+            .name(BUS_NAME)?
+            .name(SERVICE1_NAME)?
+            .serve_at(OBJECT_PATH, fdo::ObjectManager)?
+            .serve_at(
+                SERVICE1_PATH,
+                Service1 {
+                    control: control.clone(),
+                },
+            )?;
+        for (address, link) in control.links.iter() {
+            builder = builder.serve_at(
+                device_path(*address),
+                DeviceObject {
+                    address: *address,
+                    link: link.clone(),
+                },
+            )?;
+        }
+        let conn = builder
+            .serve_at(
+                OBJECT_PATH,
+                Daemon {
+                    control,
+                    app_names,
+                    config,
+                    history,
+                    load,
+                },
+            )?
+            .build()
+            .await?;
+
+        Ok(Self { conn })
+    }
+
+    /// Tells listeners on the bus of a notification that reached the desktop side, as it is
+    /// kept in the history. Only its app is told in private mode.
+    pub async fn received(
+        &self,
+        device: Address,
+        entry: &history::Entry,
+        app_name: &str,
+        category_id: Option<u8>,
+        important: bool,
+    ) {
+        let private = privacy::is_private_mode();
+        let text = |s: &Option<String>| match s {
+            Some(s) if !private => s.clone(),
+            _ => String::new(),
+        };
+        let device = display_address(device);
+        let (title, subtitle, message) = (
+            text(&entry.title),
+            text(&entry.subtitle),
+            text(&entry.message),
+        );
+        let category = category_id.unwrap_or(u8::MAX);
+        let result = async {
+            let ctxt = SignalContext::new(&self.conn, OBJECT_PATH)?;
+            Daemon::notification_received(
+                &ctxt,
+                &device,
+                entry.uid,
+                &entry.app_id,
+                app_name,
+                &title,
+                &subtitle,
+                &message,
+                category,
+                important,
+            )
+            .await?;
+            let ctxt = SignalContext::new(&self.conn, SERVICE1_PATH)?;
+            Service1::notification_received(
+                &ctxt,
+                &device,
+                entry.uid,
+                &entry.app_id,
+                app_name,
+                &title,
+                &subtitle,
+                &message,
+                category,
+                important,
+            )
+            .await
+        };
+        if let Err(e) = result.await {
+            log::warn!("Failed to signal notification {}: {:?}", entry.uid, e);
+        }
+    }
+
+    /// Tells listeners on the bus that a notification is gone from the phone.
+    pub async fn removed(&self, device: Address, uid: u32) {
+        let device = display_address(device);
+        let result = async {
+            let ctxt = SignalContext::new(&self.conn, OBJECT_PATH)?;
+            Daemon::notification_removed(&ctxt, &device, uid).await?;
+            let ctxt = SignalContext::new(&self.conn, SERVICE1_PATH)?;
+            Service1::notification_removed(&ctxt, &device, uid).await
+        };
+        if let Err(e) = result.await {
+            log::warn!("Failed to signal removing notification {}: {:?}", uid, e);
+        }
+    }
+}
+
+#[interface(name = "io.github.kmod_midori.AncsLinux")]
+impl Daemon {
+    /// Dismisses the most recent notification on the phone.
+    async fn dismiss_last(&self) -> fdo::Result<()> {
+        Ok(self.control.perform_on_last(ActionID::Negative).await?)
+    }
+
+    /// Performs the positive action of the most recent notification, e.g. answers a call.
+    async fn act_on_last(&self) -> fdo::Result<()> {
+        Ok(self.control.perform_on_last(ActionID::Positive).await?)
+    }
+
+    /// Performs the action with the given label on a notification, e.g. "Mark as Read".
+    /// `device` is the address of the phone, which may be empty while mirroring one.
+    async fn send_action(&self, device: &str, uid: u32, label: &str) -> fdo::Result<()> {
+        Ok(self.control.send_action(device, uid, label).await?)
+    }
+
+    /// Dismisses a notification on the phone, e.g. one told by `NotificationReceived`.
+    /// `device` is the address of the phone, which may be empty while mirroring one.
+    async fn dismiss_notification(&self, device: &str, uid: u32) -> fdo::Result<()> {
+        Ok(self
+            .control
+            .perform(device, uid, ActionID::Negative)
+            .await?)
+    }
+
+    /// A notification reached the desktop side: the phone's address (empty for the simulated
+    /// device), its uid, app id and name, title, subtitle, message, category (255 if unknown)
+    /// and whether the phone marked it important. Missing attributes are empty, and so is what
+    /// it says in private mode. It may still be kept off the desktop, e.g. during quiet hours.
+    #[zbus(signal)]
+    #[allow(clippy::too_many_arguments)]
+    async fn notification_received(
+        ctxt: &SignalContext<'_>,
+        device: &str,
+        uid: u32,
+        app_id: &str,
+        app_name: &str,
+        title: &str,
+        subtitle: &str,
+        message: &str,
+        category: u8,
+        important: bool,
+    ) -> zbus::Result<()>;
+
+    /// A notification is gone from the phone, e.g. dismissed there.
+    #[zbus(signal)]
+    async fn notification_removed(
+        ctxt: &SignalContext<'_>,
+        device: &str,
+        uid: u32,
+    ) -> zbus::Result<()>;
+
+    /// Makes the phone play a sound to be found, until `StopRinging`.
+    /// `device` is the address of the phone, which may be empty while mirroring one.
+    async fn ring(&self, device: &str) -> fdo::Result<()> {
+        Ok(self.control.ring(device, AlertLevel::High).await?)
+    }
+
+    async fn stop_ringing(&self, device: &str) -> fdo::Result<()> {
+        Ok(self.control.ring(device, AlertLevel::None).await?)
+    }
+
+    /// Tears down the connection to every phone and establishes it again.
+    async fn reconnect(&self) -> fdo::Result<()> {
+        Ok(self.control.send_link_command(LinkCommand::Reconnect)?)
+    }
+
+    /// Disconnects from every phone until `Reconnect` is called.
+    async fn disconnect(&self) -> fdo::Result<()> {
+        Ok(self.control.send_link_command(LinkCommand::Disconnect)?)
+    }
+
+    /// Disconnects from every phone until `Resume` is called, like pausing each.
+    async fn pause(&self) -> fdo::Result<()> {
+        Ok(self.control.send_link_command(LinkCommand::Disconnect)?)
+    }
+
+    async fn resume(&self) -> fdo::Result<()> {
+        Ok(self.control.send_link_command(LinkCommand::Reconnect)?)
+    }
+
+    /// At a glance, e.g. for a status bar: the state of the phone that is furthest along, why
+    /// in safe mode (empty if not), whether in private mode, and the notifications received
+    /// and kept off the desktop since startup.
+    async fn status(&self) -> (String, String, bool, u64, u64) {
+        let status = self.control.status();
+        (
+            status.state,
+            status.safe_mode.unwrap_or_default(),
+            status.private_mode,
+            status.received,
+            status.suppressed,
+        )
+    }
+
+    /// Whether new notifications show nothing but their app.
+    async fn private_mode(&self) -> bool {
+        privacy::is_private_mode()
+    }
+
+    async fn set_private_mode(&self, enabled: bool) {
+        log::info!("Privacy mode {}", if enabled { "on" } else { "off" });
+        privacy::set_private_mode(enabled);
+    }
+
+    /// Counters since startup, by name.
+    async fn stats(&self) -> Vec<(String, u64)> {
+        METRICS.snapshot()
+    }
+
+    /// Why the daemon is in safe mode, empty if it is not.
+    async fn safe_mode(&self) -> String {
+        safemode::reason().unwrap_or_default()
+    }
+
+    /// State of the connection to every phone: its address (empty for the simulated device),
+    /// the state, since when (RFC 3339) and why, if idle or degraded.
+    async fn sessions(&self) -> Vec<(String, String, String, String)> {
+        self.control
+            .devices()
+            .map(|address| {
+                let session = state::current(address);
+                (
+                    display_address(address),
+                    session.state.to_string(),
+                    session.since.to_rfc3339(),
+                    session.reason.unwrap_or_default(),
+                )
+            })
+            .collect()
+    }
+
+    /// Delivery to every network sink, failing since is RFC 3339 and empty while it works.
+    async fn sink_status(&self) -> Vec<SinkStatus> {
+        METRICS
+            .sinks()
+            .into_iter()
+            .map(|(name, stats)| {
+                (
+                    name,
+                    stats.delivered,
+                    stats.failed,
+                    stats.queued as u64,
+                    stats
+                        .failing_since
+                        .map(|t| t.wall.to_rfc3339())
+                        .unwrap_or_default(),
+                    stats.last_error.unwrap_or_default(),
+                )
+            })
+            .collect()
+    }
+
+    /// The most recent notifications in the history, most recent first, e.g. for a status bar.
+    async fn recent(&self, missed_only: bool, limit: u32) -> fdo::Result<Vec<HistoryEntry>> {
+        let Some(history) = &self.history else {
+            return Err(fdo::Error::Failed("The history is disabled".to_string()));
+        };
+        let entries = history
+            .query(&history::Query {
+                missed_only,
+                limit: Some(limit as usize),
+                ..Default::default()
+            })
+            .map_err(|e| fdo::Error::Failed(format!("{:#}", e)))?;
+        Ok(entries.iter().map(|e| e.listed(&self.config)).collect())
+    }
+
+    /// Known app names: bundle identifier, name and whether the user set it.
+    async fn app_names(&self) -> Vec<(String, String, bool)> {
+        let app_names = self.app_names.lock().unwrap();
+        app_names
+            .list()
+            .map(|(id, n)| (id.to_string(), n.name.clone(), n.custom))
+            .collect()
+    }
+
+    /// Shows an app under this name instead of the one the phone tells.
+    async fn set_app_name(&self, app_id: &str, name: &str) -> fdo::Result<()> {
+        if name.is_empty() {
+            return Err(fdo::Error::InvalidArgs("The name is empty".to_string()));
+        }
+        let mut app_names = self.app_names.lock().unwrap();
+        app_names
+            .set(app_id, name)
+            .map_err(|e| fdo::Error::Failed(format!("{:#}", e)))?;
+        log::info!("Showing {} as {}", app_id, name);
+        Ok(())
+    }
+
+    /// Forgets the name of an app, so that it is looked up again.
+    async fn remove_app_name(&self, app_id: &str) -> fdo::Result<()> {
+        let mut app_names = self.app_names.lock().unwrap();
+        match app_names.remove(app_id) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(fdo::Error::InvalidArgs(format!(
+                "No name is known for {}",
+                app_id
+            ))),
+            Err(e) => Err(fdo::Error::Failed(format!("{:#}", e))),
+        }
+    }
+
+    /// Overrides the log level of a target and everything below it, `default` to undo.
+    async fn set_log_level(&self, target: &str, level: &str) -> fdo::Result<()> {
+        logging::set_level(target, level).map_err(|e| fdo::Error::InvalidArgs(e.to_string()))?;
+        log::info!("Log level of {} set to {}", target, level);
+        Ok(())
+    }
+
+    /// The notifications on every phone whose attributes arrived, see the `ActiveEntry` type
+    /// of the client.
+    async fn list(&self) -> Vec<ActiveEntry> {
+        self.control
+            .list()
+            .await
+            .iter()
+            .map(Active::listed)
+            .collect()
+    }
+
+    /// Reads the config anew and applies it to notifications from now on, telling what takes
+    /// a restart still.
+    async fn reload(&self) -> fdo::Result<String> {
+        match self.control.reload_with(&*self.load) {
+            Ok(note) => Ok(note.to_string()),
+            Err(e) => Err(fdo::Error::Failed(format!("{:#}", e))),
+        }
+    }
+}
+
+/// Object path of a phone, named after its address like in BlueZ.
+fn device_path(address: Address) -> String {
+    let name = if address == Address::any() {
+        "simulated".to_string()
+    } else {
+        format!("dev_{}", address.to_string().replace(':', "_"))
+    };
+    format!("{}/devices/{}", OBJECT_PATH, name)
+}
+
+/// How far a session got towards receiving notifications.
+fn progress(state: SessionState) -> u8 {
+    match state {
+        SessionState::Idle => 0,
+        SessionState::Connecting => 1,
+        SessionState::Discovering => 2,
+        SessionState::Degraded => 3,
+        SessionState::Subscribed => 4,
+    }
+}
+
+/// How a phone is told on the bus and the control socket, empty for the simulated device.
+pub fn display_address(address: Address) -> String {
+    if address == Address::any() {
+        String::new()
+    } else {
+        address.to_string()
+    }
+}
+
+/// The counters are not kept by phone, so those are for all of them.
+#[interface(name = "org.ancslinux.Service1")]
+impl Service1 {
+    /// Dismisses a notification on the phone, which has to be the only one mirrored.
+    async fn dismiss_notification(&self, uid: u32) -> fdo::Result<()> {
+        Ok(self.control.perform("", uid, ActionID::Negative).await?)
+    }
+
+    /// Disconnects from every phone until `Resume` is called.
+    async fn pause(&self) -> fdo::Result<()> {
+        Ok(self.control.send_link_command(LinkCommand::Disconnect)?)
+    }
+
+    async fn resume(&self) -> fdo::Result<()> {
+        Ok(self.control.send_link_command(LinkCommand::Reconnect)?)
+    }
+
+    /// Like `Status` of `io.github.kmod_midori.AncsLinux`: the state of the phone that is
+    /// furthest along, why in safe mode (empty if not), whether in private mode, and the
+    /// notifications received and kept off the desktop since startup.
+    async fn get_status(&self) -> (String, String, bool, u64, u64) {
+        let status = self.control.status();
+        (
+            status.state,
+            status.safe_mode.unwrap_or_default(),
+            status.private_mode,
+            status.received,
+            status.suppressed,
+        )
+    }
+
+    /// See `NotificationReceived` of `io.github.kmod_midori.AncsLinux`.
+    #[zbus(signal)]
+    #[allow(clippy::too_many_arguments)]
+    async fn notification_received(
+        ctxt: &SignalContext<'_>,
+        device: &str,
+        uid: u32,
+        app_id: &str,
+        app_name: &str,
+        title: &str,
+        subtitle: &str,
+        message: &str,
+        category: u8,
+        important: bool,
+    ) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn notification_removed(
+        ctxt: &SignalContext<'_>,
+        device: &str,
+        uid: u32,
+    ) -> zbus::Result<()>;
+}
+
+#[interface(name = "io.github.kmod_midori.AncsLinux.Device")]
+impl DeviceObject {
+    /// Bluetooth address, empty for the simulated device.
+    #[zbus(property)]
+    async fn address(&self) -> String {
+        display_address(self.address)
+    }
+
+    /// One of idle, connecting, discovering, subscribed or degraded.
+    #[zbus(property)]
+    async fn state(&self) -> String {
+        state::current(self.address).state.to_string()
+    }
+
+    /// RFC 3339 time of the last change of `State`.
+    #[zbus(property)]
+    async fn state_since(&self) -> String {
+        state::current(self.address).since.to_rfc3339()
+    }
+
+    /// Why it is idle or degraded, empty otherwise.
+    #[zbus(property)]
+    async fn reason(&self) -> String {
+        state::current(self.address).reason.unwrap_or_default()
+    }
+
+    /// Notifications added on the phone since startup.
+    #[zbus(property)]
+    async fn received(&self) -> u64 {
+        METRICS.received.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    /// Notifications kept off the desktop since startup, for any reason.
+    #[zbus(property)]
+    async fn suppressed(&self) -> u64 {
+        metrics::suppressed()
+    }
+
+    /// Disconnects from the phone until `Resume` is called.
+    async fn pause(&self) -> fdo::Result<()> {
+        self.link
+            .send(LinkCommand::Disconnect)
+            .map_err(|_| fdo::Error::Failed("Shutting down".to_string()))
+    }
+
+    async fn resume(&self) -> fdo::Result<()> {
+        self.link
+            .send(LinkCommand::Reconnect)
+            .map_err(|_| fdo::Error::Failed("Shutting down".to_string()))
+    }
+}
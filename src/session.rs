@@ -0,0 +1,65 @@
+use futures::{stream::BoxStream, StreamExt as _};
+use zbus::{proxy, zvariant::OwnedObjectPath, Connection};
+
+#[proxy(
+    interface = "org.freedesktop.login1.User",
+    default_service = "org.freedesktop.login1",
+    default_path = "/org/freedesktop/login1/user/self"
+)]
+trait User {
+    #[zbus(property)]
+    fn display(&self) -> zbus::Result<(String, OwnedObjectPath)>;
+}
+
+#[proxy(
+    interface = "org.freedesktop.login1.Session",
+    default_service = "org.freedesktop.login1"
+)]
+trait Session {
+    #[zbus(property)]
+    fn locked_hint(&self) -> zbus::Result<bool>;
+
+    #[zbus(property)]
+    fn idle_hint(&self) -> zbus::Result<bool>;
+
+    #[zbus(property)]
+    fn active(&self) -> zbus::Result<bool>;
+}
+
+/// Watches the user's graphical session through logind.
+#[derive(Clone)]
+pub struct SessionMonitor {
+    session: SessionProxy<'static>,
+}
+
+impl SessionMonitor {
+    pub async fn new() -> zbus::Result<Self> {
+        let conn = Connection::system().await?;
+        let (id, path) = UserProxy::new(&conn).await?.display().await?;
+        log::debug!("Using logind session {}", id);
+
+        let session = SessionProxy::builder(&conn).path(path)?.build().await?;
+        Ok(Self { session })
+    }
+
+    /// Whether the session is unlocked and not idle, i.e. somebody is likely looking at it.
+    pub async fn is_active(&self) -> bool {
+        let locked = self.session.locked_hint().await.unwrap_or(false);
+        let idle = self.session.idle_hint().await.unwrap_or(false);
+        !locked && !idle
+    }
+
+    /// Whether the session is the one currently shown on its seat, as opposed to a session in
+    /// the background after switching users or a second remote session.
+    pub async fn is_foreground(&self) -> bool {
+        self.session.active().await.unwrap_or(true)
+    }
+
+    pub async fn foreground_changes(&self) -> BoxStream<'static, bool> {
+        self.session
+            .receive_active_changed()
+            .await
+            .then(|change| async move { change.get().await.unwrap_or(true) })
+            .boxed()
+    }
+}
@@ -0,0 +1,446 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use ancs::attributes::{
+    category::CategoryID,
+    command::CommandID,
+    event::{EventFlag, EventID},
+};
+use anyhow::{bail, Result};
+use futures::{stream::BoxStream, StreamExt as _};
+use tokio::sync::mpsc;
+
+use crate::protocol::{self, ActionID};
+
+/// Packets are split like on a link with the minimum ATT MTU.
+const MTU: usize = 20;
+
+/// A fake phone for developing without Apple hardware.
+///
+/// Plays a scripted scenario over and over: pre-existing notifications, single messages,
+/// bursts, long messages that need reassembly, calls with actions, removals, actions that
+/// fail and malformed packets.
+#[derive(Clone)]
+pub struct Simulator {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    notifications: BTreeMap<u32, Notification>,
+    next_uid: u32,
+    notification_tx: Option<mpsc::UnboundedSender<Vec<u8>>>,
+    data_tx: Option<mpsc::UnboundedSender<Vec<u8>>>,
+    /// Sent on the data source since starting, for benchmarks.
+    data_packets: u64,
+}
+
+#[derive(Clone)]
+struct Notification {
+    category: CategoryID,
+    important: bool,
+    app_id: &'static str,
+    title: String,
+    subtitle: String,
+    message: String,
+    positive_action: Option<&'static str>,
+    negative_action: Option<&'static str>,
+    /// Actions on it are answered with Action Failed.
+    fails_actions: bool,
+}
+
+impl Notification {
+    fn new(app_id: &'static str, category: CategoryID, title: &str, message: &str) -> Self {
+        Self {
+            category,
+            important: false,
+            app_id,
+            title: title.to_string(),
+            subtitle: String::new(),
+            message: message.to_string(),
+            positive_action: None,
+            negative_action: Some("Clear"),
+            fails_actions: false,
+        }
+    }
+
+    fn event_flags(&self) -> u8 {
+        let mut flags = 0;
+        if self.important {
+            flags |= EventFlag::Important as u8;
+        }
+        if self.positive_action.is_some() {
+            flags |= EventFlag::PositiveAction as u8;
+        }
+        if self.negative_action.is_some() {
+            flags |= EventFlag::NegativeAction as u8;
+        }
+        flags
+    }
+}
+
+impl Simulator {
+    pub fn start() -> Self {
+        let simulator = Self {
+            state: Arc::default(),
+        };
+
+        {
+            let mut state = simulator.state.lock().unwrap();
+            state.next_uid = 1;
+            // On the phone before we connect
+            state.add(Notification::new(
+                "com.apple.MobileSMS",
+                CategoryID::Social,
+                "Alice",
+                "Are we still on for tonight?",
+            ));
+            state.add(Notification::new(
+                "com.apple.mobilecal",
+                CategoryID::Schedule,
+                "Dentist",
+                "Tomorrow at 09:30",
+            ));
+        }
+
+        let script = simulator.clone();
+        tokio::spawn(async move {
+            loop {
+                script.play().await;
+            }
+        });
+
+        simulator
+    }
+
+    /// A phone without any notifications that does nothing on its own, for benchmarking.
+    pub fn idle() -> Self {
+        let simulator = Self {
+            state: Arc::default(),
+        };
+        simulator.state.lock().unwrap().next_uid = 1;
+        simulator
+    }
+
+    pub fn add_message(&self, app_id: &'static str, title: &str, message: &str) -> u32 {
+        self.add(Notification::new(
+            app_id,
+            CategoryID::Social,
+            title,
+            message,
+        ))
+    }
+
+    /// One round of the scenario.
+    async fn play(&self) {
+        let sleep = |secs| tokio::time::sleep(Duration::from_secs_f32(secs));
+
+        sleep(3.0).await;
+        self.add(Notification::new(
+            "com.apple.MobileSMS",
+            CategoryID::Social,
+            "Bob",
+            "Running 10 minutes late",
+        ));
+
+        sleep(3.0).await;
+        for i in 1..=5 {
+            self.add(Notification::new(
+                "com.tinyspeck.chatlyio",
+                CategoryID::Social,
+                "#general",
+                &format!("Burst message {} of 5", i),
+            ));
+            sleep(0.1).await;
+        }
+
+        sleep(3.0).await;
+        let mut mail = Notification::new(
+            "com.apple.mobilemail",
+            CategoryID::Email,
+            "Carol",
+            &"This message is long enough to be split over many packets. ".repeat(8),
+        );
+        mail.subtitle = "Quarterly report".to_string();
+        self.add(mail);
+
+        sleep(3.0).await;
+        let mut code = Notification::new(
+            "com.example.authenticator",
+            CategoryID::Other,
+            "Sign-in code",
+            "Your code is 123456",
+        );
+        code.fails_actions = true;
+        let code = self.add(code);
+
+        sleep(3.0).await;
+        let mut call = Notification::new(
+            "com.apple.mobilephone",
+            CategoryID::IncomingCall,
+            "Dave",
+            "Incoming call",
+        );
+        call.important = true;
+        call.positive_action = Some("Accept");
+        call.negative_action = Some("Decline");
+        let call = self.add(call);
+
+        sleep(15.0).await;
+        if self.remove(call) {
+            self.add(Notification::new(
+                "com.apple.mobilephone",
+                CategoryID::MissedCall,
+                "Dave",
+                "Missed call",
+            ));
+        }
+        self.remove(code);
+
+        // What a confused or newer phone might send
+        sleep(3.0).await;
+        self.send_notification_packet(vec![EventID::NotificationAdded as u8, 0, 0]);
+        self.send_data_packet(vec![0x7f, 1, 2, 3]);
+
+        sleep(30.0).await;
+    }
+
+    pub fn subscribe_notifications(&self) -> BoxStream<'static, Vec<u8>> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut state = self.state.lock().unwrap();
+
+        // Like the phone, re-announce everything to a new subscriber
+        let uids: Vec<u32> = state.notifications.keys().copied().collect();
+        for uid in uids {
+            let packet = state.event(
+                EventID::NotificationAdded,
+                uid,
+                EventFlag::PreExisting as u8,
+            );
+            let _ = tx.send(packet);
+        }
+        state.notification_tx = Some(tx);
+
+        receiver_stream(rx)
+    }
+
+    pub fn subscribe_data(&self) -> BoxStream<'static, Vec<u8>> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.state.lock().unwrap().data_tx = Some(tx);
+        receiver_stream(rx)
+    }
+
+    pub fn write_control_point(&self, data: &[u8]) -> Result<()> {
+        let Some((&command_id, rest)) = data.split_first() else {
+            bail!("Operation failed with ATT error: 0xa1");
+        };
+
+        if command_id == CommandID::GetNotificationAttributes as u8 {
+            let response = self.state.lock().unwrap().notification_attributes(rest)?;
+            self.send_data_packet(response);
+        } else if command_id == CommandID::GetAppAttributes as u8 {
+            let Some((app_id, _)) = protocol::split_nul_terminated(rest) else {
+                bail!("Operation failed with ATT error: 0xa2");
+            };
+            let app_id = String::from_utf8_lossy(app_id);
+            let mut response = vec![CommandID::GetAppAttributes as u8];
+            response.extend_from_slice(app_id.as_bytes());
+            response.push(0);
+            push_attribute(&mut response, 0, display_name(&app_id));
+            self.send_data_packet(response);
+        } else if command_id == CommandID::PerformNotificationAction as u8 {
+            let (Some(uid), Some(&action)) = (rest.get(..4), rest.get(4)) else {
+                bail!("Operation failed with ATT error: 0xa2");
+            };
+            let uid = u32::from_le_bytes(uid.try_into().unwrap());
+            let fails = match self.state.lock().unwrap().notifications.get(&uid) {
+                Some(n) => n.fails_actions,
+                None => bail!("Operation failed with ATT error: 0xa2"),
+            };
+            if fails {
+                bail!(
+                    "Operation failed with ATT error: 0x{:02x}",
+                    protocol::ERROR_ACTION_FAILED
+                );
+            }
+
+            log::info!(
+                "Simulator: {} action on {}",
+                if action == ActionID::Positive as u8 {
+                    "positive"
+                } else {
+                    "negative"
+                },
+                uid
+            );
+            let simulator = self.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                simulator.remove(uid);
+            });
+        } else {
+            bail!("Operation failed with ATT error: 0xa0");
+        }
+
+        Ok(())
+    }
+
+    fn add(&self, notification: Notification) -> u32 {
+        self.state.lock().unwrap().add(notification)
+    }
+
+    /// Returns whether the notification was still there.
+    pub fn remove(&self, uid: u32) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let Some(notification) = state.notifications.remove(&uid) else {
+            return false;
+        };
+
+        let packet = state.event_for(EventID::NotificationRemoved, uid, &notification, 0);
+        state.send_notification_packet(packet);
+        true
+    }
+
+    fn send_notification_packet(&self, packet: Vec<u8>) {
+        self.state.lock().unwrap().send_notification_packet(packet);
+    }
+
+    fn send_data_packet(&self, packet: Vec<u8>) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(tx) = &state.data_tx {
+            for chunk in packet.chunks(MTU) {
+                let _ = tx.send(chunk.to_vec());
+            }
+            state.data_packets += packet.chunks(MTU).len() as u64;
+        }
+    }
+
+    /// Packets sent on the data source so far.
+    pub fn data_packets(&self) -> u64 {
+        self.state.lock().unwrap().data_packets
+    }
+}
+
+impl State {
+    fn add(&mut self, notification: Notification) -> u32 {
+        let uid = self.next_uid;
+        self.next_uid += 1;
+        self.notifications.insert(uid, notification);
+
+        let packet = self.event(EventID::NotificationAdded, uid, 0);
+        self.send_notification_packet(packet);
+        uid
+    }
+
+    fn event(&self, event_id: EventID, uid: u32, extra_flags: u8) -> Vec<u8> {
+        let notification = &self.notifications[&uid];
+        self.event_for(event_id, uid, notification, extra_flags)
+    }
+
+    fn event_for(
+        &self,
+        event_id: EventID,
+        uid: u32,
+        notification: &Notification,
+        extra_flags: u8,
+    ) -> Vec<u8> {
+        let count = self
+            .notifications
+            .values()
+            .filter(|n| n.category as u8 == notification.category as u8)
+            .count();
+
+        let mut packet = vec![
+            event_id as u8,
+            notification.event_flags() | extra_flags,
+            notification.category as u8,
+            count.min(u8::MAX as usize) as u8,
+        ];
+        packet.extend_from_slice(&uid.to_le_bytes());
+        packet
+    }
+
+    fn send_notification_packet(&self, packet: Vec<u8>) {
+        if let Some(tx) = &self.notification_tx {
+            let _ = tx.send(packet);
+        }
+    }
+
+    fn notification_attributes(&self, request: &[u8]) -> Result<Vec<u8>> {
+        let Some(uid) = request.get(..4) else {
+            bail!("Operation failed with ATT error: 0xa2");
+        };
+        let uid = u32::from_le_bytes(uid.try_into().unwrap());
+        let Some(notification) = self.notifications.get(&uid) else {
+            bail!("Operation failed with ATT error: 0xa2");
+        };
+
+        let mut response = vec![CommandID::GetNotificationAttributes as u8];
+        response.extend_from_slice(&uid.to_le_bytes());
+
+        let mut rest = &request[4..];
+        while let Some((&id, next)) = rest.split_first() {
+            rest = next;
+            // Title, subtitle and message come with a maximum length
+            let max_len = if (1..=3).contains(&id) {
+                let Some(len) = rest.get(..2) else {
+                    bail!("Operation failed with ATT error: 0xa2");
+                };
+                rest = &rest[2..];
+                u16::from_le_bytes([len[0], len[1]]) as usize
+            } else {
+                usize::MAX
+            };
+
+            let value = match id {
+                0 => notification.app_id,
+                1 => &notification.title,
+                2 => &notification.subtitle,
+                3 => &notification.message,
+                6 => notification.positive_action.unwrap_or_default(),
+                7 => notification.negative_action.unwrap_or_default(),
+                _ => "",
+            };
+            push_attribute(&mut response, id, truncate(value, max_len));
+        }
+
+        Ok(response)
+    }
+}
+
+fn receiver_stream(mut rx: mpsc::UnboundedReceiver<Vec<u8>>) -> BoxStream<'static, Vec<u8>> {
+    futures::stream::poll_fn(move |cx| rx.poll_recv(cx)).boxed()
+}
+
+fn push_attribute(buf: &mut Vec<u8>, id: u8, value: &str) {
+    buf.push(id);
+    buf.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    buf.extend_from_slice(value.as_bytes());
+}
+
+/// Cuts `s` to at most `max_len` bytes, on a character boundary.
+fn truncate(s: &str, max_len: usize) -> &str {
+    if s.len() <= max_len {
+        return s;
+    }
+    let mut end = max_len;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+fn display_name(app_id: &str) -> &'static str {
+    match app_id {
+        "com.apple.MobileSMS" => "Messages",
+        "com.apple.mobilecal" => "Calendar",
+        "com.apple.mobilemail" => "Mail",
+        "com.apple.mobilephone" => "Phone",
+        "com.tinyspeck.chatlyio" => "Slack",
+        "com.example.authenticator" => "Authenticator",
+        _ => "",
+    }
+}
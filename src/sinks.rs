@@ -0,0 +1,194 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{Category, Config},
+    exec::ExecSink,
+    jsonl::JsonlSink,
+    ntfy::NtfySink,
+    outbox::Outbox,
+    output::StdoutSink,
+    paths,
+    power::PowerMonitor,
+    safemode,
+    webhook::WebhookSink,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    Received,
+    /// Received again after the phone modified it, e.g. with a new message in the same thread.
+    Modified,
+    Removed,
+}
+
+/// A notification coming or going on a phone, as every [`NotificationSink`] gets it. Of removed
+/// notifications only the uid and app are known, the rest is what the history keeps.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AncsNotification {
+    pub event: Kind,
+    /// Empty for the simulated device.
+    pub device: String,
+    pub uid: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub app_id: Option<String>,
+    /// None until the phone told the name of the app.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub app_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subtitle: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub category: Option<Category>,
+    pub important: bool,
+    pub time: DateTime<Utc>,
+}
+
+impl AncsNotification {
+    /// Without title, subtitle and message, for sinks that show what they get while in private
+    /// mode.
+    pub fn without_text(&self) -> Self {
+        Self {
+            title: None,
+            subtitle: None,
+            message: None,
+            ..self.clone()
+        }
+    }
+}
+
+/// Somewhere every notification goes besides the desktop, which the processor shows itself as it
+/// also has to answer clicks and follow what the phone does with them.
+pub trait NotificationSink: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Gets every event in order, handing off anything slow so as not to hold up the phone.
+    fn notify(&self, notification: &AncsNotification);
+}
+
+/// A notification as it is forwarded over the network.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Forwarded {
+    /// Address of the phone, empty for the simulated device and in queues from before it was
+    /// kept.
+    #[serde(default)]
+    pub device: String,
+    pub uid: u32,
+    pub app_id: String,
+    pub app_name: Option<String>,
+    pub title: Option<String>,
+    pub subtitle: Option<String>,
+    pub message: Option<String>,
+    pub important: bool,
+    pub received_at: DateTime<Utc>,
+}
+
+impl From<&AncsNotification> for Forwarded {
+    fn from(notification: &AncsNotification) -> Self {
+        Self {
+            device: notification.device.clone(),
+            uid: notification.uid,
+            app_id: notification.app_id.clone().unwrap_or_default(),
+            app_name: notification.app_name.clone(),
+            title: notification.title.clone(),
+            subtitle: notification.subtitle.clone(),
+            message: notification.message.clone(),
+            important: notification.important,
+            received_at: notification.time,
+        }
+    }
+}
+
+/// Somewhere on the network notifications are forwarded to, which may be unreachable for a
+/// while. Each gets what is received through an [`Outbox`], but not removals.
+pub trait NetworkSink: Send + Sync {
+    /// Identifies the sink in logs and in the name of its queue on disk.
+    fn name(&self) -> &str;
+
+    fn deliver<'a>(&'a self, notification: &'a Forwarded) -> BoxFuture<'a, Result<()>>;
+
+    /// Called once delivering starts, e.g. to connect ahead of the first notification.
+    fn start(&self) {}
+}
+
+/// Every network sink the config sets up, without delivering anything yet.
+pub fn configured(config: &Config) -> Result<Vec<Arc<dyn NetworkSink>>> {
+    let mut sinks: Vec<Arc<dyn NetworkSink>> = vec![];
+    if let Some(url) = &config.webhook.url {
+        let token = config.webhook.token.as_deref();
+        sinks.push(Arc::new(WebhookSink::new(url, token)?));
+    }
+    if let Some(path) = &config.jsonl.path {
+        sinks.push(Arc::new(JsonlSink::new(path)?));
+    }
+    if let Some(topic) = &config.ntfy.topic {
+        let token = config.ntfy.token.as_deref();
+        sinks.push(Arc::new(NtfySink::new(&config.ntfy.server, topic, token)?));
+    }
+    if let Some(host) = &config.mqtt.host {
+        #[cfg(feature = "mqtt")]
+        sinks.push(Arc::new(crate::mqtt::MqttSink::new(host, &config.mqtt)));
+        #[cfg(not(feature = "mqtt"))]
+        log::warn!(
+            "Not publishing to {}, ancs-linux was built without the `mqtt` feature",
+            host
+        );
+    }
+    Ok(sinks)
+}
+
+/// Starts every sink the config sets up. Network sinks get nothing in safe mode, and whatever
+/// is queued on disk for them waits until out of it.
+pub fn start(
+    config: &Config,
+    power: Option<&PowerMonitor>,
+) -> Result<Vec<Arc<dyn NotificationSink>>> {
+    let mut sinks: Vec<Arc<dyn NotificationSink>> = vec![];
+    if config.output.prints_json() {
+        sinks.push(Arc::new(StdoutSink));
+    }
+    if safemode::is_active() {
+        return Ok(sinks);
+    }
+    if !config.exec.command.is_empty() {
+        sinks.push(Arc::new(ExecSink::start(&config.exec.command)));
+    }
+    for outbox in start_network(config, power)? {
+        sinks.push(Arc::new(outbox));
+    }
+    Ok(sinks)
+}
+
+/// Starts delivering to every configured network sink, each through its own queue. Delivering
+/// is paused while `power` says it runs on battery, unless `power.network_sinks` is set.
+fn start_network(config: &Config, power: Option<&PowerMonitor>) -> Result<Vec<Outbox>> {
+    let sinks = configured(config)?;
+
+    let dir = paths::cache_dir().map(|dir| dir.join("outbox"));
+    if dir.is_none() && !sinks.is_empty() {
+        log::warn!(
+            "Cannot determine where to keep undelivered notifications, keeping them in memory"
+        );
+    }
+    let paused = power
+        .filter(|_| !config.power.network_sinks)
+        .map(PowerMonitor::watch);
+    Ok(sinks
+        .into_iter()
+        .map(|sink| {
+            let path = dir
+                .as_ref()
+                .map(|d| d.join(format!("{}.jsonl", sink.name())));
+            sink.start();
+            Outbox::start(sink, path, config.sinks.alert_after(), paused.clone())
+        })
+        .collect())
+}
@@ -0,0 +1,253 @@
+//! A control socket in `$XDG_RUNTIME_DIR` for scripts, taking one JSON object per line like
+//! `{"command": "dismiss", "uid": 12}` and answering each with one line like `{"ok": true}`.
+
+use std::{
+    os::unix::fs::PermissionsExt as _,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt as _, AsyncWrite, AsyncWriteExt as _, BufReader},
+    net::{UnixListener, UnixStream},
+};
+
+use crate::{
+    config::Config,
+    paths,
+    protocol::ActionID,
+    service::{Active, Control, ControlError, LinkCommand, Loader, Status},
+};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Command {
+    /// How the daemon is doing, like the `Status` method on the bus.
+    Status,
+    /// Disconnects from every phone until resumed.
+    Pause,
+    Resume,
+    /// The notifications on every phone.
+    List,
+    /// Dismisses a notification on the phone. `device` may be left out while mirroring one.
+    Dismiss {
+        uid: u32,
+        #[serde(default)]
+        device: String,
+    },
+    /// Reads the config anew and applies it to every notification from now on.
+    Reload,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Reply {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<Status>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notifications: Option<Vec<Active>>,
+}
+
+impl Reply {
+    fn done() -> Self {
+        Self {
+            ok: true,
+            ..Default::default()
+        }
+    }
+
+    fn failed(error: impl ToString) -> Self {
+        Self {
+            error: Some(error.to_string()),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<Result<(), ControlError>> for Reply {
+    fn from(result: Result<(), ControlError>) -> Self {
+        match result {
+            Ok(()) => Self::done(),
+            Err(e) => Self::failed(e),
+        }
+    }
+}
+
+/// `$XDG_RUNTIME_DIR/ancs-linux.sock`, None without a runtime directory.
+pub fn path() -> Option<PathBuf> {
+    Some(paths::runtime_dir()?.join("ancs-linux.sock"))
+}
+
+/// Answers commands on the socket for the whole run. `load` reads the config for `reload`,
+/// with what the command line overrides.
+pub async fn serve(control: Control, load: Loader) {
+    let Some(path) = path() else {
+        log::warn!("XDG_RUNTIME_DIR is not set, not listening on a control socket");
+        return;
+    };
+    let listener = match bind(&path).await {
+        Ok(l) => l,
+        Err(e) => {
+            log::warn!("Cannot listen on {}: {:?}", path.display(), e);
+            return;
+        }
+    };
+    log::info!("Listening for commands on {}", path.display());
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let (control, load) = (control.clone(), load.clone());
+                tokio::spawn(async move {
+                    let (reader, writer) = stream.into_split();
+                    if let Err(e) = answer(BufReader::new(reader), writer, &control, &*load).await {
+                        log::debug!("Control connection ended: {:?}", e);
+                    }
+                });
+            }
+            Err(e) => {
+                log::warn!("Failed to accept on the control socket: {:?}", e);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+/// Takes over the socket of an earlier run that is gone, but not of one still listening.
+async fn bind(path: &Path) -> Result<UnixListener> {
+    if UnixStream::connect(path).await.is_ok() {
+        bail!("Another ancs-linux listens on it");
+    }
+    match std::fs::remove_file(path) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e).context("Failed to remove the socket of an earlier run"),
+    }
+    let listener = UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+/// Answers every line read until the other end hangs up.
+async fn answer(
+    reader: impl AsyncBufRead + Unpin,
+    mut writer: impl AsyncWrite + Unpin,
+    control: &Control,
+    load: &(dyn Fn() -> Result<Config> + Send + Sync),
+) -> Result<()> {
+    let mut lines = reader.lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = match serde_json::from_str(&line) {
+            Ok(command) => execute(control, load, command).await,
+            Err(e) => Reply::failed(format!("Invalid command: {}", e)),
+        };
+        let mut reply = serde_json::to_string(&reply)?;
+        reply.push('\n');
+        writer.write_all(reply.as_bytes()).await?;
+    }
+    Ok(())
+}
+
+async fn execute(
+    control: &Control,
+    load: &(dyn Fn() -> Result<Config> + Send + Sync),
+    command: Command,
+) -> Reply {
+    log::debug!("Control socket: {:?}", command);
+    match command {
+        Command::Status => Reply {
+            status: Some(control.status()),
+            ..Reply::done()
+        },
+        Command::Pause => control.send_link_command(LinkCommand::Disconnect).into(),
+        Command::Resume => control.send_link_command(LinkCommand::Reconnect).into(),
+        Command::List => Reply {
+            notifications: Some(control.list().await),
+            ..Reply::done()
+        },
+        Command::Dismiss { uid, device } => control
+            .perform(&device, uid, ActionID::Negative)
+            .await
+            .into(),
+        Command::Reload => match control.reload_with(load) {
+            Ok(note) => Reply {
+                message: Some(note.to_string()),
+                ..Reply::done()
+            },
+            Err(e) => Reply::failed(format!("{:#}", e)),
+        },
+    }
+}
+
+/// Sends a command to the running daemon, failing with what it answered if it did not
+/// carry it out.
+pub async fn request(command: &Command) -> Result<Reply> {
+    let Some(path) = path() else {
+        bail!("XDG_RUNTIME_DIR is not set, cannot find the control socket");
+    };
+    let stream = UnixStream::connect(&path).await.with_context(|| {
+        format!(
+            "Cannot connect to {}, is `ancs-linux listen` running?",
+            path.display()
+        )
+    })?;
+    let (reader, mut writer) = stream.into_split();
+    let mut line = serde_json::to_string(command)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await?;
+
+    let Some(line) = BufReader::new(reader).lines().next_line().await? else {
+        bail!("The daemon hung up without answering");
+    };
+    let reply: Reply = serde_json::from_str(&line).context("Invalid answer from the daemon")?;
+    if !reply.ok {
+        bail!("{}", reply.error.unwrap_or_default());
+    }
+    Ok(reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn answers_every_line() {
+        let (control, _links) = Control::new(Arc::new(Config::default()), &[]);
+        let input = b"{\"command\": \"status\"}\n\n{\"command\": \"dismiss\", \"uid\": 12}\n\
+                      {\"command\": \"reboot\"}\n{\"command\": \"reload\"}\n";
+        let mut output = Vec::new();
+        let load = || Ok(Config::default());
+        answer(&input[..], &mut output, &control, &load)
+            .await
+            .unwrap();
+
+        let replies: Vec<serde_json::Value> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(replies.len(), 4);
+        assert_eq!(replies[0]["ok"], true);
+        assert_eq!(replies[0]["status"]["state"], "idle");
+        assert_eq!(
+            replies[1],
+            serde_json::json!({"ok": false, "error": "Not connected to the phone"})
+        );
+        assert!(replies[2]["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("Invalid command: unknown variant `reboot`"));
+        assert_eq!(replies[3]["ok"], true);
+    }
+}
@@ -0,0 +1,177 @@
+use std::{collections::BTreeMap, fmt, sync::Mutex, time::Duration};
+
+use bluer::Address;
+
+/// Steps of making ANCS usable on the phone, in order.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stage {
+    /// The phone connects to the computer.
+    #[default]
+    Connect,
+    /// BlueZ learns which services the phone offers.
+    Resolve,
+    /// The phone offers ANCS.
+    Service,
+    /// ANCS has all its characteristics.
+    Characteristic,
+    /// The phone accepts subscribing to notifications.
+    Subscribe,
+}
+
+impl Stage {
+    const ALL: [Stage; 5] = [
+        Stage::Connect,
+        Stage::Resolve,
+        Stage::Service,
+        Stage::Characteristic,
+        Stage::Subscribe,
+    ];
+
+    fn remediation(self, addr: Address) -> String {
+        match self {
+            Stage::Connect => "Connect from the Bluetooth settings of the phone, with the phone \
+                 unlocked and nearby. Connecting from the desktop often does not give access to \
+                 notifications."
+                .to_string(),
+            Stage::Resolve => format!(
+                "BlueZ has not learned the services of the phone. Keep the phone unlocked, or \
+                 disconnect with `bluetoothctl disconnect {}` and connect again from the phone.",
+                addr
+            ),
+            Stage::Service => format!(
+                "The phone does not offer notifications to this computer, which it only does \
+                 once paired. Pair again with `bluetoothctl remove {}` and `bluetoothctl pair \
+                 {}`, then connect from the phone.",
+                addr, addr
+            ),
+            Stage::Characteristic => "The phone offers notifications only in part. Turn \
+                 Bluetooth on the phone off and on again."
+                .to_string(),
+            Stage::Subscribe => format!(
+                "The phone refuses to share notifications, usually because the link is not \
+                 encrypted. Turn on \"Share System Notifications\" for this computer in the \
+                 Bluetooth settings of the phone, or pair again. `ancs-linux doctor {}` tells \
+                 more.",
+                addr
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Stage::Connect => "connect",
+            Stage::Resolve => "resolve",
+            Stage::Service => "service",
+            Stage::Characteristic => "characteristic",
+            Stage::Subscribe => "subscribe",
+        })
+    }
+}
+
+/// How far getting ANCS usable came since starting.
+#[derive(Default)]
+struct Progress {
+    /// Stage of the ongoing attempt.
+    stage: Stage,
+    usable: bool,
+    attempts: u32,
+    /// Stage and error of the last failed attempt.
+    failure: Option<(Stage, String)>,
+}
+
+/// Progress with every phone, by address.
+static PROGRESS: Mutex<BTreeMap<Address, Progress>> = Mutex::new(BTreeMap::new());
+
+fn update(device: Address, update: impl FnOnce(&mut Progress)) {
+    update(PROGRESS.lock().unwrap().entry(device).or_default());
+}
+
+/// Records that an attempt got to `stage`.
+pub fn enter(device: Address, stage: Stage) {
+    update(device, |progress| progress.stage = stage);
+}
+
+/// Records that the ongoing attempt failed at its stage.
+pub fn failed(device: Address, error: impl fmt::Display) {
+    update(device, |progress| {
+        if progress.usable {
+            return;
+        }
+        progress.attempts += 1;
+        progress.failure = Some((progress.stage, error.to_string()));
+        progress.stage = Stage::Connect;
+    });
+}
+
+pub fn usable(device: Address) {
+    update(device, |progress| progress.usable = true);
+}
+
+/// Logs what keeps ANCS from being usable, if it still is not after `timeout`. Retrying goes
+/// on regardless.
+pub async fn deadline(timeout: Duration, addr: Address) {
+    tokio::time::sleep(timeout).await;
+    let diagnosis = {
+        let progress = PROGRESS.lock().unwrap();
+        let progress = progress.get(&addr);
+        if progress.is_some_and(|p| p.usable) {
+            return;
+        }
+        diagnose(progress.unwrap_or(&Progress::default()), timeout, addr)
+    };
+    log::error!("{}", diagnosis);
+}
+
+fn diagnose(progress: &Progress, timeout: Duration, addr: Address) -> String {
+    let (stage, error) = match &progress.failure {
+        Some((stage, error)) => (*stage, error.as_str()),
+        None => (progress.stage, "Still waiting for the first attempt"),
+    };
+    let stages: Vec<String> = Stage::ALL
+        .iter()
+        .map(|&s| match s.cmp(&stage) {
+            std::cmp::Ordering::Less => format!("{} ok", s),
+            std::cmp::Ordering::Equal => format!("{} FAILED", s),
+            std::cmp::Ordering::Greater => format!("{} -", s),
+        })
+        .collect();
+
+    format!(
+        "Notifications from {} are still not available after {} seconds\n  \
+         stages:   {}\n  \
+         error:    {}\n  \
+         attempts: {}\n  \
+         fix:      {}",
+        addr,
+        timeout.as_secs(),
+        stages.join(", "),
+        error,
+        progress.attempts,
+        stage.remediation(addr)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_the_failed_stage() {
+        let progress = Progress {
+            stage: Stage::Connect,
+            usable: false,
+            attempts: 3,
+            failure: Some((Stage::Service, "ANCS service not found".to_string())),
+        };
+        let addr = Address::new([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]);
+        let diagnosis = diagnose(&progress, Duration::from_secs(60), addr);
+
+        assert!(diagnosis.contains(
+            "stages:   connect ok, resolve ok, service FAILED, characteristic -, subscribe -"
+        ));
+        assert!(diagnosis.contains("error:    ANCS service not found"));
+        assert!(diagnosis.contains("bluetoothctl remove AA:BB:CC:DD:EE:FF"));
+    }
+}
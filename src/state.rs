@@ -0,0 +1,181 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    sync::Mutex,
+};
+
+use bluer::Address;
+use chrono::{DateTime, Utc};
+
+use crate::{
+    metrics::{self, METRICS},
+    systemd,
+};
+
+/// Where the connection to the phone stands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionState {
+    /// Not connected, waiting for the phone or to retry.
+    Idle,
+    /// Checking that the phone is connected.
+    Connecting,
+    /// Looking for ANCS on the phone and subscribing to it.
+    Discovering,
+    /// Receiving notifications.
+    Subscribed,
+    /// Subscribed, but the phone does not send anything.
+    Degraded,
+}
+
+impl SessionState {
+    pub const ALL: [SessionState; 5] = [
+        SessionState::Idle,
+        SessionState::Connecting,
+        SessionState::Discovering,
+        SessionState::Subscribed,
+        SessionState::Degraded,
+    ];
+
+    /// Whether the session may go from this state to `next`. It falls back to idle from
+    /// anywhere when the connection is lost.
+    fn can_become(self, next: SessionState) -> bool {
+        use SessionState::*;
+        matches!(
+            (self, next),
+            (_, Idle)
+                | (Idle, Connecting)
+                | (Connecting, Discovering)
+                | (Discovering, Subscribed)
+                | (Subscribed, Degraded)
+                | (Degraded, Subscribed)
+        )
+    }
+}
+
+impl fmt::Display for SessionState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SessionState::Idle => "idle",
+            SessionState::Connecting => "connecting",
+            SessionState::Discovering => "discovering",
+            SessionState::Subscribed => "subscribed",
+            SessionState::Degraded => "degraded",
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub state: SessionState,
+    pub since: DateTime<Utc>,
+    /// Why it is idle or degraded.
+    pub reason: Option<String>,
+}
+
+/// Session with every phone, by address.
+static SESSIONS: Mutex<BTreeMap<Address, Session>> = Mutex::new(BTreeMap::new());
+/// Phones subscribed to at least once, to count those that are again.
+static SUBSCRIBED: Mutex<BTreeSet<Address>> = Mutex::new(BTreeSet::new());
+
+/// Moves the session with a phone to `next`, e.g. on the way from connecting to subscribed.
+pub fn transition(device: Address, next: SessionState, reason: Option<String>) {
+    let mut sessions = SESSIONS.lock().unwrap();
+    let session = sessions.get(&device);
+    let current = session.map_or(SessionState::Idle, |s| s.state);
+    if current == next && session.is_some_and(|s| s.reason == reason) {
+        return;
+    }
+    if !current.can_become(next) {
+        log::warn!(
+            "Unexpected session transition of {} from {} to {}",
+            device,
+            current,
+            next
+        );
+    }
+    match &reason {
+        Some(reason) => log::debug!("Session {} {} -> {}: {}", device, current, next, reason),
+        None => log::debug!("Session {} {} -> {}", device, current, next),
+    }
+    if next == SessionState::Degraded && current != next {
+        metrics::inc(&METRICS.session_degraded);
+    }
+    if next == SessionState::Subscribed
+        && current != SessionState::Degraded
+        && !SUBSCRIBED.lock().unwrap().insert(device)
+    {
+        metrics::inc(&METRICS.reconnects);
+    }
+
+    sessions.insert(
+        device,
+        Session {
+            state: next,
+            since: Utc::now(),
+            reason,
+        },
+    );
+
+    if next == SessionState::Subscribed {
+        systemd::ready();
+    }
+    let status: Vec<String> = sessions
+        .iter()
+        .map(|(&device, session)| {
+            let device = if device == Address::any() {
+                "simulated".to_string()
+            } else {
+                device.to_string()
+            };
+            match &session.reason {
+                Some(reason) => format!("{} {} ({})", device, session.state, reason),
+                None => format!("{} {}", device, session.state),
+            }
+        })
+        .collect();
+    systemd::status(&status.join(", "));
+}
+
+/// The session with every phone that has had one, by address.
+pub fn all() -> Vec<(Address, Session)> {
+    let sessions = SESSIONS.lock().unwrap();
+    sessions
+        .iter()
+        .map(|(&device, session)| (device, session.clone()))
+        .collect()
+}
+
+pub fn current(device: Address) -> Session {
+    SESSIONS
+        .lock()
+        .unwrap()
+        .get(&device)
+        .cloned()
+        .unwrap_or_else(|| Session {
+            state: SessionState::Idle,
+            since: Utc::now(),
+            reason: None,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use SessionState::*;
+
+    #[test]
+    fn only_allows_the_way_to_subscribed_or_back_to_idle() {
+        assert!(Idle.can_become(Connecting));
+        assert!(Connecting.can_become(Discovering));
+        assert!(Discovering.can_become(Subscribed));
+        assert!(Subscribed.can_become(Degraded));
+        assert!(Degraded.can_become(Subscribed));
+        for state in [Connecting, Discovering, Subscribed, Degraded] {
+            assert!(state.can_become(Idle));
+        }
+
+        assert!(!Idle.can_become(Subscribed));
+        assert!(!Connecting.can_become(Subscribed));
+        assert!(!Degraded.can_become(Discovering));
+    }
+}
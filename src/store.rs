@@ -0,0 +1,201 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    config::{StorageBackend, StorageConfig},
+    paths,
+};
+use anyhow::{bail, Context, Result};
+use rusqlite::{params, Connection, OptionalExtension as _};
+
+/// Where small pieces of state are kept across runs by name, such as the app names or the
+/// notifications already shown.
+pub trait Store: Send + Sync {
+    /// What was saved under `key`, if anything.
+    fn load(&self, key: &str) -> Result<Option<String>>;
+
+    /// Replaces what is saved under `key`, in one go.
+    fn save(&self, key: &str, content: &str) -> Result<()>;
+}
+
+/// Every key in a file of its own in a directory.
+pub struct FileStore {
+    dir: PathBuf,
+}
+
+impl FileStore {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+}
+
+impl Store for FileStore {
+    fn load(&self, key: &str) -> Result<Option<String>> {
+        let path = self.dir.join(key);
+        match std::fs::read_to_string(&path) {
+            Ok(content) => Ok(Some(content)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        }
+    }
+
+    fn save(&self, key: &str, content: &str) -> Result<()> {
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create {}", self.dir.display()))?;
+        let path = self.dir.join(key);
+        // Written in one go, so that a crash never leaves half a file behind
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, content)
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, &path)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(())
+    }
+}
+
+/// Every key in a row of one SQLite database, e.g. to keep everything in a single file.
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        let conn =
+            Connection::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS state (
+                key TEXT PRIMARY KEY,
+                content TEXT NOT NULL
+            );",
+        )?;
+
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+}
+
+impl Store for SqliteStore {
+    fn load(&self, key: &str) -> Result<Option<String>> {
+        let conn = self.conn.lock().unwrap();
+        let content = conn
+            .query_row("SELECT content FROM state WHERE key = ?1", [key], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        Ok(content)
+    }
+
+    fn save(&self, key: &str, content: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO state (key, content) VALUES (?1, ?2)
+             ON CONFLICT (key) DO UPDATE SET content = excluded.content",
+            params![key, content],
+        )?;
+        Ok(())
+    }
+}
+
+/// One key of a store.
+#[derive(Clone)]
+pub struct Slot {
+    store: Arc<dyn Store>,
+    key: String,
+}
+
+impl Slot {
+    pub fn new(store: Arc<dyn Store>, key: impl Into<String>) -> Self {
+        Self {
+            store,
+            key: key.into(),
+        }
+    }
+
+    pub fn load(&self) -> Result<Option<String>> {
+        self.store.load(&self.key)
+    }
+
+    pub fn save(&self, content: &str) -> Result<()> {
+        self.store.save(&self.key, content)
+    }
+}
+
+impl fmt::Display for Slot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.key)
+    }
+}
+
+/// The stores for what is worth keeping, and for what merely saves work after a restart.
+/// None where there is nowhere to keep it.
+#[derive(Clone)]
+pub struct Stores {
+    pub data: Option<Arc<dyn Store>>,
+    pub cache: Option<Arc<dyn Store>>,
+}
+
+impl Stores {
+    pub fn open(config: &StorageConfig) -> Result<Self> {
+        match config.backend {
+            StorageBackend::Files => Ok(Self {
+                data: paths::data_dir().map(|dir| Arc::new(FileStore::new(dir)) as Arc<dyn Store>),
+                cache: paths::cache_dir()
+                    .map(|dir| Arc::new(FileStore::new(dir)) as Arc<dyn Store>),
+            }),
+            StorageBackend::Sqlite => {
+                let path = match &config.path {
+                    Some(p) => p.clone(),
+                    None => match paths::data_dir() {
+                        Some(dir) => dir.join("state.db"),
+                        None => {
+                            bail!("Cannot determine the storage location, please set storage.path")
+                        }
+                    },
+                };
+                let store: Arc<dyn Store> = Arc::new(SqliteStore::open(&path)?);
+                Ok(Self {
+                    data: Some(store.clone()),
+                    cache: Some(store),
+                })
+            }
+        }
+    }
+
+    pub fn data(&self, key: &str) -> Option<Slot> {
+        self.data.clone().map(|store| Slot::new(store, key))
+    }
+
+    pub fn cache(&self, key: &str) -> Option<Slot> {
+        self.cache.clone().map(|store| Slot::new(store, key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stores_behave_alike() {
+        let dir = std::env::temp_dir().join(format!("ancs-linux-store-{}", std::process::id()));
+        let stores: [Arc<dyn Store>; 2] = [
+            Arc::new(FileStore::new(dir.join("files"))),
+            Arc::new(SqliteStore::open(&dir.join("state.db")).unwrap()),
+        ];
+        for store in stores {
+            assert_eq!(store.load("shown").unwrap(), None);
+            store.save("shown", "1").unwrap();
+            store.save("shown", "2").unwrap();
+            store.save("app-names.json", "{}").unwrap();
+            assert_eq!(store.load("shown").unwrap().as_deref(), Some("2"));
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,313 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Result;
+use chrono::{Datelike, Local, NaiveDate, TimeZone, Utc, Weekday};
+
+use crate::{
+    config::{Config, Day},
+    history::{self, Entry, History},
+    paths,
+    scheduler::Scheduler,
+};
+
+/// How often the daemon checks whether the summary is due.
+const CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// How often the daemon checks whether quiet hours ended.
+const QUIET_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// Apps named in the summary of quiet hours, the rest are counted together.
+const QUIET_TOP_APPS: usize = 3;
+
+/// What the last 7 days looked like.
+#[derive(Debug, PartialEq)]
+pub struct Summary {
+    pub total: usize,
+    /// Canonical app identifier with the most notifications.
+    pub top_app: Option<String>,
+    pub busiest_day: Option<Weekday>,
+}
+
+impl Summary {
+    pub fn last_week(config: &Config, history: &History) -> Result<Self> {
+        let entries = history.query(&history::Query {
+            since: Some(Utc::now() - chrono::Duration::days(7)),
+            ..Default::default()
+        })?;
+        Ok(summarize(config, &entries, &Local))
+    }
+}
+
+fn summarize<Tz: TimeZone>(config: &Config, entries: &[Entry], tz: &Tz) -> Summary {
+    let mut apps: HashMap<&str, usize> = HashMap::new();
+    let mut days: HashMap<Weekday, usize> = HashMap::new();
+    for entry in entries {
+        *apps
+            .entry(config.canonical_app_id(&entry.app_id))
+            .or_default() += 1;
+        *days
+            .entry(entry.received_at.with_timezone(tz).weekday())
+            .or_default() += 1;
+    }
+
+    // Ties go to the first in alphabetical or weekday order, to be stable
+    let top_app = apps
+        .into_iter()
+        .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(a.0)))
+        .map(|(app, _)| app.to_string());
+    let busiest_day = days
+        .into_iter()
+        .max_by(|a, b| {
+            a.1.cmp(&b.1)
+                .then(b.0.num_days_from_monday().cmp(&a.0.num_days_from_monday()))
+        })
+        .map(|(day, _)| day);
+
+    Summary {
+        total: entries.len(),
+        top_app,
+        busiest_day,
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.total == 0 {
+            return write!(f, "Last week: no notifications");
+        }
+
+        write!(f, "Last week: {} notifications", self.total)?;
+        if let Some(app) = &self.top_app {
+            write!(f, ", top app {}", app)?;
+        }
+        if let Some(day) = self.busiest_day {
+            write!(f, ", busiest day {}", day_name(day))?;
+        }
+        Ok(())
+    }
+}
+
+fn day_name(day: Weekday) -> &'static str {
+    match day {
+        Weekday::Mon => "Monday",
+        Weekday::Tue => "Tuesday",
+        Weekday::Wed => "Wednesday",
+        Weekday::Thu => "Thursday",
+        Weekday::Fri => "Friday",
+        Weekday::Sat => "Saturday",
+        Weekday::Sun => "Sunday",
+    }
+}
+
+/// Posts the summary as a desktop notification once on every `day`.
+///
+/// The date of the last one is kept on disk, so that restarting on that day does not post it
+/// again.
+pub async fn post_weekly(config: Arc<Config>, history: Arc<History>, day: Day) {
+    let Some(path) = paths::cache_dir().map(|dir| dir.join("summary")) else {
+        log::warn!("Cannot determine where to keep the weekly summary state, not posting it");
+        return;
+    };
+    let mut last_posted: Option<NaiveDate> = std::fs::read_to_string(&path)
+        .ok()
+        .and_then(|s| s.trim().parse().ok());
+
+    loop {
+        let today = Local::now().date_naive();
+        if today.weekday() == Weekday::from(day) && last_posted != Some(today) {
+            match Summary::last_week(&config, &history) {
+                Ok(summary) => {
+                    log::info!("{}", summary);
+                    let result = notify_rust::Notification::new()
+                        .appname("ancs-linux")
+                        .summary("Weekly summary")
+                        .body(&summary.to_string())
+                        .show_async()
+                        .await;
+                    if let Err(e) = result {
+                        log::warn!("Failed to show the weekly summary: {:?}", e);
+                    }
+                }
+                Err(e) => log::warn!("Failed to summarize the history: {:?}", e),
+            }
+
+            last_posted = Some(today);
+            if let Err(e) = std::fs::write(&path, today.to_string()) {
+                log::warn!("Failed to write {}: {:?}", path.display(), e);
+            }
+        }
+
+        tokio::time::sleep(CHECK_INTERVAL).await;
+    }
+}
+
+/// What was kept off the desktop during quiet hours.
+#[derive(Debug, PartialEq)]
+pub struct QuietSummary {
+    pub total: usize,
+    /// Canonical app identifiers by number of notifications, most first.
+    pub apps: Vec<(String, usize)>,
+}
+
+impl QuietSummary {
+    fn new(apps: HashMap<String, usize>) -> Self {
+        let mut apps: Vec<(String, usize)> = apps.into_iter().collect();
+        apps.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        Self {
+            total: apps.iter().map(|(_, count)| count).sum(),
+            apps,
+        }
+    }
+}
+
+impl fmt::Display for QuietSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} notifications", self.total)?;
+        let mut parts: Vec<String> = self
+            .apps
+            .iter()
+            .take(QUIET_TOP_APPS)
+            .map(|(app, count)| format!("{} {}", count, app))
+            .collect();
+        let others: usize = self.apps.iter().skip(QUIET_TOP_APPS).map(|a| a.1).sum();
+        if others > 0 {
+            parts.push(format!("{} from other apps", others));
+        }
+        if !parts.is_empty() {
+            write!(f, ": {}", parts.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Notifications the processors of every phone kept off the desktop during quiet hours, by
+/// canonical app identifier, until summed up.
+#[derive(Default)]
+pub struct QuietTally(Mutex<HashMap<String, usize>>);
+
+impl QuietTally {
+    pub fn suppressed(&self, config: &Config, app_id: &str) {
+        let app = config.canonical_app_id(app_id).to_string();
+        *self.0.lock().unwrap().entry(app).or_default() += 1;
+    }
+
+    /// What was counted, starting over.
+    fn take(&self) -> QuietSummary {
+        QuietSummary::new(std::mem::take(&mut *self.0.lock().unwrap()))
+    }
+}
+
+/// Posts what was missed as a desktop notification whenever quiet hours end.
+pub async fn post_after_quiet_hours(scheduler: Arc<Scheduler>, tally: Arc<QuietTally>) {
+    let mut quiet = false;
+    loop {
+        let was_quiet = std::mem::replace(&mut quiet, scheduler.is_quiet(Utc::now()));
+        if was_quiet && !quiet {
+            let summary = tally.take();
+            if summary.total > 0 {
+                log::info!("While quiet: {}", summary);
+                let result = notify_rust::Notification::new()
+                    .appname("ancs-linux")
+                    .summary("While quiet")
+                    .body(&summary.to_string())
+                    .show_async()
+                    .await;
+                if let Err(e) = result {
+                    log::warn!("Failed to show the quiet hours summary: {:?}", e);
+                }
+            }
+        }
+
+        tokio::time::sleep(QUIET_CHECK_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(app_id: &str, received_at: &str) -> Entry {
+        Entry {
+            uid: 0,
+            app_id: app_id.to_string(),
+            title: None,
+            subtitle: None,
+            message: None,
+            received_at: received_at.parse().unwrap(),
+            acknowledgment: None,
+            seen_at: None,
+            removed_at: None,
+        }
+    }
+
+    #[test]
+    fn summarizes_by_app_and_day() {
+        let mut config = Config::default();
+        config.aliases.insert(
+            "net.whatsapp.WhatsApp.beta".to_string(),
+            "whatsapp".to_string(),
+        );
+        // 2024-05-07 is a Tuesday
+        let entries = [
+            entry("net.whatsapp.WhatsApp.beta", "2024-05-07T10:00:00Z"),
+            entry("whatsapp", "2024-05-07T11:00:00Z"),
+            entry("whatsapp", "2024-05-08T11:00:00Z"),
+            entry("com.apple.MobileSMS", "2024-05-07T12:00:00Z"),
+            entry("com.apple.MobileSMS", "2024-05-08T12:00:00Z"),
+        ];
+
+        let summary = summarize(&config, &entries, &Utc);
+        assert_eq!(
+            summary.to_string(),
+            "Last week: 5 notifications, top app whatsapp, busiest day Tuesday"
+        );
+    }
+
+    #[test]
+    fn counts_days_in_the_given_timezone() {
+        let config = Config::default();
+        // Tuesday evening in UTC, already Wednesday in Tokyo
+        let entries = [entry("a", "2024-05-07T20:00:00Z")];
+
+        let tokyo: chrono_tz::Tz = "Asia/Tokyo".parse().unwrap();
+        let summary = summarize(&config, &entries, &tokyo);
+        assert_eq!(summary.busiest_day, Some(Weekday::Wed));
+    }
+
+    #[test]
+    fn sums_up_quiet_hours_by_app() {
+        let mut config = Config::default();
+        config
+            .aliases
+            .insert("net.whatsapp.WhatsApp".to_string(), "whatsapp".to_string());
+        let tally = QuietTally::default();
+        for app_id in [
+            "mail",
+            "whatsapp",
+            "net.whatsapp.WhatsApp",
+            "com.apple.MobileSMS",
+            "com.apple.MobileSMS",
+            "com.apple.MobileSMS",
+            "news",
+        ] {
+            tally.suppressed(&config, app_id);
+        }
+
+        let summary = tally.take();
+        assert_eq!(
+            summary.to_string(),
+            "7 notifications: 3 com.apple.MobileSMS, 2 whatsapp, 1 mail, 1 from other apps"
+        );
+        assert_eq!(tally.take().total, 0);
+    }
+
+    #[test]
+    fn empty_week() {
+        let summary = summarize(&Config::default(), &[], &Utc);
+        assert_eq!(summary.to_string(), "Last week: no notifications");
+    }
+}
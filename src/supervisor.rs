@@ -0,0 +1,117 @@
+use std::{backtrace::Backtrace, future::Future, panic::AssertUnwindSafe, time::Duration};
+
+use anyhow::Result;
+use futures::FutureExt as _;
+
+/// Delay before restarting after a panic, doubled for every panic in a row.
+const FIRST_DELAY: Duration = Duration::from_secs(10);
+const MAX_DELAY: Duration = Duration::from_secs(5 * 60);
+/// Delay before retrying after a session failed, doubled for every failure in a row.
+const FIRST_RETRY: Duration = Duration::from_secs(2);
+const MAX_RETRY: Duration = Duration::from_secs(2 * 60);
+
+/// A session ended by a panic, which is already logged with its backtrace.
+#[derive(Debug)]
+pub struct Panic(String);
+
+impl std::fmt::Display for Panic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Session panicked: {}", self.0)
+    }
+}
+
+impl std::error::Error for Panic {}
+
+/// Logs panics with a backtrace, instead of printing them to stderr where they are easily
+/// lost when running as a service.
+pub fn install_panic_hook() {
+    std::panic::set_hook(Box::new(|info| {
+        log::error!("{}\n{}", info, Backtrace::force_capture());
+    }));
+}
+
+/// Runs a session, turning a panic into a [`Panic`] error so that the caller can restart it.
+pub async fn supervise(session: impl Future<Output = Result<()>>) -> Result<()> {
+    match AssertUnwindSafe(session).catch_unwind().await {
+        Ok(result) => result,
+        Err(payload) => {
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown payload".to_string());
+            Err(Panic(message).into())
+        }
+    }
+}
+
+/// Restart delays after panics and failed sessions.
+///
+/// A panic or error that happens again right after restarting must not turn into a busy loop,
+/// while a rare one should not keep the daemon away for long.
+#[derive(Default)]
+pub struct Backoff {
+    panics: u32,
+    failures: u32,
+}
+
+impl Backoff {
+    pub fn panicked(&mut self) -> Duration {
+        let delay = FIRST_DELAY
+            .saturating_mul(2u32.saturating_pow(self.panics))
+            .min(MAX_DELAY);
+        self.panics = self.panics.saturating_add(1);
+        delay
+    }
+
+    pub fn failed(&mut self) -> Duration {
+        let delay = FIRST_RETRY
+            .saturating_mul(2u32.saturating_pow(self.failures))
+            .min(MAX_RETRY);
+        self.failures = self.failures.saturating_add(1);
+        delay
+    }
+
+    pub fn reset(&mut self) {
+        self.panics = 0;
+        self.failures = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn turns_panics_into_errors() {
+        let result = supervise(async {
+            if true {
+                panic!("unexpected packet");
+            }
+            Ok(())
+        })
+        .await;
+        let panic = result.unwrap_err().downcast::<Panic>().unwrap();
+        assert_eq!(panic.0, "unexpected packet");
+
+        assert!(supervise(async { Ok(()) }).await.is_ok());
+    }
+
+    #[test]
+    fn backs_off_on_panics_in_a_row() {
+        let mut backoff = Backoff::default();
+        assert_eq!(backoff.panicked(), Duration::from_secs(10));
+        assert_eq!(backoff.panicked(), Duration::from_secs(20));
+        for _ in 0..40 {
+            backoff.panicked();
+        }
+        assert_eq!(backoff.panicked(), MAX_DELAY);
+
+        assert_eq!(backoff.failed(), Duration::from_secs(2));
+        assert_eq!(backoff.failed(), Duration::from_secs(4));
+
+        backoff.reset();
+        assert_eq!(backoff.panicked(), Duration::from_secs(10));
+        assert_eq!(backoff.failed(), Duration::from_secs(2));
+    }
+}
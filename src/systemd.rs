@@ -0,0 +1,128 @@
+use std::{
+    collections::BTreeMap,
+    os::{
+        linux::net::SocketAddrExt as _,
+        unix::net::{SocketAddr, UnixDatagram},
+    },
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use bluer::Address;
+
+/// Whether systemd was told that notifications arrive, which it only needs to hear once.
+static READY: AtomicBool = AtomicBool::new(false);
+
+/// When every phone was last heard from, by address.
+static BEATS: Mutex<BTreeMap<Address, Instant>> = Mutex::new(BTreeMap::new());
+
+/// Sends `state` to the service manager, if started by one with `Type=notify`.
+fn notify(state: &str) {
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    let result = UnixDatagram::unbound().and_then(|socket| {
+        match path.as_encoded_bytes().strip_prefix(b"@") {
+            Some(name) => {
+                socket.send_to_addr(state.as_bytes(), &SocketAddr::from_abstract_name(name)?)
+            }
+            None => socket.send_to(state.as_bytes(), &path),
+        }
+    });
+    if let Err(e) = result {
+        log::debug!("Failed to notify systemd of {:?}: {:?}", state, e);
+    }
+}
+
+/// Tells systemd that the daemon is up, once notifications arrive from any phone.
+pub fn ready() {
+    if !READY.swap(true, Ordering::Relaxed) {
+        notify("READY=1");
+    }
+}
+
+/// Shown by `systemctl status`, e.g. how the connection to every phone stands.
+pub fn status(status: &str) {
+    notify(&format!("STATUS={}", status.replace('\n', " ")));
+}
+
+/// Whether systemd watches the daemon, so that phones need to prove their link alive.
+pub fn watching() -> bool {
+    watchdog_interval().is_some()
+}
+
+/// How often systemd wants to hear from the daemon, None without `WatchdogSec`.
+fn watchdog_interval() -> Option<Duration> {
+    let pid = std::env::var("WATCHDOG_PID").ok();
+    if pid.is_some_and(|pid| pid != std::process::id().to_string()) {
+        return None;
+    }
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec))
+}
+
+/// Records that a phone was heard from, until the guard is dropped once its subscriptions end.
+pub fn beat(device: Address) -> Beating {
+    BEATS.lock().unwrap().insert(device, Instant::now());
+    Beating(device)
+}
+
+/// The link to a phone, while subscribed. See [`beat`].
+pub struct Beating(Address);
+
+impl Beating {
+    /// Only on proof that the link is alive, e.g. an event from the phone.
+    pub fn again(&self) {
+        BEATS.lock().unwrap().insert(self.0, Instant::now());
+    }
+
+    /// Since the phone was last heard from.
+    pub fn silence(&self) -> Duration {
+        BEATS
+            .lock()
+            .unwrap()
+            .get(&self.0)
+            .map_or(Duration::ZERO, Instant::elapsed)
+    }
+}
+
+impl Drop for Beating {
+    fn drop(&mut self) {
+        BEATS.lock().unwrap().remove(&self.0);
+    }
+}
+
+/// How long a phone may stay silent before its link is probed, well within any sensible
+/// `WatchdogSec`.
+pub const BEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Pings the watchdog as long as no phone stopped answering over a link that BlueZ still
+/// considers up, so that systemd restarts the daemon once one does. Does nothing without a watchdog.
+pub async fn watchdog() {
+    let Some(interval) = watchdog_interval() else {
+        return;
+    };
+    log::debug!("Pinging the systemd watchdog every {:?}", interval / 2);
+    let mut ticks = tokio::time::interval(interval / 2);
+    loop {
+        ticks.tick().await;
+        let stuck: Vec<Address> = BEATS
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, beat)| beat.elapsed() > interval.max(BEAT_INTERVAL * 2))
+            .map(|(&device, _)| device)
+            .collect();
+        if stuck.is_empty() {
+            notify("WATCHDOG=1");
+        } else {
+            log::error!(
+                "Processing of {:?} is stuck, leaving it to the watchdog to restart",
+                stuck
+            );
+        }
+    }
+}
@@ -0,0 +1,227 @@
+//! Measuring and ordering what the phone sends for the terminal, where emoji and CJK take two
+//! columns and accents may come as characters of their own.
+
+use std::{borrow::Cow, cmp::Ordering, ffi::CString, sync::OnceLock};
+
+use icu_normalizer::DecomposingNormalizerBorrowed;
+use icu_properties::{
+    props::{EastAsianWidth, EmojiModifier, GeneralCategory},
+    CodePointMapData, CodePointSetData,
+};
+
+const ZERO_WIDTH_JOINER: char = '\u{200D}';
+const EMOJI_PRESENTATION: char = '\u{FE0F}';
+const ELLIPSIS: char = '…';
+
+/// Columns every character of `s` takes, in order, by its byte index. Characters joined into
+/// one emoji take none after the first.
+fn widths(s: &str) -> impl Iterator<Item = (usize, char, usize)> + '_ {
+    let category = CodePointMapData::<GeneralCategory>::new();
+    let east_asian = CodePointMapData::<EastAsianWidth>::new();
+    let modifier = CodePointSetData::new::<EmojiModifier>();
+
+    let mut previous: Option<(char, usize)> = None;
+    s.char_indices().map(move |(i, c)| {
+        let width = if c.is_control() {
+            0
+        } else if c == EMOJI_PRESENTATION {
+            // Turns e.g. a heart into its emoji, which is wide
+            match previous {
+                Some((_, 1)) => 1,
+                _ => 0,
+            }
+        } else if previous.is_some_and(|(p, _)| p == ZERO_WIDTH_JOINER)
+            || (modifier.contains(c) && previous.is_some())
+        {
+            0
+        } else {
+            match category.get(c) {
+                GeneralCategory::NonspacingMark
+                | GeneralCategory::EnclosingMark
+                | GeneralCategory::Format => 0,
+                _ => match east_asian.get(c) {
+                    EastAsianWidth::Wide | EastAsianWidth::Fullwidth => 2,
+                    _ => 1,
+                },
+            }
+        };
+        previous = Some((c, width));
+        (i, c, width)
+    })
+}
+
+/// Columns `s` takes on the terminal.
+pub fn width(s: &str) -> usize {
+    widths(s).map(|(_, _, w)| w).sum()
+}
+
+/// `s` cut to at most `max` columns, ending in an ellipsis if anything was cut.
+pub fn truncate(s: &str, max: usize) -> Cow<'_, str> {
+    if width(s) <= max {
+        return Cow::Borrowed(s);
+    }
+    if max == 0 {
+        return Cow::Borrowed("");
+    }
+    let mut taken = 0;
+    let mut end = 0;
+    for (i, c, w) in widths(s) {
+        // Marks and joined emoji stay with what they belong to
+        if w > 0 && taken + w + 1 > max {
+            break;
+        }
+        taken += w;
+        end = i + c.len_utf8();
+    }
+    let kept = s[..end].trim_end_matches(ZERO_WIDTH_JOINER);
+    Cow::Owned(format!("{}{}", kept, ELLIPSIS))
+}
+
+/// `s` padded with spaces to `columns`, unless it takes more.
+pub fn pad(s: &str, columns: usize) -> String {
+    let padding = columns.saturating_sub(width(s));
+    format!("{}{}", s, " ".repeat(padding))
+}
+
+extern "C" {
+    // POSIX, but missing from the libc crate
+    fn strcoll_l(
+        a: *const libc::c_char,
+        b: *const libc::c_char,
+        locale: libc::locale_t,
+    ) -> libc::c_int;
+}
+
+/// The collation of the locale the user chose with `LC_ALL`, `LC_COLLATE` or `LANG`, as the C
+/// library has it.
+struct Collation(libc::locale_t);
+
+// SAFETY: the locale is never changed or freed once created, which makes using it from any
+// thread fine
+unsafe impl Send for Collation {}
+unsafe impl Sync for Collation {}
+
+impl Collation {
+    /// None in the C and POSIX locales, which only compare code points, and for locales that
+    /// are not installed.
+    fn of_user() -> Option<&'static Collation> {
+        static COLLATION: OnceLock<Option<Collation>> = OnceLock::new();
+        COLLATION
+            .get_or_init(|| {
+                let name = ["LC_ALL", "LC_COLLATE", "LANG"]
+                    .into_iter()
+                    .filter_map(|var| std::env::var(var).ok())
+                    .find(|name| !name.is_empty())?;
+                let language = name.split(['.', '@']).next().unwrap_or_default();
+                if language == "C" || language == "POSIX" {
+                    return None;
+                }
+                let name = CString::new(name).ok()?;
+                // SAFETY: the name is a C string, and no base locale is modified
+                let locale = unsafe {
+                    libc::newlocale(libc::LC_COLLATE_MASK, name.as_ptr(), std::ptr::null_mut())
+                };
+                (!locale.is_null()).then_some(Collation(locale))
+            })
+            .as_ref()
+    }
+
+    /// None for strings the C library cannot take, i.e. with NUL in them.
+    fn compare(&self, a: &str, b: &str) -> Option<Ordering> {
+        let (a, b) = (CString::new(a).ok()?, CString::new(b).ok()?);
+        // SAFETY: both are C strings and the locale is valid
+        let result = unsafe { strcoll_l(a.as_ptr(), b.as_ptr(), self.0) };
+        Some(result.cmp(&0))
+    }
+}
+
+/// What `s` is sorted by without a locale, or where the locale finds no difference: without
+/// accents or case, with fullwidth letters and halfwidth or katakana kana as their usual forms,
+/// so that e.g. "Éclair" comes before "Zoom" and カメラ with かめら.
+fn collation_key(s: &str) -> String {
+    let category = CodePointMapData::<GeneralCategory>::new();
+    DecomposingNormalizerBorrowed::new_nfkd()
+        .normalize(s)
+        .chars()
+        .filter(|&c| {
+            !matches!(
+                category.get(c),
+                GeneralCategory::NonspacingMark | GeneralCategory::EnclosingMark
+            )
+        })
+        .flat_map(char::to_lowercase)
+        .map(|c| match c {
+            'ァ'..='ヶ' => char::from_u32(c as u32 - 0x60).unwrap_or(c),
+            _ => c,
+        })
+        .collect()
+}
+
+/// Orders names the way people look them up: by the collation of the user's locale, e.g. "ä"
+/// after "z" in Swedish, then by [`collation_key`]. Names only told apart by what that leaves
+/// out are in code point order.
+pub fn collate(a: &str, b: &str) -> Ordering {
+    collate_in(Collation::of_user(), a, b)
+}
+
+fn collate_in(collation: Option<&Collation>, a: &str, b: &str) -> Ordering {
+    collation
+        .and_then(|collation| collation.compare(a, b))
+        .unwrap_or(Ordering::Equal)
+        .then_with(|| collation_key(a).cmp(&collation_key(b)))
+        .then_with(|| a.cmp(b))
+}
+
+/// Columns of the terminal on stdout, None if it is not one.
+pub fn terminal_width() -> Option<usize> {
+    let size = rustix::termios::tcgetwinsize(std::io::stdout()).ok()?;
+    (size.ws_col > 0).then_some(size.ws_col as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measures_wide_and_joined_characters() {
+        assert_eq!(width("Messages"), 8);
+        assert_eq!(width("微信"), 4);
+        assert_eq!(width("ｱｲ"), 2);
+        assert_eq!(width("cafe\u{301}"), 4);
+        assert_eq!(width("❤\u{FE0F}"), 2);
+        assert_eq!(width("👍🏽"), 2);
+        assert_eq!(width("👨‍👩‍👧"), 2);
+
+        assert_eq!(truncate("Messages", 8), "Messages");
+        assert_eq!(truncate("日本語のテキスト", 7), "日本語…");
+        assert_eq!(truncate("ok 👨‍👩‍👧 family", 6), "ok 👨‍👩‍👧…");
+        assert_eq!(truncate("cafe\u{301}s!", 5), "cafe\u{301}…");
+        assert_eq!(pad("微信", 6), "微信  ");
+    }
+
+    #[test]
+    fn sorts_like_people_do() {
+        let mut names = vec![
+            "Zoom",
+            "WhatsApp",
+            "Éclair",
+            "eBay",
+            "カメラ",
+            "かめら",
+            "Ｌｉｎｅ",
+        ];
+        names.sort_by(|a, b| collate_in(None, a, b));
+        assert_eq!(
+            names,
+            [
+                "eBay",
+                "Éclair",
+                "Ｌｉｎｅ",
+                "WhatsApp",
+                "Zoom",
+                "かめら",
+                "カメラ"
+            ]
+        );
+    }
+}
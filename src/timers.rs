@@ -0,0 +1,39 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use tokio::time::Instant;
+
+/// A queue of delayed items, polled from the processor's select loop.
+pub struct TimerQueue<T> {
+    timers: BTreeMap<(Instant, u64), T>,
+    next_id: u64,
+}
+
+impl<T> Default for TimerQueue<T> {
+    fn default() -> Self {
+        Self {
+            timers: BTreeMap::new(),
+            next_id: 0,
+        }
+    }
+}
+
+impl<T> TimerQueue<T> {
+    pub fn schedule(&mut self, delay: Duration, item: T) {
+        // The id keeps timers with the same deadline apart
+        self.timers
+            .insert((Instant::now() + delay, self.next_id), item);
+        self.next_id += 1;
+    }
+
+    /// Waits for the next timer to fire, never completes while the queue is empty.
+    ///
+    /// Cancel safe, a timer is only removed once it is returned.
+    pub async fn next(&mut self) -> T {
+        let Some(&(deadline, _)) = self.timers.keys().next() else {
+            return std::future::pending().await;
+        };
+
+        tokio::time::sleep_until(deadline).await;
+        self.timers.pop_first().unwrap().1
+    }
+}
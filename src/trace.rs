@@ -0,0 +1,265 @@
+//! A trace of everything exchanged with the phone over ANCS, for bug reports.
+//!
+//! It is written as a transcript like those of the golden tests (see `tests/replay`), with a
+//! comment telling when and from which phone before each packet, so a trace can become a test
+//! once personal content is replaced.
+
+use std::{
+    fs::File,
+    io::{self, Write as _},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use ancs::{
+    attributes::{
+        app::AppAttributeID,
+        category::CategoryID,
+        command::CommandID,
+        event::{EventFlag, EventID},
+    },
+    characteristics::{
+        control_point::{GetAppAttributesRequest, GetNotificationAttributesRequest},
+        data_source::GetNotificationAttributesResponse,
+    },
+};
+use chrono::{Local, SecondsFormat};
+
+use crate::protocol;
+
+const FLAGS: [(EventFlag, &str); 5] = [
+    (EventFlag::Silent, "silent"),
+    (EventFlag::Important, "important"),
+    (EventFlag::PreExisting, "pre_existing"),
+    (EventFlag::PositiveAction, "positive_action"),
+    (EventFlag::NegativeAction, "negative_action"),
+];
+
+/// The file a trace goes to, shared by the clients of every phone.
+#[derive(Clone)]
+pub struct ProtocolTrace {
+    file: Arc<Mutex<File>>,
+    /// Of the phone, e.g. its address.
+    source: Option<String>,
+    /// Whether writing failed already, which is only logged once.
+    failed: Arc<AtomicBool>,
+}
+
+impl ProtocolTrace {
+    /// Starts a trace in `path`, replacing what was there.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let mut file = File::create(path)?;
+        writeln!(
+            file,
+            "# ANCS protocol trace of ancs-linux {}. Contains the content of notifications.",
+            env!("CARGO_PKG_VERSION")
+        )?;
+        Ok(Self {
+            file: Arc::new(Mutex::new(file)),
+            source: None,
+            failed: Arc::default(),
+        })
+    }
+
+    /// The same trace, telling that what follows is of `source`.
+    pub fn for_source(&self, source: impl ToString) -> Self {
+        Self {
+            source: Some(source.to_string()),
+            ..self.clone()
+        }
+    }
+
+    /// Adds a comment, e.g. that the phone connected.
+    pub fn note(&self, text: &str) {
+        let line = format!("# {} {}\n", self.header(), text);
+        self.write(&line);
+    }
+
+    /// Written to the control point.
+    pub(crate) fn control_point(&self, data: &[u8]) {
+        self.packet("cp", data, &[request_event(data)]);
+    }
+
+    /// Received on the notification source.
+    pub(crate) fn notification_source(&self, packet: &[u8]) {
+        self.packet("ns", packet, &[notification_event(packet)]);
+    }
+
+    /// Received on the data source, with the responses it completed.
+    pub(crate) fn data_source(&self, packet: &[u8], responses: &[Vec<u8>]) {
+        let events: Vec<String> = responses.iter().map(|r| response_event(r)).collect();
+        self.packet("ds", packet, &events);
+    }
+
+    fn packet(&self, kind: &str, data: &[u8], events: &[String]) {
+        let mut lines = format!("# {}\n{} {}\n", self.header(), kind, protocol::hex(data));
+        for event in events {
+            lines.push_str(&format!("= {}\n", event));
+        }
+        self.write(&lines);
+    }
+
+    fn header(&self) -> String {
+        let now = Local::now().to_rfc3339_opts(SecondsFormat::Millis, false);
+        match &self.source {
+            Some(source) => format!("{} {}", now, source),
+            None => now,
+        }
+    }
+
+    /// In one write, so that lines of several phones do not mix.
+    fn write(&self, lines: &str) {
+        let result = self.file.lock().unwrap().write_all(lines.as_bytes());
+        if let Err(e) = result {
+            if !self.failed.swap(true, Ordering::Relaxed) {
+                log::warn!("Failed to write the protocol trace: {}", e);
+            }
+        }
+    }
+}
+
+pub(crate) fn request_event(request: &[u8]) -> String {
+    match request.first().copied() {
+        Some(id) if id == CommandID::GetNotificationAttributes as u8 => {
+            match GetNotificationAttributesRequest::parse(request) {
+                Ok((_, request)) => {
+                    let mut event = format!("get notification uid={}", request.notification_uid);
+                    for (id, len) in request.attribute_ids {
+                        match len {
+                            Some(len) => event.push_str(&format!(" {:?}({})", id, len)),
+                            None => event.push_str(&format!(" {:?}", id)),
+                        }
+                    }
+                    event
+                }
+                Err(_) => format!("invalid request {}", protocol::hex(request)),
+            }
+        }
+        Some(id) if id == CommandID::GetAppAttributes as u8 => {
+            match GetAppAttributesRequest::parse(request) {
+                Ok((_, request)) => {
+                    let mut event = format!("get app {}", request.app_identifier);
+                    for id in request.attribute_ids {
+                        event.push_str(&format!(" {:?}", id));
+                    }
+                    event
+                }
+                Err(_) => format!("invalid request {}", protocol::hex(request)),
+            }
+        }
+        Some(id) if id == CommandID::PerformNotificationAction as u8 && request.len() == 6 => {
+            let uid = u32::from_le_bytes(request[1..5].try_into().unwrap());
+            let action = match request[5] {
+                0 => "positive".to_string(),
+                1 => "negative".to_string(),
+                other => other.to_string(),
+            };
+            format!("perform {} uid={}", action, uid)
+        }
+        _ => format!("unknown request {}", protocol::hex(request)),
+    }
+}
+
+pub(crate) fn notification_event(packet: &[u8]) -> String {
+    if packet.len() < 8 {
+        return format!("short {}", protocol::hex(packet));
+    }
+
+    let event = match EventID::try_from(packet[0]) {
+        Ok(id) => format!("{:?}", id),
+        Err(()) => format!("event {}", packet[0]),
+    };
+    let category = match CategoryID::try_from(packet[2]) {
+        Ok(id) => format!("{:?}", id),
+        Err(()) => packet[2].to_string(),
+    };
+    let flags: Vec<&str> = FLAGS
+        .iter()
+        .filter(|(flag, _)| packet[1] & *flag as u8 != 0)
+        .map(|(_, name)| *name)
+        .collect();
+    let uid = u32::from_le_bytes(packet[4..8].try_into().unwrap());
+
+    format!(
+        "{} uid={} category={} count={} flags=[{}]",
+        event,
+        uid,
+        category,
+        packet[3],
+        flags.join(",")
+    )
+}
+
+pub(crate) fn response_event(response: &[u8]) -> String {
+    match response.first().copied() {
+        Some(0) => match GetNotificationAttributesResponse::parse(response) {
+            Ok((rest, notif)) => {
+                let mut event = format!("notification uid={}", notif.notification_uid);
+                for attr in notif.attribute_list {
+                    event.push_str(&format!(
+                        " {:?}={:?}",
+                        attr.id,
+                        attr.value.unwrap_or_default()
+                    ));
+                }
+                if !rest.is_empty() {
+                    event.push_str(&format!(" trailing={}", protocol::hex(rest)));
+                }
+                event
+            }
+            Err(_) => format!("invalid notification {}", protocol::hex(response)),
+        },
+        Some(1) => match protocol::parse_app_attributes(response) {
+            Ok(app) => {
+                let mut event = format!("app {}", app.app_identifier);
+                for attr in app.attributes {
+                    match AppAttributeID::try_from(attr.id) {
+                        Ok(id) => event.push_str(&format!(" {:?}={:?}", id, attr.value)),
+                        Err(()) => event.push_str(&format!(" {}={:?}", attr.id, attr.value)),
+                    }
+                }
+                event
+            }
+            Err(e) => format!("invalid app {}", e),
+        },
+        _ => format!("unknown {}", protocol::hex(response)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ancs::attributes::notification::NotificationAttributeID;
+    use futures::StreamExt as _;
+
+    use super::*;
+    use crate::{replay, simulator::Simulator, AncsClient};
+
+    #[tokio::test]
+    async fn traces_a_replayable_transcript() {
+        let path =
+            std::env::temp_dir().join(format!("ancs-linux-trace-{}.txt", std::process::id()));
+        let trace = ProtocolTrace::create(&path).unwrap();
+        let simulator = Simulator::idle();
+        let client = AncsClient::simulated(simulator.clone()).await.unwrap();
+        client.trace_to(trace.for_source("AA:BB:CC:DD:EE:FF"));
+        let mut events = client.events();
+
+        let uid = simulator.add_message("com.apple.MobileSMS", "Bob", "Running late");
+        events.next().await.unwrap();
+        client
+            .fetch_attributes(uid, vec![(NotificationAttributeID::Title, Some(100))])
+            .await
+            .unwrap();
+        events.next().await.unwrap();
+
+        let transcript = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(transcript.contains(&format!("= get notification uid={} Title(100)\n", uid)));
+        assert!(transcript.contains("Title=\"Bob\"\n"));
+        assert!(transcript.contains(" AA:BB:CC:DD:EE:FF\nns "));
+        assert_eq!(replay::replay(&transcript), transcript);
+    }
+}
@@ -0,0 +1,457 @@
+//! An icon in the system tray, as a StatusNotifierItem with a menu over dbusmenu, telling how
+//! the connection to the phone stands without reading logs.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::Local;
+use futures::StreamExt as _;
+use serde::Serialize;
+use zbus::{
+    connection, fdo, interface, proxy,
+    zvariant::{OwnedObjectPath, OwnedValue, StructureBuilder, Type, Value},
+    SignalContext,
+};
+
+use crate::{
+    appnames::AppNames,
+    config::Config,
+    history::{self, History},
+    privacy,
+    service::{Control, LinkCommand},
+};
+
+const ITEM_PATH: &str = "/StatusNotifierItem";
+const MENU_PATH: &str = "/MenuBar";
+/// How often the state is looked at, which nothing signals.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// Notifications shown by "Show history".
+const HISTORY_LIMIT: usize = 10;
+
+const PAUSE: i32 = 1;
+const HISTORY: i32 = 2;
+const SEPARATOR: i32 = 3;
+const QUIT: i32 = 4;
+
+#[proxy(
+    interface = "org.kde.StatusNotifierWatcher",
+    default_service = "org.kde.StatusNotifierWatcher",
+    default_path = "/StatusNotifierWatcher"
+)]
+trait StatusNotifierWatcher {
+    fn register_status_notifier_item(&self, service: &str) -> zbus::Result<()>;
+}
+
+/// What the entries of the menu do.
+#[derive(Clone)]
+struct Actions {
+    control: Control,
+    config: Arc<Config>,
+    app_names: Arc<Mutex<AppNames>>,
+    history: Option<Arc<History>>,
+}
+
+impl Actions {
+    /// Shows the most recent notifications from the history as a desktop notification.
+    async fn show_history(&self) {
+        let body = match self.recent() {
+            Ok(body) => body,
+            Err(e) => format!("{:#}", e),
+        };
+        let result = notify_rust::Notification::new()
+            .appname("ancs-linux")
+            .summary("Recent notifications")
+            .body(&body)
+            .show_async()
+            .await;
+        if let Err(e) = result {
+            log::warn!("Failed to show the history: {:?}", e);
+        }
+    }
+
+    /// One line per notification, newest first. Only the app in private mode.
+    fn recent(&self) -> anyhow::Result<String> {
+        let Some(history) = &self.history else {
+            anyhow::bail!("The history is disabled");
+        };
+        let entries = history.query(&history::Query {
+            limit: Some(HISTORY_LIMIT),
+            ..Default::default()
+        })?;
+        if entries.is_empty() {
+            return Ok("Nothing yet".to_string());
+        }
+        let private = privacy::is_private_mode();
+        let app_names = self.app_names.lock().unwrap();
+        let lines: Vec<String> = entries
+            .iter()
+            .map(|entry| {
+                let app_id = self.config.canonical_app_id(&entry.app_id);
+                let app = app_names.get(app_id).unwrap_or(app_id);
+                let time = entry.received_at.with_timezone(&Local).format("%H:%M");
+                match &entry.title {
+                    Some(title) if !private && !title.is_empty() => {
+                        format!("{} {}: {}", time, app, title)
+                    }
+                    _ => format!("{} {}", time, app),
+                }
+            })
+            .collect();
+        Ok(lines.join("\n"))
+    }
+}
+
+struct Item {
+    actions: Actions,
+    icon: &'static str,
+    tool_tip: String,
+}
+
+#[interface(name = "org.kde.StatusNotifierItem")]
+impl Item {
+    /// Clicking the icon shows the history.
+    async fn activate(&self, _x: i32, _y: i32) {
+        self.actions.show_history().await;
+    }
+
+    async fn secondary_activate(&self, _x: i32, _y: i32) {}
+
+    /// The menu is over dbusmenu, which hosts show themselves.
+    async fn context_menu(&self, _x: i32, _y: i32) {}
+
+    async fn scroll(&self, _delta: i32, _orientation: &str) {}
+
+    #[zbus(signal)]
+    async fn new_icon(ctxt: &SignalContext<'_>) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn new_tool_tip(ctxt: &SignalContext<'_>) -> zbus::Result<()>;
+
+    #[zbus(property)]
+    async fn category(&self) -> String {
+        "Communications".to_string()
+    }
+
+    #[zbus(property)]
+    async fn id(&self) -> String {
+        "ancs-linux".to_string()
+    }
+
+    #[zbus(property)]
+    async fn title(&self) -> String {
+        "ancs-linux".to_string()
+    }
+
+    #[zbus(property)]
+    async fn status(&self) -> String {
+        "Active".to_string()
+    }
+
+    #[zbus(property)]
+    async fn icon_name(&self) -> String {
+        self.icon.to_string()
+    }
+
+    /// Icon name, pixmaps, title and description.
+    #[zbus(property)]
+    async fn tool_tip(&self) -> (String, Vec<(i32, i32, Vec<u8>)>, String, String) {
+        (
+            String::new(),
+            vec![],
+            "ancs-linux".to_string(),
+            self.tool_tip.clone(),
+        )
+    }
+
+    #[zbus(property)]
+    async fn item_is_menu(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    async fn menu(&self) -> OwnedObjectPath {
+        OwnedObjectPath::try_from(MENU_PATH).unwrap()
+    }
+}
+
+/// An entry of the menu and those below it, as dbusmenu lays them out.
+#[derive(Serialize, Type)]
+struct Layout {
+    id: i32,
+    properties: HashMap<String, OwnedValue>,
+    children: Vec<OwnedValue>,
+}
+
+struct Menu {
+    actions: Actions,
+    /// Whether paused from the menu, until resumed there.
+    paused: bool,
+    /// Counts changes of the layout, which hosts fetch anew when told.
+    revision: u32,
+}
+
+impl Menu {
+    fn properties(&self, id: i32) -> HashMap<String, OwnedValue> {
+        let mut properties = HashMap::new();
+        let mut set = |key: &str, value: Value<'_>| {
+            if let Ok(value) = OwnedValue::try_from(value) {
+                properties.insert(key.to_string(), value);
+            }
+        };
+        match id {
+            0 => set("children-display", Value::from("submenu")),
+            PAUSE => set(
+                "label",
+                Value::from(if self.paused { "Resume" } else { "Pause" }),
+            ),
+            HISTORY => {
+                set("label", Value::from("Show history"));
+                set("enabled", Value::from(self.actions.history.is_some()));
+            }
+            SEPARATOR => set("type", Value::from("separator")),
+            QUIT => set("label", Value::from("Quit")),
+            _ => {}
+        }
+        properties
+    }
+}
+
+#[interface(name = "com.canonical.dbusmenu")]
+impl Menu {
+    /// The menu is flat, so everything is below the root.
+    async fn get_layout(
+        &self,
+        parent_id: i32,
+        _recursion_depth: i32,
+        _property_names: Vec<String>,
+    ) -> (u32, Layout) {
+        let children = if parent_id == 0 {
+            [PAUSE, HISTORY, SEPARATOR, QUIT]
+                .into_iter()
+                .filter_map(|id| {
+                    let entry = StructureBuilder::new()
+                        .add_field(id)
+                        .add_field(self.properties(id))
+                        .add_field(Vec::<OwnedValue>::new())
+                        .build();
+                    OwnedValue::try_from(Value::from(entry)).ok()
+                })
+                .collect()
+        } else {
+            vec![]
+        };
+        let layout = Layout {
+            id: parent_id,
+            properties: self.properties(parent_id),
+            children,
+        };
+        (self.revision, layout)
+    }
+
+    async fn get_group_properties(
+        &self,
+        ids: Vec<i32>,
+        _property_names: Vec<String>,
+    ) -> Vec<(i32, HashMap<String, OwnedValue>)> {
+        ids.into_iter()
+            .map(|id| (id, self.properties(id)))
+            .collect()
+    }
+
+    async fn get_property(&self, id: i32, name: &str) -> fdo::Result<OwnedValue> {
+        self.properties(id)
+            .remove(name)
+            .ok_or_else(|| fdo::Error::InvalidArgs(format!("No {} on entry {}", name, id)))
+    }
+
+    async fn event(
+        &mut self,
+        id: i32,
+        event_id: &str,
+        _data: Value<'_>,
+        _timestamp: u32,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+    ) {
+        if event_id != "clicked" {
+            return;
+        }
+        match id {
+            PAUSE => {
+                self.paused = !self.paused;
+                let command = if self.paused {
+                    log::info!("Pausing from the tray");
+                    LinkCommand::Disconnect
+                } else {
+                    log::info!("Resuming from the tray");
+                    LinkCommand::Reconnect
+                };
+                if let Err(e) = self.actions.control.send_link_command(command) {
+                    log::warn!("Failed to pause or resume: {}", e);
+                }
+                self.revision += 1;
+                if let Err(e) = Self::layout_updated(&ctxt, self.revision, 0).await {
+                    log::warn!("Failed to update the tray menu: {:?}", e);
+                }
+            }
+            HISTORY => self.actions.show_history().await,
+            QUIT => {
+                log::info!("Quitting from the tray");
+                std::process::exit(0);
+            }
+            _ => {}
+        }
+    }
+
+    /// Nothing changes just before showing.
+    async fn about_to_show(&self, _id: i32) -> bool {
+        false
+    }
+
+    #[zbus(signal)]
+    async fn layout_updated(
+        ctxt: &SignalContext<'_>,
+        revision: u32,
+        parent: i32,
+    ) -> zbus::Result<()>;
+
+    #[zbus(property)]
+    async fn version(&self) -> u32 {
+        3
+    }
+
+    #[zbus(property)]
+    async fn text_direction(&self) -> String {
+        "ltr".to_string()
+    }
+
+    #[zbus(property)]
+    async fn status(&self) -> String {
+        "normal".to_string()
+    }
+
+    #[zbus(property)]
+    async fn icon_theme_path(&self) -> Vec<String> {
+        vec![]
+    }
+}
+
+/// Icon and tool tip for the state of the connection and the notifications on the phone.
+fn describe(state: &str, active: usize) -> (&'static str, String) {
+    let icon = match state {
+        "subscribed" => "phone",
+        "degraded" => "network-error",
+        _ => "network-offline",
+    };
+    let text = match state {
+        "subscribed" => "Connected",
+        "degraded" => "Connected, but the phone sends nothing",
+        "connecting" | "discovering" => "Connecting",
+        _ => "Not connected",
+    };
+    let tool_tip = match active {
+        0 => text.to_string(),
+        1 => format!("{}, 1 notification", text),
+        n => format!("{}, {} notifications", text, n),
+    };
+    (icon, tool_tip)
+}
+
+/// Shows the icon until the end, again whenever the tray restarts.
+pub async fn run(
+    control: Control,
+    config: Arc<Config>,
+    app_names: Arc<Mutex<AppNames>>,
+    history: Option<Arc<History>>,
+) {
+    if let Err(e) = serve(control, config, app_names, history).await {
+        log::warn!("Failed to show the tray icon: {:?}", e);
+    }
+}
+
+async fn serve(
+    control: Control,
+    config: Arc<Config>,
+    app_names: Arc<Mutex<AppNames>>,
+    history: Option<Arc<History>>,
+) -> zbus::Result<()> {
+    let actions = Actions {
+        control: control.clone(),
+        config,
+        app_names,
+        history,
+    };
+    let (icon, tool_tip) = describe(&control.status().state, 0);
+    let name = format!("org.kde.StatusNotifierItem-{}-1", std::process::id());
+    let conn = connection::Builder::session()?
+        .name(name.as_str())?
+        .serve_at(
+            ITEM_PATH,
+            Item {
+                actions: actions.clone(),
+                icon,
+                tool_tip,
+            },
+        )?
+        .serve_at(
+            MENU_PATH,
+            Menu {
+                actions,
+                paused: false,
+                revision: 1,
+            },
+        )?
+        .build()
+        .await?;
+    let item = conn.object_server().interface::<_, Item>(ITEM_PATH).await?;
+
+    let watcher = StatusNotifierWatcherProxy::new(&conn).await?;
+    let mut watcher_changes = watcher.inner().receive_owner_changed().await?;
+    if let Err(e) = watcher.register_status_notifier_item(&name).await {
+        log::warn!("No system tray to show the icon in yet: {}", e);
+    }
+
+    let mut poll = tokio::time::interval(POLL_INTERVAL);
+    loop {
+        tokio::select! {
+            Some(owner) = watcher_changes.next() => {
+                if owner.is_some() {
+                    watcher.register_status_notifier_item(&name).await?;
+                }
+            }
+            _ = poll.tick() => {
+                let active = control.list().await.len();
+                let (icon, tool_tip) = describe(&control.status().state, active);
+                let mut shown = item.get_mut().await;
+                if shown.icon != icon {
+                    shown.icon = icon;
+                    Item::new_icon(item.signal_context()).await?;
+                }
+                if shown.tool_tip != tool_tip {
+                    shown.tool_tip = tool_tip;
+                    Item::new_tool_tip(item.signal_context()).await?;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tells_the_state_and_count() {
+        assert_eq!(
+            describe("subscribed", 3),
+            ("phone", "Connected, 3 notifications".to_string())
+        );
+        assert_eq!(
+            describe("discovering", 1),
+            ("network-offline", "Connecting, 1 notification".to_string())
+        );
+        assert_eq!(describe("idle", 0).1, "Not connected");
+    }
+}
@@ -0,0 +1,47 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use futures::{future::BoxFuture, FutureExt as _};
+
+use crate::sinks::{Forwarded, NetworkSink};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// POSTs every notification as JSON to a URL, with a bearer token if given.
+pub struct WebhookSink {
+    client: reqwest::Client,
+    url: reqwest::Url,
+    token: Option<String>,
+}
+
+impl WebhookSink {
+    pub fn new(url: &str, token: Option<&str>) -> Result<Self> {
+        let url = url
+            .parse()
+            .with_context(|| format!("Invalid webhook URL {:?}", url))?;
+        let client = reqwest::Client::builder().timeout(TIMEOUT).build()?;
+        Ok(Self {
+            client,
+            url,
+            token: token.map(str::to_string),
+        })
+    }
+}
+
+impl NetworkSink for WebhookSink {
+    fn name(&self) -> &str {
+        "webhook"
+    }
+
+    fn deliver<'a>(&'a self, notification: &'a Forwarded) -> BoxFuture<'a, Result<()>> {
+        async move {
+            let mut request = self.client.post(self.url.clone()).json(notification);
+            if let Some(token) = &self.token {
+                request = request.bearer_auth(token);
+            }
+            request.send().await?.error_for_status()?;
+            Ok(())
+        }
+        .boxed()
+    }
+}
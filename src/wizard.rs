@@ -0,0 +1,228 @@
+use std::{
+    collections::BTreeMap,
+    io::{BufRead, Write},
+    path::Path,
+    time::Duration,
+};
+
+use ancs::{
+    attributes::{command::CommandID, event::EventID, notification::NotificationAttributeID},
+    characteristics::{
+        control_point::{
+            AppAttributeID, GetAppAttributesRequest, GetNotificationAttributesRequest,
+        },
+        data_source,
+    },
+};
+use anyhow::{bail, Context, Result};
+use bluer::{Adapter, Address};
+use futures::{pin_mut, StreamExt as _};
+
+use crate::{discovery, protocol, reassembly::Reassembler};
+
+#[derive(Default)]
+struct SeenApp {
+    display_name: Option<String>,
+    count: usize,
+}
+
+/// Watches notifications for a while without showing them, then asks which apps to allow and
+/// writes the answer to the config.
+pub async fn run(
+    adapter: &Adapter,
+    device_addr: Address,
+    duration: Duration,
+    config_path: &Path,
+) -> Result<()> {
+    let existing = match std::fs::read_to_string(config_path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read {}", config_path.display()))
+        }
+    };
+    let table: toml::Table = toml::from_str(&existing)
+        .with_context(|| format!("Failed to parse {}", config_path.display()))?;
+    if table.contains_key("filter") {
+        bail!(
+            "{} already has a [filter] section, edit it by hand instead",
+            config_path.display()
+        );
+    }
+
+    let device = adapter.device(device_addr)?;
+    if !device.is_connected().await? {
+        bail!("Device {} is not connected", device_addr);
+    }
+
+    let ancs = discovery::find_ancs(&device).await?;
+    let data_source_stream = ancs.data_source.notify().await?;
+    pin_mut!(data_source_stream);
+    let notification_stream = ancs.notification_source.notify().await?;
+    pin_mut!(notification_stream);
+
+    println!(
+        "Watching notifications for {} minutes, press Ctrl-C to stop early.",
+        duration.as_secs().div_ceil(60)
+    );
+    println!("Notifications already on the phone are included, nothing is shown meanwhile.");
+
+    let mut reassembler = Reassembler::default();
+    let mut apps: BTreeMap<String, SeenApp> = BTreeMap::new();
+    let deadline = tokio::time::sleep(duration);
+    pin_mut!(deadline);
+
+    loop {
+        tokio::select! {
+            Some(noti) = notification_stream.next() => {
+                if noti.len() < 8 || noti[0] == EventID::NotificationRemoved as u8 {
+                    continue;
+                }
+                let notification_uid = u32::from_le_bytes(noti[4..8].try_into().unwrap());
+
+                // Only the app is ever requested
+                reassembler.expect_notification(notification_uid, 1);
+                let cmd = GetNotificationAttributesRequest {
+                    command_id: CommandID::GetNotificationAttributes,
+                    notification_uid,
+                    attribute_ids: vec![(NotificationAttributeID::AppIdentifier, None)],
+                };
+                discovery::write_control_point(&ancs.control_point, &Vec::from(cmd)).await?;
+            }
+            Some(data) = data_source_stream.next() => {
+                for response in reassembler.push(&data) {
+                    match response.first().copied() {
+                        Some(0) => {
+                            let Ok((_, notif)) =
+                                data_source::GetNotificationAttributesResponse::parse(&response)
+                            else {
+                                continue;
+                            };
+                            let Some(app_id) = notif
+                                .attribute_list
+                                .into_iter()
+                                .find(|attr| attr.id == NotificationAttributeID::AppIdentifier)
+                                .and_then(|attr| attr.value)
+                            else {
+                                continue;
+                            };
+
+                            let app = apps.entry(app_id.clone()).or_default();
+                            app.count += 1;
+                            if app.count == 1 {
+                                println!("Seen {}", app_id);
+                                reassembler.expect_app(&app_id, 1);
+                                let cmd = GetAppAttributesRequest {
+                                    command_id: CommandID::GetAppAttributes,
+                                    app_identifier: app_id,
+                                    attribute_ids: vec![AppAttributeID::DisplayName],
+                                };
+                                discovery::write_control_point(&ancs.control_point, &Vec::from(cmd))
+                                    .await?;
+                            }
+                        }
+                        Some(1) => {
+                            let Ok(app) = protocol::parse_app_attributes(&response) else {
+                                continue;
+                            };
+                            let name = app
+                                .attributes
+                                .into_iter()
+                                .find(|attr| attr.id == AppAttributeID::DisplayName as u8)
+                                .map(|attr| attr.value)
+                                .filter(|name| !name.is_empty());
+                            if let Some(seen) = apps.get_mut(&app.app_identifier) {
+                                seen.display_name = name;
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            _ = &mut deadline => break,
+            _ = tokio::signal::ctrl_c() => break,
+            else => break,
+        }
+    }
+
+    if apps.is_empty() {
+        println!("No notifications seen, nothing to configure.");
+        return Ok(());
+    }
+
+    let apps: Vec<(String, SeenApp)> = apps.into_iter().collect();
+    println!();
+    for (i, (app_id, app)) in apps.iter().enumerate() {
+        println!(
+            "{:3}. {} ({}), {} notifications",
+            i + 1,
+            app.display_name.as_deref().unwrap_or(app_id),
+            app_id,
+            app.count
+        );
+    }
+
+    let allow = loop {
+        print!("Apps to allow, by number separated by spaces (empty to cancel): ");
+        std::io::stdout().flush()?;
+        let mut line = String::new();
+        std::io::stdin().lock().read_line(&mut line)?;
+        if line.trim().is_empty() {
+            println!("Cancelled, the config is unchanged.");
+            return Ok(());
+        }
+
+        match parse_selection(&line, apps.len()) {
+            Some(selected) => break selected,
+            None => println!("Please enter numbers between 1 and {}.", apps.len()),
+        }
+    };
+
+    let mut section = String::from("\n[filter]\nallow = [\n");
+    for i in allow {
+        let (app_id, app) = &apps[i];
+        let app_id = toml::Value::String(app_id.clone());
+        match &app.display_name {
+            Some(name) => {
+                let name = name.replace(['\n', '\r'], " ");
+                section.push_str(&format!("    {}, # {}\n", app_id, name));
+            }
+            None => section.push_str(&format!("    {},\n", app_id)),
+        }
+    }
+    section.push_str("]\n");
+
+    if let Some(parent) = config_path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    // Appended, so that comments in the existing config are kept
+    let content = if existing.is_empty() {
+        section.trim_start().to_string()
+    } else if existing.ends_with('\n') {
+        existing + &section
+    } else {
+        existing + "\n" + &section
+    };
+    std::fs::write(config_path, content)
+        .with_context(|| format!("Failed to write {}", config_path.display()))?;
+    println!("Written to {}", config_path.display());
+
+    Ok(())
+}
+
+/// Parses 1-based numbers into sorted, unique indices.
+fn parse_selection(line: &str, len: usize) -> Option<Vec<usize>> {
+    let mut selected = vec![];
+    for n in line.trim().split([' ', ',']).filter(|n| !n.is_empty()) {
+        let n: usize = n.parse().ok()?;
+        if n == 0 || n > len {
+            return None;
+        }
+        selected.push(n - 1);
+    }
+    selected.sort_unstable();
+    selected.dedup();
+
+    Some(selected)
+}